@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::actions::ActionHandler;
+use crate::policy::ActionContext;
+use crate::types::{ActionOutcome, ActionSpec};
+
+#[async_trait]
+pub trait AsyncActionHandler: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn spec(&self) -> ActionSpec;
+    fn validate(&self, ctx: &ActionContext, params: &Value) -> Result<()>;
+    async fn execute(&self, ctx: &ActionContext, params: &Value) -> Result<ActionOutcome>;
+}
+
+/// Runs a sync `ActionHandler` on tokio's blocking pool.
+pub struct BlockingActionHandler<H> {
+    inner: Arc<H>,
+}
+
+impl<H: ActionHandler + 'static> BlockingActionHandler<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            inner: Arc::new(handler),
+        }
+    }
+}
+
+#[async_trait]
+impl<H: ActionHandler + 'static> AsyncActionHandler for BlockingActionHandler<H> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn spec(&self) -> ActionSpec {
+        self.inner.spec()
+    }
+
+    fn validate(&self, ctx: &ActionContext, params: &Value) -> Result<()> {
+        self.inner.validate(ctx, params)
+    }
+
+    async fn execute(&self, ctx: &ActionContext, params: &Value) -> Result<ActionOutcome> {
+        let inner = Arc::clone(&self.inner);
+        let ctx = ctx.clone();
+        let params = params.clone();
+        tokio::task::spawn_blocking(move || inner.execute(&ctx, &params))
+            .await
+            .map_err(|err| anyhow!("action task failed: {err}"))?
+    }
+}
+
+/// Lets an `AsyncActionHandler` live in the sync `ActionRegistry`.
+pub(crate) struct AsyncActionBridge<H> {
+    inner: H,
+}
+
+impl<H: AsyncActionHandler> AsyncActionBridge<H> {
+    pub(crate) fn new(handler: H) -> Self {
+        Self { inner: handler }
+    }
+}
+
+impl<H: AsyncActionHandler> ActionHandler for AsyncActionBridge<H> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn spec(&self) -> ActionSpec {
+        self.inner.spec()
+    }
+
+    fn validate(&self, ctx: &ActionContext, params: &Value) -> Result<()> {
+        self.inner.validate(ctx, params)
+    }
+
+    fn execute(&self, ctx: &ActionContext, params: &Value) -> Result<ActionOutcome> {
+        block_on(self.inner.execute(ctx, params))?
+    }
+}
+
+pub(crate) fn block_on<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+        }
+        Ok(_) => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let runtime = Builder::new_current_thread().enable_all().build()?;
+                    Ok(runtime.block_on(future))
+                })
+                .join()
+                .map_err(|_| anyhow!("async action thread panicked"))?
+        }),
+        Err(_) => {
+            let runtime = Builder::new_current_thread().enable_all().build()?;
+            Ok(runtime.block_on(future))
+        }
+    }
+}
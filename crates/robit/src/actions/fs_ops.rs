@@ -1,15 +1,24 @@
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde_json::json;
 
+use crate::diff::line_diff;
 use crate::policy::ActionContext;
-use crate::types::{ActionOutcome, ActionSpec, RiskLevel};
-use crate::utils::{clean_path, expand_tilde};
+use crate::preflight::ChangePreview;
+use crate::types::{ActionOutcome, ActionSpec, RiskLevel, UndoStep};
+use crate::utils::{clean_path, expand_tilde, format_bytes};
+
+/// Diff lines shown in a change preview.
+const PREVIEW_LINES: usize = 40;
+
+/// Subdirectory of the backup directory holding creation markers.
+const CREATED_DIR: &str = "created";
 
 #[derive(Default)]
 pub struct ReadFileAction;
@@ -26,6 +35,17 @@ pub struct ListDirAction;
 #[derive(Default)]
 pub struct EnsureDirAction;
 
+/// Puts a backup back over a file, or deletes a file the undone action created. Used by `undo`.
+#[derive(Default)]
+pub struct RestoreFileAction;
+
+#[derive(Default)]
+pub struct MoveFilesAction;
+
+/// Removes empty directories, in the order given.
+#[derive(Default)]
+pub struct RemoveDirsAction;
+
 #[derive(Deserialize)]
 struct ReadFileParams {
     path: String,
@@ -65,18 +85,169 @@ struct EnsureDirParams {
     dry_run: Option<bool>,
 }
 
+#[derive(Deserialize)]
+struct RestoreFileParams {
+    path: String,
+    backup: String,
+    dry_run: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct MoveFilesParams {
+    moves: Vec<FileMove>,
+    /// Removed after the moves if they ended up empty.
+    remove_dirs: Option<Vec<String>>,
+    dry_run: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct FileMove {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+struct RemoveDirsParams {
+    paths: Vec<String>,
+    dry_run: Option<bool>,
+}
+
 fn parse_params<T: DeserializeOwned>(params: &serde_json::Value) -> Result<T> {
     serde_json::from_value(params.clone()).map_err(|err| anyhow!("invalid params: {err}"))
 }
 
-fn resolve_path(raw: &str) -> PathBuf {
-    clean_path(&expand_tilde(raw))
+/// `raw` with `~` expanded; a relative path is taken from `ctx.cwd`.
+fn resolve_path(ctx: &ActionContext, raw: &str) -> PathBuf {
+    clean_path(&ctx.cwd.join(expand_tilde(raw)))
 }
 
 fn ensure_allowed_path(ctx: &ActionContext, path: &Path) -> Result<()> {
     ctx.policy.check_path_allowed(path)
 }
 
+/// A backup to restore from must be one the file actions wrote: inside `ctx.backup_dir`.
+fn ensure_backup_path(ctx: &ActionContext, backup: &Path) -> Result<()> {
+    let dir = ctx
+        .backup_dir
+        .as_ref()
+        .ok_or_else(|| anyhow!("no backup directory is configured; nothing can be restored"))?;
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+    let backup = backup
+        .canonicalize()
+        .map_err(|_| anyhow!("backup does not exist: {}", backup.display()))?;
+    if !backup.starts_with(&dir) {
+        return Err(anyhow!(
+            "backup must be inside the backup directory {}: {}",
+            dir.display(),
+            backup.display()
+        ));
+    }
+    Ok(())
+}
+
+fn ensure_readable_size(ctx: &ActionContext, path: &Path) -> Result<()> {
+    let bytes = fs::metadata(path)?.len();
+    let what = path.display().to_string();
+    ctx.policy
+        .check_size(ctx.policy.max_read_bytes, "max_read_bytes", &what, bytes)
+}
+
+/// The current text of `path` for a preview, or why there is none.
+fn preview_source(ctx: &ActionContext, path: &Path) -> Result<String, ChangePreview> {
+    let size = fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    let unreadable = |what: String| ChangePreview {
+        summary: what,
+        diff: String::new(),
+    };
+    if ctx.policy.check_size(ctx.policy.max_read_bytes, "max_read_bytes", "file", size).is_err() {
+        return Err(unreadable(format!("changes a {} file; too large to diff", format_bytes(size))));
+    }
+    fs::read_to_string(path)
+        .map_err(|_| unreadable(format!("changes a {} file that is not text", format_bytes(size))))
+}
+
+fn ensure_writable_size(ctx: &ActionContext, content: &str) -> Result<()> {
+    ctx.policy.check_size(
+        ctx.policy.max_write_bytes,
+        "max_write_bytes",
+        "content",
+        content.len() as u64,
+    )
+}
+
+/// Copies `path` into the context's backup directory, if it has one.
+fn backup_file(ctx: &ActionContext, path: &Path) -> Result<Option<PathBuf>> {
+    let Some(dir) = &ctx.backup_dir else {
+        return Ok(None);
+    };
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string());
+    let backup = dir.join(format!("{stamp}-{name}"));
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::copy(path, &backup))
+        .with_context(|| format!("backup of {} failed", path.display()))?;
+    Ok(Some(backup))
+}
+
+/// Records in the backup directory that an action created `path`, so `undo` may delete it.
+/// The marker holds the path and lives under `created/`, apart from the backup copies.
+fn creation_marker(ctx: &ActionContext, path: &Path) -> Result<Option<PathBuf>> {
+    let Some(dir) = &ctx.backup_dir else {
+        return Ok(None);
+    };
+    let dir = dir.join(CREATED_DIR);
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let marker = dir.join(stamp.to_string());
+    fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&marker, path.to_string_lossy().as_bytes()))
+        .with_context(|| format!("recording the creation of {} failed", path.display()))?;
+    Ok(Some(marker))
+}
+
+/// Whether `backup` is a creation marker for `path` rather than a copy of it.
+fn is_creation_marker(ctx: &ActionContext, backup: &Path, path: &Path) -> Result<bool> {
+    let Some(dir) = &ctx.backup_dir else {
+        return Ok(false);
+    };
+    let dir = dir.join(CREATED_DIR);
+    let dir = dir.canonicalize().unwrap_or(dir);
+    let backup = backup.canonicalize().unwrap_or_else(|_| backup.to_path_buf());
+    if backup.parent() != Some(dir.as_path()) {
+        return Ok(false);
+    }
+    let recorded = fs::read_to_string(&backup)?;
+    if Path::new(&recorded) != path {
+        return Err(anyhow!(
+            "{} was not created by the action being undone",
+            path.display()
+        ));
+    }
+    Ok(true)
+}
+
+/// Undo for an action that wrote the file at `data.path`: the backup goes back, or the file
+/// is deleted if the action created it.
+fn restore_step(data: &serde_json::Value) -> Option<UndoStep> {
+    if data["dry_run"].as_bool() != Some(false) {
+        return None;
+    }
+    let path = data["path"].as_str()?;
+    let backup = data["backup"].as_str()?;
+    Some(UndoStep {
+        action: "fs.restore_file".to_string(),
+        params: json!({"path": path, "backup": backup}),
+    })
+}
+
 impl crate::actions::ActionHandler for ReadFileAction {
     fn name(&self) -> &'static str {
         "fs.read_file"
@@ -113,7 +284,7 @@ impl crate::actions::ActionHandler for ReadFileAction {
 
     fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
         let params: ReadFileParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         if !path.exists() {
             return Err(anyhow!("path does not exist: {}", path.display()));
@@ -121,13 +292,14 @@ impl crate::actions::ActionHandler for ReadFileAction {
         if !path.is_file() {
             return Err(anyhow!("path is not a file: {}", path.display()));
         }
-        Ok(())
+        ensure_readable_size(ctx, &path)
     }
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params: ReadFileParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
+        ensure_readable_size(ctx, &path)?;
 
         let content = fs::read_to_string(&path)?;
         let total_chars = content.chars().count();
@@ -199,8 +371,9 @@ impl crate::actions::ActionHandler for WriteFileAction {
 
     fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
         let params: WriteFileParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
+        ensure_writable_size(ctx, &params.content)?;
         let mode = params.mode.unwrap_or_else(|| "overwrite".to_string());
         if mode != "overwrite" && mode != "append" && mode != "create_only" {
             return Err(anyhow!("unsupported mode: {mode}"));
@@ -221,12 +394,19 @@ impl crate::actions::ActionHandler for WriteFileAction {
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params: WriteFileParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
+        ensure_writable_size(ctx, &params.content)?;
         let mode = params.mode.unwrap_or_else(|| "overwrite".to_string());
         let create_parents = params.create_parents.unwrap_or(true);
         let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
         let bytes = params.content.as_bytes().len();
+        let existed = path.exists();
+        let backup = match (existed, dry_run) {
+            (_, true) => None,
+            (true, false) => backup_file(ctx, &path)?,
+            (false, false) => creation_marker(ctx, &path)?,
+        };
 
         if !dry_run {
             if create_parents {
@@ -271,10 +451,47 @@ impl crate::actions::ActionHandler for WriteFileAction {
                 "path": path.to_string_lossy(),
                 "bytes": bytes,
                 "mode": mode,
-                "dry_run": dry_run
+                "dry_run": dry_run,
+                "created": !existed,
+                "backup": backup.map(|path| path.to_string_lossy().to_string())
             }),
         })
     }
+
+    fn undo(&self, _params: &serde_json::Value, outcome: &ActionOutcome) -> Option<UndoStep> {
+        restore_step(&outcome.data)
+    }
+
+    fn preview(&self, ctx: &ActionContext, params: &serde_json::Value) -> Option<ChangePreview> {
+        let params: WriteFileParams = parse_params(params).ok()?;
+        let path = resolve_path(ctx, &params.path);
+        let existed = path.is_file();
+        let old = if existed {
+            match preview_source(ctx, &path) {
+                Ok(old) => old,
+                Err(preview) => return Some(preview),
+            }
+        } else {
+            String::new()
+        };
+        let new = if params.mode.as_deref() == Some("append") {
+            format!("{old}{}", params.content)
+        } else {
+            params.content
+        };
+        let diff = line_diff(&old, &new, PREVIEW_LINES);
+        let summary = if !existed {
+            format!("new file, {} lines", diff.added)
+        } else if old == new {
+            "no change".to_string()
+        } else {
+            format!("+{} -{} lines", diff.added, diff.removed)
+        };
+        Some(ChangePreview {
+            summary,
+            diff: diff.text,
+        })
+    }
 }
 
 impl crate::actions::ActionHandler for ReplaceTextAction {
@@ -318,7 +535,7 @@ impl crate::actions::ActionHandler for ReplaceTextAction {
         if params.find.is_empty() {
             return Err(anyhow!("find string cannot be empty"));
         }
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         if !path.exists() {
             return Err(anyhow!("path does not exist: {}", path.display()));
@@ -331,21 +548,15 @@ impl crate::actions::ActionHandler for ReplaceTextAction {
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params: ReplaceTextParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
         let content = fs::read_to_string(&path)?;
+        let (updated, replaced) = apply_replacements(&content, &params);
 
-        let do_all = params.all.unwrap_or(params.count.is_none());
-        let (updated, replaced) = if do_all {
-            let count = content.matches(&params.find).count();
-            (content.replace(&params.find, &params.replace), count)
-        } else {
-            let count = params.count.unwrap_or(1).max(1);
-            replace_n(&content, &params.find, &params.replace, count)
-        };
-
+        let mut backup = None;
         if !dry_run && replaced > 0 {
+            backup = backup_file(ctx, &path)?;
             fs::write(&path, updated)?;
         }
 
@@ -363,10 +574,38 @@ impl crate::actions::ActionHandler for ReplaceTextAction {
             data: json!({
                 "path": path.to_string_lossy(),
                 "replaced": replaced,
-                "dry_run": dry_run
+                "dry_run": dry_run,
+                "backup": backup.map(|path| path.to_string_lossy().to_string())
             }),
         })
     }
+
+    fn undo(&self, _params: &serde_json::Value, outcome: &ActionOutcome) -> Option<UndoStep> {
+        restore_step(&outcome.data)
+    }
+
+    fn preview(&self, ctx: &ActionContext, params: &serde_json::Value) -> Option<ChangePreview> {
+        let params: ReplaceTextParams = parse_params(params).ok()?;
+        let content = match preview_source(ctx, &resolve_path(ctx, &params.path)) {
+            Ok(content) => content,
+            Err(preview) => return Some(preview),
+        };
+        let (updated, replaced) = apply_replacements(&content, &params);
+        if replaced == 0 {
+            return Some(ChangePreview {
+                summary: "no matches".to_string(),
+                diff: String::new(),
+            });
+        }
+        let diff = line_diff(&content, &updated, PREVIEW_LINES);
+        Some(ChangePreview {
+            summary: format!(
+                "{replaced} replacement(s), +{} -{} lines",
+                diff.added, diff.removed
+            ),
+            diff: diff.text,
+        })
+    }
 }
 
 impl crate::actions::ActionHandler for ListDirAction {
@@ -404,7 +643,7 @@ impl crate::actions::ActionHandler for ListDirAction {
 
     fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
         let params: ListDirParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         if !path.exists() {
             return Err(anyhow!("path does not exist: {}", path.display()));
@@ -417,7 +656,7 @@ impl crate::actions::ActionHandler for ListDirAction {
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params: ListDirParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         let include_hidden = params.include_hidden.unwrap_or(false);
         let max_entries = params.max_entries.unwrap_or(200).max(1);
@@ -510,7 +749,7 @@ impl crate::actions::ActionHandler for EnsureDirAction {
 
     fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
         let params: EnsureDirParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         if path.exists() && !path.is_dir() {
             return Err(anyhow!("path exists and is not a directory: {}", path.display()));
@@ -520,11 +759,22 @@ impl crate::actions::ActionHandler for EnsureDirAction {
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params: EnsureDirParams = parse_params(params)?;
-        let path = resolve_path(&params.path);
+        let path = resolve_path(ctx, &params.path);
         ensure_allowed_path(ctx, &path)?;
         let create_parents = params.create_parents.unwrap_or(true);
         let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
         let existed = path.exists();
+        // Deepest first, the order `undo` removes them in.
+        let missing: Vec<String> = if create_parents {
+            path.ancestors()
+                .take_while(|dir| !dir.exists())
+                .map(|dir| dir.to_string_lossy().to_string())
+                .collect()
+        } else if existed {
+            Vec::new()
+        } else {
+            vec![path.to_string_lossy().to_string()]
+        };
 
         if !dry_run && !existed {
             if create_parents {
@@ -551,12 +801,304 @@ impl crate::actions::ActionHandler for EnsureDirAction {
             data: json!({
                 "path": path.to_string_lossy(),
                 "created": created,
+                "created_dirs": missing,
+                "dry_run": dry_run
+            }),
+        })
+    }
+
+    fn undo(&self, _params: &serde_json::Value, outcome: &ActionOutcome) -> Option<UndoStep> {
+        if outcome.data["dry_run"].as_bool() != Some(false) {
+            return None;
+        }
+        let dirs = outcome.data["created_dirs"].as_array()?;
+        if dirs.is_empty() {
+            return None;
+        }
+        Some(UndoStep {
+            action: "fs.remove_dirs".to_string(),
+            params: json!({"paths": dirs}),
+        })
+    }
+}
+
+impl crate::actions::ActionHandler for RestoreFileAction {
+    fn name(&self) -> &'static str {
+        "fs.restore_file"
+    }
+
+    fn spec(&self) -> ActionSpec {
+        ActionSpec {
+            name: self.name().to_string(),
+            version: "1".to_string(),
+            description: "Restore a file from a backup copy, or delete a file the undone action \
+                          created."
+                .to_string(),
+            params_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "backup": { "type": "string" },
+                    "dry_run": { "type": "boolean" }
+                },
+                "required": ["path", "backup"]
+            }),
+            result_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "restored": { "type": "boolean" },
+                    "dry_run": { "type": "boolean" }
+                }
+            }),
+            risk: RiskLevel::Medium,
+            requires_approval: true,
+            capabilities: vec!["filesystem".to_string()],
+        }
+    }
+
+    fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
+        let params: RestoreFileParams = parse_params(params)?;
+        let path = resolve_path(ctx, &params.path);
+        ensure_allowed_path(ctx, &path)?;
+        let backup = resolve_path(ctx, &params.backup);
+        ensure_backup_path(ctx, &backup)?;
+        if !backup.is_file() {
+            return Err(anyhow!("backup does not exist: {}", backup.display()));
+        }
+        if is_creation_marker(ctx, &backup, &path)? && !path.is_file() {
+            return Err(anyhow!("path is not a file: {}", path.display()));
+        }
+        Ok(())
+    }
+
+    fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
+        let params: RestoreFileParams = parse_params(params)?;
+        let path = resolve_path(ctx, &params.path);
+        ensure_allowed_path(ctx, &path)?;
+        let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
+        let backup = resolve_path(ctx, &params.backup);
+        ensure_backup_path(ctx, &backup)?;
+        let restored = !is_creation_marker(ctx, &backup, &path)?;
+
+        if !dry_run {
+            if restored {
+                fs::copy(&backup, &path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            fs::remove_file(&backup)?;
+        }
+
+        let summary = match (dry_run, restored) {
+            (true, true) => format!("dry run: would restore {} from backup", path.display()),
+            (true, false) => format!("dry run: would remove {}", path.display()),
+            (false, true) => format!("restored {} from backup", path.display()),
+            (false, false) => format!("removed {}", path.display()),
+        };
+
+        Ok(ActionOutcome {
+            summary,
+            data: json!({
+                "path": path.to_string_lossy(),
+                "restored": restored,
                 "dry_run": dry_run
             }),
         })
     }
 }
 
+impl crate::actions::ActionHandler for MoveFilesAction {
+    fn name(&self) -> &'static str {
+        "fs.move_files"
+    }
+
+    fn spec(&self) -> ActionSpec {
+        ActionSpec {
+            name: self.name().to_string(),
+            version: "1".to_string(),
+            description: "Move files, then remove the listed directories if they are empty."
+                .to_string(),
+            params_schema: json!({
+                "type": "object",
+                "properties": {
+                    "moves": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "from": { "type": "string" },
+                                "to": { "type": "string" }
+                            },
+                            "required": ["from", "to"]
+                        }
+                    },
+                    "remove_dirs": { "type": "array", "items": { "type": "string" } },
+                    "dry_run": { "type": "boolean" }
+                },
+                "required": ["moves"]
+            }),
+            result_schema: json!({
+                "type": "object",
+                "properties": {
+                    "moved": { "type": "integer" },
+                    "removed_dirs": { "type": "integer" },
+                    "dry_run": { "type": "boolean" }
+                }
+            }),
+            risk: RiskLevel::Medium,
+            requires_approval: true,
+            capabilities: vec!["filesystem".to_string()],
+        }
+    }
+
+    fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
+        let params: MoveFilesParams = parse_params(params)?;
+        for item in &params.moves {
+            let from = resolve_path(ctx, &item.from);
+            let to = resolve_path(ctx, &item.to);
+            ensure_allowed_path(ctx, &from)?;
+            ensure_allowed_path(ctx, &to)?;
+            if !from.exists() {
+                return Err(anyhow!("path does not exist: {}", from.display()));
+            }
+            if to.exists() {
+                return Err(anyhow!("destination already exists: {}", to.display()));
+            }
+        }
+        for dir in params.remove_dirs.iter().flatten() {
+            ensure_allowed_path(ctx, &resolve_path(ctx, dir))?;
+        }
+        Ok(())
+    }
+
+    fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
+        let params: MoveFilesParams = parse_params(params)?;
+        let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
+        let mut moved = 0usize;
+        for item in &params.moves {
+            let from = resolve_path(ctx, &item.from);
+            let to = resolve_path(ctx, &item.to);
+            ensure_allowed_path(ctx, &from)?;
+            ensure_allowed_path(ctx, &to)?;
+            if !dry_run {
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&from, &to)?;
+            }
+            moved += 1;
+        }
+        let mut removed_dirs = 0usize;
+        for dir in params.remove_dirs.iter().flatten() {
+            let dir = resolve_path(ctx, dir);
+            ensure_allowed_path(ctx, &dir)?;
+            let empty = fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_none());
+            if !dry_run && empty {
+                fs::remove_dir(&dir)?;
+                removed_dirs += 1;
+            }
+        }
+
+        let summary = if dry_run {
+            format!("dry run: would move {moved} files")
+        } else {
+            format!("moved {moved} files, removed {removed_dirs} empty directories")
+        };
+
+        Ok(ActionOutcome {
+            summary,
+            data: json!({
+                "moved": moved,
+                "removed_dirs": removed_dirs,
+                "dry_run": dry_run
+            }),
+        })
+    }
+}
+
+impl crate::actions::ActionHandler for RemoveDirsAction {
+    fn name(&self) -> &'static str {
+        "fs.remove_dirs"
+    }
+
+    fn spec(&self) -> ActionSpec {
+        ActionSpec {
+            name: self.name().to_string(),
+            version: "1".to_string(),
+            description: "Remove empty directories.".to_string(),
+            params_schema: json!({
+                "type": "object",
+                "properties": {
+                    "paths": { "type": "array", "items": { "type": "string" } },
+                    "dry_run": { "type": "boolean" }
+                },
+                "required": ["paths"]
+            }),
+            result_schema: json!({
+                "type": "object",
+                "properties": {
+                    "removed": { "type": "integer" },
+                    "dry_run": { "type": "boolean" }
+                }
+            }),
+            risk: RiskLevel::Medium,
+            requires_approval: true,
+            capabilities: vec!["filesystem".to_string()],
+        }
+    }
+
+    fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
+        let params: RemoveDirsParams = parse_params(params)?;
+        for raw in &params.paths {
+            let path = resolve_path(ctx, raw);
+            ensure_allowed_path(ctx, &path)?;
+            if !path.is_dir() {
+                return Err(anyhow!("path is not a directory: {}", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
+        let params: RemoveDirsParams = parse_params(params)?;
+        let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
+        for raw in &params.paths {
+            let path = resolve_path(ctx, raw);
+            ensure_allowed_path(ctx, &path)?;
+            if !dry_run {
+                fs::remove_dir(&path)
+                    .with_context(|| format!("cannot remove {}", path.display()))?;
+            }
+        }
+        let removed = params.paths.len();
+        let summary = if dry_run {
+            format!("dry run: would remove {removed} directories")
+        } else {
+            format!("removed {removed} directories")
+        };
+
+        Ok(ActionOutcome {
+            summary,
+            data: json!({
+                "removed": removed,
+                "dry_run": dry_run
+            }),
+        })
+    }
+}
+
+/// `content` with the replacements `params` ask for, and how many were made.
+fn apply_replacements(content: &str, params: &ReplaceTextParams) -> (String, usize) {
+    if params.all.unwrap_or(params.count.is_none()) {
+        let count = content.matches(&params.find).count();
+        (content.replace(&params.find, &params.replace), count)
+    } else {
+        let count = params.count.unwrap_or(1).max(1);
+        replace_n(content, &params.find, &params.replace, count)
+    }
+}
+
 fn replace_n(haystack: &str, needle: &str, replacement: &str, limit: usize) -> (String, usize) {
     if needle.is_empty() || limit == 0 {
         return (haystack.to_string(), 0);
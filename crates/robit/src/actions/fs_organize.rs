@@ -5,7 +5,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use crate::policy::ActionContext;
-use crate::types::{ActionOutcome, ActionSpec, RiskLevel};
+use crate::types::{ActionOutcome, ActionSpec, RiskLevel, UndoStep};
 use crate::utils::{clean_path, expand_tilde};
 
 const SORTED_DIR: &str = "robit_sorted";
@@ -86,7 +86,7 @@ impl crate::actions::ActionHandler for OrganizeDirectoryAction {
 
     fn validate(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
         let params = self.parse_params(params)?;
-        let target = expand_tilde(&params.path);
+        let target = ctx.cwd.join(expand_tilde(&params.path));
         ctx.policy.check_path_allowed(&target)?;
         if !target.exists() {
             return Err(anyhow!("path does not exist: {}", target.display()));
@@ -103,12 +103,15 @@ impl crate::actions::ActionHandler for OrganizeDirectoryAction {
 
     fn execute(&self, ctx: &ActionContext, params: &serde_json::Value) -> Result<ActionOutcome> {
         let params = self.parse_params(params)?;
-        let target = clean_path(&expand_tilde(&params.path));
+        let target = clean_path(&ctx.cwd.join(expand_tilde(&params.path)));
         let dry_run = ctx.dry_run || params.dry_run.unwrap_or(false);
 
         let sorted_root = target.join(SORTED_DIR);
         let mut moved = 0usize;
         let mut buckets = Vec::new();
+        // What `undo` needs: each move, and the directories this run created.
+        let mut manifest = Vec::new();
+        let mut created_dirs = Vec::new();
 
         for entry in fs::read_dir(&target)? {
             let entry = entry?;
@@ -135,9 +138,18 @@ impl crate::actions::ActionHandler for OrganizeDirectoryAction {
             let dest_path = Self::ensure_unique_destination(&dest_dir, file_name);
 
             if !dry_run {
+                for dir in [&sorted_root, &dest_dir] {
+                    if !dir.exists() {
+                        created_dirs.push(dir.to_string_lossy().to_string());
+                    }
+                }
                 fs::create_dir_all(&dest_dir)?;
                 fs::rename(&path, &dest_path)?;
             }
+            manifest.push(json!({
+                "from": path.to_string_lossy(),
+                "to": dest_path.to_string_lossy(),
+            }));
             moved += 1;
         }
 
@@ -162,7 +174,28 @@ impl crate::actions::ActionHandler for OrganizeDirectoryAction {
                 "buckets": buckets,
                 "destination": sorted_root.to_string_lossy(),
                 "dry_run": dry_run,
+                "manifest": manifest,
+                "created_dirs": created_dirs,
             }),
         })
     }
+
+    /// Moves every file back and removes the directories the run created.
+    fn undo(&self, _params: &serde_json::Value, outcome: &ActionOutcome) -> Option<UndoStep> {
+        if outcome.data["dry_run"].as_bool() != Some(false) {
+            return None;
+        }
+        let manifest = outcome.data["manifest"].as_array().filter(|items| !items.is_empty())?;
+        let moves: Vec<_> = manifest
+            .iter()
+            .rev()
+            .map(|item| json!({"from": item["to"], "to": item["from"]}))
+            .collect();
+        let mut dirs = outcome.data["created_dirs"].as_array().cloned().unwrap_or_default();
+        dirs.reverse();
+        Some(UndoStep {
+            action: "fs.move_files".to_string(),
+            params: json!({"moves": moves, "remove_dirs": dirs}),
+        })
+    }
 }
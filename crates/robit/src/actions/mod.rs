@@ -5,8 +5,11 @@ use anyhow::Result;
 use serde_json::Value;
 
 use crate::policy::ActionContext;
-use crate::types::{ActionOutcome, ActionSpec};
+use crate::preflight::ChangePreview;
+use crate::types::{ActionOutcome, ActionSpec, UndoStep};
 
+#[cfg(feature = "async")]
+mod async_action;
 pub mod fs_organize;
 pub mod fs_ops;
 pub mod shell;
@@ -14,6 +17,9 @@ pub mod browser;
 #[cfg(feature = "web")]
 pub mod web;
 
+#[cfg(feature = "async")]
+pub use async_action::{AsyncActionHandler, BlockingActionHandler};
+
 pub fn default_registry() -> ActionRegistry {
     let mut registry = ActionRegistry::new();
     registry.register(fs_organize::OrganizeDirectoryAction::default());
@@ -22,6 +28,9 @@ pub fn default_registry() -> ActionRegistry {
     registry.register(fs_ops::ReplaceTextAction::default());
     registry.register(fs_ops::ListDirAction::default());
     registry.register(fs_ops::EnsureDirAction::default());
+    registry.register(fs_ops::RestoreFileAction);
+    registry.register(fs_ops::MoveFilesAction);
+    registry.register(fs_ops::RemoveDirsAction);
     registry.register(shell::ShellRunAction::default());
     registry.register(browser::BrowserOpenUrlAction::default());
     #[cfg(feature = "web")]
@@ -37,6 +46,18 @@ pub trait ActionHandler: Send + Sync {
     fn spec(&self) -> ActionSpec;
     fn validate(&self, ctx: &ActionContext, params: &Value) -> Result<()>;
     fn execute(&self, ctx: &ActionContext, params: &Value) -> Result<ActionOutcome>;
+
+    /// The action that reverses a completed run, offered by the `undo` command. Most actions
+    /// cannot be undone.
+    fn undo(&self, _params: &Value, _outcome: &ActionOutcome) -> Option<UndoStep> {
+        None
+    }
+
+    /// What running with `params` would change, worked out without changing anything. It
+    /// goes into the preflight report and the approval prompt.
+    fn preview(&self, _ctx: &ActionContext, _params: &Value) -> Option<ChangePreview> {
+        None
+    }
 }
 
 #[derive(Default)]
@@ -56,6 +77,11 @@ impl ActionRegistry {
             .insert(action.name().to_string(), Arc::new(action));
     }
 
+    #[cfg(feature = "async")]
+    pub fn register_async<A: AsyncActionHandler + 'static>(&mut self, action: A) {
+        self.register(async_action::AsyncActionBridge::new(action));
+    }
+
     pub fn get(&self, name: &str) -> Option<Arc<dyn ActionHandler>> {
         self.actions.get(name).cloned()
     }
@@ -1,11 +1,15 @@
+use std::env;
+use std::io::Read;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::policy::ActionContext;
+use crate::policy::{ActionContext, ShellSandbox};
 use crate::types::{ActionOutcome, ActionSpec, RiskLevel};
 use crate::utils::{clean_path, expand_tilde};
 
@@ -107,12 +111,11 @@ impl crate::actions::ActionHandler for ShellRunAction {
             });
         }
 
-        let mut cmd = Command::new("sh");
-        cmd.arg("-lc").arg(&command);
+        let mut cmd = sandboxed_command(&ctx.policy.shell, &command)?;
         if let Some(dir) = &cwd {
             cmd.current_dir(dir);
         }
-        let output = cmd.output().map_err(|err| anyhow!("failed to run command: {err}"))?;
+        let output = run_until(cmd, ctx.deadline)?;
         let mut stdout = String::from_utf8_lossy(&output.stdout).to_string();
         let mut stderr = String::from_utf8_lossy(&output.stderr).to_string();
         let mut truncated = false;
@@ -146,3 +149,124 @@ impl crate::actions::ActionHandler for ShellRunAction {
         })
     }
 }
+
+/// Runs `cmd` to completion, or kills it, and everything it started, at `deadline`.
+fn run_until(mut cmd: Command, deadline: Option<Instant>) -> Result<Output> {
+    let Some(deadline) = deadline else {
+        return cmd.output().map_err(|err| anyhow!("failed to run command: {err}"));
+    };
+    // Its own process group, so the whole group can be killed at the deadline.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| anyhow!("failed to run command: {err}"))?;
+    // Read both pipes while waiting, so a chatty command cannot block on a full pipe.
+    let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read_all(child.stdout.take().map(|pipe| Box::new(pipe) as Box<dyn Read + Send>));
+    let stderr = read_all(child.stderr.take().map(|pipe| Box::new(pipe) as Box<dyn Read + Send>));
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            kill_group(&mut child);
+            return Err(anyhow!("command ran past its time limit and was stopped"));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Kills `child` and, on Unix, the rest of the process group it leads.
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pgid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: `kill` takes no pointers; a negative pid names the process group.
+        unsafe {
+            libc::kill(-pgid, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// The shell invocation for `command` under the policy's `[policy.shell]` settings.
+fn sandboxed_command(sandbox: &ShellSandbox, command: &str) -> Result<Command> {
+    let mut script = String::new();
+    if let Some(secs) = sandbox.max_cpu_secs {
+        script.push_str(&format!("ulimit -t {secs} || exit 126; "));
+    }
+    if let Some(mb) = sandbox.max_memory_mb {
+        script.push_str(&format!("ulimit -v {} || exit 126; ", mb * 1024));
+    }
+    script.push_str(command);
+    let limited = sandbox.max_cpu_secs.is_some() || sandbox.max_memory_mb.is_some();
+    // A login shell sources the user's profile, which can put back the PATH and secrets
+    // the sandbox took out, so it is only used when nothing is sandboxed.
+    let sandboxed = sandbox.scrub_env || sandbox.path.is_some() || limited || sandbox.deny_network;
+    let flag = if sandboxed { "-c" } else { "-lc" };
+
+    let mut cmd = if cfg!(windows) {
+        if limited || sandbox.deny_network {
+            return Err(anyhow!(
+                "max_cpu_secs, max_memory_mb and deny_network are not supported on Windows"
+            ));
+        }
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else if !sandbox.deny_network {
+        let mut cmd = Command::new("sh");
+        cmd.arg(flag).arg(&script);
+        cmd
+    } else if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("sandbox-exec");
+        cmd.args(["-p", "(version 1)(allow default)(deny network*)", "sh", flag, &script]);
+        cmd
+    } else if cfg!(target_os = "linux") {
+        let mut cmd = Command::new("unshare");
+        cmd.args(["-rn", "sh", flag, &script]);
+        cmd
+    } else {
+        return Err(anyhow!("deny_network is not supported on this platform"));
+    };
+    if sandbox.scrub_env {
+        cmd.env_clear();
+        for (name, value) in env::vars_os() {
+            let keep = name.to_str().is_some_and(|name| {
+                !looks_secret(name) || sandbox.keep_env.iter().any(|kept| kept == name)
+            });
+            if keep {
+                cmd.env(name, value);
+            }
+        }
+    }
+    if let Some(path) = &sandbox.path {
+        cmd.env("PATH", path);
+    }
+    Ok(cmd)
+}
+
+fn looks_secret(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    upper.starts_with("AWS_")
+        || ["KEY", "TOKEN", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL", "PRIVATE"]
+            .iter()
+            .any(|part| upper.contains(part))
+}
@@ -1,3 +1,5 @@
+use std::io::Read;
+
 use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
 use serde_json::json;
@@ -20,7 +22,9 @@ struct FetchUrlParams {
 #[derive(Deserialize)]
 struct BraveSearchParams {
     query: String,
-    api_key: String,
+    /// Falls back to `BRAVE_API_KEY`.
+    #[serde(default)]
+    api_key: Option<String>,
     count: Option<u32>,
 }
 
@@ -32,10 +36,33 @@ impl FetchUrlAction {
 
 impl BraveSearchAction {
     fn parse_params(&self, params: &serde_json::Value) -> Result<BraveSearchParams> {
-        serde_json::from_value(params.clone()).map_err(|err| anyhow!("invalid params: {err}"))
+        let mut params: BraveSearchParams = serde_json::from_value(params.clone())
+            .map_err(|err| anyhow!("invalid params: {err}"))?;
+        if params.api_key.as_deref().is_none_or(|key| key.trim().is_empty()) {
+            params.api_key = std::env::var("BRAVE_API_KEY").ok();
+        }
+        Ok(params)
     }
 }
 
+/// Follows up to 10 redirects, each checked against the preflight domain and scheme lists:
+/// preflight only saw the first URL.
+fn redirect_policy(ctx: &ActionContext) -> reqwest::redirect::Policy {
+    let preflight = ctx.preflight.clone();
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= 10 {
+            return attempt.error("too many redirects");
+        }
+        let refusal = preflight
+            .as_ref()
+            .and_then(|config| config.url_refusal(attempt.url().as_str()));
+        match refusal {
+            Some(reason) => attempt.error(format!("redirect refused, {reason}")),
+            None => attempt.follow(),
+        }
+    })
+}
+
 impl crate::actions::ActionHandler for FetchUrlAction {
     fn name(&self) -> &'static str {
         "web.fetch_url"
@@ -94,6 +121,7 @@ impl crate::actions::ActionHandler for FetchUrlAction {
         }
         let client = reqwest::blocking::Client::builder()
             .timeout(std::time::Duration::from_secs(20))
+            .redirect(redirect_policy(ctx))
             .build()
             .context("failed to build http client")?;
         let resp = client
@@ -107,7 +135,19 @@ impl crate::actions::ActionHandler for FetchUrlAction {
             .and_then(|v| v.to_str().ok())
             .unwrap_or("")
             .to_string();
-        let body = resp.text().unwrap_or_default();
+        let limit = ctx.policy.max_fetch_bytes;
+        if let Some(length) = resp.content_length() {
+            ctx.policy
+                .check_size(limit, "max_fetch_bytes", "response body", length)?;
+        }
+        // Content-Length can be missing or wrong; read at most one byte past the limit.
+        let mut raw = Vec::new();
+        resp.take(limit.map_or(u64::MAX, |limit| limit.saturating_add(1)))
+            .read_to_end(&mut raw)
+            .context("failed to read response body")?;
+        ctx.policy
+            .check_size(limit, "max_fetch_bytes", "response body", raw.len() as u64)?;
+        let body = String::from_utf8_lossy(&raw).into_owned();
         let max_chars = params.max_chars.unwrap_or(20_000).max(1);
         let truncated = body.chars().count() > max_chars;
         let out = if truncated {
@@ -147,7 +187,7 @@ impl crate::actions::ActionHandler for BraveSearchAction {
                     "api_key": { "type": "string" },
                     "count": { "type": "integer", "minimum": 1, "maximum": 20 }
                 },
-                "required": ["query", "api_key"]
+                "required": ["query"]
             }),
             result_schema: json!({
                 "type": "object",
@@ -167,8 +207,8 @@ impl crate::actions::ActionHandler for BraveSearchAction {
         if params.query.trim().is_empty() {
             return Err(anyhow!("query cannot be empty"));
         }
-        if params.api_key.trim().is_empty() {
-            return Err(anyhow!("api_key cannot be empty"));
+        if params.api_key.as_deref().is_none_or(|key| key.trim().is_empty()) {
+            return Err(anyhow!("api_key is missing; pass it or set BRAVE_API_KEY"));
         }
         Ok(())
     }
@@ -184,6 +224,9 @@ impl crate::actions::ActionHandler for BraveSearchAction {
                 }),
             });
         }
+        let api_key = params
+            .api_key
+            .ok_or_else(|| anyhow!("api_key is missing; pass it or set BRAVE_API_KEY"))?;
         let client = reqwest::blocking::Client::builder()
             .timeout(std::time::Duration::from_secs(20))
             .build()
@@ -199,7 +242,7 @@ impl crate::actions::ActionHandler for BraveSearchAction {
         let resp = client
             .get(url)
             .header("Accept", "application/json")
-            .header("X-Subscription-Token", params.api_key)
+            .header("X-Subscription-Token", api_key)
             .send()
             .context("failed to call brave search")?;
         let status = resp.status();
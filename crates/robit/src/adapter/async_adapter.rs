@@ -0,0 +1,100 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use crate::adapter::{Adapter, AdapterPoll};
+use crate::format::ReplyFormat;
+use crate::types::{InboundMessage, OutboundMessage};
+
+#[async_trait]
+pub trait AsyncAdapter: Send {
+    fn name(&self) -> &'static str;
+    async fn recv(&mut self) -> Result<Option<InboundMessage>>;
+    async fn send(&mut self, msg: OutboundMessage) -> Result<()>;
+
+    /// See `Adapter::max_message_chars`.
+    fn max_message_chars(&self) -> Option<usize> {
+        None
+    }
+
+    fn reply_format(&self) -> ReplyFormat {
+        ReplyFormat::Plain
+    }
+
+    /// Waits at most `timeout` for a message. The default cancels `recv` on timeout,
+    /// so adapters whose `recv` is not cancel-safe should override this.
+    async fn recv_timeout(&mut self, timeout: Duration) -> Result<AdapterPoll> {
+        match tokio::time::timeout(timeout, self.recv()).await {
+            Ok(msg) => msg.map(AdapterPoll::from),
+            Err(_) => Ok(AdapterPoll::Idle),
+        }
+    }
+}
+
+/// Runs a sync `Adapter` on tokio's blocking pool.
+pub struct BlockingAdapter<A> {
+    inner: Arc<Mutex<A>>,
+    name: &'static str,
+    max_message_chars: Option<usize>,
+    reply_format: ReplyFormat,
+}
+
+impl<A: Adapter + Send + 'static> BlockingAdapter<A> {
+    pub fn new(adapter: A) -> Self {
+        let name = adapter.name();
+        let max_message_chars = adapter.max_message_chars();
+        let reply_format = adapter.reply_format();
+        Self {
+            inner: Arc::new(Mutex::new(adapter)),
+            name,
+            max_message_chars,
+            reply_format,
+        }
+    }
+
+    async fn run_blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut A) -> Result<T> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || {
+            let mut adapter = inner
+                .lock()
+                .map_err(|_| anyhow!("adapter lock poisoned"))?;
+            f(&mut adapter)
+        })
+        .await
+        .map_err(|err| anyhow!("adapter task failed: {err}"))?
+    }
+}
+
+#[async_trait]
+impl<A: Adapter + Send + 'static> AsyncAdapter for BlockingAdapter<A> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn max_message_chars(&self) -> Option<usize> {
+        self.max_message_chars
+    }
+
+    fn reply_format(&self) -> ReplyFormat {
+        self.reply_format
+    }
+
+    async fn recv(&mut self) -> Result<Option<InboundMessage>> {
+        self.run_blocking(|adapter| adapter.recv()).await
+    }
+
+    async fn send(&mut self, msg: OutboundMessage) -> Result<()> {
+        self.run_blocking(move |adapter| adapter.send(msg)).await
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Result<AdapterPoll> {
+        self.run_blocking(move |adapter| adapter.recv_timeout(timeout))
+            .await
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use mail_parser::MessageParser;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::adapter::{Adapter, AdapterPoll};
+use crate::types::{InboundMessage, OutboundMessage};
+
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    pub imap_host: String,
+    pub imap_port: u16,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub username: String,
+    pub password: String,
+    pub from_address: String,
+    pub mailbox: String,
+    /// Only mail from these addresses is accepted; an empty list accepts nobody. The From
+    /// header alone is easy to forge, so also set `auth_server` or `shared_secret`.
+    pub allowed_senders: Vec<String>,
+    /// The authserv-id your mail server writes into `Authentication-Results`, e.g.
+    /// `mx.example.com`. When set, mail is only accepted if that server's results show a DKIM,
+    /// SPF or DMARC pass for the sender's domain.
+    pub auth_server: Option<String>,
+    /// When set, mail is only accepted if its subject or body contains this token, which is
+    /// removed from the text.
+    pub shared_secret: Option<String>,
+    /// When set, only subjects starting with this prefix are picked up.
+    pub subject_prefix: Option<String>,
+    pub workspace_id: String,
+    pub poll_interval: Duration,
+}
+
+impl EmailConfig {
+    pub fn new(
+        imap_host: impl Into<String>,
+        smtp_host: impl Into<String>,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        let username = username.into();
+        Self {
+            imap_host: imap_host.into(),
+            imap_port: 993,
+            smtp_host: smtp_host.into(),
+            smtp_port: 465,
+            from_address: username.clone(),
+            username,
+            password: password.into(),
+            mailbox: "INBOX".to_string(),
+            allowed_senders: Vec::new(),
+            auth_server: None,
+            shared_secret: None,
+            subject_prefix: None,
+            workspace_id: "email".to_string(),
+            poll_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+struct EmailThread {
+    address: String,
+    subject: String,
+    message_id: Option<String>,
+}
+
+pub struct EmailAdapter {
+    config: EmailConfig,
+    smtp: SmtpTransport,
+    queue: VecDeque<InboundMessage>,
+    threads: HashMap<String, EmailThread>,
+    last_poll: Option<Instant>,
+}
+
+impl EmailAdapter {
+    pub fn new(config: EmailConfig) -> Result<Self> {
+        if config.auth_server.is_none() && config.shared_secret.is_none() {
+            warn!("email: no auth_server or shared_secret set; senders are trusted by From alone");
+        }
+        let smtp = SmtpTransport::relay(&config.smtp_host)
+            .context("invalid smtp host")?
+            .port(config.smtp_port)
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build();
+        Ok(Self {
+            config,
+            smtp,
+            queue: VecDeque::new(),
+            threads: HashMap::new(),
+            last_poll: None,
+        })
+    }
+
+    fn poll_due(&self) -> Option<Duration> {
+        match self.last_poll {
+            None => None,
+            Some(at) => {
+                let elapsed = at.elapsed();
+                if elapsed >= self.config.poll_interval {
+                    None
+                } else {
+                    Some(self.config.poll_interval - elapsed)
+                }
+            }
+        }
+    }
+
+    fn poll_mailbox(&mut self) -> Result<()> {
+        self.last_poll = Some(Instant::now());
+        let mut session = ImapSession::connect(&self.config.imap_host, self.config.imap_port)?;
+        session.command(&format!(
+            "LOGIN {} {}",
+            quote(&self.config.username),
+            quote(&self.config.password)
+        ))?;
+        session.command(&format!("SELECT {}", quote(&self.config.mailbox)))?;
+        let uids: Vec<u32> = session
+            .command("UID SEARCH UNSEEN")?
+            .iter()
+            .filter_map(|resp| resp.line.strip_prefix("* SEARCH"))
+            .flat_map(|rest| rest.split_whitespace().filter_map(|uid| uid.parse().ok()))
+            .collect();
+        for uid in uids {
+            let responses = session.command(&format!("UID FETCH {uid} BODY.PEEK[]"))?;
+            let raw = responses
+                .into_iter()
+                .find_map(|resp| resp.literals.into_iter().next());
+            session.command(&format!("UID STORE {uid} +FLAGS (\\Seen)"))?;
+            if let Some(raw) = raw
+                && let Some(msg) = self.parse_email(uid, &raw)
+            {
+                self.queue.push_back(msg);
+            }
+        }
+        let _ = session.command("LOGOUT");
+        Ok(())
+    }
+
+    fn parse_email(&mut self, uid: u32, raw: &[u8]) -> Option<InboundMessage> {
+        let parsed = MessageParser::default().parse(raw)?;
+        let address = parsed.from()?.first()?.address()?.to_lowercase();
+        if !self
+            .config
+            .allowed_senders
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&address))
+        {
+            info!("email: ignoring mail from {address} (not in allowlist)");
+            return None;
+        }
+        if let Some(server) = &self.config.auth_server {
+            let domain = address.rsplit_once('@')?.1;
+            let passed = parsed
+                .headers_raw()
+                .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+                .any(|(_, value)| auth_passed(value, server, domain));
+            if !passed {
+                warn!("email: ignoring mail from {address} ({server} did not authenticate it)");
+                return None;
+            }
+        }
+        let mut subject = parsed.subject().unwrap_or("").trim().to_string();
+        let mut body = parsed
+            .body_text(0)
+            .map(|body| strip_quoted_reply(&body))
+            .unwrap_or_default();
+        if let Some(secret) = &self.config.shared_secret {
+            if !subject.contains(secret.as_str()) && !body.contains(secret.as_str()) {
+                warn!("email: ignoring mail from {address} (shared secret missing)");
+                return None;
+            }
+            subject = subject.replace(secret.as_str(), "").trim().to_string();
+            body = body.replace(secret.as_str(), "").trim().to_string();
+        }
+        let mut text = subject.clone();
+        if let Some(prefix) = &self.config.subject_prefix {
+            text = subject.strip_prefix(prefix.as_str())?.trim().to_string();
+        }
+        if !body.is_empty() {
+            if text.is_empty() {
+                text = body;
+            } else {
+                text = format!("{text}\n{body}");
+            }
+        }
+        if text.trim().is_empty() {
+            return None;
+        }
+        let id = format!("email-{uid}");
+        let message_id = parsed.message_id().map(|value| value.to_string());
+        self.threads.insert(
+            id.clone(),
+            EmailThread {
+                address: address.clone(),
+                subject,
+                message_id: message_id.clone(),
+            },
+        );
+        Some(InboundMessage {
+            id,
+            text,
+            sender: address.clone(),
+            channel: address,
+            workspace_id: Some(self.config.workspace_id.clone()),
+            metadata: json!({ "source": "email", "message_id": message_id }),
+        })
+    }
+}
+
+impl Adapter for EmailAdapter {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn recv(&mut self) -> Result<Option<InboundMessage>> {
+        loop {
+            if let AdapterPoll::Message(msg) = self.recv_timeout(self.config.poll_interval)? {
+                return Ok(Some(msg));
+            }
+        }
+    }
+
+    fn send(&mut self, msg: OutboundMessage) -> Result<()> {
+        let thread = msg
+            .in_reply_to
+            .as_ref()
+            .and_then(|id| self.threads.get(id));
+        let to = thread
+            .map(|thread| thread.address.clone())
+            .unwrap_or_else(|| msg.recipient.clone());
+        let subject = match thread {
+            Some(thread) if thread.subject.to_lowercase().starts_with("re:") => {
+                thread.subject.clone()
+            }
+            Some(thread) => format!("Re: {}", thread.subject),
+            None => "robit".to_string(),
+        };
+        let mut builder = Message::builder()
+            .from(self.config.from_address.parse()?)
+            .to(to.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        if let Some(message_id) = thread.and_then(|thread| thread.message_id.clone()) {
+            let reference = format!("<{message_id}>");
+            builder = builder.in_reply_to(reference.clone()).references(reference);
+        }
+        let email = builder.body(msg.text)?;
+        self.smtp
+            .send(&email)
+            .map_err(|err| anyhow!("smtp send failed: {err}"))?;
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<AdapterPoll> {
+        self.recv_timeout(Duration::ZERO)
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<AdapterPoll> {
+        if let Some(msg) = self.queue.pop_front() {
+            return Ok(AdapterPoll::Message(msg));
+        }
+        match self.poll_due() {
+            None => {
+                if let Err(err) = self.poll_mailbox() {
+                    warn!("email poll failed: {err}");
+                }
+            }
+            Some(wait) => std::thread::sleep(wait.min(timeout)),
+        }
+        Ok(self
+            .queue
+            .pop_front()
+            .map(AdapterPoll::Message)
+            .unwrap_or(AdapterPoll::Idle))
+    }
+}
+
+/// Whether an `Authentication-Results` header written by `server` shows a DKIM, SPF or DMARC
+/// pass for `domain`. Headers from other servers are ignored, since a sender can add its own.
+fn auth_passed(header: &str, server: &str, domain: &str) -> bool {
+    let mut results = header.split(';');
+    let authserv_id = results.next().unwrap_or("").split_whitespace().next().unwrap_or("");
+    if !authserv_id.eq_ignore_ascii_case(server) {
+        return false;
+    }
+    results.any(|result| {
+        let mut tokens = result.split_whitespace();
+        let passed = match tokens.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("dkim=pass") => "header.d",
+            Some("spf=pass") => "smtp.mailfrom",
+            Some("dmarc=pass") => "header.from",
+            _ => return false,
+        };
+        tokens.filter_map(|token| token.split_once('=')).any(|(key, value)| {
+            let value = value.rsplit_once('@').map_or(value, |(_, domain)| domain);
+            key.eq_ignore_ascii_case(passed) && value.eq_ignore_ascii_case(domain)
+        })
+    })
+}
+
+/// Drops the quoted history most mail clients append below a reply.
+fn strip_quoted_reply(body: &str) -> String {
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('>') {
+            continue;
+        }
+        if trimmed.starts_with("On ") && trimmed.ends_with("wrote:") {
+            break;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+struct ImapResponse {
+    line: String,
+    literals: Vec<Vec<u8>>,
+}
+
+/// Just enough IMAP4rev1 over TLS to search, fetch, and flag messages.
+struct ImapSession {
+    stream: BufReader<StreamOwned<ClientConnection, TcpStream>>,
+    next_tag: u32,
+}
+
+impl ImapSession {
+    fn connect(host: &str, port: u16) -> Result<Self> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|err| anyhow!("invalid imap host: {err}"))?;
+        let conn = ClientConnection::new(Arc::new(config), server_name)?;
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("imap host did not resolve: {host}"))?;
+        let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(20))?;
+        tcp.set_read_timeout(Some(Duration::from_secs(60)))?;
+        let mut session = Self {
+            stream: BufReader::new(StreamOwned::new(conn, tcp)),
+            next_tag: 1,
+        };
+        let greeting = session.read_response()?;
+        if !greeting.line.starts_with("* OK") {
+            return Err(anyhow!("unexpected imap greeting: {}", greeting.line));
+        }
+        Ok(session)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Vec<ImapResponse>> {
+        let tag = format!("A{}", self.next_tag);
+        self.next_tag += 1;
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{tag} {command}\r\n").as_bytes())?;
+        stream.flush()?;
+        let mut responses = Vec::new();
+        loop {
+            let response = self.read_response()?;
+            if let Some(status) = response.line.strip_prefix(&format!("{tag} ")) {
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                let verb = command.split_whitespace().next().unwrap_or(command);
+                return Err(anyhow!("imap {verb} failed: {status}"));
+            }
+            responses.push(response);
+        }
+    }
+
+    fn read_response(&mut self) -> Result<ImapResponse> {
+        let mut line = String::new();
+        let mut literals = Vec::new();
+        loop {
+            let mut chunk = String::new();
+            if self.stream.read_line(&mut chunk)? == 0 {
+                return Err(anyhow!("imap connection closed"));
+            }
+            let chunk = chunk.trim_end_matches(['\r', '\n']);
+            line.push_str(chunk);
+            let Some(size) = literal_size(chunk) else {
+                return Ok(ImapResponse { line, literals });
+            };
+            let mut literal = vec![0u8; size];
+            self.stream.read_exact(&mut literal)?;
+            literals.push(literal);
+        }
+    }
+}
+
+fn literal_size(line: &str) -> Option<usize> {
+    let rest = line.strip_suffix('}')?;
+    let start = rest.rfind('{')?;
+    rest[start + 1..].parse().ok()
+}
@@ -1,12 +1,63 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
+use crate::format::ReplyFormat;
 use crate::types::{InboundMessage, OutboundMessage};
 
+#[cfg(feature = "async")]
+mod async_adapter;
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod robrix;
 pub mod stdin;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+#[cfg(feature = "async")]
+pub use async_adapter::{AsyncAdapter, BlockingAdapter};
+
+#[derive(Debug)]
+pub enum AdapterPoll {
+    Message(InboundMessage),
+    Idle,
+    Closed,
+}
+
+impl From<Option<InboundMessage>> for AdapterPoll {
+    fn from(msg: Option<InboundMessage>) -> Self {
+        match msg {
+            Some(msg) => AdapterPoll::Message(msg),
+            None => AdapterPoll::Closed,
+        }
+    }
+}
 
 pub trait Adapter {
     fn name(&self) -> &'static str;
     fn recv(&mut self) -> Result<Option<InboundMessage>>;
     fn send(&mut self, msg: OutboundMessage) -> Result<()>;
+
+    /// Longest reply text the transport takes in one message; longer replies are paged.
+    fn max_message_chars(&self) -> Option<usize> {
+        None
+    }
+
+    /// How the transport shows reply text; rooms can override it with `reply_format`.
+    fn reply_format(&self) -> ReplyFormat {
+        ReplyFormat::Plain
+    }
+
+    /// Returns immediately. Adapters that cannot poll fall back to a blocking `recv`.
+    fn try_recv(&mut self) -> Result<AdapterPoll> {
+        self.recv().map(AdapterPoll::from)
+    }
+
+    /// Waits at most `timeout` for a message. Adapters that cannot poll fall back to a
+    /// blocking `recv`.
+    fn recv_timeout(&mut self, _timeout: Duration) -> Result<AdapterPoll> {
+        self.recv().map(AdapterPoll::from)
+    }
 }
@@ -0,0 +1,259 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use rumqttc::{
+    Client, Connection, ConnectionError, Event, MqttOptions, Packet, QoS, RecvTimeoutError,
+    TryRecvError,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::adapter::{Adapter, AdapterPoll};
+use crate::types::{InboundMessage, OutboundMessage};
+
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub inbound_topic: String,
+    pub outbound_prefix: String,
+    pub workspace_id: String,
+    pub keep_alive_secs: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "robit".to_string(),
+            username: None,
+            password: None,
+            inbound_topic: "robit/inbound/#".to_string(),
+            outbound_prefix: "robit/outbound".to_string(),
+            workspace_id: "mqtt".to_string(),
+            keep_alive_secs: 30,
+        }
+    }
+}
+
+/// The longest wait between reconnect attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+pub struct MqttAdapter {
+    client: Client,
+    connection: Connection,
+    config: MqttConfig,
+    counter: u64,
+    /// The wait after the last connection error; doubles while the broker stays away.
+    backoff: Duration,
+    /// When the connection may be polled again, which reconnects it.
+    retry_at: Option<Instant>,
+    /// Set after a connection error: a clean session loses the subscription.
+    resubscribe: bool,
+}
+
+#[derive(Deserialize)]
+struct MqttInboundPayload {
+    #[serde(default)]
+    id: Option<String>,
+    text: String,
+    #[serde(default)]
+    sender: Option<String>,
+    #[serde(default)]
+    workspace_id: Option<String>,
+    #[serde(default)]
+    metadata: Value,
+}
+
+impl MqttAdapter {
+    pub fn new(config: MqttConfig) -> Result<Self> {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(config.keep_alive_secs.max(5)));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        let (client, connection) = Client::new(options, 32);
+        client
+            .subscribe(config.inbound_topic.clone(), QoS::AtLeastOnce)
+            .map_err(|err| anyhow!("mqtt subscribe failed: {err}"))?;
+        Ok(Self {
+            client,
+            connection,
+            config,
+            counter: 1,
+            backoff: Duration::ZERO,
+            retry_at: None,
+            resubscribe: false,
+        })
+    }
+
+    fn next_id(&mut self) -> String {
+        let id = self.counter;
+        self.counter += 1;
+        format!("mqtt-{id}")
+    }
+
+    fn room_for_topic(&self, topic: &str) -> String {
+        let prefix = self.config.inbound_topic.trim_end_matches('#');
+        let room = topic.strip_prefix(prefix).unwrap_or("").trim_matches('/');
+        if room.is_empty() {
+            "mqtt".to_string()
+        } else {
+            room.to_string()
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        event: std::result::Result<Event, ConnectionError>,
+    ) -> Result<Option<InboundMessage>> {
+        match event {
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                Ok(self.parse_publish(&publish.topic, &publish.payload))
+            }
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                self.backoff = Duration::ZERO;
+                if std::mem::take(&mut self.resubscribe) {
+                    let topic = self.config.inbound_topic.clone();
+                    if let Err(err) = self.client.try_subscribe(topic, QoS::AtLeastOnce) {
+                        warn!("mqtt: resubscribe failed: {err}");
+                    }
+                }
+                Ok(None)
+            }
+            Ok(_) => Ok(None),
+            // Polling again reconnects, so a broker restart is waited out rather than
+            // ending the adapter.
+            Err(err) => {
+                self.backoff = (self.backoff * 2).clamp(Duration::from_secs(1), MAX_BACKOFF);
+                warn!(
+                    "mqtt: connection error, retrying in {}s: {err}",
+                    self.backoff.as_secs()
+                );
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.resubscribe = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// How long until the connection may be polled again after an error.
+    fn backoff_left(&self) -> Duration {
+        self.retry_at
+            .map_or(Duration::ZERO, |at| at.saturating_duration_since(Instant::now()))
+    }
+
+    fn parse_publish(&mut self, topic: &str, payload: &[u8]) -> Option<InboundMessage> {
+        let room = self.room_for_topic(topic);
+        let raw = String::from_utf8_lossy(payload).trim().to_string();
+        if raw.is_empty() {
+            return None;
+        }
+        if raw.starts_with('{') {
+            match serde_json::from_str::<MqttInboundPayload>(&raw) {
+                Ok(parsed) => {
+                    let id = parsed.id.unwrap_or_else(|| self.next_id());
+                    return Some(InboundMessage {
+                        id,
+                        text: parsed.text,
+                        sender: parsed.sender.unwrap_or_else(|| "mqtt".to_string()),
+                        channel: room,
+                        workspace_id: Some(
+                            parsed
+                                .workspace_id
+                                .unwrap_or_else(|| self.config.workspace_id.clone()),
+                        ),
+                        metadata: parsed.metadata,
+                    });
+                }
+                Err(err) => {
+                    warn!("mqtt: invalid inbound json on {topic}: {err}");
+                    return None;
+                }
+            }
+        }
+        Some(InboundMessage {
+            id: self.next_id(),
+            text: raw,
+            sender: "mqtt".to_string(),
+            channel: room,
+            workspace_id: Some(self.config.workspace_id.clone()),
+            metadata: Value::Null,
+        })
+    }
+}
+
+impl Adapter for MqttAdapter {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn recv(&mut self) -> Result<Option<InboundMessage>> {
+        loop {
+            thread::sleep(self.backoff_left());
+            let Ok(event) = self.connection.recv() else {
+                return Ok(None);
+            };
+            if let Some(msg) = self.handle_event(event)? {
+                return Ok(Some(msg));
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<AdapterPoll> {
+        loop {
+            if !self.backoff_left().is_zero() {
+                return Ok(AdapterPoll::Idle);
+            }
+            let event = match self.connection.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => return Ok(AdapterPoll::Idle),
+                Err(TryRecvError::Disconnected) => return Ok(AdapterPoll::Closed),
+            };
+            if let Some(msg) = self.handle_event(event)? {
+                return Ok(AdapterPoll::Message(msg));
+            }
+        }
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<AdapterPoll> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(AdapterPoll::Idle);
+            }
+            let wait = self.backoff_left();
+            if !wait.is_zero() {
+                thread::sleep(wait.min(remaining));
+                continue;
+            }
+            let event = match self.connection.recv_timeout(remaining) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Ok(AdapterPoll::Idle),
+                Err(RecvTimeoutError::Disconnected) => return Ok(AdapterPoll::Closed),
+            };
+            if let Some(msg) = self.handle_event(event)? {
+                return Ok(AdapterPoll::Message(msg));
+            }
+        }
+    }
+
+    fn send(&mut self, msg: OutboundMessage) -> Result<()> {
+        let topic = format!(
+            "{}/{}",
+            self.config.outbound_prefix.trim_end_matches('/'),
+            msg.channel
+        );
+        let payload = serde_json::to_vec(&msg)?;
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .map_err(|err| anyhow!("mqtt publish failed: {err}"))
+    }
+}
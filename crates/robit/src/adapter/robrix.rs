@@ -1,14 +1,17 @@
-use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use serde_json::Value;
 
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, AdapterPoll};
+use crate::format::ReplyFormat;
 use crate::types::{InboundMessage, OutboundMessage};
 
 pub struct RobrixAdapter {
     inbound: Receiver<InboundMessage>,
     outbound: Sender<OutboundMessage>,
+    max_message_chars: Option<usize>,
 }
 
 pub struct RobrixHandle {
@@ -24,6 +27,7 @@ impl RobrixAdapter {
             Self {
                 inbound: in_rx,
                 outbound: out_tx,
+                max_message_chars: None,
             },
             RobrixHandle {
                 inbound: in_tx,
@@ -31,6 +35,12 @@ impl RobrixAdapter {
             },
         )
     }
+
+    /// Pages replies longer than `limit` characters.
+    pub fn with_max_message_chars(mut self, limit: usize) -> Self {
+        self.max_message_chars = Some(limit);
+        self
+    }
 }
 
 impl Adapter for RobrixAdapter {
@@ -38,6 +48,15 @@ impl Adapter for RobrixAdapter {
         "robrix"
     }
 
+    fn max_message_chars(&self) -> Option<usize> {
+        self.max_message_chars
+    }
+
+    /// Matrix clients render Markdown.
+    fn reply_format(&self) -> ReplyFormat {
+        ReplyFormat::Markdown
+    }
+
     fn recv(&mut self) -> Result<Option<InboundMessage>> {
         match self.inbound.recv() {
             Ok(msg) => Ok(Some(msg)),
@@ -50,6 +69,22 @@ impl Adapter for RobrixAdapter {
             .send(msg)
             .map_err(|_| anyhow!("robrix outbound channel closed"))
     }
+
+    fn try_recv(&mut self) -> Result<AdapterPoll> {
+        match self.inbound.try_recv() {
+            Ok(msg) => Ok(AdapterPoll::Message(msg)),
+            Err(TryRecvError::Empty) => Ok(AdapterPoll::Idle),
+            Err(TryRecvError::Disconnected) => Ok(AdapterPoll::Closed),
+        }
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<AdapterPoll> {
+        match self.inbound.recv_timeout(timeout) {
+            Ok(msg) => Ok(AdapterPoll::Message(msg)),
+            Err(RecvTimeoutError::Timeout) => Ok(AdapterPoll::Idle),
+            Err(RecvTimeoutError::Disconnected) => Ok(AdapterPoll::Closed),
+        }
+    }
 }
 
 impl RobrixHandle {
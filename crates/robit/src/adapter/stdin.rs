@@ -1,4 +1,5 @@
 use std::io::{self, Write};
+use std::path::PathBuf;
 
 use anyhow::Result;
 use serde_json::Value;
@@ -8,22 +9,88 @@ use crate::types::{InboundMessage, OutboundMessage};
 
 pub struct StdinAdapter {
     prompt: String,
+    continuation_prompt: String,
     counter: u64,
+    #[cfg(feature = "readline")]
+    history_path: Option<PathBuf>,
+    #[cfg(feature = "readline")]
+    editor: Option<readline::StdinEditor>,
 }
 
 impl StdinAdapter {
     pub fn new() -> Self {
         Self {
             prompt: "robit> ".to_string(),
+            continuation_prompt: "...> ".to_string(),
             counter: 1,
+            #[cfg(feature = "readline")]
+            history_path: None,
+            #[cfg(feature = "readline")]
+            editor: readline::StdinEditor::new().ok(),
         }
     }
 
+    /// Action names offered by tab completion (e.g. from `ActionRegistry::list_specs`).
+    #[cfg_attr(not(feature = "readline"), allow(unused_mut, unused_variables))]
+    pub fn with_action_names(mut self, names: Vec<String>) -> Self {
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            editor.set_action_names(names);
+        }
+        self
+    }
+
+    #[cfg_attr(not(feature = "readline"), allow(unused_mut, unused_variables))]
+    pub fn with_history_path(mut self, path: PathBuf) -> Self {
+        #[cfg(feature = "readline")]
+        {
+            if let Some(editor) = self.editor.as_mut() {
+                editor.load_history(&path);
+            }
+            self.history_path = Some(path);
+        }
+        self
+    }
+
     fn next_id(&mut self) -> String {
         let id = self.counter;
         self.counter += 1;
         format!("in-{id}")
     }
+
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            return editor.read_line(prompt);
+        }
+        print!("{prompt}");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    fn read_input(&mut self) -> Result<Option<String>> {
+        let prompt = self.prompt.clone();
+        let Some(mut text) = self.read_line(&prompt)? else {
+            return Ok(None);
+        };
+        while needs_continuation(&text) {
+            let prompt = self.continuation_prompt.clone();
+            let Some(more) = self.read_line(&prompt)? else {
+                break;
+            };
+            text.push('\n');
+            text.push_str(&more);
+        }
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            editor.record(&text, self.history_path.as_deref());
+        }
+        Ok(Some(text))
+    }
 }
 
 impl Adapter for StdinAdapter {
@@ -32,13 +99,9 @@ impl Adapter for StdinAdapter {
     }
 
     fn recv(&mut self) -> Result<Option<InboundMessage>> {
-        print!("{}", self.prompt);
-        io::stdout().flush()?;
-        let mut line = String::new();
-        let stdin = io::stdin();
-        if stdin.read_line(&mut line)? == 0 {
+        let Some(line) = self.read_input()? else {
             return Ok(None);
-        }
+        };
         let text = line.trim().to_string();
         if matches!(text.as_str(), "exit" | "quit") {
             return Ok(None);
@@ -64,3 +127,193 @@ impl Adapter for StdinAdapter {
         Ok(())
     }
 }
+
+/// True while the input has unclosed `{`/`[` outside of strings, i.e. JSON params
+/// that continue on the next line.
+fn needs_continuation(text: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_str = false;
+    let mut escape = false;
+    for ch in text.chars() {
+        if in_str {
+            if escape {
+                escape = false;
+            } else if ch == '\\' {
+                escape = true;
+            } else if ch == '"' {
+                in_str = false;
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_str = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+#[cfg(feature = "readline")]
+mod readline {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use rustyline::completion::Completer;
+    use rustyline::error::ReadlineError;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::history::DefaultHistory;
+    use rustyline::validate::Validator;
+    use rustyline::{Context, Editor, Helper};
+    use tracing::warn;
+
+    const CONTROL_COMMANDS: &[&str] = &[
+        "help",
+        "actions",
+        "backend",
+        "usage",
+        "dry-run on",
+        "dry-run off",
+        "role",
+        "read-only on",
+        "read-only off",
+        "reload",
+        "explain",
+        "approvals",
+        "approve",
+        "approve-all",
+        "deny",
+        "remember ",
+        "trust ",
+        "trust off",
+        "model use ",
+        "plan save ",
+        "plan run ",
+        "plans",
+        "show more",
+        "schedules",
+        "schedule delete ",
+        "undo",
+        "history",
+        "stats",
+        "quota",
+        "set var ",
+        "get var",
+        "alias ",
+        "alias list",
+        "unalias ",
+        "/help",
+        "/read ",
+        "/run ",
+        "/organize ",
+        "/search ",
+        "exit",
+        "quit",
+    ];
+
+    pub(super) struct StdinEditor {
+        editor: Editor<StdinHelper, DefaultHistory>,
+    }
+
+    impl StdinEditor {
+        pub(super) fn new() -> Result<Self> {
+            let mut editor = Editor::new()?;
+            editor.set_helper(Some(StdinHelper::default()));
+            Ok(Self { editor })
+        }
+
+        pub(super) fn set_action_names(&mut self, names: Vec<String>) {
+            if let Some(helper) = self.editor.helper_mut() {
+                helper.actions = names;
+                helper.actions.sort();
+            }
+        }
+
+        pub(super) fn load_history(&mut self, path: &Path) {
+            if path.exists()
+                && let Err(err) = self.editor.load_history(path)
+            {
+                warn!("history load failed: {err}");
+            }
+        }
+
+        pub(super) fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+            match self.editor.readline(prompt) {
+                Ok(line) => Ok(Some(line)),
+                Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+                Err(ReadlineError::Eof) => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }
+
+        pub(super) fn record(&mut self, text: &str, history_path: Option<&Path>) {
+            if text.trim().is_empty() {
+                return;
+            }
+            let _ = self.editor.add_history_entry(text);
+            let Some(path) = history_path else {
+                return;
+            };
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            if let Err(err) = self.editor.save_history(path) {
+                warn!("history save failed: {err}");
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct StdinHelper {
+        actions: Vec<String>,
+    }
+
+    impl Completer for StdinHelper {
+        type Candidate = String;
+
+        fn complete(
+            &self,
+            line: &str,
+            pos: usize,
+            _ctx: &Context<'_>,
+        ) -> rustyline::Result<(usize, Vec<String>)> {
+            let head = &line[..pos];
+            if let Some(rest) = head.strip_prefix("action:") {
+                if !rest.contains(char::is_whitespace) {
+                    let matches = self
+                        .actions
+                        .iter()
+                        .filter(|name| name.starts_with(rest))
+                        .cloned()
+                        .collect();
+                    return Ok(("action:".len(), matches));
+                }
+                return Ok((pos, Vec::new()));
+            }
+            let mut matches: Vec<String> = CONTROL_COMMANDS
+                .iter()
+                .filter(|cmd| cmd.starts_with(head))
+                .map(|cmd| cmd.to_string())
+                .collect();
+            matches.extend(
+                self.actions
+                    .iter()
+                    .map(|name| format!("action:{name}"))
+                    .filter(|candidate| candidate.starts_with(head)),
+            );
+            Ok((0, matches))
+        }
+    }
+
+    impl Hinter for StdinHelper {
+        type Hint = String;
+    }
+
+    impl Highlighter for StdinHelper {}
+
+    impl Validator for StdinHelper {}
+
+    impl Helper for StdinHelper {}
+}
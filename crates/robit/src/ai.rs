@@ -4,9 +4,10 @@ use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::types::{ActionRequest, ActionSpec, PlanStep};
+use crate::types::{ActionRequest, ActionSpec, ForEach, PlanStep, UndoStep};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum AiDecision {
     Action(ActionRequest),
     NeedInput {
@@ -20,6 +21,41 @@ pub enum AiDecision {
     Unknown { message: String },
 }
 
+/// Kind of AI call, used to route cheap turns to a smaller model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AiTask {
+    /// Action planning; falls back to the engine's main backend.
+    Planning,
+    /// Conversational turns. A chat backend triages first and hands off anything that
+    /// needs actions to the planning backend.
+    Chat,
+    /// Summarizing plan results.
+    Summarize,
+    /// Reviewing AI-proposed actions before the approval prompt.
+    Critic,
+}
+
+impl AiTask {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AiTask::Planning => "planning",
+            AiTask::Chat => "chat",
+            AiTask::Summarize => "summarize",
+            AiTask::Critic => "critic",
+        }
+    }
+}
+
+/// Per-request overrides, e.g. from a room's provider binding.
+#[derive(Clone, Debug, Default)]
+pub struct AiRequestOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    /// Appended to the planner system prompt.
+    pub system_prompt_extra: Option<String>,
+}
+
 pub trait AiPlanner: Send + Sync {
     fn plan_with_history(
         &self,
@@ -27,6 +63,72 @@ pub trait AiPlanner: Send + Sync {
         actions: &[ActionSpec],
         history: &[AiChatMessage],
     ) -> Result<AiDecision>;
+
+    /// Backends that cannot switch models per request ignore the options.
+    fn plan_with_options(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        _options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        self.plan_with_history(input, actions, history)
+    }
+
+    /// Label of the backend that produced the last decision, for planners that pick one.
+    fn last_backend(&self) -> Option<String> {
+        None
+    }
+
+    /// Token usage of the last call, when the backend reports or can estimate it.
+    fn last_usage(&self) -> Option<AiUsage> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AiUsage {
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// True when counts come from `estimate_tokens` rather than the provider.
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+impl AiUsage {
+    pub fn cost_usd(&self) -> Option<f64> {
+        let (input, output) = price_per_million_tokens(&self.model)?;
+        Some(
+            (self.prompt_tokens as f64 * input + self.completion_tokens as f64 * output)
+                / 1_000_000.0,
+        )
+    }
+}
+
+/// Approximate list prices (USD per million input/output tokens) for common models.
+/// Local models cost nothing; unknown models return `None`.
+pub fn price_per_million_tokens(model: &str) -> Option<(f64, f64)> {
+    let model = model.to_lowercase();
+    let prices: &[(&str, (f64, f64))] = &[
+        ("gpt-4o-mini", (0.15, 0.6)),
+        ("gpt-4o", (2.5, 10.0)),
+        ("gpt-4.1-nano", (0.1, 0.4)),
+        ("gpt-4.1-mini", (0.4, 1.6)),
+        ("gpt-4.1", (2.0, 8.0)),
+        ("gpt-3.5", (0.5, 1.5)),
+        ("deepseek-reasoner", (0.55, 2.19)),
+        ("deepseek", (0.27, 1.1)),
+        ("haiku", (0.8, 4.0)),
+        ("sonnet", (3.0, 15.0)),
+        ("opus", (15.0, 75.0)),
+        ("qwen", (0.0, 0.0)),
+        ("llama", (0.0, 0.0)),
+    ];
+    prices
+        .iter()
+        .find(|(family, _)| model.contains(family))
+        .map(|(_, price)| *price)
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
@@ -41,16 +143,100 @@ pub struct AiChatMessage {
     pub content: String,
 }
 
+/// Files an `omnix-mlx` model directory must hold.
+pub(crate) const MLX_MODEL_FILES: &[&str] = &[
+    "config.json",
+    "model.safetensors.index.json",
+    "tokenizer.json",
+    "tokenizer_config.json",
+];
+
+/// Per-message framing overhead (role markers, separators) in estimated tokens.
+const MESSAGE_TOKEN_OVERHEAD: usize = 4;
+
+/// Tokens held back for the model's answer when no explicit limit applies.
+const RESPONSE_TOKEN_RESERVE: usize = 1024;
+
+/// Rough token count without a tokenizer: about four ASCII characters per token, and one
+/// token per CJK or other non-ASCII character.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), ch| {
+        if ch.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// Context window for well-known model families; unknown models get a conservative default.
+pub fn context_window_for(model: &str) -> usize {
+    let model = model.to_lowercase();
+    if model.contains("claude") {
+        200_000
+    } else if ["gpt-4o", "gpt-4.1", "gpt-4-turbo", "o1", "o3", "o4"]
+        .iter()
+        .any(|family| model.contains(family))
+    {
+        128_000
+    } else if model.contains("deepseek") {
+        64_000
+    } else if model.contains("qwen") {
+        32_768
+    } else if model.contains("gpt-3.5") {
+        16_385
+    } else {
+        8_192
+    }
+}
+
+/// Keeps the most recent turns whose estimated size fits in `budget` tokens.
+pub fn trim_history_to_budget(history: &[AiChatMessage], budget: usize) -> &[AiChatMessage] {
+    let mut used = 0;
+    let mut start = history.len();
+    for (idx, message) in history.iter().enumerate().rev() {
+        let cost = estimate_tokens(&message.content) + MESSAGE_TOKEN_OVERHEAD;
+        if used + cost > budget {
+            break;
+        }
+        used += cost;
+        start = idx;
+    }
+    &history[start..]
+}
+
+/// Tokens left for history once the fixed prompt parts and the response are accounted for.
+fn history_budget(context_window: usize, response_reserve: usize, fixed: &[&str]) -> usize {
+    let fixed: usize = fixed
+        .iter()
+        .map(|part| estimate_tokens(part) + MESSAGE_TOKEN_OVERHEAD)
+        .sum();
+    context_window.saturating_sub(response_reserve + fixed)
+}
+
 #[cfg(feature = "ai-http")]
 use reqwest::blocking::Client;
+#[cfg(feature = "ai-http")]
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 
 #[cfg(feature = "ai-http")]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum AiProvider {
     OpenAI,
     DeepSeek,
+    /// Claude models via the Anthropic Messages API.
+    #[serde(alias = "claude")]
+    Anthropic,
 }
 
+#[cfg(feature = "ai-http")]
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[cfg(feature = "ai-http")]
+const ANTHROPIC_MAX_TOKENS: u32 = 4096;
+
 #[cfg(feature = "ai-http")]
 #[derive(Clone, Debug)]
 pub struct AiConfig {
@@ -59,16 +245,32 @@ pub struct AiConfig {
     pub model: String,
     pub base_url: Option<String>,
     pub temperature: Option<f64>,
+    /// Sent as `OpenAI-Organization`.
+    pub organization: Option<String>,
+    /// Extra headers sent with every request, e.g. for LiteLLM/OpenRouter gateways.
+    pub extra_headers: Vec<(String, String)>,
+    /// Send actions as native tools instead of describing them in the prompt.
+    pub tool_calling: bool,
+    /// Constrain replies to the decision schema with `response_format` (OpenAI and
+    /// llama.cpp servers take the JSON schema, DeepSeek gets JSON mode).
+    pub structured_output: bool,
+    /// Overrides the context window inferred from the model name when trimming history.
+    pub context_window: Option<usize>,
 }
 
 #[cfg(feature = "ai-http")]
 #[derive(Clone, Debug)]
 pub struct AiClient {
     client: Client,
+    provider: AiProvider,
     api_key: String,
     base_url: String,
     model: String,
     temperature: f64,
+    tool_calling: bool,
+    structured_output: bool,
+    context_window: Option<usize>,
+    last_usage: std::sync::Arc<std::sync::Mutex<Option<AiUsage>>>,
 }
 
 #[cfg(feature = "ai-http")]
@@ -82,21 +284,46 @@ impl AiClient {
             None => match config.provider {
                 AiProvider::OpenAI => "https://api.openai.com/v1".to_string(),
                 AiProvider::DeepSeek => "https://api.deepseek.com/v1".to_string(),
+                AiProvider::Anthropic => "https://api.anthropic.com/v1".to_string(),
             },
         };
+        let mut headers = HeaderMap::new();
+        if let Some(organization) = &config.organization {
+            headers.insert(
+                "OpenAI-Organization",
+                HeaderValue::from_str(organization).context("invalid organization header")?,
+            );
+        }
+        for (name, value) in &config.extra_headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name: {name}"))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value for header {name}"))?;
+            headers.insert(name, value);
+        }
         let client = Client::builder()
             .timeout(Duration::from_secs(25))
+            .default_headers(headers)
             .build()
             .context("failed to build http client")?;
         Ok(Self {
             client,
+            provider: config.provider,
             api_key: config.api_key,
             base_url,
             model: config.model,
             temperature: config.temperature.unwrap_or(0.2),
+            tool_calling: config.tool_calling,
+            structured_output: config.structured_output,
+            context_window: config.context_window,
+            last_usage: Default::default(),
         })
     }
 
+    pub fn last_usage(&self) -> Option<AiUsage> {
+        self.last_usage.lock().ok().and_then(|usage| usage.clone())
+    }
+
     pub fn plan(&self, input: &str, actions: &[ActionSpec]) -> Result<AiDecision> {
         self.plan_with_history(input, actions, &[])
     }
@@ -107,16 +334,57 @@ impl AiClient {
         actions: &[ActionSpec],
         history: &[AiChatMessage],
     ) -> Result<AiDecision> {
-        let system = system_prompt_with_backend(Some(&self.model));
-        let action_specs = serde_json::to_string(actions).unwrap_or_else(|_| "[]".to_string());
-        let user = format!(
-            "{system}\n\nUser request:\n{input}\n\nAvailable actions (JSON):\n{action_specs}\n\nReturn JSON only.",
-            system = system,
-            input = input,
-            action_specs = action_specs
+        self.plan_with_options(input, actions, history, &AiRequestOptions::default())
+    }
+
+    pub fn plan_with_options(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        let model = options.model.as_deref().unwrap_or(&self.model);
+        let temperature = options.temperature.unwrap_or(self.temperature);
+        let tools = self.tool_calling && !actions.is_empty();
+        let system = if tools {
+            tool_system_prompt(Some(model))
+        } else {
+            system_prompt_with_backend(Some(model))
+        };
+        let system = with_prompt_extra(system, options.system_prompt_extra.as_deref());
+        let user = if tools {
+            input.to_string()
+        } else {
+            let action_specs =
+                serde_json::to_string(actions).unwrap_or_else(|_| "[]".to_string());
+            format!(
+                "{system}\n\nUser request:\n{input}\n\nAvailable actions (JSON):\n{action_specs}\n\nReturn JSON only.",
+                system = system,
+                input = input,
+                action_specs = action_specs
+            )
+        };
+        let tools_json = if tools {
+            serde_json::to_string(actions).unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let response_reserve = match self.provider {
+            AiProvider::Anthropic => ANTHROPIC_MAX_TOKENS as usize,
+            AiProvider::OpenAI | AiProvider::DeepSeek => RESPONSE_TOKEN_RESERVE,
+        };
+        let budget = history_budget(
+            self.context_window
+                .unwrap_or_else(|| context_window_for(model)),
+            response_reserve,
+            &[&system, &user, &tools_json],
         );
+        let history = trim_history_to_budget(history, budget);
         let mut messages = Vec::with_capacity(2 + history.len());
-        messages.push(json!({"role": "system", "content": system}));
+        if self.provider != AiProvider::Anthropic {
+            messages.push(json!({"role": "system", "content": system}));
+        }
         for message in history {
             let role = match message.role {
                 AiChatRole::User => "user",
@@ -125,33 +393,196 @@ impl AiClient {
             messages.push(json!({"role": role, "content": message.content}));
         }
         messages.push(json!({"role": "user", "content": user}));
-        let body = json!({
-            "model": self.model,
+        let tool_specs = if tools { actions } else { &[] };
+        let response_format = if self.structured_output && !tools {
+            response_format_for(self.provider)
+        } else {
+            None
+        };
+        let reply = match self.provider {
+            AiProvider::Anthropic => {
+                self.request_anthropic(model, temperature, &system, messages, tool_specs)?
+            }
+            AiProvider::OpenAI | AiProvider::DeepSeek => self.request_chat_completions(
+                model,
+                temperature,
+                messages,
+                tool_specs,
+                response_format.as_ref(),
+            )?,
+        };
+        let usage = match reply.usage {
+            Some((prompt_tokens, completion_tokens)) => AiUsage {
+                model: model.to_string(),
+                prompt_tokens,
+                completion_tokens,
+                estimated: false,
+            },
+            None => AiUsage {
+                model: model.to_string(),
+                prompt_tokens: (estimate_tokens(&system)
+                    + estimate_tokens(&user)
+                    + estimate_tokens(&tools_json)
+                    + history
+                        .iter()
+                        .map(|message| estimate_tokens(&message.content))
+                        .sum::<usize>()) as u64,
+                completion_tokens: estimate_tokens(&reply.text) as u64,
+                estimated: true,
+            },
+        };
+        if let Ok(mut last) = self.last_usage.lock() {
+            *last = Some(usage);
+        }
+        if reply.tool_calls.is_empty() {
+            if response_format.is_some() {
+                // Constrained replies are plain JSON; the lenient parser stays as a fallback.
+                if let Ok(payload) = serde_json::from_str::<AiDecisionPayload>(reply.text.trim()) {
+                    return decision_from_payload(payload, input);
+                }
+            }
+            return parse_decision(&reply.text, input);
+        }
+        Ok(tool_calls_to_decision(reply, actions, input))
+    }
+
+    fn request_chat_completions(
+        &self,
+        model: &str,
+        temperature: f64,
+        messages: Vec<Value>,
+        actions: &[ActionSpec],
+        response_format: Option<&Value>,
+    ) -> Result<AiReply> {
+        let mut body = json!({
+            "model": model,
             "messages": messages,
-            "temperature": self.temperature,
+            "temperature": temperature,
             "stream": false
         });
+        if let Some(format) = response_format {
+            body["response_format"] = format.clone();
+        }
+        if !actions.is_empty() {
+            let tools: Vec<Value> = actions
+                .iter()
+                .map(|spec| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool_name(&spec.name),
+                            "description": spec.description,
+                            "parameters": tool_parameters(&spec.params_schema),
+                        }
+                    })
+                })
+                .collect();
+            body["tools"] = Value::Array(tools);
+            body["tool_choice"] = Value::String("auto".to_string());
+        }
         let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
-        let resp = self
-            .client
-            .post(url)
-            .bearer_auth(&self.api_key)
-            .json(&body)
-            .send()
-            .context("failed to send ai request")?;
-        let status = resp.status();
-        let value: Value = resp.json().context("failed to parse ai response")?;
-        if !status.is_success() {
-            return Err(anyhow!("ai http error {status}: {value}"));
-        }
-        let content = value
+        let request = self.client.post(url).bearer_auth(&self.api_key).json(&body);
+        let value = send_ai_request(request)?;
+        let message = value
             .get("choices")
             .and_then(|v| v.get(0))
             .and_then(|v| v.get("message"))
-            .and_then(|v| v.get("content"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        parse_decision(content, input)
+            .cloned()
+            .unwrap_or(Value::Null);
+        let tool_calls = message
+            .get("tool_calls")
+            .and_then(|v| v.as_array())
+            .map(|calls| {
+                calls
+                    .iter()
+                    .filter_map(|call| {
+                        let function = call.get("function")?;
+                        let name = function.get("name")?.as_str()?.to_string();
+                        // Arguments arrive as a JSON-encoded string.
+                        let arguments = match function.get("arguments") {
+                            Some(Value::String(raw)) => {
+                                serde_json::from_str(raw).unwrap_or_else(|_| json!({}))
+                            }
+                            Some(value) => value.clone(),
+                            None => json!({}),
+                        };
+                        Some((name, arguments))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(AiReply {
+            text: message
+                .get("content")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            tool_calls,
+            usage: usage_counts(&value["usage"], "prompt_tokens", "completion_tokens"),
+        })
+    }
+
+    /// Anthropic takes the system prompt as a top-level field and returns a list of
+    /// content blocks instead of `choices`.
+    fn request_anthropic(
+        &self,
+        model: &str,
+        temperature: f64,
+        system: &str,
+        messages: Vec<Value>,
+        actions: &[ActionSpec],
+    ) -> Result<AiReply> {
+        let mut body = json!({
+            "model": model,
+            "system": system,
+            "messages": alternate_roles(messages),
+            "max_tokens": ANTHROPIC_MAX_TOKENS,
+            "temperature": temperature,
+        });
+        if !actions.is_empty() {
+            let tools: Vec<Value> = actions
+                .iter()
+                .map(|spec| {
+                    json!({
+                        "name": tool_name(&spec.name),
+                        "description": spec.description,
+                        "input_schema": tool_parameters(&spec.params_schema),
+                    })
+                })
+                .collect();
+            body["tools"] = Value::Array(tools);
+        }
+        let url = format!("{}/messages", self.base_url.trim_end_matches('/'));
+        let request = self
+            .client
+            .post(url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body);
+        let value = send_ai_request(request)?;
+        let mut reply = AiReply {
+            text: String::new(),
+            tool_calls: Vec::new(),
+            usage: usage_counts(&value["usage"], "input_tokens", "output_tokens"),
+        };
+        let blocks = value.get("content").and_then(|v| v.as_array());
+        for block in blocks.into_iter().flatten() {
+            match block.get("type").and_then(|v| v.as_str()) {
+                Some("text") => {
+                    reply
+                        .text
+                        .push_str(block.get("text").and_then(|v| v.as_str()).unwrap_or(""));
+                }
+                Some("tool_use") => {
+                    if let Some(name) = block.get("name").and_then(|v| v.as_str()) {
+                        let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+                        reply.tool_calls.push((name.to_string(), input));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(reply)
     }
 
     pub fn model_name(&self) -> &str {
@@ -159,6 +590,134 @@ impl AiClient {
     }
 }
 
+#[cfg(feature = "ai-http")]
+struct AiReply {
+    text: String,
+    tool_calls: Vec<(String, Value)>,
+    usage: Option<(u64, u64)>,
+}
+
+#[cfg(feature = "ai-http")]
+fn usage_counts(usage: &Value, prompt_key: &str, completion_key: &str) -> Option<(u64, u64)> {
+    Some((usage.get(prompt_key)?.as_u64()?, usage.get(completion_key)?.as_u64()?))
+}
+
+/// Tool names must match `^[a-zA-Z0-9_-]{1,64}$`, so `fs.read_file` becomes `fs__read_file`.
+#[cfg(feature = "ai-http")]
+fn tool_name(action: &str) -> String {
+    action.replace('.', "__")
+}
+
+#[cfg(feature = "ai-http")]
+fn tool_parameters(schema: &Value) -> Value {
+    if schema.get("type").and_then(|v| v.as_str()) == Some("object") {
+        schema.clone()
+    } else {
+        json!({"type": "object", "properties": {}})
+    }
+}
+
+/// One tool call is a single action; several become a plan executed in order.
+#[cfg(feature = "ai-http")]
+fn tool_calls_to_decision(reply: AiReply, actions: &[ActionSpec], raw_input: &str) -> AiDecision {
+    let resolve = |name: &str| {
+        actions
+            .iter()
+            .find(|spec| tool_name(&spec.name) == name || spec.name == name)
+            .map(|spec| spec.name.clone())
+            .unwrap_or_else(|| name.to_string())
+    };
+    let message = Some(reply.text.trim().to_string()).filter(|text| !text.is_empty());
+    let mut calls = reply.tool_calls;
+    if calls.len() == 1 {
+        let (name, params) = calls.remove(0);
+        return AiDecision::Action(ActionRequest {
+            name: resolve(&name),
+            params,
+            raw_input: raw_input.to_string(),
+        });
+    }
+    let steps = calls
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (name, params))| PlanStep {
+            id: Some(format!("s{}", idx + 1)),
+            action: resolve(&name),
+            params,
+            note: None,
+            requires_approval: None,
+            retries: None,
+            retry_delay: None,
+            timeout_secs: None,
+            undo: None,
+            foreach: None,
+        })
+        .collect();
+    AiDecision::Plan { steps, message }
+}
+
+/// Non-success HTTP response from an AI provider.
+#[cfg(feature = "ai-http")]
+#[derive(Debug)]
+pub struct AiHttpError {
+    pub status: u16,
+    pub body: Value,
+}
+
+#[cfg(feature = "ai-http")]
+impl AiHttpError {
+    /// Rate limits and server errors are worth retrying or handing to another backend.
+    pub fn is_transient(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+}
+
+#[cfg(feature = "ai-http")]
+impl std::fmt::Display for AiHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ai http error {}: {}", self.status, self.body)
+    }
+}
+
+#[cfg(feature = "ai-http")]
+impl std::error::Error for AiHttpError {}
+
+#[cfg(feature = "ai-http")]
+fn send_ai_request(request: reqwest::blocking::RequestBuilder) -> Result<Value> {
+    let resp = request.send().context("failed to send ai request")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.json().unwrap_or(Value::Null);
+        return Err(AiHttpError {
+            status: status.as_u16(),
+            body,
+        }
+        .into());
+    }
+    resp.json().context("failed to parse ai response")
+}
+
+/// The Messages API requires strictly alternating turns that start with the user.
+#[cfg(feature = "ai-http")]
+fn alternate_roles(messages: Vec<Value>) -> Vec<Value> {
+    let mut out: Vec<Value> = Vec::with_capacity(messages.len());
+    for message in messages {
+        let role = message["role"].as_str().unwrap_or("user");
+        let content = message["content"].as_str().unwrap_or("");
+        if out.is_empty() && role != "user" {
+            continue;
+        }
+        match out.last_mut() {
+            Some(last) if last["role"].as_str() == Some(role) => {
+                let merged = format!("{}\n\n{content}", last["content"].as_str().unwrap_or(""));
+                last["content"] = Value::String(merged);
+            }
+            _ => out.push(json!({"role": role, "content": content})),
+        }
+    }
+    out
+}
+
 #[cfg(feature = "ai-http")]
 impl AiPlanner for AiClient {
     fn plan_with_history(
@@ -169,13 +728,28 @@ impl AiPlanner for AiClient {
     ) -> Result<AiDecision> {
         AiClient::plan_with_history(self, input, actions, history)
     }
+
+    fn plan_with_options(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        AiClient::plan_with_options(self, input, actions, history, options)
+    }
+
+    fn last_usage(&self) -> Option<AiUsage> {
+        AiClient::last_usage(self)
+    }
 }
 
 #[cfg(feature = "ai-omnix-mlx")]
 mod omnix {
     use super::{
-        parse_decision, system_prompt_with_backend, AiChatMessage, AiChatRole, AiDecision,
-        AiPlanner, ActionSpec,
+        context_window_for, history_budget, parse_decision, system_prompt_with_backend,
+        trim_history_to_budget, with_prompt_extra, AiChatMessage, AiChatRole, AiDecision,
+        AiPlanner, AiRequestOptions, AiUsage, ActionSpec, MLX_MODEL_FILES,
     };
     use anyhow::{anyhow, Context, Result};
     use mlx_lm_utils::tokenizer::{
@@ -188,11 +762,22 @@ mod omnix {
     use std::path::PathBuf;
     use std::sync::Mutex;
 
+    /// Qwen3 `<|endoftext|>` and `<|im_end|>`.
+    pub const DEFAULT_STOP_TOKEN_IDS: &[u32] = &[151643, 151645];
+
     #[derive(Clone, Debug)]
     pub struct MlxQwenConfig {
         pub model_dir: PathBuf,
         pub temperature: f32,
         pub max_tokens: usize,
+        /// Overrides the context window inferred from the model directory name.
+        pub context_window: Option<usize>,
+        /// Token ids that end generation; empty uses `DEFAULT_STOP_TOKEN_IDS`.
+        pub stop_token_ids: Vec<u32>,
+        /// Generation stops before the first occurrence of any of these strings.
+        pub stop_strings: Vec<String>,
+        /// Seeds the MLX random generator before each generation for reproducible output.
+        pub seed: Option<u64>,
     }
 
     pub struct MlxQwenClient {
@@ -202,6 +787,11 @@ mod omnix {
         model_id: String,
         temperature: f32,
         max_tokens: usize,
+        context_window: usize,
+        stop_token_ids: Vec<u32>,
+        stop_strings: Vec<String>,
+        seed: Option<u64>,
+        last_usage: Mutex<Option<AiUsage>>,
     }
 
     impl MlxQwenClient {
@@ -213,12 +803,7 @@ mod omnix {
                     model_dir.display()
                 ));
             }
-            for required in [
-                "config.json",
-                "model.safetensors.index.json",
-                "tokenizer.json",
-                "tokenizer_config.json",
-            ] {
+            for required in MLX_MODEL_FILES {
                 let path = model_dir.join(required);
                 if !path.is_file() {
                     return Err(anyhow!(
@@ -241,6 +826,9 @@ mod omnix {
                 .ok_or_else(|| anyhow!("chat template not found in tokenizer_config.json"))?;
 
             let model = load_model(&model_dir).context("failed to load qwen3 model")?;
+            let context_window = config
+                .context_window
+                .unwrap_or_else(|| context_window_for(&model_id));
 
             Ok(Self {
                 model: Mutex::new(model),
@@ -249,6 +837,19 @@ mod omnix {
                 model_id,
                 temperature: config.temperature,
                 max_tokens: config.max_tokens,
+                context_window,
+                stop_token_ids: if config.stop_token_ids.is_empty() {
+                    DEFAULT_STOP_TOKEN_IDS.to_vec()
+                } else {
+                    config.stop_token_ids
+                },
+                stop_strings: config
+                    .stop_strings
+                    .into_iter()
+                    .filter(|stop| !stop.is_empty())
+                    .collect(),
+                seed: config.seed,
+                last_usage: Mutex::new(None),
             })
         }
 
@@ -257,9 +858,21 @@ mod omnix {
             input: &str,
             actions_json: &str,
             history: &[AiChatMessage],
+            system_prompt_extra: Option<&str>,
         ) -> Vec<Conversation<Role, String>> {
+            let system = with_prompt_extra(
+                system_prompt_with_backend(Some(&self.model_id)),
+                system_prompt_extra,
+            );
+            let user = format!(
+                "{system}\n\nUser request:\n{input}\n\nAvailable actions (JSON):\n{actions_json}\n\nReturn JSON only.",
+                system = system,
+                input = input,
+                actions_json = actions_json
+            );
+            let budget = history_budget(self.context_window, self.max_tokens, &[&user]);
             let mut conversations = Vec::new();
-            for message in history {
+            for message in trim_history_to_budget(history, budget) {
                 let role = match message.role {
                     AiChatRole::User => Role::User,
                     AiChatRole::Assistant => Role::Assistant,
@@ -269,13 +882,6 @@ mod omnix {
                     content: message.content.clone(),
                 });
             }
-            let system = system_prompt_with_backend(Some(&self.model_id));
-            let user = format!(
-                "{system}\n\nUser request:\n{input}\n\nAvailable actions (JSON):\n{actions_json}\n\nReturn JSON only.",
-                system = system,
-                input = input,
-                actions_json = actions_json
-            );
             conversations.push(Conversation {
                 role: Role::User,
                 content: user,
@@ -307,7 +913,11 @@ mod omnix {
             Ok(Array::from(&prompt[..]).index(NewAxis))
         }
 
-        fn generate_text(&self, prompt_tokens: &Array) -> Result<String> {
+        /// Returns the decoded text and the number of generated tokens.
+        fn generate_text(&self, prompt_tokens: &Array) -> Result<(String, usize)> {
+            if let Some(seed) = self.seed {
+                mlx_rs::random::seed(seed)?;
+            }
             let mut model = self.model.lock().unwrap();
             let mut cache = Vec::new();
             let generator =
@@ -315,16 +925,21 @@ mod omnix {
 
             let mut tokens = Vec::new();
             let mut output = String::new();
+            let mut generated = 0;
 
             for (i, token) in generator.enumerate() {
                 let token = token?;
                 let token_id = token.item::<u32>();
-                if token_id == 151643 || token_id == 151645 {
+                if self.stop_token_ids.contains(&token_id) {
                     break;
                 }
                 tokens.push(token);
+                generated += 1;
                 if tokens.len() % 5 == 0 {
                     self.decode_tokens(&mut tokens, &mut output)?;
+                    if self.truncate_at_stop(&mut output) {
+                        return Ok((output, generated));
+                    }
                 }
                 if i >= self.max_tokens.saturating_sub(1) {
                     break;
@@ -333,7 +948,24 @@ mod omnix {
             if !tokens.is_empty() {
                 self.decode_tokens(&mut tokens, &mut output)?;
             }
-            Ok(output)
+            self.truncate_at_stop(&mut output);
+            Ok((output, generated))
+        }
+
+        /// Cuts `output` at the earliest stop string; returns true if one was found.
+        fn truncate_at_stop(&self, output: &mut String) -> bool {
+            let earliest = self
+                .stop_strings
+                .iter()
+                .filter_map(|stop| output.find(stop.as_str()))
+                .min();
+            match earliest {
+                Some(idx) => {
+                    output.truncate(idx);
+                    true
+                }
+                None => false,
+            }
         }
 
         fn decode_tokens(&self, tokens: &mut Vec<Array>, output: &mut String) -> Result<()> {
@@ -357,20 +989,47 @@ mod omnix {
             input: &str,
             actions: &[ActionSpec],
             history: &[AiChatMessage],
+        ) -> Result<AiDecision> {
+            self.plan_with_options(input, actions, history, &AiRequestOptions::default())
+        }
+
+        fn plan_with_options(
+            &self,
+            input: &str,
+            actions: &[ActionSpec],
+            history: &[AiChatMessage],
+            options: &AiRequestOptions,
         ) -> Result<AiDecision> {
             let actions_json =
                 serde_json::to_string(actions).unwrap_or_else(|_| "[]".to_string());
-            let conversations = self.build_conversation(input, &actions_json, history);
+            let conversations = self.build_conversation(
+                input,
+                &actions_json,
+                history,
+                options.system_prompt_extra.as_deref(),
+            );
             let prompt_tokens = self.encode_prompt(conversations)?;
-            let response = self.generate_text(&prompt_tokens)?;
+            let (response, generated) = self.generate_text(&prompt_tokens)?;
+            if let Ok(mut last) = self.last_usage.lock() {
+                *last = Some(AiUsage {
+                    model: self.model_id.clone(),
+                    prompt_tokens: prompt_tokens.size() as u64,
+                    completion_tokens: generated as u64,
+                    estimated: false,
+                });
+            }
             parse_decision(response.trim(), input)
         }
+
+        fn last_usage(&self) -> Option<AiUsage> {
+            self.last_usage.lock().ok().and_then(|usage| usage.clone())
+        }
     }
 
 }
 
 #[cfg(feature = "ai-omnix-mlx")]
-pub use omnix::{MlxQwenClient, MlxQwenConfig};
+pub use omnix::{MlxQwenClient, MlxQwenConfig, DEFAULT_STOP_TOKEN_IDS};
 
 #[derive(Debug, Deserialize, Serialize)]
 struct AiDecisionPayload {
@@ -406,6 +1065,87 @@ struct PlanStepPayload {
     note: Option<String>,
     #[serde(default)]
     requires_approval: Option<bool>,
+    #[serde(default)]
+    retries: Option<u32>,
+    #[serde(default)]
+    retry_delay: Option<u64>,
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[serde(default)]
+    undo: Option<UndoStep>,
+    #[serde(default)]
+    foreach: Option<ForEach>,
+}
+
+/// JSON schema of `AiDecisionPayload`, for providers that support constrained decoding.
+/// Params stay free-form objects, so the schema is not strict.
+pub fn decision_schema() -> Value {
+    let params = json!({"type": "object"});
+    json!({
+        "type": "object",
+        "properties": {
+            "type": {
+                "type": "string",
+                "enum": ["action", "plan", "need_input", "chat", "unknown"]
+            },
+            "name": {"type": "string"},
+            "action": {"type": "string"},
+            "params": params,
+            "steps": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "action": {"type": "string"},
+                        "params": params,
+                        "note": {"type": "string"},
+                        "requires_approval": {"type": "boolean"},
+                        "retries": {"type": "integer", "minimum": 0},
+                        "retry_delay": {"type": "integer", "minimum": 0},
+                        "timeout_secs": {"type": "integer", "minimum": 1},
+                        "undo": {
+                            "type": "object",
+                            "properties": {
+                                "action": {"type": "string"},
+                                "params": params
+                            },
+                            "required": ["action"]
+                        },
+                        "foreach": {
+                            "type": "object",
+                            "properties": {
+                                "glob": {"type": "string"},
+                                "items": {},
+                                "var": {"type": "string"}
+                            }
+                        }
+                    },
+                    "required": ["action"]
+                }
+            },
+            "missing": {"type": "array", "items": {"type": "string"}},
+            "message": {"type": "string"},
+            "prompt": {"type": "string"}
+        },
+        "required": ["type"]
+    })
+}
+
+#[cfg(feature = "ai-http")]
+fn response_format_for(provider: AiProvider) -> Option<Value> {
+    match provider {
+        AiProvider::OpenAI => Some(json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "ai_decision",
+                "strict": false,
+                "schema": decision_schema(),
+            }
+        })),
+        AiProvider::DeepSeek => Some(json!({"type": "json_object"})),
+        AiProvider::Anthropic => None,
+    }
 }
 
 fn parse_decision(content: &str, raw_input: &str) -> Result<AiDecision> {
@@ -436,7 +1176,10 @@ fn parse_decision(content: &str, raw_input: &str) -> Result<AiDecision> {
             }
         }
     };
+    decision_from_payload(payload, raw_input)
+}
 
+fn decision_from_payload(payload: AiDecisionPayload, raw_input: &str) -> Result<AiDecision> {
     let ty = payload.r#type.to_lowercase();
     if ty == "action" || payload.name.is_some() || payload.action.is_some() {
         let name = payload
@@ -487,6 +1230,11 @@ fn parse_decision(content: &str, raw_input: &str) -> Result<AiDecision> {
                 params: step.params.unwrap_or_else(|| json!({})),
                 note: step.note,
                 requires_approval: step.requires_approval,
+                retries: step.retries,
+                retry_delay: step.retry_delay,
+                timeout_secs: step.timeout_secs,
+                undo: step.undo,
+                foreach: step.foreach,
             });
         }
         return Ok(AiDecision::Plan {
@@ -675,6 +1423,10 @@ Pick an action only from the provided action list.\n\
 Use conversation context to fill missing details.\n\
 If the user is chatting or the request doesn't map to an action, respond with type=chat.\n\
 If the task needs multiple actions, respond with type=plan.\n\
+A plan step may set \"retries\" and \"retry_delay\" (seconds) when it can fail transiently, e.g. network fetches, and \"timeout_secs\" when it could hang.\n\
+A plan step that changes files may set \"undo\":{\"action\":\"...\",\"params\":{...}} to reverse it if a later step fails.\n\
+A plan step can use an earlier step's result in its params, e.g. \"path\":\"{{steps.s1.data.path}}\" or {{steps.s1.summary}}.\n\
+To repeat a step over many files, set \"foreach\":{\"glob\":\"~/Desktop/*.png\",\"var\":\"file\"} (or \"items\":\"{{steps.s1.data.entries}}\") and use {{file}}, {{file.stem}}, {{file.dir}} in params instead of listing the files.\n\
 If you ask for missing info, return type=need_input and include action + missing fields.\n\
 If the user mentions desktop/桌面, interpret as ~/Desktop.\n\
 If the user says current directory/当前目录 and a Context block provides cwd, use it.\n\
@@ -682,8 +1434,35 @@ If the user input looks like a shell command (e.g. ls, pwd), plan using shell.ru
 If the user asks about system status (cpu/memory/disk/network/uptime), respond with a plan of read-only shell.run probes."
 }
 
+#[cfg(feature = "ai-http")]
+fn tool_system_prompt(backend: Option<&str>) -> String {
+    let mut prompt = "You are an action planner for robit.\n\
+Use the provided tools to act on the user's request.\n\
+Call several tools in order when the task needs multiple actions.\n\
+If the user is chatting, or details are missing, reply in plain text instead of calling a tool.\n\
+Use conversation context to fill missing details.\n\
+If the user mentions desktop/桌面, interpret as ~/Desktop.\n\
+If the user says current directory/当前目录 and a Context block provides cwd, use it."
+        .to_string();
+    append_backend_label(&mut prompt, backend);
+    prompt
+}
+
 fn system_prompt_with_backend(backend: Option<&str>) -> String {
     let mut prompt = system_prompt_base().to_string();
+    append_backend_label(&mut prompt, backend);
+    prompt
+}
+
+fn with_prompt_extra(mut prompt: String, extra: Option<&str>) -> String {
+    if let Some(extra) = extra.map(str::trim).filter(|extra| !extra.is_empty()) {
+        prompt.push('\n');
+        prompt.push_str(extra);
+    }
+    prompt
+}
+
+fn append_backend_label(prompt: &mut String, backend: Option<&str>) {
     if let Some(label) = backend {
         let label = label.trim();
         if !label.is_empty() {
@@ -694,5 +1473,4 @@ fn system_prompt_with_backend(backend: Option<&str>) -> String {
             );
         }
     }
-    prompt
 }
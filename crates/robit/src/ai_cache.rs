@@ -0,0 +1,230 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::ai::{AiChatMessage, AiDecision, AiPlanner, AiRequestOptions, AiUsage};
+use crate::types::ActionSpec;
+
+/// Caches planner decisions keyed on (model, system prompt extra, input, actions, history), so
+/// repeated control-style requests skip the model. Unknown decisions are never cached.
+pub struct AiPlannerCache {
+    inner: Arc<dyn AiPlanner>,
+    label: String,
+    capacity: usize,
+    ttl: Duration,
+    path: Option<PathBuf>,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheState {
+    entries: HashMap<String, CachedDecision>,
+    order: VecDeque<String>,
+    #[serde(skip)]
+    last_hit: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedDecision {
+    decision: AiDecision,
+    stored_at: u64,
+    /// What the key hashes; a lookup only hits when it matches, so a hash collision misses.
+    #[serde(default)]
+    material: String,
+}
+
+impl AiPlannerCache {
+    /// `label` identifies the wrapped backend's model when no per-request model is set.
+    pub fn new(label: impl Into<String>, inner: Arc<dyn AiPlanner>) -> Self {
+        Self {
+            inner,
+            label: label.into(),
+            capacity: 256,
+            ttl: Duration::from_secs(600),
+            path: None,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Persists the cache as JSON at `path`, e.g. `~/.robit/cache/planner.json`.
+    pub fn with_disk_path(mut self, path: PathBuf) -> Result<Self> {
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            let state: CacheState = serde_json::from_str(&content)?;
+            self.state = Mutex::new(state);
+        }
+        self.path = Some(path);
+        Ok(self)
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.entries.clear();
+            state.order.clear();
+            self.persist(&state);
+        }
+    }
+
+    /// The entry key and the material it hashes.
+    fn cache_key(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> (String, String) {
+        let actions_json = serde_json::to_string(actions).unwrap_or_default();
+        let history_json = serde_json::to_string(history).unwrap_or_default();
+        let model = options.model.as_deref().unwrap_or(&self.label);
+        let extra = options.system_prompt_extra.as_deref().unwrap_or("");
+        let material = serde_json::to_string(&[model, extra, input, &actions_json, &history_json])
+            .unwrap_or_default();
+        (format!("{:016x}", fnv1a(&[&material])), material)
+    }
+
+    fn lookup(&self, key: &str, material: &str) -> Option<AiDecision> {
+        let mut state = self.state.lock().ok()?;
+        let cached = state.entries.get(key)?.clone();
+        if cached.material != material {
+            return None;
+        }
+        if now_secs().saturating_sub(cached.stored_at) > self.ttl.as_secs() {
+            state.entries.remove(key);
+            state.order.retain(|k| k != key);
+            return None;
+        }
+        state.order.retain(|k| k != key);
+        state.order.push_back(key.to_string());
+        Some(cached.decision)
+    }
+
+    fn store(&self, key: String, material: String, decision: &AiDecision) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.order.retain(|k| k != &key);
+        state.order.push_back(key.clone());
+        state.entries.insert(
+            key,
+            CachedDecision {
+                decision: decision.clone(),
+                stored_at: now_secs(),
+                material,
+            },
+        );
+        while state.order.len() > self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.entries.remove(&oldest);
+            }
+        }
+        self.persist(&state);
+    }
+
+    fn persist(&self, state: &CacheState) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_string(state)?)?;
+            Ok(())
+        })();
+        if let Err(err) = result {
+            warn!("planner cache save failed: {err}");
+        }
+    }
+
+    fn set_last_hit(&self, hit: bool) {
+        if let Ok(mut state) = self.state.lock() {
+            state.last_hit = hit;
+        }
+    }
+
+    fn last_hit(&self) -> bool {
+        self.state.lock().map(|state| state.last_hit).unwrap_or(false)
+    }
+}
+
+impl AiPlanner for AiPlannerCache {
+    fn plan_with_history(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+    ) -> Result<AiDecision> {
+        self.plan_with_options(input, actions, history, &AiRequestOptions::default())
+    }
+
+    fn plan_with_options(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        let (key, material) = self.cache_key(input, actions, history, options);
+        if let Some(decision) = self.lookup(&key, &material) {
+            self.set_last_hit(true);
+            return Ok(decision);
+        }
+        self.set_last_hit(false);
+        let decision = self
+            .inner
+            .plan_with_options(input, actions, history, options)?;
+        if !matches!(decision, AiDecision::Unknown { .. }) {
+            self.store(key, material, &decision);
+        }
+        Ok(decision)
+    }
+
+    fn last_backend(&self) -> Option<String> {
+        if self.last_hit() {
+            return Some(format!("{} (cached)", self.label));
+        }
+        self.inner.last_backend()
+    }
+
+    fn last_usage(&self) -> Option<AiUsage> {
+        if self.last_hit() {
+            return None;
+        }
+        self.inner.last_usage()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Stable across builds, unlike `DefaultHasher`, so disk entries stay valid.
+fn fnv1a(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0xff)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
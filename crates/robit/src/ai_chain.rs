@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+use crate::ai::{AiChatMessage, AiDecision, AiPlanner, AiRequestOptions, AiUsage};
+use crate::types::ActionSpec;
+
+/// Tries backends in order, retrying transient failures (timeouts, 429, 5xx) with
+/// exponential backoff before falling through to the next one.
+pub struct AiPlannerChain {
+    backends: Vec<(String, Arc<dyn AiPlanner>)>,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    last_backend: Mutex<Option<String>>,
+    last_usage: Mutex<Option<AiUsage>>,
+}
+
+impl AiPlannerChain {
+    pub fn new(label: impl Into<String>, primary: Arc<dyn AiPlanner>) -> Self {
+        Self {
+            backends: vec![(label.into(), primary)],
+            max_retries: 1,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            last_backend: Mutex::new(None),
+            last_usage: Mutex::new(None),
+        }
+    }
+
+    pub fn with_fallback(mut self, label: impl Into<String>, backend: Arc<dyn AiPlanner>) -> Self {
+        self.backends.push((label.into(), backend));
+        self
+    }
+
+    /// Retries per backend before falling back; the delay doubles after each attempt.
+    pub fn with_retries(mut self, max_retries: u32, initial_backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.backends.iter().map(|(label, _)| label.clone()).collect()
+    }
+}
+
+impl AiPlanner for AiPlannerChain {
+    fn plan_with_history(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+    ) -> Result<AiDecision> {
+        self.plan_with_options(input, actions, history, &AiRequestOptions::default())
+    }
+
+    fn plan_with_options(
+        &self,
+        input: &str,
+        actions: &[ActionSpec],
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        let mut last_err = None;
+        for (label, backend) in &self.backends {
+            let mut backoff = self.initial_backoff;
+            for attempt in 0..=self.max_retries {
+                match backend.plan_with_options(input, actions, history, options) {
+                    Ok(decision) => {
+                        let answered_by = backend.last_backend().unwrap_or_else(|| label.clone());
+                        if let Ok(mut last) = self.last_backend.lock() {
+                            *last = Some(answered_by);
+                        }
+                        if let Ok(mut last) = self.last_usage.lock() {
+                            *last = backend.last_usage();
+                        }
+                        return Ok(decision);
+                    }
+                    Err(err) if is_transient(&err) => {
+                        warn!("ai backend {label} failed (attempt {}): {err}", attempt + 1);
+                        last_err = Some(err);
+                        if attempt < self.max_retries {
+                            std::thread::sleep(backoff);
+                            backoff = (backoff * 2).min(self.max_backoff);
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("no ai backends configured")))
+    }
+
+    fn last_backend(&self) -> Option<String> {
+        self.last_backend.lock().ok().and_then(|last| last.clone())
+    }
+
+    fn last_usage(&self) -> Option<AiUsage> {
+        self.last_usage.lock().ok().and_then(|last| last.clone())
+    }
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        #[cfg(feature = "ai-http")]
+        {
+            if let Some(http) = cause.downcast_ref::<crate::ai::AiHttpError>() {
+                return http.is_transient();
+            }
+            if let Some(req) = cause.downcast_ref::<reqwest::Error>() {
+                return req.is_timeout() || req.is_connect();
+            }
+        }
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io| io.kind() == std::io::ErrorKind::TimedOut)
+    })
+}
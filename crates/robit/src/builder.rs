@@ -0,0 +1,497 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+use crate::actions::{default_registry, ActionRegistry};
+use crate::ai::{AiPlanner, AiTask};
+use crate::encryption::StorageKey;
+use crate::engine::Engine;
+use crate::format::ReplyFormat;
+use crate::hooks::EngineHook;
+use crate::i18n::Locale;
+use crate::memory::MemoryIndex;
+use crate::middleware::Middleware;
+use crate::planner::{Planner, RulePlanner};
+use crate::policy::Policy;
+use crate::preflight::{PreflightCheck, PreflightConfig};
+
+/// Collects everything an embedder sets up and builds the engine in one step. Unset parts
+/// fall back to `default_registry()`, `RulePlanner::new()`, `Policy::default_with_home()`
+/// with the config files' `[policy]` over it, and the preflight settings from the config file.
+///
+/// ```ignore
+/// let engine = Engine::builder()
+///     .ai_backend(backend, Some("openai".to_string()))
+///     .conversation_path(paths.conversation_path("bot"))
+///     .approvals_path(paths.approvals_path())
+///     .build()?;
+/// ```
+#[derive(Default)]
+pub struct EngineBuilder {
+    registry: Option<ActionRegistry>,
+    planner: Option<RulePlanner>,
+    policy: Option<Policy>,
+    preflight: Option<PreflightConfig>,
+    preflight_checks: Vec<Box<dyn PreflightCheck>>,
+    ai_backend: Option<(Arc<dyn AiPlanner>, Option<String>)>,
+    ai_routes: Vec<(AiTask, Arc<dyn AiPlanner>, Option<String>)>,
+    named_backends: Vec<(String, Arc<dyn AiPlanner>)>,
+    critic: Option<bool>,
+    memory: Option<MemoryIndex>,
+    storage_key: Option<StorageKey>,
+    conversation_path: Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    conversation_db_path: Option<PathBuf>,
+    approvals_path: Option<PathBuf>,
+    seen_messages_path: Option<PathBuf>,
+    schedules_path: Option<PathBuf>,
+    action_history_path: Option<PathBuf>,
+    vars_path: Option<PathBuf>,
+    facts_path: Option<PathBuf>,
+    fact_extraction: Option<bool>,
+    session_log_path: Option<PathBuf>,
+    config_state_path: Option<PathBuf>,
+    rules_path: Option<PathBuf>,
+    aliases_path: Option<PathBuf>,
+    plan_templates_dir: Option<PathBuf>,
+    backup_dir: Option<PathBuf>,
+    attachment_dir: Option<PathBuf>,
+    planners: Vec<Box<dyn Planner>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    hooks: Vec<Box<dyn EngineHook>>,
+    tick_interval: Option<Duration>,
+    action_timeout: Option<Duration>,
+    max_reply_chars: Option<usize>,
+    reply_format: Option<ReplyFormat>,
+    locale: Option<Locale>,
+    redact_patterns: Vec<String>,
+    profile: Option<String>,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn registry(mut self, registry: ActionRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub fn planner(mut self, planner: RulePlanner) -> Self {
+        self.planner = Some(planner);
+        self
+    }
+
+    /// Used as is: the config files' `[policy]` does not apply, on startup or on reload.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Replaces the preflight settings loaded from the config file.
+    pub fn preflight(mut self, config: PreflightConfig) -> Self {
+        self.preflight = Some(config);
+        self
+    }
+
+    /// See `Engine::add_preflight_check`.
+    pub fn preflight_check<C: PreflightCheck + 'static>(mut self, check: C) -> Self {
+        self.preflight_checks.push(Box::new(check));
+        self
+    }
+
+    pub fn ai_backend(mut self, backend: Arc<dyn AiPlanner>, label: Option<String>) -> Self {
+        self.ai_backend = Some((backend, label));
+        self
+    }
+
+    #[cfg(feature = "ai-http")]
+    pub fn ai_client(self, client: crate::ai::AiClient) -> Self {
+        let label = format!("http:{}", client.model_name());
+        self.ai_backend(Arc::new(client), Some(label))
+    }
+
+    /// See `Engine::set_ai_route`.
+    pub fn ai_route(
+        mut self,
+        task: AiTask,
+        backend: Arc<dyn AiPlanner>,
+        label: Option<String>,
+    ) -> Self {
+        self.ai_routes.push((task, backend, label));
+        self
+    }
+
+    /// See `Engine::register_ai_backend`.
+    pub fn named_backend(mut self, name: impl Into<String>, backend: Arc<dyn AiPlanner>) -> Self {
+        self.named_backends.push((name.into(), backend));
+        self
+    }
+
+    pub fn critic(mut self, enabled: bool) -> Self {
+        self.critic = Some(enabled);
+        self
+    }
+
+    pub fn memory(mut self, memory: MemoryIndex) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// See `Engine::set_storage_key`. A `memory` index without a key of its own is sealed
+    /// with it too.
+    pub fn storage_key(mut self, key: StorageKey) -> Self {
+        self.storage_key = Some(key);
+        self
+    }
+
+    pub fn conversation_path(mut self, path: PathBuf) -> Self {
+        self.conversation_path = Some(path);
+        self
+    }
+
+    pub fn approvals_path(mut self, path: PathBuf) -> Self {
+        self.approvals_path = Some(path);
+        self
+    }
+
+    pub fn seen_messages_path(mut self, path: PathBuf) -> Self {
+        self.seen_messages_path = Some(path);
+        self
+    }
+
+    pub fn schedules_path(mut self, path: PathBuf) -> Self {
+        self.schedules_path = Some(path);
+        self
+    }
+
+    /// See `Engine::enable_action_history`.
+    pub fn action_history_path(mut self, path: PathBuf) -> Self {
+        self.action_history_path = Some(path);
+        self
+    }
+
+    /// Keeps conversations in SQLite instead of the `conversation_path` JSON file, which is
+    /// migrated into it once; see `Engine::enable_conversation_db`.
+    #[cfg(feature = "sqlite")]
+    pub fn conversation_db_path(mut self, path: PathBuf) -> Self {
+        self.conversation_db_path = Some(path);
+        self
+    }
+
+    /// See `Engine::enable_var_persistence`.
+    pub fn vars_path(mut self, path: PathBuf) -> Self {
+        self.vars_path = Some(path);
+        self
+    }
+
+    /// See `Engine::enable_fact_persistence`.
+    pub fn facts_path(mut self, path: PathBuf) -> Self {
+        self.facts_path = Some(path);
+        self
+    }
+
+    /// See `Engine::set_fact_extraction`.
+    pub fn fact_extraction(mut self, enabled: bool) -> Self {
+        self.fact_extraction = Some(enabled);
+        self
+    }
+
+    /// See `Engine::record_session`.
+    pub fn session_log_path(mut self, path: PathBuf) -> Self {
+        self.session_log_path = Some(path);
+        self
+    }
+
+    /// See `Engine::enable_config_persistence`.
+    pub fn config_state_path(mut self, path: PathBuf) -> Self {
+        self.config_state_path = Some(path);
+        self
+    }
+
+    /// See `Engine::enable_alias_persistence`.
+    pub fn aliases_path(mut self, path: PathBuf) -> Self {
+        self.aliases_path = Some(path);
+        self
+    }
+
+    /// Extra rules for the rule planner, re-read when the file changes; see `RuleConfig`.
+    pub fn rules_path(mut self, path: PathBuf) -> Self {
+        self.rules_path = Some(path);
+        self
+    }
+
+    pub fn plan_templates_dir(mut self, dir: PathBuf) -> Self {
+        self.plan_templates_dir = Some(dir);
+        self
+    }
+
+    /// See `Engine::set_backup_dir`.
+    pub fn backup_dir(mut self, dir: PathBuf) -> Self {
+        self.backup_dir = Some(dir);
+        self
+    }
+
+    /// See `Engine::set_attachment_dir`.
+    pub fn attachment_dir(mut self, dir: PathBuf) -> Self {
+        self.attachment_dir = Some(dir);
+        self
+    }
+
+    /// See `Engine::add_planner`.
+    pub fn add_planner<P: Planner + 'static>(mut self, planner: P) -> Self {
+        self.planners.push(Box::new(planner));
+        self
+    }
+
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn hook<H: EngineHook + 'static>(mut self, hook: H) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn tick_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = Some(interval);
+        self
+    }
+
+    pub fn action_timeout(mut self, timeout: Duration) -> Self {
+        self.action_timeout = Some(timeout);
+        self
+    }
+
+    /// See `Engine::set_max_reply_chars`.
+    pub fn max_reply_chars(mut self, limit: usize) -> Self {
+        self.max_reply_chars = Some(limit);
+        self
+    }
+
+    /// Overrides the format taken from the adapter; see `ReplyFormat`.
+    pub fn reply_format(mut self, format: ReplyFormat) -> Self {
+        self.reply_format = Some(format);
+        self
+    }
+
+    /// See `Engine::set_locale`.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = Some(locale);
+        self
+    }
+
+    /// Masks matches of `pattern` like the built-in secret patterns; see `Engine::add_redaction_pattern`.
+    pub fn redact_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.redact_patterns.push(pattern.into());
+        self
+    }
+
+    /// Applies `[profiles.<name>]` from the config files; see `Engine::use_profile`.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Checks the settings, then builds the engine. Persisted approvals are restored last,
+    /// against the final registry and backends.
+    pub fn build(self) -> Result<Engine> {
+        self.validate()?;
+        let mut planner = self.planner.unwrap_or_default();
+        let loaded = self.rules_path.map(|path| planner.load_rules(path));
+        if let Some(Err(err)) = loaded {
+            warn!("rules load failed: {err}");
+        }
+        let keep_policy = self.policy.is_some();
+        let mut engine = Engine::with_profile(
+            self.registry.unwrap_or_else(default_registry),
+            planner,
+            self.policy.unwrap_or_else(Policy::default_with_home),
+            keep_policy,
+            self.profile.clone(),
+        )?;
+        if let Some(name) = &self.profile {
+            let known = engine.profiles()?;
+            if known.is_empty() {
+                return Err(anyhow!("unknown profile {name}: the config files define no profiles"));
+            }
+            if !known.contains(name) {
+                return Err(anyhow!("unknown profile {name} (known: {})", known.join(", ")));
+            }
+        }
+        for planner in self.planners {
+            engine.add_boxed_planner(planner);
+        }
+        for pattern in &self.redact_patterns {
+            engine.add_redaction_pattern(pattern)?;
+        }
+        if let Some(config) = self.preflight {
+            engine.set_preflight_config(config);
+        }
+        for check in self.preflight_checks {
+            engine.add_boxed_preflight_check(check);
+        }
+        if let Some((backend, label)) = self.ai_backend {
+            engine.set_ai_backend_with_label(Some(backend), label);
+        }
+        for (task, backend, label) in self.ai_routes {
+            engine.set_ai_route(task, Some(backend), label);
+        }
+        for (name, backend) in self.named_backends {
+            engine.register_ai_backend(name, backend);
+        }
+        if let Some(enabled) = self.critic {
+            engine.set_critic_enabled(enabled);
+        }
+        if let Some(enabled) = self.fact_extraction {
+            engine.set_fact_extraction(enabled);
+        }
+        if let Some(mut memory) = self.memory {
+            if let Some(key) = &self.storage_key {
+                memory.adopt_storage_key(key)?;
+            }
+            engine.enable_memory(memory);
+        }
+        for middleware in self.middleware {
+            engine.add_boxed_middleware(middleware);
+        }
+        for hook in self.hooks {
+            engine.add_boxed_hook(hook);
+        }
+        if let Some(interval) = self.tick_interval {
+            engine.set_tick_interval(interval);
+        }
+        if self.action_timeout.is_some() {
+            engine.set_action_timeout(self.action_timeout);
+        }
+        if self.max_reply_chars.is_some() {
+            engine.set_max_reply_chars(self.max_reply_chars);
+        }
+        if let Some(format) = self.reply_format {
+            engine.set_reply_format(format);
+        }
+        if let Some(locale) = self.locale {
+            engine.set_locale(locale);
+        }
+        if let Some(key) = self.storage_key {
+            engine.set_storage_key(key);
+        }
+        if let Some(dir) = self.plan_templates_dir {
+            engine.enable_plan_templates(dir);
+        }
+        if let Some(dir) = self.backup_dir {
+            engine.set_backup_dir(dir);
+        }
+        if let Some(dir) = self.attachment_dir {
+            engine.set_attachment_dir(dir);
+        }
+        #[cfg(feature = "sqlite")]
+        let conversation_path = match self.conversation_db_path {
+            Some(db) => {
+                engine.enable_conversation_db(db, self.conversation_path.as_deref());
+                None
+            }
+            None => self.conversation_path,
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let conversation_path = self.conversation_path;
+        if let Some(path) = conversation_path {
+            engine.enable_conversation_persistence(path);
+        }
+        if let Some(path) = self.seen_messages_path {
+            engine.enable_seen_message_persistence(path);
+        }
+        if let Some(path) = self.schedules_path {
+            engine.enable_schedule_persistence(path);
+        }
+        if let Some(path) = self.action_history_path {
+            engine.enable_action_history(path);
+        }
+        if let Some(path) = self.vars_path {
+            engine.enable_var_persistence(path);
+        }
+        if let Some(path) = self.facts_path {
+            engine.enable_fact_persistence(path);
+        }
+        if let Some(path) = self.aliases_path {
+            engine.enable_alias_persistence(path);
+        }
+        if let Some(path) = self.session_log_path {
+            engine.record_session(path);
+        }
+        if let Some(path) = self.config_state_path {
+            engine.enable_config_persistence(path);
+        }
+        if let Some(path) = self.approvals_path {
+            engine.enable_approval_persistence(path);
+        }
+        Ok(engine)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for (name, _) in &self.named_backends {
+            if name.trim().is_empty() {
+                return Err(anyhow!("named backends need a non-empty name"));
+            }
+            if !names.insert(name.as_str()) {
+                return Err(anyhow!("backend {name} is registered twice"));
+            }
+        }
+        #[allow(unused_mut)]
+        let mut files = vec![
+            ("conversation", &self.conversation_path),
+            ("approvals", &self.approvals_path),
+            ("seen messages", &self.seen_messages_path),
+            ("schedules", &self.schedules_path),
+            ("action history", &self.action_history_path),
+            ("vars", &self.vars_path),
+            ("facts", &self.facts_path),
+            ("session log", &self.session_log_path),
+            ("rules", &self.rules_path),
+            ("aliases", &self.aliases_path),
+            ("config state", &self.config_state_path),
+        ];
+        #[cfg(feature = "sqlite")]
+        files.push(("conversation database", &self.conversation_db_path));
+        let mut seen: Vec<(&str, &PathBuf)> = Vec::new();
+        for (what, path) in files {
+            let Some(path) = path else {
+                continue;
+            };
+            if path.is_dir() {
+                return Err(anyhow!("{what} path {} is a directory", path.display()));
+            }
+            if let Some((other, _)) = seen.iter().find(|(_, other)| *other == path) {
+                return Err(anyhow!(
+                    "{what} and {other} persistence share {}",
+                    path.display()
+                ));
+            }
+            seen.push((what, path));
+        }
+        let templates = self.plan_templates_dir.as_ref();
+        if let Some(dir) = templates.filter(|dir| dir.exists() && !dir.is_dir()) {
+            return Err(anyhow!("plan templates path {} is not a directory", dir.display()));
+        }
+        if let Some(dir) = self.backup_dir.as_ref().filter(|dir| dir.exists() && !dir.is_dir()) {
+            return Err(anyhow!("backup path {} is not a directory", dir.display()));
+        }
+        if self.tick_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(anyhow!("tick interval must be greater than zero"));
+        }
+        if self.action_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(anyhow!("action timeout must be greater than zero"));
+        }
+        if self.max_reply_chars == Some(0) {
+            return Err(anyhow!("max reply chars must be greater than zero"));
+        }
+        Ok(())
+    }
+}
@@ -1,46 +1,680 @@
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use toml::Table;
 
-use crate::policy::{Policy, PolicyConfig};
+use crate::ai::AiPlanner;
+use crate::engine::model_loader_for;
+use crate::paths::RobitPaths;
+use crate::policy::{parse_risk_level, Policy, PolicyConfig, TimeWindow};
 use crate::preflight::PreflightConfig;
+use crate::schedule::{parse_interval, ScheduleEntry};
+use crate::utils::expand_tilde;
 
 #[derive(Debug, Deserialize)]
 struct RobitConfigFile {
     preflight: Option<PreflightConfig>,
     policy: Option<PolicyConfig>,
+    ai: Option<AiFileConfig>,
+    redaction: Option<RedactionFileConfig>,
+    conversation: Option<ConversationFileConfig>,
+    #[serde(default)]
+    schedules: Vec<ScheduleEntry>,
 }
 
-pub(crate) fn load_default_config(
-    base_policy: Policy,
-    base_preflight: PreflightConfig,
-) -> Result<(Policy, PreflightConfig)> {
-    let Some(path) = default_config_path() else {
-        return Ok((base_policy, base_preflight));
+/// `[conversation]` section of the config file: how much conversation history is kept.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct ConversationFileConfig {
+    /// Messages kept per room for the AI context and the JSON store; 50 when unset.
+    #[serde(default)]
+    pub(crate) max_messages: Option<usize>,
+    /// Messages older than this, e.g. `30d` or `12h`, are dropped when history is loaded or
+    /// saved, and hourly.
+    #[serde(default)]
+    pub(crate) max_age: Option<String>,
+}
+
+impl ConversationFileConfig {
+    pub(crate) fn max_age_secs(&self) -> Result<Option<u64>> {
+        self.max_age
+            .as_deref()
+            .map(|age| parse_interval(age).context("[conversation] max_age"))
+            .transpose()
+    }
+}
+
+/// `[redaction]` section of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct RedactionFileConfig {
+    /// Regexes masked in addition to the built-in ones; a `secret` group masks only that part.
+    #[serde(default)]
+    pub(crate) patterns: Vec<String>,
+}
+
+/// `[ai]` section of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct AiFileConfig {
+    /// The backend the `robit` binary starts with: `openai`, `deepseek`, `anthropic`, or
+    /// `omnix-mlx`. The fields below it mean the same as in `[[ai.models]]`.
+    #[serde(default)]
+    pub(crate) provider: Option<String>,
+    #[serde(default)]
+    pub(crate) model: Option<String>,
+    #[serde(default)]
+    pub(crate) model_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) base_url: Option<String>,
+    #[serde(default)]
+    pub(crate) api_key_env: Option<String>,
+    #[serde(default)]
+    pub(crate) temperature: Option<f64>,
+    /// Appended to the planner system prompt (tone, language, house rules).
+    #[serde(default)]
+    pub(crate) system_prompt_extra: Option<String>,
+    /// Review AI-proposed actions with a second AI call before asking for approval.
+    #[serde(default)]
+    pub(crate) critic: bool,
+    /// Models that `model use <name>` can switch between at runtime.
+    #[serde(default)]
+    pub(crate) models: Vec<AiModelEntry>,
+}
+
+/// `[[ai.models]]` entry; which fields are read depends on the enabled AI features.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[cfg_attr(not(all(feature = "ai-http", feature = "ai-omnix-mlx")), allow(dead_code))]
+pub(crate) struct AiModelEntry {
+    pub(crate) name: String,
+    /// `openai`, `deepseek`, `anthropic`, or `omnix-mlx`.
+    pub(crate) provider: String,
+    #[serde(default)]
+    pub(crate) model: Option<String>,
+    /// Local model directory for `omnix-mlx`.
+    #[serde(default)]
+    pub(crate) model_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) base_url: Option<String>,
+    /// Environment variable holding the API key; defaults to e.g. `OPENAI_API_KEY`.
+    #[serde(default)]
+    pub(crate) api_key_env: Option<String>,
+    #[serde(default)]
+    pub(crate) temperature: Option<f64>,
+}
+
+impl AiFileConfig {
+    /// The `provider` fields as a model entry named after the provider.
+    pub(crate) fn backend_entry(&self) -> Option<AiModelEntry> {
+        let provider = self.provider.clone()?;
+        Some(AiModelEntry {
+            name: provider.clone(),
+            provider,
+            model: self.model.clone(),
+            model_dir: self.model_dir.clone(),
+            base_url: self.base_url.clone(),
+            api_key_env: self.api_key_env.clone(),
+            temperature: self.temperature,
+        })
+    }
+}
+
+/// Builds the backend set by `[ai] provider` in the config file, with `profile`'s section
+/// applied and the provider as its label; `None` when there is no file or it sets no
+/// provider.
+pub fn ai_backend_from_config(profile: Option<&str>) -> Result<Option<(Arc<dyn AiPlanner>, String)>> {
+    let (parsed, _) = read_layers(&config_layers(), profile)?;
+    let Some(entry) = parsed.ai.and_then(|ai| ai.backend_entry()) else {
+        return Ok(None);
     };
-    load_config_from_path(&path, base_policy, base_preflight)
+    let label = entry.name.clone();
+    let backend = model_loader_for(entry)?()?;
+    Ok(Some((backend, label)))
 }
 
-fn load_config_from_path(
-    path: &Path,
+/// The `[ai]` backend followed by the `[[ai.models]]` that differ from it.
+pub(crate) fn ai_entries(profile: Option<&str>) -> Result<Vec<AiModelEntry>> {
+    let (parsed, _) = read_layers(&config_layers(), profile)?;
+    let Some(ai) = parsed.ai else {
+        return Ok(Vec::new());
+    };
+    let mut entries: Vec<AiModelEntry> = ai.backend_entry().into_iter().collect();
+    for model in ai.models {
+        if !entries.contains(&model) {
+            entries.push(model);
+        }
+    }
+    Ok(entries)
+}
+
+pub(crate) struct LoadedConfig {
+    pub(crate) policy: Policy,
+    pub(crate) preflight: PreflightConfig,
+    pub(crate) ai: AiFileConfig,
+    pub(crate) redaction: RedactionFileConfig,
+    pub(crate) conversation: ConversationFileConfig,
+    pub(crate) schedules: Vec<ScheduleEntry>,
+}
+
+/// One of the files the config is merged from.
+pub(crate) struct ConfigLayer {
+    /// `system`, `user`, `default` or `project`.
+    name: &'static str,
+    path: PathBuf,
+}
+
+/// The config files the engine started with, so `[policy]` and `[preflight]` can be re-read
+/// while it runs.
+pub(crate) struct ConfigSource {
+    layers: Vec<ConfigLayer>,
+    modified: Vec<Option<SystemTime>>,
+    /// The policy the files' `[policy]` is applied on top of.
     base_policy: Policy,
-    base_preflight: PreflightConfig,
-) -> Result<(Policy, PreflightConfig)> {
-    if !path.exists() {
-        return Ok((base_policy, base_preflight));
-    }
-    let content = fs::read_to_string(path)?;
-    let parsed: RobitConfigFile = toml::from_str(&content)?;
-    let policy = if let Some(cfg) = parsed.policy {
-        base_policy.apply_config(cfg)?
-    } else {
-        base_policy
+    /// The embedder set the policy itself, so the files' `[policy]` is ignored.
+    keep_policy: bool,
+    /// `[profiles.<name>]` merged over the rest; `None` for the plain files.
+    profile: Option<String>,
+    #[cfg(feature = "watch")]
+    watcher: Option<ConfigWatcher>,
+}
+
+#[cfg(feature = "watch")]
+struct ConfigWatcher {
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ConfigSource {
+    pub(crate) fn new(base_policy: Policy, keep_policy: bool, profile: Option<String>) -> Self {
+        let layers = config_layers();
+        Self {
+            modified: vec![None; layers.len()],
+            layers,
+            base_policy,
+            keep_policy,
+            profile,
+            #[cfg(feature = "watch")]
+            watcher: None,
+        }
+    }
+
+    /// Watches the directories holding the layers, so `changed` reads file events instead
+    /// of checking modification times. Directories that do not exist yet are not watched.
+    #[cfg(feature = "watch")]
+    pub(crate) fn watch(&mut self) -> Result<()> {
+        use notify::{RecursiveMode, Watcher};
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let mut dirs: Vec<&Path> = self
+            .layers
+            .iter()
+            .filter_map(|layer| layer.path.parent())
+            .filter(|dir| dir.is_dir())
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("watching {}", dir.display()))?;
+        }
+        self.watcher = Some(ConfigWatcher {
+            _watcher: watcher,
+            events,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Takes effect on the next `load`.
+    pub(crate) fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
+
+    /// The `[profiles.<name>]` sections in the files, sorted.
+    pub(crate) fn profiles(&self) -> Result<Vec<String>> {
+        let (merged, _) = merge_layers(&self.layers)?;
+        Ok(profile_names(&merged))
+    }
+
+    /// The layers that exist, in merge order.
+    pub(crate) fn paths(&self) -> Vec<&Path> {
+        self.layers
+            .iter()
+            .map(|layer| layer.path.as_path())
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// Whether a layer changed, appeared or went away since the files were last loaded.
+    pub(crate) fn changed(&self) -> bool {
+        #[cfg(feature = "watch")]
+        if let Some(watcher) = &self.watcher {
+            let mut changed = false;
+            // Loading the files shows up as access events; only writes count.
+            for event in watcher.events.try_iter().flatten() {
+                changed |= !event.kind.is_access()
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| self.layers.iter().any(|layer| &layer.path == path));
+            }
+            return changed;
+        }
+        self.layers
+            .iter()
+            .map(|layer| modified_at(&layer.path))
+            .ne(self.modified.iter().copied())
+    }
+
+    /// `[preflight]` falls back to `base_preflight` when no file has one.
+    pub(crate) fn load(&mut self, base_preflight: PreflightConfig) -> Result<LoadedConfig> {
+        // Remember the attempt so a broken file is reported once, not on every check.
+        self.modified = self.layers.iter().map(|layer| modified_at(&layer.path)).collect();
+        let (parsed, paths) = read_layers(&self.layers, self.profile.as_deref())?;
+        let mut policy = match parsed.policy {
+            Some(cfg) if !self.keep_policy => self.base_policy.clone().apply_config(cfg)?,
+            _ => self.base_policy.clone(),
+        };
+        // Actions must not rewrite the policy they run under.
+        for path in paths {
+            policy
+                .denied_roots
+                .push(path.canonicalize().unwrap_or(path));
+        }
+        Ok(LoadedConfig {
+            policy,
+            preflight: parsed.preflight.unwrap_or(base_preflight),
+            ai: parsed.ai.unwrap_or_default(),
+            redaction: parsed.redaction.unwrap_or_default(),
+            conversation: parsed.conversation.unwrap_or_default(),
+            schedules: parsed.schedules,
+        })
+    }
+
+    /// Each layer in merge order and whether it was found, for `config show`.
+    pub(crate) fn text(&self) -> String {
+        let mut text = String::from("config layers (later ones override earlier ones):\n");
+        if let Some(profile) = &self.profile {
+            text = format!("profile: {profile} ([profiles.{profile}] applied last)\n{text}");
+        }
+        for ((index, layer), ignored) in self.layers.iter().enumerate().zip(self.ignored()) {
+            let state = if layer.path.exists() { "loaded" } else { "not found" };
+            text.push_str(&format!(
+                "  {}. {:<8} {} ({state})\n",
+                index + 1,
+                layer.name,
+                layer.path.display()
+            ));
+            if !ignored.is_empty() {
+                text.push_str(&format!(
+                    "     ignored, not in trusted_projects: {}\n",
+                    ignored.join(", ")
+                ));
+            }
+        }
+        text
+    }
+
+    pub(crate) fn layers_json(&self) -> serde_json::Value {
+        self.layers
+            .iter()
+            .zip(self.ignored())
+            .map(|(layer, ignored)| {
+                serde_json::json!({
+                    "layer": layer.name,
+                    "path": layer.path,
+                    "loaded": layer.path.exists(),
+                    "ignored": ignored,
+                })
+            })
+            .collect()
+    }
+
+    /// The keys each layer sets that are not applied (see `ConfigLayer::restrict`).
+    fn ignored(&self) -> Vec<Vec<String>> {
+        let mut merged = Table::new();
+        self.layers
+            .iter()
+            .map(|layer| {
+                let Some(table) = fs::read_to_string(&layer.path)
+                    .ok()
+                    .and_then(|content| toml::from_str::<Table>(&content).ok())
+                else {
+                    return Vec::new();
+                };
+                let (table, ignored) = layer.restrict(&merged, table);
+                merge_table(&mut merged, table);
+                ignored
+            })
+            .collect()
+    }
+}
+
+/// Every problem in the config files at once, each naming where it is; empty when they
+/// are fine. Capability and action names are checked against the registry's, with
+/// `profile`'s section applied.
+pub(crate) fn validate_layers(
+    profile: Option<&str>,
+    known_capabilities: &[String],
+    known_actions: &[String],
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut merged = Table::new();
+    for layer in config_layers().iter().filter(|layer| layer.path.exists()) {
+        let table = fs::read_to_string(&layer.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(toml::from_str::<Table>(&content)?))
+            .map(|table| layer.restrict(&merged, table).0);
+        match table {
+            Ok(table) => merge_table(&mut merged, table),
+            Err(err) => problems.push(format!("{}: {err}", layer.path.display())),
+        }
+    }
+    if let Err(err) = apply_profile(&mut merged, profile) {
+        problems.push(format!("{err:#}"));
+    }
+    // Section by section, so one bad section does not hide problems in the others.
+    let policy: Option<PolicyConfig> = section(&merged, "policy", &mut problems);
+    let preflight: Option<PreflightConfig> = section(&merged, "preflight", &mut problems);
+    let ai: Option<AiFileConfig> = section(&merged, "ai", &mut problems);
+    let redaction: Option<RedactionFileConfig> = section(&merged, "redaction", &mut problems);
+    let conversation: Option<ConversationFileConfig> =
+        section(&merged, "conversation", &mut problems);
+    let schedules = match merged.get("schedules") {
+        Some(toml::Value::Array(entries)) => entries.clone(),
+        Some(_) => {
+            problems.push("[[schedules]]: expected an array of tables".to_string());
+            Vec::new()
+        }
+        None => Vec::new(),
     };
-    let preflight = parsed.preflight.unwrap_or(base_preflight);
-    Ok((policy, preflight))
+    let mut check_capabilities = |key: &str, caps: &[String]| {
+        for cap in caps.iter().filter(|cap| !known_capabilities.contains(cap)) {
+            problems.push(format!(
+                "{key}: unknown capability {cap:?} (known: {})",
+                known_capabilities.join(", ")
+            ));
+        }
+    };
+
+    if let Some(policy) = &policy {
+        check_capabilities(
+            "[policy] allowed_capabilities",
+            policy.allowed_capabilities.as_deref().unwrap_or_default(),
+        );
+        check_capabilities(
+            "[policy] denied_capabilities",
+            policy.denied_capabilities.as_deref().unwrap_or_default(),
+        );
+    }
+    if let Some(preflight) = &preflight {
+        check_capabilities("[preflight] allowed_capabilities", &preflight.allowed_capabilities);
+        check_capabilities("[preflight] denied_capabilities", &preflight.denied_capabilities);
+        check_capabilities("[preflight] warn_capabilities", &preflight.warn_capabilities);
+    }
+    if let Some(policy) = policy {
+        for root in policy.allowed_roots.iter().flatten() {
+            if !expand_tilde(root).is_dir() {
+                problems.push(format!("[policy] allowed_roots: {root} is not a directory"));
+            }
+        }
+        for level in policy.approval_risk_levels.iter().flatten() {
+            if let Err(err) = parse_risk_level(level) {
+                problems.push(format!("[policy] approval_risk_levels: {err}"));
+            }
+        }
+        for (index, window) in policy.time_windows.into_iter().flatten().enumerate() {
+            let key = format!("[[policy.time_windows]] #{}", index + 1);
+            for action in window.actions.iter().filter(|action| !known_actions.contains(action)) {
+                problems.push(format!("{key}: unknown action {action:?}"));
+            }
+            if let Err(err) = TimeWindow::from_config(window) {
+                problems.push(format!("{key}: {err}"));
+            }
+        }
+    }
+
+    let ai = ai.unwrap_or_default();
+    if let Some(Err(err)) = ai.backend_entry().map(check_ai_entry) {
+        problems.push(format!("[ai]: {err:#}"));
+    }
+    for entry in ai.models {
+        let name = entry.name.clone();
+        if let Err(err) = check_ai_entry(entry) {
+            problems.push(format!("[[ai.models]] {name}: {err:#}"));
+        }
+    }
+    if let Some(Err(err)) = conversation.map(|conversation| conversation.max_age_secs()) {
+        problems.push(format!("{err:#}"));
+    }
+    if let Some(redaction) = redaction {
+        for pattern in &redaction.patterns {
+            if let Err(err) = regex::Regex::new(pattern) {
+                problems.push(format!("[redaction] patterns: {pattern:?}: {err}"));
+            }
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    for (index, entry) in schedules.into_iter().enumerate() {
+        let checked = ScheduleEntry::deserialize(entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|entry| entry.into_schedule(now));
+        if let Err(err) = checked {
+            problems.push(format!("[[schedules]] #{}: {err:#}", index + 1));
+        }
+    }
+    problems
+}
+
+/// `[key]` of the merged config, or `None` with the reason added to `problems`.
+fn section<T: DeserializeOwned>(merged: &Table, key: &str, problems: &mut Vec<String>) -> Option<T> {
+    let value = merged.get(key)?.clone();
+    T::deserialize(value)
+        .map_err(|err| problems.push(format!("[{key}]: {}", err.message())))
+        .ok()
+}
+
+/// Whether `entry` names a usable provider and its credentials resolve. HTTP clients are
+/// built, which makes no request; MLX models are not loaded, only their directory checked.
+fn check_ai_entry(entry: AiModelEntry) -> Result<()> {
+    if matches!(entry.provider.as_str(), "omnix-mlx" | "mlx") {
+        if let Some(dir) = entry.model_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(anyhow::anyhow!("model_dir {} is not a directory", dir.display()));
+        }
+        return model_loader_for(entry).map(|_| ());
+    }
+    model_loader_for(entry)?()?;
+    Ok(())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Merges the layers that exist and then `profile`'s section, and returns the result with
+/// the paths that were read.
+fn read_layers(
+    layers: &[ConfigLayer],
+    profile: Option<&str>,
+) -> Result<(RobitConfigFile, Vec<PathBuf>)> {
+    let (mut merged, paths) = merge_layers(layers)?;
+    apply_profile(&mut merged, profile)?;
+    let parsed = RobitConfigFile::deserialize(toml::Value::Table(merged))?;
+    Ok((parsed, paths))
+}
+
+/// The layers that exist, each merged over the ones before it.
+fn merge_layers(layers: &[ConfigLayer]) -> Result<(Table, Vec<PathBuf>)> {
+    let mut merged = Table::new();
+    let mut paths = Vec::new();
+    for layer in layers.iter().filter(|layer| layer.path.exists()) {
+        let table: Table = fs::read_to_string(&layer.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(toml::from_str(&content)?))
+            .with_context(|| format!("{} config {}", layer.name, layer.path.display()))?;
+        let (table, _) = layer.restrict(&merged, table);
+        merge_table(&mut merged, table);
+        paths.push(layer.path.clone());
+    }
+    Ok((merged, paths))
+}
+
+/// Takes the `[profiles]` table out of `merged` and merges `profile`'s section over the
+/// rest, so a profile can set anything the files can.
+fn apply_profile(merged: &mut Table, profile: Option<&str>) -> Result<()> {
+    let names = profile_names(merged);
+    let mut profiles = match merged.remove("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => return Err(anyhow::anyhow!("[profiles]: expected a table")),
+        None => Table::new(),
+    };
+    let Some(name) = profile else {
+        return Ok(());
+    };
+    match profiles.remove(name) {
+        Some(toml::Value::Table(overlay)) => {
+            merge_table(merged, overlay);
+            Ok(())
+        }
+        Some(_) => Err(anyhow::anyhow!("[profiles.{name}]: expected a table")),
+        None if names.is_empty() => Err(anyhow::anyhow!(
+            "unknown profile {name}: the config files define no [profiles.<name>]"
+        )),
+        None => Err(anyhow::anyhow!(
+            "unknown profile {name} (known: {})",
+            names.join(", ")
+        )),
+    }
+}
+
+fn profile_names(merged: &Table) -> Vec<String> {
+    let mut names: Vec<String> = match merged.get("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+/// Tables merge key by key; any other value, arrays included, replaces the earlier one.
+fn merge_table(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_table(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Keys a project file may only set when its directory is in `trusted_projects`, since
+/// any checkout can carry one: they widen what robit may do or where data is sent.
+const PROJECT_RESTRICTED: &[&str] = &[
+    "policy",
+    "preflight",
+    "redaction",
+    "trusted_projects",
+    "ai.base_url",
+    "ai.api_key_env",
+    "ai.models",
+];
+
+impl ConfigLayer {
+    /// `table` without the `PROJECT_RESTRICTED` keys when this is an untrusted project
+    /// file, checked against `trusted_projects` in `merged`, the layers before it. Also
+    /// returns the keys it dropped.
+    fn restrict(&self, merged: &Table, mut table: Table) -> (Table, Vec<String>) {
+        if self.name != "project" || self.trusted(merged) {
+            return (table, Vec::new());
+        }
+        let mut dropped = strip_keys(&mut table, "");
+        if let Some(toml::Value::Table(profiles)) = table.get_mut("profiles") {
+            for (name, profile) in profiles.iter_mut() {
+                if let toml::Value::Table(profile) = profile {
+                    dropped.extend(strip_keys(profile, &format!("profiles.{name}.")));
+                }
+            }
+        }
+        (table, dropped)
+    }
+
+    fn trusted(&self, merged: &Table) -> bool {
+        let Some(dir) = self.path.parent().and_then(|dir| dir.canonicalize().ok()) else {
+            return false;
+        };
+        let Some(toml::Value::Array(trusted)) = merged.get("trusted_projects") else {
+            return false;
+        };
+        trusted.iter().filter_map(toml::Value::as_str).any(|path| {
+            let path = expand_tilde(path);
+            path.canonicalize().unwrap_or(path) == dir
+        })
+    }
+}
+
+/// Removes the `PROJECT_RESTRICTED` keys from `table` and returns them, named with
+/// `prefix`.
+fn strip_keys(table: &mut Table, prefix: &str) -> Vec<String> {
+    let mut dropped = Vec::new();
+    for key in PROJECT_RESTRICTED {
+        let removed = match key.split_once('.') {
+            Some((section, field)) => match table.get_mut(section) {
+                Some(toml::Value::Table(section)) => section.remove(field).is_some(),
+                _ => false,
+            },
+            None => table.remove(*key).is_some(),
+        };
+        if removed {
+            dropped.push(format!("{prefix}{key}"));
+        }
+    }
+    dropped
+}
+
+/// `/etc/robit/config.toml`, `config.toml` in the user config directory (see
+/// `RobitPaths`), the default file (see `default_config_path`), then `.robit.toml` in the
+/// working directory.
+fn config_layers() -> Vec<ConfigLayer> {
+    let mut layers = vec![ConfigLayer {
+        name: "system",
+        path: PathBuf::from("/etc/robit/config.toml"),
+    }];
+    if let Some(paths) = RobitPaths::from_env() {
+        layers.push(ConfigLayer {
+            name: "user",
+            path: paths.config_file(),
+        });
+    }
+    if let Some(path) = default_config_path() {
+        layers.push(ConfigLayer {
+            name: "default",
+            path,
+        });
+    }
+    layers.push(ConfigLayer {
+        name: "project",
+        path: PathBuf::from(".robit.toml"),
+    });
+    // Absolute, so they match the paths in file events.
+    for layer in &mut layers {
+        if let Ok(path) = std::path::absolute(&layer.path) {
+            layer.path = path;
+        }
+    }
+    layers
 }
 
 fn default_config_path() -> Option<PathBuf> {
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::engine::Engine;
+use crate::protocol::{ProtocolEvent, SCHEMA_VERSION};
+use crate::replay::ReplyDecision;
+
+/// The canonical `robit.v1` events in `crates/robit/fixtures/protocol`, by file name.
+const BUILTIN_FIXTURES: &[(&str, &str)] = &[
+    ("action_list_request", include_str!("../fixtures/protocol/action_list_request.json")),
+    (
+        "approval_decision_unknown",
+        include_str!("../fixtures/protocol/approval_decision_unknown.json"),
+    ),
+    ("config_query", include_str!("../fixtures/protocol/config_query.json")),
+    ("config_reloaded", include_str!("../fixtures/protocol/config_reloaded.json")),
+    ("config_update", include_str!("../fixtures/protocol/config_update.json")),
+    ("error", include_str!("../fixtures/protocol/error.json")),
+    ("export_request", include_str!("../fixtures/protocol/export_request.json")),
+    ("hello", include_str!("../fixtures/protocol/hello.json")),
+    ("history_request", include_str!("../fixtures/protocol/history_request.json")),
+    ("message_attachment", include_str!("../fixtures/protocol/message_attachment.json")),
+    ("message_context_only", include_str!("../fixtures/protocol/message_context_only.json")),
+    ("message_help", include_str!("../fixtures/protocol/message_help.json")),
+    ("ping", include_str!("../fixtures/protocol/ping.json")),
+    ("pong", include_str!("../fixtures/protocol/pong.json")),
+    ("response_action_result", include_str!("../fixtures/protocol/response_action_result.json")),
+    ("room_scope", include_str!("../fixtures/protocol/room_scope.json")),
+    ("search_request", include_str!("../fixtures/protocol/search_request.json")),
+    ("status_request", include_str!("../fixtures/protocol/status_request.json")),
+    (
+        "unsupported_schema_version",
+        include_str!("../fixtures/protocol/unsupported_schema_version.json"),
+    ),
+    ("usage_request", include_str!("../fixtures/protocol/usage_request.json")),
+];
+
+/// One canonical event and what the engine answers it with.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProtocolFixture {
+    /// The file name without `.json`.
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Kept as written, so the round trip is compared with the fixture's JSON.
+    pub event: Value,
+    /// One per reply, compared like replayed turns: kind, action and error code. `None` for
+    /// events only the engine sends, which are only round-tripped.
+    #[serde(default)]
+    pub replies: Option<Vec<ReplyDecision>>,
+}
+
+fn parse_fixture(name: &str, text: &str) -> Result<ProtocolFixture> {
+    let mut fixture: ProtocolFixture =
+        serde_json::from_str(text).with_context(|| format!("fixture {name}"))?;
+    fixture.name = name.to_string();
+    Ok(fixture)
+}
+
+/// The fixtures shipped with robit.
+pub fn builtin_fixtures() -> Result<Vec<ProtocolFixture>> {
+    BUILTIN_FIXTURES
+        .iter()
+        .map(|(name, text)| parse_fixture(name, text))
+        .collect()
+}
+
+/// Every `*.json` fixture in `dir`, sorted by name.
+pub fn load_fixtures(dir: &Path) -> Result<Vec<ProtocolFixture>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            parse_fixture(&name, &text)
+        })
+        .collect()
+}
+
+/// Parses `value` as an event and writes it back. Every field `value` sets must come back
+/// unchanged; fields it leaves out may come back with their defaults. Writing the parsed
+/// event again must give the same JSON.
+pub fn round_trip(value: &Value) -> Result<ProtocolEvent> {
+    let event: ProtocolEvent = serde_json::from_value(value.clone())?;
+    let written = serde_json::to_value(&event)?;
+    if let Some(path) = first_difference(value, &written, "") {
+        return Err(anyhow!("{path} changed in the round trip"));
+    }
+    let again: ProtocolEvent = serde_json::from_value(written.clone())?;
+    if serde_json::to_value(&again)? != written {
+        return Err(anyhow!("writing the event a second time gives different JSON"));
+    }
+    Ok(event)
+}
+
+/// The JSON pointer of the first value in `expected` that `actual` lacks or differs in. A
+/// `null` in `expected` matches a missing field.
+fn first_difference(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    let here = || if path.is_empty() { "/".to_string() } else { path.to_string() };
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected.iter().find_map(|(key, value)| {
+                let path = format!("{path}/{key}");
+                match actual.get(key) {
+                    Some(actual) => first_difference(value, actual, &path),
+                    None if value.is_null() => None,
+                    None => Some(path),
+                }
+            })
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            expected.iter().zip(actual).enumerate().find_map(|(index, (expected, actual))| {
+                first_difference(expected, actual, &format!("{path}/{index}"))
+            })
+        }
+        (Value::Number(expected), Value::Number(actual)) => {
+            (expected.as_f64() != actual.as_f64()).then(here)
+        }
+        _ => (expected != actual).then(here),
+    }
+}
+
+/// A fixture the round trip or the engine did not pass.
+#[derive(Clone, Debug)]
+pub struct ConformanceFailure {
+    pub fixture: String,
+    pub problem: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConformanceReport {
+    pub fixtures: usize,
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn fail(&mut self, fixture: &ProtocolFixture, problem: impl Into<String>) {
+        self.failures.push(ConformanceFailure {
+            fixture: fixture.name.clone(),
+            problem: problem.into(),
+        });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "checked {} fixture(s): {} failed", self.fixtures, self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n{}: {}", failure.fixture, failure.problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Round-trips every fixture's event, then sends the ones with `replies` to an engine from
+/// `new_engine`, a fresh one per fixture so none sees another's scope or settings. Each reply
+/// must round-trip too, carry the current schema version and answer the fixture's event.
+pub fn check(
+    fixtures: &[ProtocolFixture],
+    mut new_engine: impl FnMut() -> Result<Engine>,
+) -> ConformanceReport {
+    let mut report = ConformanceReport {
+        fixtures: fixtures.len(),
+        failures: Vec::new(),
+    };
+    for fixture in fixtures {
+        let event = match round_trip(&fixture.event) {
+            Ok(event) => event,
+            Err(err) => {
+                report.fail(fixture, format!("{err:#}"));
+                continue;
+            }
+        };
+        let Some(expected) = &fixture.replies else {
+            continue;
+        };
+        let mut engine = match new_engine() {
+            Ok(engine) => engine,
+            Err(err) => {
+                report.fail(fixture, format!("engine: {err:#}"));
+                continue;
+            }
+        };
+        let replies = engine.handle_protocol_event(event.clone());
+        for reply in &replies {
+            let kind = reply.body.event_type();
+            let written = serde_json::to_value(reply).map_err(anyhow::Error::from);
+            if let Err(err) = written.and_then(|value| round_trip(&value)) {
+                report.fail(fixture, format!("{kind} reply: {err:#}"));
+            }
+            if reply.schema_version != SCHEMA_VERSION {
+                report.fail(fixture, format!("{kind} reply has version {}", reply.schema_version));
+            }
+            if !reply.is_reply_to(&event) {
+                report.fail(fixture, format!("{kind} reply does not answer {}", event.id));
+            }
+        }
+        let actual: Vec<ReplyDecision> = replies.iter().map(ReplyDecision::from_event).collect();
+        let same = actual.len() == expected.len()
+            && actual.iter().zip(expected).all(|(actual, expected)| actual.same_as(expected));
+        if !same {
+            let list = |decisions: &[ReplyDecision]| {
+                decisions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+            };
+            report.fail(
+                fixture,
+                format!("expected [{}], got [{}]", list(expected), list(&actual)),
+            );
+        }
+    }
+    report
+}
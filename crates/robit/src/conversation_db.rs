@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, params_from_iter, Connection};
+
+use crate::ai::{AiChatMessage, AiChatRole};
+use crate::encryption::{is_sealed, open_text, seal_text, Record, SealMark, StorageKey};
+use crate::engine::{matches_terms, StoredMessage};
+use crate::protocol::SearchHit;
+
+/// Bumped with each change to the tables; `PRAGMA user_version` records what a file has.
+const SCHEMA_VERSION: i64 = 2;
+
+/// Conversation history in SQLite, one row per message. Rows are only added, so a busy room
+/// costs one insert per message instead of rewriting every room's history; the engine keeps
+/// the recent messages in memory and reads the rest back at startup. `created_at` is Unix
+/// milliseconds, 0 for messages stored without a time; `action` is set on tool results. With a storage key `content` is sealed
+/// per row; the other columns stay readable so rooms can still be listed and pruned.
+pub(crate) struct ConversationDb {
+    conn: Connection,
+    key: Option<StorageKey>,
+    /// Whether the file was sealed by an earlier run; see `SealMark`.
+    sealed_before: bool,
+}
+
+impl ConversationDb {
+    pub(crate) fn open(path: &Path, key: Option<StorageKey>) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("opening conversation database {}", path.display()))?;
+        let mark = SealMark::for_store(path);
+        let mut db = Self { conn, key, sealed_before: mark.is_set() };
+        db.migrate()?;
+        db.seal_plain_rows().with_context(|| mark.refused(path))?;
+        mark.update(db.key.is_some())?;
+        Ok(db)
+    }
+
+    /// Decrypts a row's content if it was sealed.
+    fn opened(
+        &self,
+        workspace_id: &str,
+        room_id: &str,
+        mut stored: StoredMessage,
+    ) -> Result<StoredMessage> {
+        let room = row_key(workspace_id, room_id);
+        let content = &stored.message.content;
+        stored.message.content =
+            open_text(self.key.as_ref(), row_record(&room), content, self.sealed_before)?;
+        Ok(stored)
+    }
+
+    /// Seals rows written before a storage key was set. Plain rows in a file that was sealed
+    /// before, by its mark or by holding sealed rows, are refused instead.
+    fn seal_plain_rows(&mut self) -> Result<()> {
+        let Some(key) = self.key.clone() else {
+            return Ok(());
+        };
+        let tx = self.conn.transaction()?;
+        let rows: Vec<(i64, String, String, String)> = tx
+            .prepare("SELECT id, workspace_id, room_id, content FROM messages")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        self.sealed_before |= rows.iter().any(|(.., content)| is_sealed(content));
+        let mut plain = rows.into_iter().filter(|(.., content)| !is_sealed(content)).peekable();
+        if self.sealed_before && plain.peek().is_some() {
+            return Err(anyhow!(
+                "found plain messages in a sealed database; it was replaced or rolled back"
+            ));
+        }
+        for (id, workspace_id, room_id, content) in plain {
+            let room = row_key(&workspace_id, &room_id);
+            tx.execute(
+                "UPDATE messages SET content = ?1 WHERE id = ?2",
+                params![seal_text(Some(&key), row_record(&room), content)?, id],
+            )?;
+        }
+        tx.commit()?;
+        self.sealed_before = true;
+        Ok(())
+    }
+
+    fn migrate(&self) -> Result<()> {
+        let version: i64 = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(anyhow!(
+                "conversation database has schema {version}; this build knows up to {SCHEMA_VERSION}"
+            ));
+        }
+        if version < 1 {
+            self.conn.execute_batch(
+                "BEGIN;
+                 CREATE TABLE IF NOT EXISTS messages (
+                     id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     workspace_id TEXT NOT NULL,
+                     room_id TEXT NOT NULL,
+                     role TEXT NOT NULL,
+                     content TEXT NOT NULL,
+                     created_at INTEGER NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS messages_room
+                     ON messages (workspace_id, room_id, created_at);
+                 PRAGMA user_version = 1;
+                 COMMIT;",
+            )?;
+        }
+        if version < 2 {
+            self.conn.execute_batch(
+                "BEGIN;
+                 ALTER TABLE messages ADD COLUMN action TEXT;
+                 PRAGMA user_version = 2;
+                 COMMIT;",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The last `max_messages` of every room, oldest first.
+    pub(crate) fn load(&self, max_messages: usize) -> Result<HashMap<(String, String), Vec<StoredMessage>>> {
+        let mut statement = self.conn.prepare(
+            "SELECT workspace_id, room_id, role, content, created_at, action FROM (
+                 SELECT *, ROW_NUMBER() OVER (
+                     PARTITION BY workspace_id, room_id ORDER BY created_at DESC, id DESC
+                 ) AS recent
+                 FROM messages
+             )
+             WHERE recent <= ?1
+             ORDER BY workspace_id, room_id, created_at, id",
+        )?;
+        let rows = statement.query_map([max_messages as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                stored_message(row, 2)?,
+            ))
+        })?;
+        let mut history: HashMap<(String, String), Vec<StoredMessage>> = HashMap::new();
+        for row in rows {
+            let (workspace_id, room_id, message) = row?;
+            let message = self.opened(&workspace_id, &room_id, message)?;
+            history.entry((workspace_id, room_id)).or_default().push(message);
+        }
+        Ok(history)
+    }
+
+    pub(crate) fn append(&mut self, key: &(String, String), messages: &[StoredMessage]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        insert(&tx, key, messages, self.key.as_ref())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replaces the room's stored history with `messages`, e.g. after an import.
+    pub(crate) fn replace(&mut self, key: &(String, String), messages: &[StoredMessage]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM messages WHERE workspace_id = ?1 AND room_id = ?2",
+            params![key.0, key.1],
+        )?;
+        insert(&tx, key, messages, self.key.as_ref())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Messages in `workspace_id` containing every term, in rooms `rooms` accepts, newest
+    /// first. The whole stored history is searched, not only what the engine keeps in memory.
+    pub(crate) fn search(
+        &self,
+        workspace_id: &str,
+        rooms: impl Fn(&str) -> bool,
+        terms: &[String],
+        limit: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let mut sql = String::from(
+            "SELECT room_id, role, content, created_at, action FROM messages
+             WHERE workspace_id = ? AND action IS NULL",
+        );
+        let mut values = vec![workspace_id.to_string()];
+        // Sealed content can only be matched once decrypted, below.
+        let like_terms = if self.key.is_some() { &[][..] } else { terms };
+        for term in like_terms {
+            // LIKE ignores ASCII case only; `matches_terms` below settles the rest.
+            sql.push_str(" AND content LIKE ? ESCAPE '\\'");
+            let escaped = term
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            values.push(format!("%{escaped}%"));
+        }
+        sql.push_str(" ORDER BY created_at DESC, id DESC");
+        let mut statement = self.conn.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, String>(0)?, stored_message(row, 1)?))
+        })?;
+        let mut hits = Vec::new();
+        for row in rows {
+            let (room_id, stored) = row?;
+            if !rooms(&room_id) {
+                continue;
+            }
+            let stored = self.opened(workspace_id, &room_id, stored)?;
+            if !matches_terms(&stored.message.content, terms) {
+                continue;
+            }
+            hits.push(SearchHit {
+                workspace_id: workspace_id.to_string(),
+                room_id,
+                role: stored.message.role,
+                content: stored.message.content,
+                at: stored.at,
+            });
+            if hits.len() >= limit {
+                break;
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Every stored message of the rooms in `workspace_id` that `rooms` accepts, oldest first.
+    pub(crate) fn room_messages(
+        &self,
+        workspace_id: &str,
+        rooms: impl Fn(&str) -> bool,
+    ) -> Result<Vec<StoredMessage>> {
+        let mut statement = self.conn.prepare(
+            "SELECT room_id, role, content, created_at, action FROM messages
+             WHERE workspace_id = ?1 ORDER BY created_at, id",
+        )?;
+        let rows = statement.query_map([workspace_id], |row| {
+            Ok((row.get::<_, String>(0)?, stored_message(row, 1)?))
+        })?;
+        let mut messages = Vec::new();
+        for row in rows {
+            let (room_id, stored) = row?;
+            if rooms(&room_id) {
+                messages.push(self.opened(workspace_id, &room_id, stored)?);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Deletes the messages of the rooms in `workspace_id` that `rooms` accepts; returns how
+    /// many went.
+    pub(crate) fn clear(&mut self, workspace_id: &str, rooms: impl Fn(&str) -> bool) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let room_ids: Vec<String> = tx
+            .prepare("SELECT DISTINCT room_id FROM messages WHERE workspace_id = ?1")?
+            .query_map([workspace_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut deleted = 0;
+        for room_id in room_ids.iter().filter(|room_id| rooms(room_id)) {
+            deleted += tx.execute(
+                "DELETE FROM messages WHERE workspace_id = ?1 AND room_id = ?2",
+                params![workspace_id, room_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Deletes messages recorded before `cutoff` (Unix seconds); ones without a time stay.
+    pub(crate) fn prune_before(&mut self, cutoff: u64) -> Result<usize> {
+        Ok(self.conn.execute(
+            "DELETE FROM messages WHERE created_at > 0 AND created_at < ?1",
+            [cutoff as i64 * 1000],
+        )?)
+    }
+
+    /// Copies the rooms of a JSON store (`enable_conversation_persistence`) into an empty
+    /// database and renames the file to `<name>.migrated`; returns how many rooms came over.
+    /// A database that already has messages is left alone.
+    pub(crate) fn import_json(
+        &mut self,
+        path: &Path,
+        history: HashMap<(String, String), Vec<StoredMessage>>,
+    ) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+        if count > 0 {
+            return Ok(0);
+        }
+        let tx = self.conn.transaction()?;
+        for (room, messages) in &history {
+            insert(&tx, room, messages, self.key.as_ref())?;
+        }
+        tx.commit()?;
+        let mut migrated = path.as_os_str().to_owned();
+        migrated.push(".migrated");
+        fs::rename(path, PathBuf::from(migrated))?;
+        Ok(history.len())
+    }
+}
+
+fn insert(
+    conn: &Connection,
+    key: &(String, String),
+    messages: &[StoredMessage],
+    storage_key: Option<&StorageKey>,
+) -> Result<()> {
+    let mut statement = conn.prepare_cached(
+        "INSERT INTO messages (workspace_id, room_id, role, content, created_at, action)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    let now = unix_millis();
+    let room = row_key(&key.0, &key.1);
+    for stored in messages {
+        // Messages recorded just now keep millisecond order; older ones have only seconds.
+        let created_at = match stored.at {
+            Some(at) if now / 1000 == at as i64 => now,
+            Some(at) => at as i64 * 1000,
+            None => 0,
+        };
+        statement.execute(params![
+            key.0,
+            key.1,
+            role_name(stored.message.role),
+            seal_text(storage_key, row_record(&room), stored.message.content.clone())?,
+            created_at,
+            stored.action
+        ])?;
+    }
+    Ok(())
+}
+
+/// A row's content is sealed for its room, so it cannot be moved to another.
+fn row_key(workspace_id: &str, room_id: &str) -> String {
+    format!("{workspace_id}\0{room_id}")
+}
+
+fn row_record(room: &str) -> Record<'_> {
+    Record {
+        store: "conversations",
+        key: room,
+    }
+}
+
+/// Reads `role, content, created_at, action` starting at column `first`.
+/// `content` is as stored; see `ConversationDb::opened`.
+fn stored_message(row: &rusqlite::Row, first: usize) -> rusqlite::Result<StoredMessage> {
+    let role: String = row.get(first)?;
+    let created_at: i64 = row.get(first + 2)?;
+    Ok(StoredMessage {
+        message: AiChatMessage {
+            role: parse_role(&role, first)?,
+            content: row.get(first + 1)?,
+        },
+        at: (created_at > 0).then_some((created_at / 1000) as u64),
+        action: row.get(first + 3)?,
+    })
+}
+
+fn role_name(role: AiChatRole) -> &'static str {
+    match role {
+        AiChatRole::User => "user",
+        AiChatRole::Assistant => "assistant",
+    }
+}
+
+fn parse_role(name: &str, column: usize) -> rusqlite::Result<AiChatRole> {
+    match name {
+        "user" => Ok(AiChatRole::User),
+        "assistant" => Ok(AiChatRole::Assistant),
+        other => Err(rusqlite::Error::InvalidColumnType(
+            column,
+            format!("role {other:?}"),
+            rusqlite::types::Type::Text,
+        )),
+    }
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or(0)
+}
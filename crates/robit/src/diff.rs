@@ -0,0 +1,117 @@
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 2;
+/// Above this many cells the changed middle is shown as removed then added, unaligned.
+const MAX_TABLE: usize = 1_000_000;
+/// Longer lines are cut in the rendered diff.
+const MAX_LINE_CHARS: usize = 200;
+
+pub(crate) struct LineDiff {
+    pub(crate) added: usize,
+    pub(crate) removed: usize,
+    /// Changed lines as `+` / `-`, with a little unchanged context as ` ` and `…` between
+    /// distant changes. Empty when nothing changed.
+    pub(crate) text: String,
+}
+
+enum Op<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Diffs `old` and `new` line by line; the text stops after `max_lines` lines.
+pub(crate) fn line_diff(old: &str, new: &str, max_lines: usize) -> LineDiff {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut ops: Vec<Op> = old[..prefix].iter().map(|line| Op::Same(line)).collect();
+    ops.extend(middle_ops(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]));
+    ops.extend(old[old.len() - suffix..].iter().map(|line| Op::Same(line)));
+
+    let added = ops.iter().filter(|op| matches!(op, Op::Added(_))).count();
+    let removed = ops.iter().filter(|op| matches!(op, Op::Removed(_))).count();
+    LineDiff {
+        added,
+        removed,
+        text: render(&ops, max_lines),
+    }
+}
+
+/// Longest-common-subsequence alignment of the lines that differ.
+fn middle_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    if old.len().saturating_mul(new.len()) > MAX_TABLE {
+        let mut ops: Vec<Op> = old.iter().map(|line| Op::Removed(line)).collect();
+        ops.extend(new.iter().map(|line| Op::Added(line)));
+        return ops;
+    }
+    let width = new.len() + 1;
+    // lcs[i * width + j]: common lines of old[i..] and new[j..].
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            ops.push(Op::Removed(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Added(new[j]));
+            j += 1;
+        }
+    }
+    ops
+}
+
+fn render(ops: &[Op], max_lines: usize) -> String {
+    let mut shown = vec![false; ops.len()];
+    for (index, op) in ops.iter().enumerate() {
+        if !matches!(op, Op::Same(_)) {
+            let end = (index + CONTEXT + 1).min(ops.len());
+            shown[index.saturating_sub(CONTEXT)..end].fill(true);
+        }
+    }
+    let mut lines = Vec::new();
+    let mut last_shown = None;
+    for (index, op) in ops.iter().enumerate().filter(|(index, _)| shown[*index]) {
+        if last_shown.is_some_and(|last| last + 1 < index) {
+            lines.push("…".to_string());
+        }
+        last_shown = Some(index);
+        let (mark, full) = match op {
+            Op::Same(line) => (' ', *line),
+            Op::Removed(line) => ('-', *line),
+            Op::Added(line) => ('+', *line),
+        };
+        let mut line: String = full.chars().take(MAX_LINE_CHARS).collect();
+        if line.len() < full.len() {
+            line.push('…');
+        }
+        lines.push(format!("{mark}{line}"));
+        if lines.len() > max_lines {
+            break;
+        }
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        lines.push("… (diff cut short)".to_string());
+    }
+    lines.join("\n")
+}
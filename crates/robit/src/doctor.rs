@@ -0,0 +1,248 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::ai::MLX_MODEL_FILES;
+use crate::config::{self, AiModelEntry};
+use crate::engine::{model_loader_for, Engine};
+use crate::paths::RobitPaths;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but something is missing that some actions or setups need.
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// What `robit doctor` found, one line per check.
+#[derive(Clone, Debug, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<DoctorCheck>,
+}
+
+impl DoctorReport {
+    /// No check failed; warnings are allowed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != CheckStatus::Fail)
+    }
+
+    fn push(&mut self, name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) {
+        self.checks.push(DoctorCheck {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        });
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|check| check.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            writeln!(f, "{}  {:width$}  {}", check.status, check.name, check.detail)?;
+        }
+        let count = |status| self.checks.iter().filter(|check| check.status == status).count();
+        write!(
+            f,
+            "{} passed, {} warning(s), {} failed",
+            count(CheckStatus::Pass),
+            count(CheckStatus::Warn),
+            count(CheckStatus::Fail)
+        )
+    }
+}
+
+/// Checks the config files, the allowed roots, each AI backend, the state directory and the
+/// programs actions start. With `ping`, HTTP backends get a one-word request, which costs a
+/// few tokens; MLX models are never loaded, only their files checked.
+pub fn run(engine: &Engine, paths: Option<&RobitPaths>, ping: bool) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    check_config(engine, &mut report);
+    check_roots(engine.allowed_roots(), &mut report);
+    match config::ai_entries(engine.profile()) {
+        Ok(entries) if entries.is_empty() => report.push(
+            "ai",
+            CheckStatus::Warn,
+            "no [ai] provider or [[ai.models]]; only rules and commands plan",
+        ),
+        Ok(entries) => {
+            for entry in entries {
+                check_ai_entry(entry, ping, &mut report);
+            }
+        }
+        // Already reported by the config check.
+        Err(_) => {}
+    }
+    match paths {
+        Some(paths) => check_state_dir(&paths.data_dir, &mut report),
+        None => report.push(
+            "state directory",
+            CheckStatus::Warn,
+            "no home directory or ROBIT_HOME; nothing is saved",
+        ),
+    }
+    check_programs(&mut report);
+    report
+}
+
+fn check_config(engine: &Engine, report: &mut DoctorReport) {
+    let problems = engine.validate_config();
+    if problems.is_empty() {
+        report.push("config", CheckStatus::Pass, "config files parse and validate");
+    } else {
+        report.push("config", CheckStatus::Fail, problems.join("; "));
+    }
+}
+
+fn check_roots(roots: &[PathBuf], report: &mut DoctorReport) {
+    if roots.is_empty() {
+        report.push("allowed roots", CheckStatus::Warn, "none; file actions are refused");
+        return;
+    }
+    let missing: Vec<String> = roots
+        .iter()
+        .filter(|root| !root.is_dir())
+        .map(|root| root.display().to_string())
+        .collect();
+    if missing.is_empty() {
+        let roots: Vec<String> = roots.iter().map(|root| root.display().to_string()).collect();
+        report.push("allowed roots", CheckStatus::Pass, roots.join(", "));
+    } else {
+        report.push(
+            "allowed roots",
+            CheckStatus::Fail,
+            format!("not a directory: {}", missing.join(", ")),
+        );
+    }
+}
+
+fn check_ai_entry(entry: AiModelEntry, ping: bool, report: &mut DoctorReport) {
+    let name = format!("ai {}", entry.name);
+    if matches!(entry.provider.as_str(), "omnix-mlx" | "mlx") {
+        let Some(dir) = entry.model_dir.as_ref() else {
+            report.push(name, CheckStatus::Fail, "provider omnix-mlx needs model_dir");
+            return;
+        };
+        let missing: Vec<&str> = MLX_MODEL_FILES
+            .iter()
+            .copied()
+            .filter(|file| !dir.join(file).is_file())
+            .collect();
+        if !missing.is_empty() {
+            report.push(
+                name,
+                CheckStatus::Fail,
+                format!("{} lacks {}", dir.display(), missing.join(", ")),
+            );
+        } else if cfg!(not(feature = "ai-omnix-mlx")) {
+            report.push(
+                name,
+                CheckStatus::Warn,
+                format!(
+                    "model files present in {}, but robit was built without `ai-omnix-mlx`",
+                    dir.display()
+                ),
+            );
+        } else {
+            let detail = format!("model files present in {}", dir.display());
+            report.push(name, CheckStatus::Pass, detail);
+        }
+        return;
+    }
+    let backend = match model_loader_for(entry).and_then(|load| load()) {
+        Ok(backend) => backend,
+        Err(err) => {
+            report.push(name, CheckStatus::Fail, format!("{err:#}"));
+            return;
+        }
+    };
+    if !ping {
+        report.push(name, CheckStatus::Pass, "client built; not pinged");
+        return;
+    }
+    let started = Instant::now();
+    match backend.plan_with_history("ping", &[], &[]) {
+        Ok(_) => report.push(
+            name,
+            CheckStatus::Pass,
+            format!("answered in {} ms", started.elapsed().as_millis()),
+        ),
+        Err(err) => report.push(name, CheckStatus::Fail, format!("{err:#}")),
+    }
+}
+
+fn check_state_dir(dir: &Path, report: &mut DoctorReport) {
+    let probe = dir.join(".robit-doctor");
+    let writable = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b"ok"))
+        .and_then(|_| fs::remove_file(&probe));
+    match writable {
+        Ok(()) => report.push(
+            "state directory",
+            CheckStatus::Pass,
+            format!("{} is writable", dir.display()),
+        ),
+        Err(err) => report.push(
+            "state directory",
+            CheckStatus::Fail,
+            format!("{} is not writable: {err}", dir.display()),
+        ),
+    }
+}
+
+/// The programs actions start, and what goes without them.
+fn check_programs(report: &mut DoctorReport) {
+    let programs: &[(&str, CheckStatus, &str)] = if cfg!(windows) {
+        &[
+            ("cmd", CheckStatus::Fail, "shell.run"),
+            ("cargo", CheckStatus::Warn, "cargo commands in shell.run"),
+        ]
+    } else {
+        &[
+            ("sh", CheckStatus::Fail, "shell.run"),
+            ("open", CheckStatus::Warn, "browser.open_url"),
+            ("cargo", CheckStatus::Warn, "cargo commands in shell.run"),
+        ]
+    };
+    for (program, missing_status, needed_by) in programs {
+        let name = format!("program {program}");
+        match find_program(program) {
+            Some(path) => report.push(name, CheckStatus::Pass, path.display().to_string()),
+            None => {
+                report.push(name, *missing_status, format!("not on PATH; needed by {needed_by}"))
+            }
+        }
+    }
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(name);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        let exe = candidate.with_extension("exe");
+        (cfg!(windows) && exe.is_file()).then_some(exe)
+    })
+}
@@ -0,0 +1,246 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+
+/// Marks a sealed text: the rest is base64 of the nonce and the ChaCha20-Poly1305 ciphertext.
+/// The associated data is this prefix, the store's name and the record's key, so a sealed text
+/// only opens in the place it was written for.
+const SEALED_PREFIX: &str = "robit-sealed:v1:";
+const KEY_LEN: usize = 32;
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+const NO_FEATURE: &str = "robit was built without the `encryption` feature";
+
+/// Encrypts the files that hold conversations, memory facts, approvals and the action history
+/// at rest, with ChaCha20-Poly1305 and a random nonce per write. Files written before a key was
+/// set still load, and are sealed the next time they are saved; once sealed, a store only
+/// loads sealed data (see `SealMark`).
+///
+/// `from_env` reads the key as base64 of 32 bytes from `ROBIT_STORAGE_KEY`, or from the output
+/// of `ROBIT_STORAGE_KEY_COMMAND`, so it can live in the OS keychain:
+///
+/// ```text
+/// ROBIT_STORAGE_KEY_COMMAND="security find-generic-password -w -s robit"   # macOS
+/// ROBIT_STORAGE_KEY_COMMAND="secret-tool lookup service robit"             # Linux
+/// ```
+#[derive(Clone)]
+pub struct StorageKey {
+    #[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+    key: [u8; KEY_LEN],
+}
+
+impl StorageKey {
+    /// `None` when neither variable is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        if let Some(key) = env::var("ROBIT_STORAGE_KEY").ok().filter(|key| !key.trim().is_empty()) {
+            return Self::from_base64(&key).context("ROBIT_STORAGE_KEY").map(Some);
+        }
+        let Some(command) = env::var("ROBIT_STORAGE_KEY_COMMAND")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let output = Command::new(shell)
+            .arg(flag)
+            .arg(&command)
+            .output()
+            .context("running ROBIT_STORAGE_KEY_COMMAND")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "ROBIT_STORAGE_KEY_COMMAND failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let key = String::from_utf8_lossy(&output.stdout);
+        Self::from_base64(&key).context("ROBIT_STORAGE_KEY_COMMAND output").map(Some)
+    }
+
+    pub fn from_base64(text: &str) -> Result<Self> {
+        if cfg!(not(feature = "encryption")) {
+            return Err(anyhow!("cannot use a storage key: {NO_FEATURE}"));
+        }
+        let bytes = BASE64
+            .decode(text.trim())
+            .map_err(|err| anyhow!("storage key is not base64: {err}"))?;
+        let key = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| anyhow!("storage key has {} bytes; expected {KEY_LEN}", bytes.len()))?;
+        Ok(Self { key })
+    }
+
+    /// A new random key, as base64 for `ROBIT_STORAGE_KEY`.
+    #[cfg(feature = "encryption")]
+    pub fn generate() -> Result<String> {
+        use ring::rand::{SecureRandom, SystemRandom};
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("no system randomness for a storage key"))?;
+        Ok(BASE64.encode(key))
+    }
+
+    #[cfg(feature = "encryption")]
+    fn seal(&self, aad: &[u8], plaintext: &str) -> Result<String> {
+        use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+        use ring::rand::{SecureRandom, SystemRandom};
+        let key = LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &self.key).map_err(|_| anyhow!("bad storage key"))?,
+        );
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("no system randomness for a nonce"))?;
+        let mut sealed = plaintext.as_bytes().to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("encryption failed"))?;
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&sealed);
+        Ok(format!("{SEALED_PREFIX}{}", BASE64.encode(out)))
+    }
+
+    #[cfg(feature = "encryption")]
+    fn open(&self, aad: &[u8], sealed: &str) -> Result<String> {
+        use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+        let key = LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &self.key).map_err(|_| anyhow!("bad storage key"))?,
+        );
+        let bytes = BASE64
+            .decode(sealed.trim())
+            .map_err(|err| anyhow!("sealed data is not base64: {err}"))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(anyhow!("sealed data is truncated"));
+        }
+        let (nonce, rest) = bytes.split_at(NONCE_LEN);
+        let mut rest = rest.to_vec();
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("bad nonce"))?;
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(aad), &mut rest)
+            .map_err(|_| anyhow!("cannot decrypt: wrong storage key, damaged or moved data"))?;
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn generate() -> Result<String> {
+        Err(anyhow!(NO_FEATURE))
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn seal(&self, _aad: &[u8], _plaintext: &str) -> Result<String> {
+        Err(anyhow!(NO_FEATURE))
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn open(&self, _aad: &[u8], _sealed: &str) -> Result<String> {
+        Err(anyhow!(NO_FEATURE))
+    }
+}
+
+impl std::fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StorageKey(..)")
+    }
+}
+
+/// Where a sealed text belongs: the store's name, e.g. `facts`, and the record's key within it,
+/// empty for stores sealed whole.
+#[derive(Clone, Copy)]
+pub(crate) struct Record<'a> {
+    pub(crate) store: &'a str,
+    pub(crate) key: &'a str,
+}
+
+impl<'a> Record<'a> {
+    pub(crate) const fn whole(store: &'a str) -> Self {
+        Self { store, key: "" }
+    }
+
+    fn aad(&self) -> Vec<u8> {
+        format!("{SEALED_PREFIX}{}\0{}", self.store, self.key).into_bytes()
+    }
+}
+
+/// `text` sealed with `key` for `record`, or as is without a key.
+pub(crate) fn seal_text(key: Option<&StorageKey>, record: Record, text: String) -> Result<String> {
+    match key {
+        Some(key) => key.seal(&record.aad(), &text),
+        None => Ok(text),
+    }
+}
+
+/// Reads what `seal_text` wrote for the same `record`. Sealed text needs the key. Plain text
+/// passes through, unless a key is set and the store was `sealed_before`.
+pub(crate) fn open_text(
+    key: Option<&StorageKey>,
+    record: Record,
+    text: &str,
+    sealed_before: bool,
+) -> Result<String> {
+    let Some(sealed) = text.trim_start().strip_prefix(SEALED_PREFIX) else {
+        if key.is_some() && sealed_before {
+            return Err(anyhow!(
+                "found plain data in a store that was sealed before; it was replaced or rolled back"
+            ));
+        }
+        return Ok(text.to_string());
+    };
+    match key {
+        Some(key) => key.open(&record.aad(), sealed),
+        None => Err(anyhow!(
+            "data is encrypted; set ROBIT_STORAGE_KEY or ROBIT_STORAGE_KEY_COMMAND"
+        )),
+    }
+}
+
+pub(crate) fn is_sealed(text: &str) -> bool {
+    text.trim_start().starts_with(SEALED_PREFIX)
+}
+
+/// Records that a store was written sealed, as an empty `<store>.sealed` next to it. With a
+/// key set, plain data in such a store is refused rather than sealed: it can only be a file
+/// someone swapped in or an old copy. Only deleting it by hand clears the mark; stores that
+/// hold sealed records are treated as marked even without it.
+pub(crate) struct SealMark(PathBuf);
+
+impl SealMark {
+    pub(crate) fn for_store(store: &Path) -> Self {
+        let mut path = store.as_os_str().to_owned();
+        path.push(".sealed");
+        Self(PathBuf::from(path))
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.exists()
+    }
+
+    /// Sets the mark when the store is now written `sealed`. A plain write leaves it.
+    pub(crate) fn update(&self, sealed: bool) -> Result<()> {
+        if sealed && !self.is_set() {
+            if let Some(parent) = self.0.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.0, "")?;
+        }
+        Ok(())
+    }
+
+    /// Context for a refused load, naming the way out for a deliberate rollback.
+    pub(crate) fn refused(&self, store: &Path) -> String {
+        format!(
+            "{} (remove {} to accept a plain copy)",
+            store.display(),
+            self.0.display()
+        )
+    }
+}
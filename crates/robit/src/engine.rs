@@ -1,59 +1,185 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tracing::{debug, info, info_span, warn};
 
-use crate::adapter::Adapter;
-use crate::ai::{AiChatMessage, AiChatRole, AiDecision, AiPlanner};
-use crate::preflight::{PreflightConfig, PreflightEngine, PreflightReport};
+use crate::adapter::{Adapter, AdapterPoll};
+use crate::ai::{
+    AiChatMessage, AiChatRole, AiDecision, AiPlanner, AiRequestOptions, AiTask, AiUsage,
+};
+use crate::preflight::{
+    CheckId, PreflightCheck, PreflightConfig, PreflightEngine, PreflightReport, Severity,
+};
 use crate::protocol::{
-    ActionListResultPayload, ApprovalDecisionPayload, ConfigMode, ConfigUpdatePayload,
-    ProtocolBody, ProtocolEvent, ResponsePayload, RoomScopePayload,
+    ActionListResultPayload, ApprovalDecisionPayload, Attachment, CapabilitiesPayload, ConfigMode,
+    ConfigReloadedPayload, ConfigResultPayload, ConfigScope, ConfigUpdatePayload,
+    ConversationDocument, ErrorPayload,
+    HelloPayload, ImportRequestPayload, ImportResultPayload, MemoryFact, ProtocolBody, ProtocolEvent, ProviderBinding,
+    Quotas, RateLimits, ResponsePayload, RiskPolicy, RoomScopePayload, HistoryResultPayload, RoomUsage,
+    SearchHit, SearchResultPayload, StatusResultPayload, UsageResultPayload, CONVERSATION_FORMAT, SCHEMA_VERSIONS,
 };
-use crate::policy::ActionContext;
+use crate::encryption::{open_text, seal_text, Record, SealMark, StorageKey};
+use crate::error::RobitError;
+use crate::facts::{extract_facts, FactStore, UserFact};
+use crate::policy::{ActionContext, Role};
 use crate::types::{
-    ActionOutcome, ActionRequest, ActionSpec, InboundMessage, OutboundMessage, PlannerResponse,
-    PlanStep, RiskLevel,
+    ActionOutcome, ActionRequest, ActionSpec, ForEach, InboundMessage, OutboundMessage,
+    PlannerResponse, PlanStep, RiskLevel, UndoStep,
 };
-use crate::config;
-use crate::{ActionRegistry, Policy, RulePlanner};
+use crate::utils::{clean_path, expand_glob, expand_tilde, format_bytes, guess_mime};
+use crate::config::{self, ConfigSource};
+use crate::format::{self, ApprovalPrompt, CriticNote, PlanNote, ReplyFormat};
+use crate::memory::{MemoryIndex, MemoryKind};
+use crate::metrics::EngineMetrics;
+use crate::builder::EngineBuilder;
+use crate::history::{ActionHistory, ActionRecord};
+use crate::hooks::{ActionEvent, ApprovalEvent, EngineHook, HookChain, PlanEvent};
+use crate::i18n::{fill, Locale};
+use crate::middleware::{Middleware, MiddlewareChain, MiddlewareFlow};
+use crate::plan_template::{PlanTemplate, PlanTemplateStore};
+use crate::planner::{
+    heuristic_plan_for, match_probe, parse_kv_params, CommandPlanner, HostOs, Planner,
+    PlannerChain, Probe, SlashPlanner,
+};
+use crate::schedule::{ScheduleBook, ScheduleCreateAction, SCHEDULE_CREATE};
+use crate::quota::QuotaTracker;
+use crate::redact::Redactor;
+use crate::replay::{RecordedAiCall, RecordedTurn, ReplayInput, ReplyDecision, SessionLog};
+use crate::vars::VarStore;
+use crate::{ActionHandler, ActionRegistry, Policy, RulePlanner};
 
 struct PendingAction {
     request: ActionRequest,
     spec: ActionSpec,
-    sender: String,
+    /// The message that asked for the action; expiry notices reply to it.
+    origin: InboundMessage,
     config: RoomConfig,
     plan: Option<PlanContext>,
+    created_at: Instant,
+    expires_at: Option<Instant>,
+    /// Approvers so far, when the action needs more than one.
+    approved_by: Vec<String>,
+}
+
+enum ApproverCheck {
+    Proceed,
+    Forbidden(String),
+    Waiting(String),
 }
 
 #[derive(Clone)]
 struct PendingInput {
     action: String,
     params: serde_json::Value,
+    /// Asked for in order, one reply each.
     missing: Vec<String>,
+    filled: Vec<String>,
     prompt: String,
 }
 
+/// What a reply to a `need_input` prompt led to.
+enum PendingReply {
+    Ready(ActionRequest),
+    /// The input is kept and the next question asked, or the same one again.
+    Ask(PendingInput, String),
+    Cancelled,
+    /// Nothing usable in the reply; the input goes to the AI along with it.
+    Unresolved(PendingInput),
+}
+
+/// What one sender has set up in one room. Kept apart so one user's `dry-run off` or
+/// `trust` never changes how the engine treats anyone else.
+#[derive(Default)]
+struct Session {
+    dry_run: Option<bool>,
+    trust_until: Option<Instant>,
+    pending_input: Option<PendingInput>,
+    plan_run: Option<PendingPlanRun>,
+}
+
+impl Session {
+    fn is_empty(&self) -> bool {
+        self.dry_run.is_none()
+            && self.trust_until.is_none()
+            && self.pending_input.is_none()
+            && self.plan_run.is_none()
+    }
+}
+
+const SEEN_MESSAGES_CAPACITY: usize = 10_000;
+/// Long replies kept for `show more`; the oldest is dropped first.
+const RETAINED_REPLIES: usize = 50;
+/// Blocked actions `explain` can still describe.
+const BLOCKED_REPORTS: usize = 50;
+const DEFAULT_APPROVAL_TTL: Duration = Duration::from_secs(30 * 60);
+const MAX_TRUST_WINDOW: Duration = Duration::from_secs(8 * 3600);
+/// Largest attachment sent or accepted inline as base64.
+const DEFAULT_INLINE_ATTACHMENT_BYTES: u64 = 1024 * 1024;
+/// How long conversation changes wait before the JSON file is rewritten, so a busy room
+/// costs one write per interval instead of one per message.
+const CONVERSATION_SAVE_DELAY: Duration = Duration::from_secs(2);
+/// Matches `search` shows.
+pub(crate) const SEARCH_LIMIT: usize = 20;
+/// Characters of an action's outcome data kept in the conversation for the model.
+const ACTION_RESULT_CHARS: usize = 1500;
+/// Per room, unless `[conversation] max_messages` says otherwise.
+const DEFAULT_CONVERSATION_MESSAGES: usize = 50;
+/// How often `tick` applies `[conversation] max_age` to the SQLite store.
+const CONVERSATION_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+const SEARCH_SNIPPET_CHARS: usize = 160;
+
+/// The last action in a room that `undo` can reverse.
+struct UndoableAction {
+    action: String,
+    summary: String,
+    undo: UndoStep,
+}
+
+/// A `plan run` waiting for placeholder values.
+struct PendingPlanRun {
+    template: PlanTemplate,
+    values: HashMap<String, String>,
+    missing: Vec<String>,
+}
+
 #[derive(Clone)]
 struct PlanResultItem {
+    /// Step id, or `s<n>` for steps without one; referenced as `{{steps.<step>...}}`.
+    step: String,
     action: String,
     summary: String,
     data: serde_json::Value,
+    attempts: u32,
 }
 
 #[derive(Clone)]
 struct PlanProgress {
     id: String,
+    /// (workspace, room) the plan runs in.
+    room: (String, String),
     total_steps: usize,
     results: Vec<PlanResultItem>,
+    /// The step that stopped the plan, with its attempt count.
+    failure: Option<String>,
+    /// Undo steps of completed steps, in execution order.
+    compensations: Vec<PlanStep>,
+    deadline: Option<Duration>,
+    /// Time spent executing steps so far.
+    busy: Duration,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct PlanContext {
     plan_id: String,
+    step_key: String,
+    /// The step awaiting approval.
+    step: PlanStep,
     remaining: Vec<PlanStep>,
     auto_approve: bool,
     completed_steps: usize,
@@ -64,6 +190,29 @@ struct ApprovalStore {
     next_id: u64,
     pending: HashMap<String, PendingAction>,
     latest_by_sender: HashMap<String, String>,
+    persist_path: Option<PathBuf>,
+    key: Option<StorageKey>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedApproval {
+    id: String,
+    request: ActionRequest,
+    action: String,
+    origin: InboundMessage,
+    config: RoomConfig,
+    plan: Option<PlanContext>,
+    /// Unix seconds.
+    created_at: u64,
+    expires_at: Option<u64>,
+    #[serde(default)]
+    approved_by: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedApprovals {
+    next_id: u64,
+    approvals: Vec<PersistedApproval>,
 }
 
 impl ApprovalStore {
@@ -72,12 +221,73 @@ impl ApprovalStore {
             next_id: 1,
             pending: HashMap::new(),
             latest_by_sender: HashMap::new(),
+            persist_path: None,
+            key: None,
+        }
+    }
+
+    fn insert(&mut self, id: String, pending: PendingAction) {
+        self.latest_by_sender
+            .insert(pending.origin.sender.clone(), id.clone());
+        self.pending.insert(id, pending);
+    }
+
+    fn read_persisted(&self, path: &Path) -> Result<Option<PersistedApprovals>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mark = SealMark::for_store(path);
+        let record = Record::whole("approvals");
+        let content = fs::read_to_string(path)?;
+        let content = open_text(self.key.as_ref(), record, &content, mark.is_set())
+            .with_context(|| mark.refused(path))?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.persist_path else {
+            return;
+        };
+        if let Err(err) = self.save_to_path(path) {
+            warn!("approvals save failed: {err}");
+        }
+    }
+
+    fn save_to_path(&self, path: &Path) -> Result<()> {
+        let now = unix_now();
+        let mut approvals: Vec<PersistedApproval> = self
+            .pending
+            .iter()
+            .map(|(id, pending)| PersistedApproval {
+                id: id.clone(),
+                request: pending.request.clone(),
+                action: pending.spec.name.clone(),
+                origin: pending.origin.clone(),
+                config: pending.config.clone(),
+                plan: pending.plan.clone(),
+                created_at: now.saturating_sub(pending.created_at.elapsed().as_secs()),
+                expires_at: pending.expires_at.map(|at| {
+                    now + at.saturating_duration_since(Instant::now()).as_secs()
+                }),
+                approved_by: pending.approved_by.clone(),
+            })
+            .collect();
+        approvals.sort_by_key(|item| item.created_at);
+        let store = PersistedApprovals {
+            next_id: self.next_id,
+            approvals,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        let data = serde_json::to_string_pretty(&store)?;
+        fs::write(path, seal_text(self.key.as_ref(), Record::whole("approvals"), data)?)?;
+        SealMark::for_store(path).update(self.key.is_some())
     }
 
     fn create(
         &mut self,
-        sender: &str,
+        origin: &InboundMessage,
         request: ActionRequest,
         spec: ActionSpec,
         config: RoomConfig,
@@ -85,44 +295,312 @@ impl ApprovalStore {
     ) -> String {
         let id = format!("appr-{}", self.next_id);
         self.next_id += 1;
-        self.pending.insert(
+        let created_at = Instant::now();
+        let expires_at = config.approval_ttl().map(|ttl| created_at + ttl);
+        self.insert(
             id.clone(),
             PendingAction {
                 request,
                 spec,
-                sender: sender.to_string(),
+                origin: origin.clone(),
                 config,
                 plan,
+                created_at,
+                expires_at,
+                approved_by: Vec::new(),
             },
         );
-        self.latest_by_sender
-            .insert(sender.to_string(), id.clone());
+        self.save();
         id
     }
 
     fn take(&mut self, id: &str) -> Option<PendingAction> {
         if let Some(pending) = self.pending.remove(id) {
-            self.latest_by_sender.remove(&pending.sender);
+            self.latest_by_sender.remove(&pending.origin.sender);
+            self.save();
             return Some(pending);
         }
         None
     }
 
+    /// Removes approvals whose TTL has passed.
+    fn expire(&mut self, now: Instant) -> Vec<(String, PendingAction)> {
+        let ids: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.expires_at.is_some_and(|at| at <= now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut expired = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(pending) = self.pending.remove(&id) {
+                let sender = &pending.origin.sender;
+                if self.latest_by_sender.get(sender) == Some(&id) {
+                    self.latest_by_sender.remove(sender);
+                }
+                expired.push((id, pending));
+            }
+        }
+        if !expired.is_empty() {
+            self.save();
+        }
+        expired.sort_by_key(|(_, pending)| pending.created_at);
+        expired
+    }
+
+    /// Pending approvals requested in a room, oldest first.
+    fn in_room(&self, workspace_id: Option<&str>, channel: &str) -> Vec<(&String, &PendingAction)> {
+        let mut items: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| {
+                pending.origin.channel == channel
+                    && pending.origin.workspace_id.as_deref() == workspace_id
+            })
+            .collect();
+        items.sort_by_key(|(_, pending)| pending.created_at);
+        items
+    }
+
     fn latest_for_sender(&self, sender: &str) -> Option<String> {
         self.latest_by_sender.get(sender).cloned()
     }
 }
 
+/// The unsent rest of a paged reply.
+struct RetainedReply {
+    id: String,
+    room: (String, String),
+    rest: String,
+    page: usize,
+}
+
+#[derive(Default)]
+struct RetainedReplies {
+    items: VecDeque<RetainedReply>,
+}
+
+impl RetainedReplies {
+    fn insert(&mut self, reply: RetainedReply) {
+        if self.items.len() >= RETAINED_REPLIES {
+            self.items.pop_front();
+        }
+        self.items.push_back(reply);
+    }
+
+    /// The named reply, or the room's most recent one.
+    fn take(&mut self, room: &(String, String), id: Option<&str>) -> Option<RetainedReply> {
+        let index = self.items.iter().rposition(|item| {
+            &item.room == room && id.is_none_or(|id| item.id == id)
+        })?;
+        self.items.remove(index)
+    }
+}
+
+/// A recently blocked action's preflight report, kept for `explain`.
+struct BlockedReport {
+    id: String,
+    room: (String, String),
+    report: PreflightReport,
+}
+
+#[derive(Default)]
+struct BlockedReports {
+    items: VecDeque<BlockedReport>,
+    next_id: u64,
+}
+
+impl BlockedReports {
+    /// Keeps `report` and returns `text` pointing at it.
+    fn keep(&mut self, room: (String, String), report: PreflightReport, text: String) -> String {
+        if self.items.len() >= BLOCKED_REPORTS {
+            self.items.pop_front();
+        }
+        self.next_id += 1;
+        let id = format!("blk-{}", self.next_id);
+        let text = format!("{text} (explain {id})");
+        self.items.push_back(BlockedReport { id, room, report });
+        text
+    }
+
+    /// The named report, or the room's most recent one.
+    fn get(&self, room: &(String, String), id: Option<&str>) -> Option<&BlockedReport> {
+        self.items
+            .iter()
+            .rev()
+            .find(|item| &item.room == room && id.is_none_or(|id| item.id == id))
+    }
+}
+
+/// Recently handled protocol message ids; the oldest is forgotten once `capacity` is
+/// reached. When persisted, ids are appended to a file one per line, and the file is
+/// rewritten with just the remembered ids once it grows past twice the capacity.
+struct SeenMessages {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+    persist_path: Option<PathBuf>,
+    persisted_lines: usize,
+}
+
+impl SeenMessages {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+            persist_path: None,
+            persisted_lines: 0,
+        }
+    }
+
+    /// Records `id`; false when it was already seen.
+    fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        self.remember(id.to_string());
+        if let Err(err) = self.persist(id) {
+            warn!("seen messages save failed: {err}");
+        }
+        true
+    }
+
+    fn remember(&mut self, id: String) {
+        while self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.clone());
+        self.order.push_back(id);
+    }
+
+    fn load_from_path(&mut self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(path)?;
+        for id in content.lines().filter(|line| !line.is_empty()) {
+            self.persisted_lines += 1;
+            if !self.ids.contains(id) {
+                self.remember(id.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self, id: &str) -> Result<()> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        if id.contains('\n') {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if self.persisted_lines >= self.capacity * 2 {
+            let mut data = self
+                .order
+                .iter()
+                .filter(|id| !id.contains('\n'))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join("\n");
+            data.push('\n');
+            fs::write(path, data)?;
+            self.persisted_lines = self.order.len();
+            return Ok(());
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{id}")?;
+        self.persisted_lines += 1;
+        Ok(())
+    }
+}
+
 struct ConversationStore {
     max_messages: usize,
-    history: HashMap<(String, String), Vec<AiChatMessage>>,
+    history: HashMap<(String, String), Vec<StoredMessage>>,
+    /// Seals the JSON file.
+    key: Option<StorageKey>,
+}
+
+/// A conversation message and when it was recorded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StoredMessage {
+    #[serde(flatten)]
+    pub(crate) message: AiChatMessage,
+    /// Unix seconds; `None` for messages imported or saved before timestamps were kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) at: Option<u64>,
+    /// Set on a tool result: the action whose outcome data `content` holds, as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) action: Option<String>,
+}
+
+impl StoredMessage {
+    fn now(role: AiChatRole, content: &str) -> Self {
+        Self {
+            message: AiChatMessage {
+                role,
+                content: content.to_string(),
+            },
+            at: Some(unix_now()),
+            action: None,
+        }
+    }
+
+    /// The outcome data of an `action_result` reply, trimmed to `ACTION_RESULT_CHARS`, so a
+    /// follow-up such as "delete the ones you listed" can refer to it.
+    fn action_result(reply: &OutboundMessage) -> Option<Self> {
+        if reply.metadata.get("kind").and_then(|kind| kind.as_str()) != Some("action_result") {
+            return None;
+        }
+        let action = reply.metadata.get("action")?.as_str()?;
+        let data = reply.metadata.get("data").filter(|data| !data.is_null())?;
+        let data = data.to_string();
+        let mut content: String = data.chars().take(ACTION_RESULT_CHARS).collect();
+        if content.len() < data.len() {
+            content.push_str("…(truncated)");
+        }
+        Some(Self {
+            action: Some(action.to_string()),
+            ..Self::now(AiChatRole::Assistant, &content)
+        })
+    }
+
+    /// What the model sees.
+    fn chat_message(&self) -> AiChatMessage {
+        match &self.action {
+            Some(action) => AiChatMessage {
+                role: self.message.role,
+                content: format!("[{action} result] {}", self.message.content),
+            },
+            None => self.message.clone(),
+        }
+    }
+}
+
+/// Where conversation history is saved.
+enum ConversationPersistence {
+    /// Every room in one JSON file, rewritten by `tick` once changes are
+    /// `CONVERSATION_SAVE_DELAY` old, and on `flush_conversations`.
+    Json(PathBuf),
+    /// One row per message.
+    #[cfg(feature = "sqlite")]
+    Sqlite(crate::conversation_db::ConversationDb),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct PersistedConversation {
     workspace_id: String,
     room_id: String,
-    messages: Vec<AiChatMessage>,
+    messages: Vec<StoredMessage>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -136,6 +614,7 @@ impl ConversationStore {
         Self {
             max_messages: max_messages.max(2),
             history: HashMap::new(),
+            key: None,
         }
     }
 
@@ -152,56 +631,165 @@ impl ConversationStore {
     }
 
     fn history_for(&self, key: &(String, String)) -> Vec<AiChatMessage> {
-        self.history.get(key).cloned().unwrap_or_default()
+        self.history
+            .get(key)
+            .map(|messages| messages.iter().map(StoredMessage::chat_message).collect())
+            .unwrap_or_default()
     }
 
+    /// Returns the messages added.
     fn record_exchange(
         &mut self,
         key: &(String, String),
         user_input: &str,
         replies: &[OutboundMessage],
-    ) {
-        let entry = self.history.entry(key.clone()).or_default();
-        entry.push(AiChatMessage {
-            role: AiChatRole::User,
-            content: user_input.trim().to_string(),
-        });
+    ) -> Vec<StoredMessage> {
+        let mut added = vec![StoredMessage::now(AiChatRole::User, user_input.trim())];
         for reply in replies {
-            if reply.text.trim().is_empty() {
-                continue;
+            if !reply.text.trim().is_empty() {
+                added.push(StoredMessage::now(AiChatRole::Assistant, reply.text.trim()));
             }
-            entry.push(AiChatMessage {
-                role: AiChatRole::Assistant,
-                content: reply.text.trim().to_string(),
-            });
+            added.extend(StoredMessage::action_result(reply));
         }
+        let entry = self.history.entry(key.clone()).or_default();
+        entry.extend(added.iter().cloned());
         if entry.len() > self.max_messages {
             let start = entry.len().saturating_sub(self.max_messages);
             entry.drain(0..start);
         }
+        added
     }
 
-    fn record_context(&mut self, key: &(String, String), role: AiChatRole, content: &str) {
+    /// Returns the message added, if `content` was not blank.
+    fn record_context(
+        &mut self,
+        key: &(String, String),
+        role: AiChatRole,
+        content: &str,
+    ) -> Option<StoredMessage> {
         let text = content.trim();
         if text.is_empty() {
-            return;
+            return None;
         }
+        let message = StoredMessage::now(role, text);
         let entry = self.history.entry(key.clone()).or_default();
-        entry.push(AiChatMessage {
-            role,
-            content: text.to_string(),
-        });
+        entry.push(message.clone());
         if entry.len() > self.max_messages {
             let start = entry.len().saturating_sub(self.max_messages);
             entry.drain(0..start);
         }
+        Some(message)
+    }
+
+    /// Puts `messages` before the room's current ones, or in their place with `replace`;
+    /// returns how many the room now has.
+    fn import(&mut self, key: &(String, String), messages: Vec<AiChatMessage>, replace: bool) -> usize {
+        let mut merged: Vec<StoredMessage> = messages
+            .into_iter()
+            .map(|message| StoredMessage {
+                message,
+                at: None,
+                action: None,
+            })
+            .collect();
+        if !replace {
+            merged.extend(self.history.get(key).into_iter().flatten().cloned());
+        }
+        if merged.len() > self.max_messages {
+            let start = merged.len().saturating_sub(self.max_messages);
+            merged.drain(0..start);
+        }
+        let count = merged.len();
+        self.history.insert(key.clone(), merged);
+        count
+    }
+
+    /// The messages of the rooms `rooms` accepts, merged oldest first.
+    fn room_messages(&self, rooms: impl Fn(&(String, String)) -> bool) -> Vec<StoredMessage> {
+        let mut messages: Vec<StoredMessage> = self
+            .history
+            .iter()
+            .filter(|(key, _)| rooms(key))
+            .flat_map(|(_, messages)| messages.iter().cloned())
+            .collect();
+        messages.sort_by_key(|stored| stored.at.unwrap_or(0));
+        messages
+    }
+
+    /// Trims every room to the new limit.
+    fn set_max_messages(&mut self, max_messages: usize) {
+        self.max_messages = max_messages.max(2);
+        for messages in self.history.values_mut() {
+            let start = messages.len().saturating_sub(self.max_messages);
+            messages.drain(0..start);
+        }
+    }
+
+    /// Drops the rooms `rooms` accepts; returns how many messages went.
+    fn clear(&mut self, rooms: impl Fn(&(String, String)) -> bool) -> usize {
+        let mut removed = 0;
+        self.history.retain(|key, messages| {
+            let keep = !rooms(key);
+            if !keep {
+                removed += messages.len();
+            }
+            keep
+        });
+        removed
+    }
+
+    /// Drops messages recorded before `cutoff` (Unix seconds); ones without a time stay.
+    fn prune_before(&mut self, cutoff: u64) -> usize {
+        let mut removed = 0;
+        for messages in self.history.values_mut() {
+            let before = messages.len();
+            messages.retain(|stored| stored.at.is_none_or(|at| at >= cutoff));
+            removed += before - messages.len();
+        }
+        self.history.retain(|_, messages| !messages.is_empty());
+        removed
+    }
+
+    /// Messages in rooms matching `rooms` that contain every term, newest first.
+    fn search(
+        &self,
+        rooms: impl Fn(&(String, String)) -> bool,
+        terms: &[String],
+        limit: usize,
+    ) -> Vec<SearchHit> {
+        let mut hits: Vec<SearchHit> = self
+            .history
+            .iter()
+            .filter(|(key, _)| rooms(key))
+            .flat_map(|(key, messages)| {
+                messages
+                    .iter()
+                    .rev()
+                    .filter(|stored| stored.action.is_none())
+                    .filter(|stored| matches_terms(&stored.message.content, terms))
+                    .map(|stored| SearchHit {
+                        workspace_id: key.0.clone(),
+                        room_id: key.1.clone(),
+                        role: stored.message.role,
+                        content: stored.message.content.clone(),
+                        at: stored.at,
+                    })
+            })
+            .collect();
+        hits.sort_by_key(|hit| std::cmp::Reverse(hit.at));
+        hits.truncate(limit);
+        hits
     }
 
     fn load_from_path(&mut self, path: &Path) -> Result<()> {
         if !path.exists() {
             return Ok(());
         }
+        let mark = SealMark::for_store(path);
+        let record = Record::whole("conversations");
         let content = fs::read_to_string(path)?;
+        let content = open_text(self.key.as_ref(), record, &content, mark.is_set())
+            .with_context(|| mark.refused(path))?;
         let store: PersistedStore = serde_json::from_str(&content)?;
         self.history.clear();
         for convo in store.conversations {
@@ -233,72 +821,297 @@ impl ConversationStore {
             conversations,
         };
         let data = serde_json::to_string_pretty(&store)?;
-        fs::write(path, data)?;
-        Ok(())
+        let data = seal_text(self.key.as_ref(), Record::whole("conversations"), data)?;
+        // Through a temporary file, so a crash mid-write keeps the previous history.
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, data)?;
+        fs::rename(&temp, path)?;
+        SealMark::for_store(path).update(self.key.is_some())
     }
 }
 
-#[derive(Clone, Copy)]
+/// The room id and profile in a conversation key's room part, e.g.
+/// `!abc:server::profile=work::ai=openai`.
+fn split_conversation_room(decorated: &str) -> (&str, Option<&str>) {
+    let mut parts = decorated.split("::");
+    let room = parts.next().unwrap_or_default();
+    (room, parts.find_map(|part| part.strip_prefix("profile=")))
+}
+
+/// Lowercased words of a search query.
+pub(crate) fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(str::to_lowercase).collect()
+}
+
+pub(crate) fn matches_terms(content: &str, terms: &[String]) -> bool {
+    let content = content.to_lowercase();
+    terms.iter().all(|term| content.contains(term.as_str()))
+}
+
+#[derive(Clone)]
+struct AiRoute {
+    backend: std::sync::Arc<dyn AiPlanner>,
+    label: Option<String>,
+}
+
+#[derive(Clone)]
 enum ApprovalDecision {
-    Approve,
-    ApproveAll,
-    Deny,
+    /// With param overrides, e.g. `approve appr-2 path=~/Documents`.
+    Approve(Option<serde_json::Value>),
+    ApproveAll(Option<serde_json::Value>),
+    /// With the user's reason, if they gave one.
+    Deny(Option<String>),
 }
 
+/// Builds a backend on demand so switching models only keeps one of them loaded.
+pub type AiModelLoader =
+    Box<dyn Fn() -> Result<std::sync::Arc<dyn AiPlanner>> + Send + Sync>;
+
 pub struct Engine {
     registry: ActionRegistry,
-    planner: RulePlanner,
+    planners: PlannerChain,
     ai_backend: Option<std::sync::Arc<dyn AiPlanner>>,
     ai_backend_label: Option<String>,
+    ai_routes: HashMap<AiTask, AiRoute>,
+    named_backends: HashMap<String, AiRoute>,
+    ai_models: HashMap<String, AiModelLoader>,
+    /// Names in `ai_models` that came from `[[ai.models]]`, replaced on reload.
+    file_ai_models: Vec<String>,
+    /// The `[ai] provider` settings last read; a reload that changes them replaces the
+    /// main backend.
+    ai_backend_entry: Option<config::AiModelEntry>,
+    active_model: Option<String>,
+    critic_enabled: bool,
+    ai_answered_by: Option<String>,
+    ai_turn_usage: Vec<AiUsage>,
+    usage: HashMap<(String, String), RoomUsage>,
+    metrics: EngineMetrics,
+    created_at: Instant,
+    memory: Option<MemoryIndex>,
     ctx: ActionContext,
     preflight: PreflightEngine,
     approvals: ApprovalStore,
     next_message_id: u64,
     next_plan_id: u64,
-    pending_inputs: HashMap<(String, String), PendingInput>,
     plans: HashMap<String, PlanProgress>,
-    seen_messages: HashSet<String>,
+    last_plans: HashMap<(String, String), Vec<PlanStep>>,
+    plan_templates: Option<PlanTemplateStore>,
+    schedules: ScheduleBook,
+    /// Per-sender state by (workspace, room, sender).
+    sessions: HashMap<(String, String, String), Session>,
+    limiter: RateLimiter,
+    quotas: QuotaTracker,
+    redactor: Redactor,
+    retained_replies: RetainedReplies,
+    blocked: BlockedReports,
+    /// By (workspace, room).
+    undoable: HashMap<(String, String), UndoableAction>,
+    history: ActionHistory,
+    vars: VarStore,
+    /// `alias` shortcuts, also per workspace.
+    aliases: VarStore,
+    facts: FactStore,
+    /// Picks facts out of ordinary messages; `remember` works either way.
+    fact_extraction: bool,
+    /// Page limit for rooms whose config sets none, usually the adapter's.
+    max_reply_chars: Option<usize>,
+    /// What the protocol client said it supports in its `Hello`.
+    peer: Option<HelloPayload>,
+    /// Where inline attachments on incoming messages are saved; without it they are refused.
+    attachment_dir: Option<PathBuf>,
+    inline_attachment_bytes: u64,
+    /// Reply format for rooms whose config sets none; the adapter's unless set explicitly.
+    reply_format: Option<ReplyFormat>,
+    /// Language for rooms whose config sets none.
+    locale: Locale,
+    /// Messages not tied to an inbound message, delivered by `tick`.
+    notices: Vec<OutboundMessage>,
+    seen_messages: SeenMessages,
     scope: RoomScope,
     config_store: ConfigStore,
+    /// Re-read for `[policy]` and `[preflight]` when it changes, or on `reload`.
+    config_source: ConfigSource,
+    /// False once an embedder sets preflight itself; reloads then leave it alone.
+    preflight_from_file: bool,
+    /// The latest reload triggered by a file change, for `tick_protocol`.
+    config_reloaded: Option<ConfigReloadedPayload>,
     conversations: ConversationStore,
-    conversation_persist_path: Option<PathBuf>,
+    conversation_persistence: Option<ConversationPersistence>,
+    /// When the oldest change not yet in the JSON file was made.
+    conversations_dirty_since: Option<Instant>,
+    /// `[conversation] max_age` in seconds.
+    conversation_max_age: Option<u64>,
+    conversations_pruned_at: Option<Instant>,
+    tick_interval: Duration,
+    /// Time limit for actions and plan steps that don't set their own.
+    action_timeout: Option<Duration>,
+    middleware: MiddlewareChain,
+    hooks: HookChain,
+    /// Kept for stores enabled after `set_storage_key`, like the session log.
+    storage_key: Option<StorageKey>,
+    session_log: Option<SessionLog>,
+    /// AI calls of the turn being recorded.
+    recorded_ai_calls: Vec<RecordedAiCall>,
+    /// Set by replays: every action runs as a dry run, whatever the room says.
+    force_dry_run: bool,
+    /// The last action run timed out and was still running; its plan offers no rollback.
+    timed_out_running: bool,
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.flush_conversations();
+    }
 }
 
 impl Engine {
+    /// The documented way to set up an engine; see `EngineBuilder`.
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
     pub fn new(registry: ActionRegistry, planner: RulePlanner, policy: Policy) -> Result<Self> {
+        Self::with_profile(registry, planner, policy, false, None)
+    }
+
+    /// Like `new`, with `[profiles.<profile>]` applied to the config files from the start.
+    /// With `keep_policy` the files' `[policy]` is ignored and `policy` is used as is.
+    pub(crate) fn with_profile(
+        registry: ActionRegistry,
+        planner: RulePlanner,
+        policy: Policy,
+        keep_policy: bool,
+        profile: Option<String>,
+    ) -> Result<Self> {
         let cwd = std::env::current_dir()?;
         let mut policy = policy;
         let mut preflight_config = PreflightConfig::default();
-        match config::load_default_config(policy.clone(), preflight_config.clone()) {
-            Ok((loaded_policy, loaded_preflight)) => {
-                policy = loaded_policy;
-                preflight_config = loaded_preflight;
+        let mut config_store = ConfigStore::default();
+        let mut ai_models = HashMap::new();
+        let mut file_ai_models = Vec::new();
+        let mut ai_backend_entry = None;
+        let mut critic_enabled = false;
+        let mut conversation_max_messages = DEFAULT_CONVERSATION_MESSAGES;
+        let mut conversation_max_age = None;
+        let mut schedules = Vec::new();
+        let mut redactor = Redactor::default();
+        let mut config_source = ConfigSource::new(policy.clone(), keep_policy, profile);
+        #[cfg(feature = "watch")]
+        if let Err(err) = config_source.watch() {
+            warn!("config watch failed: {err:#}; checking modification times instead");
+        }
+        match config_source.load(preflight_config.clone()) {
+            Ok(loaded) => {
+                policy = loaded.policy;
+                preflight_config = loaded.preflight;
+                ai_backend_entry = loaded.ai.backend_entry();
+                config_store.file_prompt_extra = loaded.ai.system_prompt_extra;
+                critic_enabled = loaded.ai.critic;
+                conversation_max_messages = loaded
+                    .conversation
+                    .max_messages
+                    .unwrap_or(DEFAULT_CONVERSATION_MESSAGES);
+                match loaded.conversation.max_age_secs() {
+                    Ok(max_age) => conversation_max_age = max_age,
+                    Err(err) => warn!("{err:#}; conversation history is kept"),
+                }
+                for pattern in &loaded.redaction.patterns {
+                    if let Err(err) = redactor.add_pattern(pattern) {
+                        warn!("{err}; pattern skipped");
+                    }
+                }
+                for entry in loaded.schedules {
+                    match entry.into_schedule(unix_now()) {
+                        Ok(schedule) => schedules.push(schedule),
+                        Err(err) => warn!("schedule skipped: {err:#}"),
+                    }
+                }
+                file_ai_models = load_ai_models(loaded.ai.models, &mut ai_models);
             }
             Err(err) => {
-                eprintln!("robit config load failed: {err}");
+                warn!("config load failed: {err}; `robit check-config` lists every problem");
             }
         }
+        let mut registry = registry;
+        registry.register(ScheduleCreateAction);
+        let mut planners = PlannerChain::default();
+        planners.push(Box::new(SlashPlanner::new()));
+        planners.push(Box::new(CommandPlanner::new()));
+        planners.push(Box::new(planner));
         Ok(Self {
             registry,
-            planner,
+            planners,
             ai_backend: None,
             ai_backend_label: None,
+            ai_routes: HashMap::new(),
+            named_backends: HashMap::new(),
+            ai_models,
+            file_ai_models,
+            ai_backend_entry,
+            active_model: None,
+            critic_enabled,
+            ai_answered_by: None,
+            ai_turn_usage: Vec::new(),
+            usage: HashMap::new(),
+            metrics: EngineMetrics::default(),
+            created_at: Instant::now(),
+            memory: None,
             ctx: ActionContext {
                 cwd,
                 dry_run: true,
                 policy,
+                backup_dir: None,
+                deadline: None,
+                preflight: None,
             },
             preflight: PreflightEngine::new(preflight_config),
             approvals: ApprovalStore::new(),
             next_message_id: 1,
             next_plan_id: 1,
-            pending_inputs: HashMap::new(),
             plans: HashMap::new(),
-            seen_messages: HashSet::new(),
+            last_plans: HashMap::new(),
+            plan_templates: None,
+            schedules: ScheduleBook::new(schedules),
+            sessions: HashMap::new(),
+            limiter: RateLimiter::default(),
+            quotas: QuotaTracker::default(),
+            redactor,
+            retained_replies: RetainedReplies::default(),
+            blocked: BlockedReports::default(),
+            undoable: HashMap::new(),
+            history: ActionHistory::default(),
+            vars: VarStore::default(),
+            facts: FactStore::default(),
+            fact_extraction: true,
+            aliases: VarStore::default(),
+            max_reply_chars: None,
+            peer: None,
+            attachment_dir: None,
+            inline_attachment_bytes: DEFAULT_INLINE_ATTACHMENT_BYTES,
+            reply_format: None,
+            locale: Locale::default(),
+            notices: Vec::new(),
+            seen_messages: SeenMessages::new(SEEN_MESSAGES_CAPACITY),
             scope: RoomScope::default(),
-            config_store: ConfigStore::default(),
-            conversations: ConversationStore::new(50),
-            conversation_persist_path: None,
+            config_store,
+            config_source,
+            preflight_from_file: true,
+            config_reloaded: None,
+            conversations: ConversationStore::new(conversation_max_messages),
+            conversation_persistence: None,
+            conversations_dirty_since: None,
+            conversation_max_age,
+            conversations_pruned_at: None,
+            tick_interval: Duration::from_secs(1),
+            action_timeout: None,
+            middleware: MiddlewareChain::default(),
+            hooks: HookChain::default(),
+            storage_key: None,
+            session_log: None,
+            recorded_ai_calls: Vec::new(),
+            force_dry_run: false,
+            timed_out_running: false,
         })
     }
 
@@ -315,184 +1128,2207 @@ impl Engine {
         self.ai_backend_label = label;
     }
 
-    #[cfg(feature = "ai-http")]
-    pub fn set_ai_client(&mut self, ai_client: Option<crate::ai::AiClient>) {
-        let label = ai_client
-            .as_ref()
-            .map(|client| format!("http:{}", client.model_name()));
-        let backend = ai_client.map(|client| {
-            std::sync::Arc::new(client) as std::sync::Arc<dyn AiPlanner>
-        });
-        self.set_ai_backend_with_label(backend, label);
-    }
-
-    pub fn enable_conversation_persistence(&mut self, path: PathBuf) {
-        self.conversation_persist_path = Some(path.clone());
-        if let Err(err) = self.conversations.load_from_path(&path) {
-            eprintln!("robit context load failed: {err}");
+    /// Routes one kind of AI call to a dedicated backend; `None` restores the main backend.
+    pub fn set_ai_route(
+        &mut self,
+        task: AiTask,
+        backend: Option<std::sync::Arc<dyn AiPlanner>>,
+        label: Option<String>,
+    ) {
+        match backend {
+            Some(backend) => {
+                self.ai_routes.insert(task, AiRoute { backend, label });
+            }
+            None => {
+                self.ai_routes.remove(&task);
+            }
         }
     }
 
-    pub fn set_preflight_config(&mut self, config: PreflightConfig) {
-        self.preflight.set_config(config);
+    /// Makes a backend selectable per room through `provider_binding.backend`.
+    pub fn register_ai_backend(
+        &mut self,
+        name: impl Into<String>,
+        backend: std::sync::Arc<dyn AiPlanner>,
+    ) {
+        let name = name.into();
+        self.named_backends.insert(
+            name.clone(),
+            AiRoute {
+                backend,
+                label: Some(name),
+            },
+        );
     }
 
-    fn log_preflight(&self, report: &PreflightReport) {
-        if let Ok(json) = serde_json::to_string(report) {
-            eprintln!("robit preflight: {json}");
-        }
+    /// Adds a model that `model use <name>` can switch the main backend to.
+    pub fn register_ai_model(&mut self, name: impl Into<String>, loader: AiModelLoader) {
+        self.ai_models.insert(name.into(), loader);
     }
 
-    fn conversation_key_for(&self, msg: &InboundMessage) -> (String, String) {
-        let (workspace_id, room_id) = self.conversations.key_for(msg);
-        self.decorate_conversation_key(workspace_id, room_id)
+    /// Replaces the main backend with a registered model. The old backend is dropped before
+    /// the new one loads, so a local MLX model is unloaded unless a route still holds it.
+    pub fn use_ai_model(&mut self, name: &str) -> Result<()> {
+        let Some(loader) = self.ai_models.get(name) else {
+            return Err(anyhow!(
+                "unknown model: {name} (available: {})",
+                self.model_names().join(", ")
+            ));
+        };
+        self.ai_backend = None;
+        self.ai_backend_label = None;
+        self.active_model = None;
+        let backend = loader().with_context(|| format!("loading model {name} failed"))?;
+        self.ai_backend = Some(backend);
+        self.ai_backend_label = Some(name.to_string());
+        self.active_model = Some(name.to_string());
+        Ok(())
     }
 
-    fn conversation_key_parts(&self, workspace_id: &str, room_id: &str) -> (String, String) {
-        self.decorate_conversation_key(workspace_id.to_string(), room_id.to_string())
+    fn model_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.ai_models.keys().cloned().collect();
+        names.sort();
+        names
     }
 
-    fn decorate_conversation_key(
-        &self,
-        workspace_id: String,
-        room_id: String,
-    ) -> (String, String) {
-        let decorated_room = if let Some(label) = self.ai_backend_label.as_deref() {
-            format!("{room_id}::ai={label}")
-        } else {
-            room_id
-        };
-        (workspace_id, decorated_room)
+    /// Reviews AI-proposed actions before the approval prompt, using the `AiTask::Critic`
+    /// route if one is set and the main backend otherwise.
+    pub fn set_critic_enabled(&mut self, enabled: bool) {
+        self.critic_enabled = enabled;
     }
 
-    pub fn handle_message(&mut self, msg: InboundMessage) -> Vec<OutboundMessage> {
-        self.handle_message_with_config(msg, None)
+    fn planning_backend_for(&self, room_cfg: &RoomConfig) -> Option<AiRoute> {
+        let bound = room_cfg
+            .provider_binding
+            .as_ref()
+            .and_then(|binding| binding.backend.as_deref());
+        if let Some(name) = bound {
+            match self.named_backends.get(name) {
+                Some(route) => return Some(route.clone()),
+                None => warn!("ai backend not registered: {name}"),
+            }
+        }
+        self.ai_backend_for(AiTask::Planning)
+    }
+
+    fn ai_backend_for(&self, task: AiTask) -> Option<AiRoute> {
+        if let Some(route) = self.ai_routes.get(&task) {
+            return Some(route.clone());
+        }
+        if task == AiTask::Chat {
+            return None;
+        }
+        self.ai_backend.clone().map(|backend| AiRoute {
+            backend,
+            label: self.ai_backend_label.clone(),
+        })
+    }
+
+    /// Lets the chat backend answer conversational turns; anything else is planned by the
+    /// planning backend. Rooms bound to a backend skip the chat route.
+    fn plan_routed(
+        &mut self,
+        planner: &AiRoute,
+        room_cfg: &RoomConfig,
+        input: &str,
+        history: &[AiChatMessage],
+        options: &AiRequestOptions,
+    ) -> Result<AiDecision> {
+        let actions = self.available_specs();
+        let input = &self.redactor.text(input);
+        let history = &self.redact_history(history);
+        let room_bound = room_cfg
+            .provider_binding
+            .as_ref()
+            .is_some_and(|binding| binding.backend.is_some());
+        let chat_route = if room_bound {
+            None
+        } else {
+            self.ai_backend_for(AiTask::Chat)
+        };
+        if let Some(chat) = chat_route {
+            let _span = info_span!("ai", task = "chat", backend = chat.label.as_deref()).entered();
+            let decision = chat.backend.plan_with_options(input, &actions, history, options);
+            self.note_ai_call(AiTask::Chat, &decision);
+            match decision {
+                Ok(decision @ AiDecision::Chat { .. }) => {
+                    self.note_ai_usage(chat.backend.as_ref());
+                    self.ai_answered_by = chat.backend.last_backend().or(chat.label);
+                    return Ok(decision);
+                }
+                Ok(_) => self.note_ai_usage(chat.backend.as_ref()),
+                Err(err) => warn!("ai chat route failed: {err}"),
+            }
+        }
+        let _span = info_span!("ai", task = "planning", backend = planner.label.as_deref()).entered();
+        let started = Instant::now();
+        let decision = planner
+            .backend
+            .plan_with_options(input, &actions, history, options);
+        self.note_ai_call(AiTask::Planning, &decision);
+        debug!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            ok = decision.is_ok(),
+            "ai call finished"
+        );
+        if decision.is_ok() {
+            self.note_ai_usage(planner.backend.as_ref());
+            self.ai_answered_by = planner
+                .backend
+                .last_backend()
+                .or_else(|| planner.label.clone());
+        }
+        decision
+    }
+
+    fn redact_history(&self, history: &[AiChatMessage]) -> Vec<AiChatMessage> {
+        history
+            .iter()
+            .map(|message| AiChatMessage {
+                role: message.role,
+                content: self.redactor.text(&message.content),
+            })
+            .collect()
+    }
+
+    fn note_ai_call(&mut self, task: AiTask, decision: &Result<AiDecision>) {
+        if self.session_log.is_some() {
+            self.recorded_ai_calls.push(RecordedAiCall::new(task, decision));
+        }
+    }
+
+    fn note_ai_usage(&mut self, backend: &dyn AiPlanner) {
+        self.metrics.ai_calls += 1;
+        if let Some(usage) = backend.last_usage() {
+            self.ai_turn_usage.push(usage);
+        }
+    }
+
+    /// Adds this turn's AI calls to the room totals and returns the reply metadata.
+    fn account_turn_usage(&mut self, msg: &InboundMessage) -> Option<serde_json::Value> {
+        if self.ai_turn_usage.is_empty() {
+            return None;
+        }
+        let (workspace_id, room_id) = room_key(msg);
+        let ai_key = format!("ai:{workspace_id}/{room_id}");
+        for _ in 0..self.ai_turn_usage.len() {
+            self.limiter.record(&ai_key, Instant::now());
+        }
+        let workspace_id = msg
+            .workspace_id
+            .clone()
+            .unwrap_or_else(|| "default".to_string());
+        let entry = self
+            .usage
+            .entry((workspace_id.clone(), msg.channel.clone()))
+            .or_insert_with(|| RoomUsage {
+                workspace_id,
+                room_id: msg.channel.clone(),
+                ..RoomUsage::default()
+            });
+        let (mut prompt, mut completion, mut cost, mut estimated) = (0, 0, 0.0, false);
+        let mut models = Vec::new();
+        for usage in self.ai_turn_usage.drain(..) {
+            prompt += usage.prompt_tokens;
+            completion += usage.completion_tokens;
+            cost += usage.cost_usd().unwrap_or(0.0);
+            estimated |= usage.estimated;
+            if !models.contains(&usage.model) {
+                models.push(usage.model);
+            }
+            entry.requests += 1;
+        }
+        entry.prompt_tokens += prompt;
+        entry.completion_tokens += completion;
+        entry.estimated_cost_usd += cost;
+        Some(json!({
+            "models": models,
+            "prompt_tokens": prompt,
+            "completion_tokens": completion,
+            "estimated_cost_usd": cost,
+            "estimated_tokens": estimated,
+        }))
+    }
+
+    fn usage_for(&self, workspace_id: Option<&str>, room_id: Option<&str>) -> Vec<RoomUsage> {
+        let mut rooms: Vec<RoomUsage> = self
+            .usage
+            .values()
+            .filter(|usage| workspace_id.is_none_or(|ws| usage.workspace_id == ws))
+            .filter(|usage| room_id.is_none_or(|room| usage.room_id == room))
+            .cloned()
+            .collect();
+        rooms.sort_by(|a, b| (&a.workspace_id, &a.room_id).cmp(&(&b.workspace_id, &b.room_id)));
+        rooms
+    }
+
+    fn usage_text(&self, msg: &InboundMessage) -> String {
+        let workspace_id = msg.workspace_id.as_deref().unwrap_or("default");
+        let format_usage = |usage: &RoomUsage| {
+            format!(
+                "{} requests, {} prompt + {} completion tokens, ~${:.4}",
+                usage.requests,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.estimated_cost_usd
+            )
+        };
+        let room = self
+            .usage_for(Some(workspace_id), Some(&msg.channel))
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let mut total = RoomUsage::default();
+        for usage in self.usage.values() {
+            total.requests += usage.requests;
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+            total.estimated_cost_usd += usage.estimated_cost_usd;
+        }
+        let mut text = format!(
+            "ai usage (this room): {}\nai usage (all rooms): {}",
+            format_usage(&room),
+            format_usage(&total)
+        );
+        if let Some(avg_completion) = room.completion_tokens.checked_div(room.requests)
+            && avg_completion < 200
+            && room.estimated_cost_usd > 0.0
+        {
+            text.push_str(&format!(
+                "\nreplies here average {avg_completion} tokens; a local model may be enough."
+            ));
+        }
+        text
+    }
+
+    #[cfg(feature = "ai-http")]
+    pub fn set_ai_client(&mut self, ai_client: Option<crate::ai::AiClient>) {
+        let label = ai_client
+            .as_ref()
+            .map(|client| format!("http:{}", client.model_name()));
+        let backend = ai_client.map(|client| {
+            std::sync::Arc::new(client) as std::sync::Arc<dyn AiPlanner>
+        });
+        self.set_ai_backend_with_label(backend, label);
+    }
+
+    /// Encrypts the conversation store, facts, approvals and the action history at rest. Call
+    /// it before the `enable_*` methods that load them; memory takes the key in
+    /// `MemoryIndex::with_storage_key`.
+    pub fn set_storage_key(&mut self, key: StorageKey) {
+        self.history.set_storage_key(key.clone());
+        self.facts.set_storage_key(key.clone());
+        self.conversations.key = Some(key.clone());
+        self.approvals.key = Some(key.clone());
+        self.storage_key = Some(key);
+    }
+
+    /// Appends every message and protocol event the engine handles to `path`, with the AI
+    /// answers and the decisions it made, for `replay::replay`. The file holds messages as
+    /// sent, so it is sealed like the other stores when a storage key is set.
+    pub fn record_session(&mut self, path: PathBuf) {
+        self.session_log = Some(SessionLog::new(path, self.storage_key.clone()));
+    }
+
+    /// Runs every action as a dry run, whatever the room or session sets. Approvals are still
+    /// asked as the room's setting calls for.
+    pub fn set_force_dry_run(&mut self, on: bool) {
+        self.force_dry_run = on;
+    }
+
+    pub fn enable_conversation_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.conversations.load_from_path(&path) {
+            // Saving would replace a file that may only need the right storage key.
+            warn!("context load failed: {err:#}; conversations are not saved");
+            return;
+        }
+        // A file written before the key was set is sealed by the next save.
+        if self.conversations.key.is_some() && path.exists() {
+            self.conversations_dirty_since.get_or_insert_with(Instant::now);
+        }
+        self.conversation_persistence = Some(ConversationPersistence::Json(path));
+        self.apply_conversation_retention();
+    }
+
+    /// Keeps conversation history in a SQLite database at `path` instead, one row per
+    /// message. A JSON file from `enable_conversation_persistence` at `legacy_json` is moved
+    /// into an empty database and renamed to `<name>.migrated`. If the database cannot be
+    /// opened, history is not saved.
+    #[cfg(feature = "sqlite")]
+    pub fn enable_conversation_db(&mut self, path: PathBuf, legacy_json: Option<&Path>) {
+        let key = self.conversations.key.clone();
+        let opened = crate::conversation_db::ConversationDb::open(&path, key.clone()).and_then(|mut db| {
+            if let Some(json) = legacy_json.filter(|json| json.exists()) {
+                let mut legacy = ConversationStore::new(usize::MAX);
+                legacy.key = key;
+                legacy.load_from_path(json)?;
+                let rooms = db.import_json(json, legacy.history)?;
+                if rooms > 0 {
+                    info!(
+                        "context: moved {rooms} room(s) from {} to {}",
+                        json.display(),
+                        path.display()
+                    );
+                }
+            }
+            let history = db.load(self.conversations.max_messages)?;
+            Ok((db, history))
+        });
+        match opened {
+            Ok((db, history)) => {
+                self.conversations.history = history;
+                self.conversation_persistence = Some(ConversationPersistence::Sqlite(db));
+                self.apply_conversation_retention();
+            }
+            Err(err) => warn!("context load failed: {err:#}"),
+        }
+    }
+
+    /// Remembers handled protocol message ids in `path`, so a sync replayed after a restart
+    /// does not run them again.
+    pub fn enable_seen_message_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.seen_messages.load_from_path(&path) {
+            warn!("seen messages load failed: {err}");
+        }
+        self.seen_messages.persist_path = Some(path);
+    }
+
+    /// Keeps pending approvals in `path` so they survive a restart. Approvals found there are
+    /// checked against the current actions and announced again, or expired, on the next `tick`.
+    pub fn enable_approval_persistence(&mut self, path: PathBuf) {
+        let persisted = match self.approvals.read_persisted(&path) {
+            Ok(persisted) => persisted,
+            Err(err) => {
+                // Saving now would overwrite a file that may only need the right key.
+                warn!("approvals load failed: {err:#}; approvals are not saved");
+                return;
+            }
+        };
+        self.approvals.persist_path = Some(path);
+        let Some(persisted) = persisted else {
+            return;
+        };
+        self.approvals.next_id = self.approvals.next_id.max(persisted.next_id);
+        for item in persisted.approvals {
+            self.restore_approval(item);
+        }
+        self.approvals.save();
+    }
+
+    fn restore_approval(&mut self, item: PersistedApproval) {
+        let now = unix_now();
+        let age = Duration::from_secs(now.saturating_sub(item.created_at));
+        let checked = match self.registry.get(&item.action) {
+            None => Err(anyhow!("action is no longer available")),
+            Some(action) => {
+                let ctx = self.build_context(&item.config);
+                action
+                    .validate(&ctx, &item.request.params)
+                    .map(|()| (action.spec(), ctx))
+            }
+        };
+        let (spec, ctx) = match checked {
+            Err(err) => {
+                let notice = self.reply(
+                    &item.origin,
+                    format!("approval {} for '{}' dropped after restart: {err}", item.id, item.action),
+                    "approval_expired",
+                    json!({"approval_id": item.id, "action": item.action}),
+                );
+                self.notices.push(notice);
+                return;
+            }
+            Ok(found) if item.expires_at.is_none_or(|at| at > now) => found,
+            Ok(_) => {
+                let notice = self.reply(
+                    &item.origin,
+                    format!(
+                        "approval {} for '{}' expired after {} while robit was offline",
+                        item.id,
+                        item.action,
+                        format_age(age)
+                    ),
+                    "approval_expired",
+                    json!({"approval_id": item.id, "action": item.action}),
+                );
+                self.notices.push(notice);
+                return;
+            }
+        };
+        let hint = item.plan.as_ref().map(|plan| {
+            // Results of steps that ran before the restart are gone.
+            self.start_plan_progress(&plan.plan_id, room_key(&item.origin), plan.total_steps, None);
+            PlanApprovalHint {
+                plan_id: plan.plan_id.clone(),
+                step_index: plan.completed_steps + 1,
+                total_steps: plan.total_steps,
+                allow_approve_all: true,
+            }
+        });
+        let prompt = format_approval_prompt(
+            &spec,
+            &item.request.params,
+            &ctx,
+            &item.id,
+            None,
+            hint,
+            None,
+        );
+        let text = prompt.plain(self.locale_for(&item.origin));
+        let mut data = json!({"approval_id": item.id, "restored": true, "prompt": prompt});
+        if let Some(plan) = &item.plan {
+            data["plan_id"] = json!(plan.plan_id);
+        }
+        let notice = self.reply(&item.origin, text, "approval_request", data);
+        self.notices.push(notice);
+        let created_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        let expires_at = item
+            .expires_at
+            .map(|at| Instant::now() + Duration::from_secs(at.saturating_sub(now)));
+        self.approvals.insert(
+            item.id,
+            PendingAction {
+                request: item.request,
+                spec,
+                origin: item.origin,
+                config: item.config,
+                plan: item.plan,
+                created_at,
+                expires_at,
+                approved_by: item.approved_by,
+            },
+        );
+    }
+
+    /// Enables `plan save` / `plan run` with templates stored in `dir`.
+    pub fn enable_plan_templates(&mut self, dir: PathBuf) {
+        self.plan_templates = Some(PlanTemplateStore::new(dir));
+    }
+
+    /// Keeps schedules created with `schedule.create` across restarts.
+    pub fn enable_schedule_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.schedules.enable_persistence(path) {
+            warn!("schedules load failed: {err}");
+        }
+    }
+
+    /// Appends every executed action to `path` as a JSON line, for `history`.
+    pub fn enable_action_history(&mut self, path: PathBuf) {
+        if let Err(err) = self.history.enable_persistence(path) {
+            warn!("history load failed: {err}");
+        }
+    }
+
+    /// Keeps `alias` definitions across restarts.
+    pub fn enable_alias_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.aliases.enable_persistence(path) {
+            warn!("aliases load failed: {err}");
+        }
+    }
+
+    /// Keeps the workspaces' user facts across restarts.
+    pub fn enable_fact_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.facts.enable_persistence(path) {
+            warn!("facts load failed: {err:#}; facts are not saved");
+        }
+    }
+
+    /// Whether statements such as "I prefer English replies" are kept as facts on their own.
+    pub fn set_fact_extraction(&mut self, enabled: bool) {
+        self.fact_extraction = enabled;
+    }
+
+    /// The workspace's user facts, oldest first.
+    pub fn facts(&self, workspace_id: &str) -> Vec<UserFact> {
+        self.facts.list(workspace_id).to_vec()
+    }
+
+    /// Returns the new fact's id, or `None` if `source` already shared it in the workspace.
+    /// `source` is also the sender whose role decides which prompts include it.
+    pub fn add_fact(&mut self, workspace_id: &str, text: &str, source: &str) -> Result<Option<u64>> {
+        self.add_fact_from(workspace_id, text, source, source)
+    }
+
+    fn add_fact_from(
+        &mut self,
+        workspace_id: &str,
+        text: &str,
+        source: &str,
+        sender: &str,
+    ) -> Result<Option<u64>> {
+        let text = self.redactor.text(text);
+        self.facts.add(workspace_id, &text, source, sender, unix_now())
+    }
+
+    /// The workspace's facts a prompt for `sender` includes: their own, and those shared by
+    /// senders whose role is at least theirs, so a guest's facts never steer an admin's requests.
+    fn prompt_facts(&self, workspace_id: &str, sender: &str) -> Vec<&UserFact> {
+        let role = self.ctx.policy.role_for(sender);
+        self.facts
+            .list(workspace_id)
+            .iter()
+            .filter(|fact| {
+                !fact.sender.is_empty()
+                    && (fact.sender == sender || self.ctx.policy.role_for(&fact.sender) >= role)
+            })
+            .collect()
+    }
+
+    /// Forgets one fact, or all of the workspace's with `None`; returns how many went.
+    pub fn forget_fact(&mut self, workspace_id: &str, id: Option<u64>) -> Result<usize> {
+        self.facts.forget(workspace_id, id)
+    }
+
+    /// Keeps `set var` variables across restarts.
+    pub fn enable_var_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.vars.enable_persistence(path) {
+            warn!("vars load failed: {err}");
+        }
+    }
+
+    /// Keeps the global, workspace and room settings from `ConfigUpdate`s across restarts,
+    /// replacing any applied before this call. A file that fails to load is left alone and
+    /// nothing is saved.
+    pub fn enable_config_persistence(&mut self, path: PathBuf) {
+        if let Err(err) = self.config_store.enable_persistence(path) {
+            warn!("config state load failed: {err}");
+        }
+    }
+
+    /// Recalls related memories into the planner prompt and remembers AI-handled exchanges.
+    pub fn enable_memory(&mut self, memory: MemoryIndex) {
+        self.memory = Some(memory);
+    }
+
+    /// Access for indexing facts or files from the host application.
+    pub fn memory_mut(&mut self) -> Option<&mut MemoryIndex> {
+        self.memory.as_mut()
+    }
+
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Appends a planner to the chain, after the built-in command and rule planners and before
+    /// the AI.
+    pub fn add_planner<P: Planner + 'static>(&mut self, planner: P) {
+        self.planners.push(Box::new(planner));
+    }
+
+    /// Replaces the whole chain, built-in planners included.
+    pub fn set_planners(&mut self, planners: PlannerChain) {
+        self.planners = planners;
+    }
+
+    pub fn add_hook<H: EngineHook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// See `PreflightCheck`.
+    pub fn add_preflight_check<C: PreflightCheck + 'static>(&mut self, check: C) {
+        self.preflight.register_check(check);
+    }
+
+    pub(crate) fn add_boxed_preflight_check(&mut self, check: Box<dyn PreflightCheck>) {
+        self.preflight.register_boxed_check(check);
+    }
+
+    pub(crate) fn add_boxed_middleware(&mut self, middleware: Box<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
+    pub(crate) fn add_boxed_planner(&mut self, planner: Box<dyn Planner>) {
+        self.planners.push(planner);
+    }
+
+    pub(crate) fn add_boxed_hook(&mut self, hook: Box<dyn EngineHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        self.tick_interval = interval.max(Duration::from_millis(10));
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        self.tick_interval
+    }
+
+    /// Stops waiting for an action after `timeout` unless its plan step sets `timeout_secs`.
+    pub fn set_action_timeout(&mut self, timeout: Option<Duration>) {
+        self.action_timeout = timeout;
+    }
+
+    /// Replies longer than `limit` characters are cut into pages, with the rest available
+    /// through `show more <id>`. Room configs can set their own `max_reply_chars`.
+    pub fn set_max_reply_chars(&mut self, limit: Option<usize>) {
+        self.max_reply_chars = limit.filter(|limit| *limit > 0);
+    }
+
+    pub fn set_reply_format(&mut self, format: ReplyFormat) {
+        self.reply_format = Some(format);
+    }
+
+    /// Language of approval prompts, plan summaries and fallback replies in rooms whose
+    /// config sets no `locale`.
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.locale = locale;
+    }
+
+    fn locale_for(&self, msg: &InboundMessage) -> Locale {
+        let (workspace_id, room_id) = room_key(msg);
+        self.config_store
+            .effective_for(&workspace_id, &room_id)
+            .locale
+            .unwrap_or(self.locale)
+    }
+
+    /// Keeps pages within what the adapter can send, below any limit set on the engine, and
+    /// takes the adapter's reply format unless one was set.
+    pub(crate) fn fit_adapter(&mut self, max_chars: Option<usize>, format: ReplyFormat) {
+        if let Some(limit) = max_chars {
+            self.max_reply_chars = Some(self.max_reply_chars.map_or(limit, |own| own.min(limit)));
+        }
+        self.reply_format.get_or_insert(format);
+    }
+
+    /// Keeps copies of files that actions overwrite, so `undo` can restore them.
+    pub fn set_backup_dir(&mut self, dir: PathBuf) {
+        self.ctx.backup_dir = Some(dir);
+    }
+
+    /// Saves inline attachments of incoming messages under `dir/<message id>/`, so actions
+    /// can read them by path.
+    pub fn set_attachment_dir(&mut self, dir: PathBuf) {
+        self.attachment_dir = Some(dir);
+    }
+
+    /// Attachments up to `bytes` travel inline as base64, both ways; larger ones only by
+    /// path. 1 MiB by default.
+    pub fn set_max_inline_attachment_bytes(&mut self, bytes: u64) {
+        self.inline_attachment_bytes = bytes;
+    }
+
+    pub fn set_preflight_config(&mut self, config: PreflightConfig) {
+        self.preflight.set_config(config);
+        self.preflight_from_file = false;
+    }
+
+    /// Re-reads `[policy]`, `[preflight]` and `[ai]` from the config files and applies them;
+    /// the rest of the engine's state is kept. Nothing is applied unless all of it loads,
+    /// including a changed `[ai] provider` backend. Read-only mode stays on if it was, until
+    /// `read-only off`. `[redaction]` and schedules still need a restart.
+    pub fn reload_config(&mut self) -> Result<()> {
+        let loaded = self.config_source.load(PreflightConfig::default())?;
+        let conversation_max_age = loaded.conversation.max_age_secs()?;
+        let backend_entry = loaded.ai.backend_entry();
+        let backend = match &backend_entry {
+            Some(entry) if backend_entry != self.ai_backend_entry => {
+                let backend = model_loader_for(entry.clone())?()
+                    .with_context(|| format!("loading ai provider {} failed", entry.name))?;
+                Some((backend, entry.name.clone()))
+            }
+            _ => None,
+        };
+
+        let mut policy = loaded.policy;
+        policy.read_only |= self.ctx.policy.read_only;
+        self.ctx.policy = policy;
+        if self.preflight_from_file {
+            self.preflight.set_config(loaded.preflight);
+        }
+        self.config_store.file_prompt_extra = loaded.ai.system_prompt_extra;
+        self.critic_enabled = loaded.ai.critic;
+        self.conversations.set_max_messages(
+            loaded
+                .conversation
+                .max_messages
+                .unwrap_or(DEFAULT_CONVERSATION_MESSAGES),
+        );
+        self.conversation_max_age = conversation_max_age;
+        self.conversations_pruned_at = None;
+        for name in self.file_ai_models.drain(..) {
+            self.ai_models.remove(&name);
+        }
+        self.file_ai_models = load_ai_models(loaded.ai.models, &mut self.ai_models);
+        if let Some((backend, label)) = backend {
+            self.set_ai_backend_with_label(Some(backend), Some(label));
+            self.active_model = None;
+        }
+        self.ai_backend_entry = backend_entry;
+        Ok(())
+    }
+
+    /// Every problem in the config files at once: TOML or field errors, `allowed_roots` that
+    /// do not exist, unknown risk levels, capabilities and actions, AI providers whose
+    /// credentials do not resolve, bad redaction patterns and schedules. Empty when the files
+    /// are fine.
+    pub fn validate_config(&self) -> Vec<String> {
+        let specs = self.registry.list_specs();
+        let mut capabilities: Vec<String> = specs
+            .iter()
+            .flat_map(|spec| spec.capabilities.iter().cloned())
+            .collect();
+        capabilities.sort();
+        capabilities.dedup();
+        let actions: Vec<String> = specs.into_iter().map(|spec| spec.name).collect();
+        config::validate_layers(self.config_source.profile(), &capabilities, &actions)
+    }
+
+    /// The roots file actions may touch, after the config files' `[policy]`.
+    pub fn allowed_roots(&self) -> &[PathBuf] {
+        &self.ctx.policy.allowed_roots
+    }
+
+    /// The active `[profiles.<name>]` section; `None` when the plain files apply.
+    pub fn profile(&self) -> Option<&str> {
+        self.config_source.profile()
+    }
+
+    /// The profiles the config files define, sorted.
+    pub fn profiles(&self) -> Result<Vec<String>> {
+        self.config_source.profiles()
+    }
+
+    /// Switches to `[profiles.<name>]`, or back to the plain files with `None`, by reloading
+    /// `[policy]`, `[preflight]` and `[ai]` as `reload_config` does; conversations are kept
+    /// apart per profile. A profile without `[ai] provider` keeps the current backend.
+    /// Refused while approvals are pending or plans are running, since they were checked
+    /// under the current profile. Nothing changes if the profile does not load.
+    pub fn use_profile(&mut self, profile: Option<&str>) -> Result<()> {
+        if !self.approvals.pending.is_empty() || !self.plans.is_empty() {
+            return Err(anyhow!(
+                "{} pending approval(s) and {} running plan(s); resolve them before switching profiles",
+                self.approvals.pending.len(),
+                self.plans.len()
+            ));
+        }
+        let previous = self.config_source.profile().map(str::to_string);
+        self.config_source.set_profile(profile.map(str::to_string));
+        if let Err(err) = self.reload_config() {
+            self.config_source.set_profile(previous);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Reloads the config files if one changed. A broken file keeps the current settings.
+    fn refresh_config(&mut self) {
+        if !self.config_source.changed() {
+            return;
+        }
+        let error = self.reload_config().err().map(|err| format!("{err:#}"));
+        if let Some(err) = &error {
+            warn!("config reload failed: {err}");
+        }
+        let paths = self
+            .config_source
+            .paths()
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        self.config_reloaded = Some(ConfigReloadedPayload { paths, error });
+    }
+
+    /// Masks matches of `pattern` in action results, history, conversation context, preflight
+    /// logs and AI prompts, on top of the built-in patterns for API keys and tokens.
+    pub fn add_redaction_pattern(&mut self, pattern: &str) -> Result<()> {
+        self.redactor.add_pattern(pattern)
+    }
+
+    fn log_preflight(&self, report: &PreflightReport) {
+        if let Ok(json) = serde_json::to_string(report) {
+            debug!(report = %self.redactor.text(&json), "preflight");
+        }
+    }
+
+    fn conversation_key_for(&self, msg: &InboundMessage) -> (String, String) {
+        let (workspace_id, room_id) = self.conversations.key_for(msg);
+        self.decorate_conversation_key(workspace_id, room_id)
+    }
+
+    fn conversation_key_parts(&self, workspace_id: &str, room_id: &str) -> (String, String) {
+        self.decorate_conversation_key(workspace_id.to_string(), room_id.to_string())
+    }
+
+    fn decorate_conversation_key(
+        &self,
+        workspace_id: String,
+        room_id: String,
+    ) -> (String, String) {
+        let mut decorated_room = room_id;
+        if let Some(profile) = self.config_source.profile() {
+            decorated_room = format!("{decorated_room}::profile={profile}");
+        }
+        if let Some(label) = self.ai_backend_label.as_deref() {
+            decorated_room = format!("{decorated_room}::ai={label}");
+        }
+        (workspace_id, decorated_room)
+    }
+
+    pub fn handle_message(&mut self, msg: InboundMessage) -> Vec<OutboundMessage> {
+        let recorded = self.session_log.is_some().then(|| ReplayInput::Message(msg.clone()));
+        let replies = if let Some(role) = context_only_role(&msg.metadata) {
+            let convo_key = self.conversation_key_for(&msg);
+            self.record_context_and_persist(&convo_key, role, &msg.text);
+            Vec::new()
+        } else {
+            self.handle_message_with_config(msg, None)
+        };
+        if let Some(input) = recorded {
+            self.record_turn(input, replies.iter().map(ReplyDecision::from_reply).collect());
+        }
+        replies
+    }
+
+    /// Every reply carries `event`'s id as its envelope `in_reply_to`.
+    pub fn handle_protocol_event(&mut self, event: ProtocolEvent) -> Vec<ProtocolEvent> {
+        let recorded = self.session_log.is_some().then(|| ReplayInput::Event(Box::new(event.clone())));
+        let request_id = event.id.clone();
+        let mut replies = self.dispatch_protocol_event(event);
+        for reply in &mut replies {
+            reply.in_reply_to.get_or_insert_with(|| request_id.clone());
+        }
+        if let Some(input) = recorded {
+            self.record_turn(input, replies.iter().map(ReplyDecision::from_event).collect());
+        }
+        replies
+    }
+
+    fn record_turn(&mut self, input: ReplayInput, decisions: Vec<ReplyDecision>) {
+        let turn = RecordedTurn {
+            input,
+            ai_calls: std::mem::take(&mut self.recorded_ai_calls),
+            decisions,
+        };
+        let saved = self.session_log.as_ref().map(|log| log.append(&turn));
+        if let Some(Err(err)) = saved {
+            warn!("session log save failed: {err:#}");
+        }
+    }
+
+    fn dispatch_protocol_event(&mut self, event: ProtocolEvent) -> Vec<ProtocolEvent> {
+        if let ProtocolBody::Hello(hello) = event.body {
+            return vec![self.handle_hello(event.id, hello)];
+        }
+        if !SCHEMA_VERSIONS.contains(&event.schema_version.as_str()) {
+            let error = ErrorPayload {
+                in_reply_to: event.id,
+                room_id: String::new(),
+                workspace_id: String::new(),
+                code: RobitError::InvalidRequest(String::new()).code().to_string(),
+                message: format!(
+                    "unsupported schema_version {:?}; supported: {}; send Hello to negotiate",
+                    event.schema_version,
+                    SCHEMA_VERSIONS.join(", ")
+                ),
+                metadata: serde_json::Value::Null,
+            };
+            return vec![self.error_event(error)];
+        }
+
+        match event.body {
+            ProtocolBody::Message(payload) => {
+                if !self.scope.allows(&payload.workspace_id, &payload.room_id) {
+                    return Vec::new();
+                }
+                if !self.seen_messages.insert(&payload.message_id) {
+                    return Vec::new();
+                }
+                if let Some(role) = context_only_role(&payload.metadata) {
+                    let convo_key =
+                        self.conversation_key_parts(&payload.workspace_id, &payload.room_id);
+                    self.record_context_and_persist(&convo_key, role, &payload.text);
+                    return Vec::new();
+                }
+                let room_cfg = self
+                    .config_store
+                    .effective_for(&payload.workspace_id, &payload.room_id);
+                let attachments = self.receive_attachments(&payload.message_id, payload.attachments);
+                let mut msg = InboundMessage {
+                    id: event.id,
+                    text: payload.text,
+                    sender: payload.sender_id,
+                    channel: payload.room_id,
+                    workspace_id: Some(payload.workspace_id),
+                    metadata: payload.metadata,
+                };
+                match attachments {
+                    Ok(attachments) if !attachments.is_empty() => {
+                        for attachment in &attachments {
+                            msg.text.push_str(&format!("\n{}", attachment_line(attachment)));
+                        }
+                        msg.text = msg.text.trim_start().to_string();
+                        if !msg.metadata.is_object() {
+                            msg.metadata = json!({});
+                        }
+                        msg.metadata["attachments"] = json!(attachments);
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        let reply =
+                            self.error_reply(&msg, RobitError::InvalidRequest(format!("{err:#}")));
+                        return vec![self.wrap_response(reply)];
+                    }
+                }
+                let replies = match self.throttle_messages(&msg, &room_cfg) {
+                    Some(replies) => replies,
+                    None => self.handle_message_with_config(msg, Some(room_cfg.clone())),
+                };
+                replies
+                    .into_iter()
+                    .map(|reply| self.wrap_response(reply))
+                    .collect()
+            }
+            ProtocolBody::ApprovalDecision(payload) => self.handle_approval_decision(payload),
+            ProtocolBody::RoomScope(payload) => {
+                self.scope.update(payload);
+                Vec::new()
+            }
+            ProtocolBody::ConfigUpdate(mut payload) => {
+                if let Some(name) = payload.ai_model.take()
+                    && let Err(err) = self.use_ai_model(&name)
+                {
+                    warn!("model switch failed: {err:#}");
+                }
+                self.config_store.apply(payload);
+                if let Err(err) = self.config_store.save() {
+                    warn!("config state save failed: {err}");
+                }
+                Vec::new()
+            }
+            ProtocolBody::ActionListRequest(_) => {
+                let actions = self.available_specs();
+                vec![ProtocolEvent::new(ProtocolBody::ActionListResult(
+                    ActionListResultPayload { actions },
+                ))]
+            }
+            ProtocolBody::UsageRequest(payload) => {
+                if let (Some(workspace), Some(room)) = (&payload.workspace_id, &payload.room_id)
+                    && !self.scope.allows(workspace, room)
+                {
+                    return Vec::new();
+                }
+                // Wider requests drop the rooms outside the scope instead.
+                let rooms = self
+                    .usage_for(payload.workspace_id.as_deref(), payload.room_id.as_deref())
+                    .into_iter()
+                    .filter(|usage| self.scope.allows(&usage.workspace_id, &usage.room_id))
+                    .collect();
+                vec![ProtocolEvent::new(ProtocolBody::UsageResult(
+                    UsageResultPayload { rooms },
+                ))]
+            }
+            ProtocolBody::HistoryRequest(payload) => {
+                if !self.scope.allows(&payload.workspace_id, &payload.room_id) {
+                    return Vec::new();
+                }
+                let actions = self.history.recent(
+                    Some(&payload.workspace_id),
+                    Some(&payload.room_id),
+                    payload.limit.unwrap_or(10),
+                );
+                vec![ProtocolEvent::new(ProtocolBody::HistoryResult(
+                    HistoryResultPayload {
+                        workspace_id: payload.workspace_id,
+                        room_id: payload.room_id,
+                        actions,
+                    },
+                ))]
+            }
+            ProtocolBody::ExportRequest(payload) => {
+                if !self.scope.allows(&payload.workspace_id, &payload.room_id) {
+                    return Vec::new();
+                }
+                let document = self.export_conversation(&payload.workspace_id, &payload.room_id);
+                vec![ProtocolEvent::new(ProtocolBody::ExportResult(document))]
+            }
+            ProtocolBody::ImportRequest(payload) => {
+                let (workspace_id, room_id) = payload.target();
+                let (workspace_id, room_id) = (workspace_id.to_string(), room_id.to_string());
+                if !self.scope.allows(&workspace_id, &room_id) {
+                    return Vec::new();
+                }
+                match self.import_conversation(payload) {
+                    Ok(result) => vec![ProtocolEvent::new(ProtocolBody::ImportResult(result))],
+                    Err(err) => vec![self.error_event(ErrorPayload {
+                        in_reply_to: event.id,
+                        room_id,
+                        workspace_id,
+                        code: RobitError::InvalidRequest(String::new()).code().to_string(),
+                        message: format!("{err:#}"),
+                        metadata: serde_json::Value::Null,
+                    })],
+                }
+            }
+            ProtocolBody::SearchRequest(payload) => {
+                let room = payload.room_id.as_deref();
+                // A workspace-wide search drops hits from rooms outside the scope instead.
+                if room.is_some_and(|room| !self.scope.allows(&payload.workspace_id, room)) {
+                    return Vec::new();
+                }
+                let hits = self.search_conversations(
+                    &payload.workspace_id,
+                    room,
+                    &payload.query,
+                    payload.limit.unwrap_or(SEARCH_LIMIT),
+                );
+                vec![ProtocolEvent::new(ProtocolBody::SearchResult(SearchResultPayload {
+                    workspace_id: payload.workspace_id,
+                    room_id: payload.room_id,
+                    query: payload.query,
+                    hits,
+                }))]
+            }
+            ProtocolBody::ConfigQuery(payload) => {
+                if let Some(scope) = &payload.scope
+                    && !self.scope.allows_config(scope)
+                {
+                    return Vec::new();
+                }
+                let config = self.effective_config(payload.scope.as_ref());
+                vec![ProtocolEvent::new(ProtocolBody::ConfigResult(config))]
+            }
+            ProtocolBody::StatusRequest(_) => {
+                vec![ProtocolEvent::new(ProtocolBody::StatusResult(self.status()))]
+            }
+            ProtocolBody::Ping(_) => vec![ProtocolEvent::new(ProtocolBody::Pong(
+                crate::protocol::PongPayload { in_reply_to: event.id },
+            ))],
+            _ => Vec::new(),
+        }
+    }
+    pub fn run_with_adapter<A: Adapter>(&mut self, adapter: &mut A) -> Result<()> {
+        self.fit_adapter(adapter.max_message_chars(), adapter.reply_format());
+        loop {
+            let msg = match adapter.recv_timeout(self.tick_interval)? {
+                AdapterPoll::Message(msg) => msg,
+                AdapterPoll::Idle => {
+                    for notice in self.tick() {
+                        adapter.send(notice)?;
+                    }
+                    continue;
+                }
+                AdapterPoll::Closed => break,
+            };
+            if !msg.text.trim().is_empty() {
+                let responses = self.handle_message(msg);
+                for response in responses {
+                    adapter.send(response)?;
+                }
+            }
+            for notice in self.tick() {
+                adapter.send(notice)?;
+            }
+        }
+        self.flush_conversations();
+        Ok(())
+    }
+
+    /// Async counterpart of `run_with_adapter`. Message handling runs on the current
+    /// worker via `block_in_place` on multi-threaded runtimes so adapter IO keeps flowing.
+    /// `EngineHandle::run` keeps receiving while a message is being handled.
+    #[cfg(feature = "async")]
+    pub async fn run<A: crate::adapter::AsyncAdapter>(&mut self, adapter: &mut A) -> Result<()> {
+        self.fit_adapter(adapter.max_message_chars(), adapter.reply_format());
+        loop {
+            let msg = match adapter.recv_timeout(self.tick_interval).await? {
+                AdapterPoll::Message(msg) => msg,
+                AdapterPoll::Idle => {
+                    for notice in self.tick() {
+                        adapter.send(notice).await?;
+                    }
+                    continue;
+                }
+                AdapterPoll::Closed => break,
+            };
+            if !msg.text.trim().is_empty() {
+                let responses = blocking_section(|| self.handle_message(msg));
+                for response in responses {
+                    adapter.send(response).await?;
+                }
+            }
+            for notice in self.tick() {
+                adapter.send(notice).await?;
+            }
+        }
+        self.flush_conversations();
+        Ok(())
+    }
+
+    /// Periodic housekeeping, driven by `run_with_adapter` between messages.
+    /// Embedders that own their own loop should call this regularly and deliver the
+    /// returned notices, e.g. approvals that expired.
+    pub fn tick(&mut self) -> Vec<OutboundMessage> {
+        if self
+            .conversations_dirty_since
+            .is_some_and(|since| since.elapsed() >= CONVERSATION_SAVE_DELAY)
+        {
+            self.flush_conversations();
+        }
+        if self
+            .conversations_pruned_at
+            .is_some_and(|at| at.elapsed() >= CONVERSATION_PRUNE_INTERVAL)
+        {
+            self.apply_conversation_retention();
+        }
+        self.refresh_config();
+        self.run_due_schedules();
+        self.expire_approvals();
+        self.prune_sessions();
+        self.limiter.prune(Instant::now());
+        self.quotas.prune(Instant::now());
+        let notices = std::mem::take(&mut self.notices);
+        self.finish_replies(notices)
+    }
+
+    /// `tick` for protocol embedders: notices come back as response events, plus a
+    /// `ConfigReloaded` after the config files changed.
+    pub fn tick_protocol(&mut self) -> Vec<ProtocolEvent> {
+        let notices = self.tick();
+        let mut events: Vec<ProtocolEvent> = notices
+            .into_iter()
+            .map(|notice| self.wrap_response(notice))
+            .collect();
+        if let Some(reloaded) = self.config_reloaded.take()
+            && self.peer.as_ref().is_none_or(|peer| peer.accepts_event("ConfigReloaded"))
+        {
+            events.push(ProtocolEvent::new(ProtocolBody::ConfigReloaded(reloaded)));
+        }
+        events
+    }
+
+    /// Sends each due schedule's text through the engine; replies go out as notices.
+    /// Rooms outside the current scope are skipped until they are back in it.
+    fn run_due_schedules(&mut self) {
+        let now = unix_now();
+        for schedule in self.schedules.take_due(now) {
+            let workspace_id = schedule.workspace_id.clone().unwrap_or_default();
+            if !self.scope.allows(&workspace_id, &schedule.room_id) {
+                continue;
+            }
+            let room_cfg = self
+                .config_store
+                .effective_for(&workspace_id, &schedule.room_id);
+            let replies = self.handle_message_with_config(schedule.message(now), Some(room_cfg));
+            self.notices.extend(replies);
+        }
+    }
+
+    fn schedules_text(&self, msg: &InboundMessage) -> String {
+        let items = self
+            .schedules
+            .in_room(msg.workspace_id.as_deref(), &msg.channel);
+        if items.is_empty() {
+            return "no schedules".to_string();
+        }
+        let now = unix_now();
+        let mut text = String::from("schedules:\n");
+        for schedule in items {
+            text.push_str(&format!(
+                "  {id}  {when}  {text}, next in {next}\n",
+                id = schedule.id,
+                when = schedule.when,
+                text = schedule.text,
+                next = format_age(Duration::from_secs(schedule.next_run.saturating_sub(now))),
+            ));
+        }
+        text
+    }
+
+    /// Runs `schedule.create` for the room and sender of `msg`.
+    fn create_schedule(
+        &mut self,
+        msg: &InboundMessage,
+        ctx: &ActionContext,
+        params: &serde_json::Value,
+    ) -> Result<ActionOutcome> {
+        if ctx.dry_run {
+            let text = params.get("text").or_else(|| params.get("plan"));
+            return Ok(ActionOutcome {
+                summary: "dry-run: schedule not created".to_string(),
+                data: json!({"dry_run": true, "text": text}),
+            });
+        }
+        let schedule = self.schedules.create(msg, params, unix_now())?;
+        Ok(ActionOutcome {
+            summary: format!(
+                "scheduled {} ({}): {}",
+                schedule.id, schedule.when, schedule.text
+            ),
+            data: json!({
+                "id": schedule.id,
+                "when": schedule.when.to_string(),
+                "text": schedule.text,
+                "next_run": schedule.next_run,
+            }),
+        })
+    }
+
+    /// `run_step`, except for actions the engine carries out itself. Counts the run against
+    /// the room's quotas, and refuses it once one is spent.
+    fn run_action(
+        &mut self,
+        action: &std::sync::Arc<dyn ActionHandler>,
+        msg: &InboundMessage,
+        ctx: &ActionContext,
+        params: &serde_json::Value,
+        policy: &StepPolicy,
+        quotas: Option<&Quotas>,
+    ) -> (Result<ActionOutcome>, u32) {
+        let _span = info_span!("action", action = action.name(), dry_run = ctx.dry_run).entered();
+        self.timed_out_running = false;
+        if action.name() == SCHEDULE_CREATE {
+            return (self.create_schedule(msg, ctx, params), 1);
+        }
+        let spec = action.spec();
+        let room = room_key(msg);
+        let checked = quotas
+            .filter(|_| !ctx.dry_run)
+            .map(|quotas| self.quotas.check(&room, quotas, &spec, params, Instant::now()));
+        if let Some(Err(err)) = checked {
+            return (Err(err), 0);
+        }
+        let started = Instant::now();
+        let (result, attempts) = run_step(action, ctx, params, policy);
+        self.timed_out_running = result
+            .as_ref()
+            .err()
+            .and_then(|err| err.downcast_ref::<StepTimedOut>())
+            .is_some_and(|timeout| timeout.still_running);
+        debug!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            attempts,
+            ok = result.is_ok(),
+            "action finished"
+        );
+        if !ctx.dry_run {
+            self.quotas
+                .record(&room, &spec, result.as_ref().ok(), Instant::now());
+        }
+        if let Ok(outcome) = &result
+            && let Some(undo) = action.undo(params, outcome)
+        {
+            let entry = UndoableAction {
+                action: action.name().to_string(),
+                summary: self.redactor.text(&outcome.summary),
+                undo,
+            };
+            self.undoable.insert(room_key(msg), entry);
+        }
+        (self.redact_result(result), attempts)
+    }
+
+    /// Masks secrets in what an action returned before it reaches replies, history, plan
+    /// results or the AI. An error is only rebuilt when it held one.
+    fn redact_result(&self, result: Result<ActionOutcome>) -> Result<ActionOutcome> {
+        match result {
+            Ok(outcome) => Ok(ActionOutcome {
+                summary: self.redactor.text(&outcome.summary),
+                data: self.redactor.value(&outcome.data),
+            }),
+            Err(err) => {
+                let text = format!("{err:#}");
+                let redacted = self.redactor.text(&text);
+                if redacted == text {
+                    Err(err)
+                } else {
+                    Err(anyhow!(redacted))
+                }
+            }
+        }
+    }
+
+    /// Tells the hooks an action ran and adds it to the history.
+    fn finish_action(
+        &mut self,
+        event: &ActionEvent,
+        result: &Result<ActionOutcome>,
+        elapsed: Duration,
+    ) {
+        self.hooks.action_finish(event, result, elapsed);
+        self.metrics.record_action(event.action, result.is_ok(), elapsed);
+        let finished_at = unix_now();
+        let (ok, summary) = match result {
+            Ok(outcome) => (true, outcome.summary.clone()),
+            Err(err) => (false, format!("{err:#}")),
+        };
+        let (workspace_id, room_id) = room_key(event.msg);
+        self.history.record(ActionRecord {
+            workspace_id,
+            room_id,
+            sender: event.msg.sender.clone(),
+            action: event.action.to_string(),
+            params: truncate_text(
+                &self.redactor.text(&format_params_compact(event.params)),
+                200,
+            ),
+            ok,
+            summary: truncate_text(&summary, 500),
+            plan_id: event.plan_id.map(str::to_string),
+            started_at: finished_at.saturating_sub(elapsed.as_secs()),
+            finished_at,
+        });
+    }
+
+    /// `set var name=value`; an empty value removes the variable.
+    fn set_var(&mut self, msg: &InboundMessage, assignment: &str) -> OutboundMessage {
+        let Some((name, value)) = assignment.split_once('=') else {
+            return self.reply(
+                msg,
+                "usage: set var <name>=<value>",
+                "error",
+                serde_json::Value::Null,
+            );
+        };
+        let (name, value) = (name.trim(), value.trim());
+        match self.vars.set(&var_workspace(msg), name, value) {
+            Ok(()) if value.is_empty() => {
+                self.reply(msg, format!("${name} removed"), "info", json!({"name": name}))
+            }
+            Ok(()) => self.reply(
+                msg,
+                format!("${name} = {value}"),
+                "info",
+                json!({"name": name, "value": value}),
+            ),
+            Err(err) => self.reply(
+                msg,
+                format!("set var failed: {err:#}"),
+                "error",
+                serde_json::Value::Null,
+            ),
+        }
+    }
+
+    /// `alias list`, or `alias <name> = <text>`; an empty text removes the alias.
+    fn alias_command(&mut self, msg: &InboundMessage, rest: &str) -> OutboundMessage {
+        if rest == "list" {
+            let aliases = self
+                .aliases
+                .all(&var_workspace(msg))
+                .cloned()
+                .unwrap_or_default();
+            if aliases.is_empty() {
+                return self.reply(msg, "no aliases defined", "info", json!({"aliases": {}}));
+            }
+            let mut text = String::from("aliases:\n");
+            for (name, expansion) in &aliases {
+                text.push_str(&format!("  {name} = {expansion}\n"));
+            }
+            return self.reply(msg, text, "info", json!({"aliases": aliases}));
+        }
+        let Some((name, expansion)) = rest.split_once('=') else {
+            return self.reply(
+                msg,
+                "usage: alias <name> = <text>, alias list, unalias <name>",
+                "error",
+                serde_json::Value::Null,
+            );
+        };
+        let (name, expansion) = (name.trim(), expansion.trim());
+        if matches!(name, "list" | "alias" | "unalias") {
+            return self.reply(msg, format!("{name} is reserved"), "error", serde_json::Value::Null);
+        }
+        match self.aliases.set(&var_workspace(msg), name, expansion) {
+            Ok(()) if expansion.is_empty() => {
+                self.reply(msg, format!("alias {name} removed"), "info", json!({"name": name}))
+            }
+            Ok(()) => self.reply(
+                msg,
+                format!("alias {name} = {expansion}"),
+                "info",
+                json!({"name": name, "expansion": expansion}),
+            ),
+            Err(err) => self.reply(
+                msg,
+                format!("alias failed: {err:#}"),
+                "error",
+                serde_json::Value::Null,
+            ),
+        }
+    }
+
+    /// A message whose first word is an alias becomes its text, followed by the rest of the
+    /// message. Expansions are not expanded again.
+    fn expand_alias(&self, msg: &InboundMessage) -> Option<String> {
+        let text = msg.text.trim();
+        let (name, rest) = text
+            .split_once(char::is_whitespace)
+            .map_or((text, ""), |(name, rest)| (name, rest.trim()));
+        let expansion = self.aliases.all(&var_workspace(msg))?.get(name)?;
+        Some(if rest.is_empty() {
+            expansion.clone()
+        } else {
+            format!("{expansion} {rest}")
+        })
+    }
+
+    /// `get var [name]`: one variable, or all of the workspace's.
+    fn get_var(&mut self, msg: &InboundMessage, name: &str) -> OutboundMessage {
+        let workspace_id = var_workspace(msg);
+        let vars = self.vars.all(&workspace_id).cloned().unwrap_or_default();
+        if !name.is_empty() {
+            let name = name.trim_start_matches('$');
+            return match vars.get(name) {
+                Some(value) => self.reply(
+                    msg,
+                    format!("${name} = {value}"),
+                    "info",
+                    json!({"name": name, "value": value}),
+                ),
+                None => self.reply(
+                    msg,
+                    format!("no variable ${name}"),
+                    "error",
+                    serde_json::Value::Null,
+                ),
+            };
+        }
+        if vars.is_empty() {
+            return self.reply(msg, "no variables set", "info", json!({"vars": {}}));
+        }
+        let mut text = String::from("variables:\n");
+        for (name, value) in &vars {
+            text.push_str(&format!("  ${name} = {value}\n"));
+        }
+        self.reply(msg, text, "info", json!({"vars": vars}))
+    }
+
+    /// `history [n]`: the room's last executed actions, newest first.
+    fn history_text(&self, msg: &InboundMessage, limit: usize) -> String {
+        let (workspace_id, room_id) = room_key(msg);
+        let records = self.history.recent(Some(&workspace_id), Some(&room_id), limit);
+        if records.is_empty() {
+            return "no actions have run in this room".to_string();
+        }
+        let mut text = String::from("recent actions:\n");
+        for record in records {
+            text.push_str(&record.line());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Proposes the inverse of the room's last reversible action, through the usual
+    /// approval flow. Each action is offered for undo once.
+    fn undo_last(&mut self, msg: &InboundMessage, room_cfg: &RoomConfig) -> Vec<OutboundMessage> {
+        let Some(last) = self.undoable.remove(&room_key(msg)) else {
+            return vec![self.reply(
+                msg,
+                "nothing to undo in this room",
+                "info",
+                serde_json::Value::Null,
+            )];
+        };
+        let note = self.reply(
+            msg,
+            format!("undo {}: {}", last.action, last.summary),
+            "info",
+            json!({"action": last.action, "undo": last.undo}),
+        );
+        let request = ActionRequest {
+            name: last.undo.action,
+            params: last.undo.params,
+            raw_input: msg.text.clone(),
+        };
+        let mut replies = vec![note];
+        replies.extend(self.handle_action_request(msg, request, Some(room_cfg.clone())));
+        replies
+    }
+
+    /// Drops approvals past their TTL and queues a notice for each.
+    fn expire_approvals(&mut self) {
+        for (id, pending) in self.approvals.expire(Instant::now()) {
+            if let Some(plan) = &pending.plan {
+                self.plans.remove(&plan.plan_id);
+            }
+            let age = format_age(pending.created_at.elapsed());
+            let notice = self.reply(
+                &pending.origin,
+                format!(
+                    "approval {id} for '{}' expired after {age}; ask again if it is still needed",
+                    pending.spec.name
+                ),
+                "approval_expired",
+                json!({"approval_id": id, "action": pending.spec.name}),
+            );
+            self.notices.push(notice);
+        }
+    }
+
+    fn approvals_text(&self, msg: &InboundMessage) -> String {
+        let items = self
+            .approvals
+            .in_room(msg.workspace_id.as_deref(), &msg.channel);
+        if items.is_empty() {
+            return "no pending approvals".to_string();
+        }
+        let now = Instant::now();
+        let mut text = String::from("pending approvals:\n");
+        for (id, pending) in items {
+            text.push_str(&format!(
+                "  {id}  {action}  by {sender}, {age} ago",
+                action = pending.spec.name,
+                sender = pending.origin.sender,
+                age = format_age(now.duration_since(pending.created_at)),
+            ));
+            if let Some(at) = pending.expires_at {
+                text.push_str(&format!(
+                    ", expires in {}",
+                    format_age(at.saturating_duration_since(now))
+                ));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn handle_message_with_config(
+        &mut self,
+        msg: InboundMessage,
+        room_cfg: Option<RoomConfig>,
+    ) -> Vec<OutboundMessage> {
+        self.refresh_config();
+        self.ai_answered_by = None;
+        self.ai_turn_usage.clear();
+        self.metrics.messages += 1;
+        let _span = info_span!(
+            "message",
+            id = %msg.id,
+            workspace = msg.workspace_id.as_deref(),
+            room = %msg.channel,
+            sender = %msg.sender,
+        )
+        .entered();
+        let started = Instant::now();
+        let mut replies = match self.middleware.inbound(msg.clone()) {
+            MiddlewareFlow::Continue(msg) => {
+                self.hooks.message(&msg);
+                self.dispatch_message(msg, room_cfg)
+            }
+            MiddlewareFlow::Reject(reason) => {
+                vec![self.reply(&msg, reason, "rejected", serde_json::Value::Null)]
+            }
+            MiddlewareFlow::Drop => Vec::new(),
+        };
+        if let Some(backend) = self.ai_answered_by.take() {
+            for reply in &mut replies {
+                if reply.metadata.is_object() {
+                    reply.metadata["backend"] = serde_json::Value::String(backend.clone());
+                }
+            }
+        }
+        if let Some(usage) = self.account_turn_usage(&msg) {
+            for reply in &mut replies {
+                if reply.metadata.is_object() {
+                    reply.metadata["usage"] = usage.clone();
+                }
+            }
+        }
+        let replies = self.middleware.outbound(replies);
+        let replies = self.finish_replies(replies);
+        self.metrics.message_latency.record(started.elapsed());
+        replies
     }
 
-    pub fn handle_protocol_event(&mut self, event: ProtocolEvent) -> Vec<ProtocolEvent> {
-        if event.schema_version != "robit.v1" {
+    /// The settings that apply in `scope`; `None` for the global ones.
+    pub fn effective_config(&self, scope: Option<&ConfigScope>) -> ConfigResultPayload {
+        let scope = scope.cloned().unwrap_or_default();
+        let config = self.config_store.effective_for(
+            scope.workspace_id.as_deref().unwrap_or_default(),
+            scope.room_id.as_deref().unwrap_or_default(),
+        );
+        let risk_policy = match &config.risk_policy {
+            Some(policy) => RiskPolicy {
+                low_auto_execute: Some(policy.low_auto_execute),
+                approval_for: Some(policy.approval_for.clone()),
+            },
+            None => {
+                let levels = self.ctx.policy.approval_risk_levels.clone();
+                RiskPolicy {
+                    low_auto_execute: Some(!levels.contains(&RiskLevel::Low)),
+                    approval_for: Some(levels),
+                }
+            }
+        };
+        let sorted = |names: &HashSet<String>| {
+            let mut names: Vec<String> = names.iter().cloned().collect();
+            names.sort();
+            names
+        };
+        ConfigResultPayload {
+            risk_policy,
+            action_allowlist: config.action_allowlist.as_ref().map(sorted),
+            action_denylist: config.action_denylist.as_ref().map(sorted).unwrap_or_default(),
+            dry_run_default: config.dry_run_default.unwrap_or(self.ctx.dry_run),
+            provider_binding: config.provider_binding.clone(),
+            system_prompt_extra: config.system_prompt_extra.clone(),
+            plan_preview: config.plan_preview.unwrap_or(false),
+            plan_deadline_secs: config.plan_deadline_secs,
+            approval_ttl_secs: config.approval_ttl().map(|ttl| ttl.as_secs()),
+            approvers: config.approvers.as_ref().map(sorted),
+            high_risk_approvals: config.approvals_needed(RiskLevel::High) as u32,
+            rate_limits: config.rate_limits.clone(),
+            quotas: config.quotas.clone(),
+            max_reply_chars: config.max_reply_chars.or(self.max_reply_chars),
+            reply_format: config.reply_format.or(self.reply_format).unwrap_or_default(),
+            locale: config.locale.unwrap_or(self.locale),
+            scope,
+        }
+    }
+
+    /// Messages in the stored conversation history that contain every word of `query`,
+    /// ignoring case, newest first: `room_id`'s, or every room's in the workspace when
+    /// `None`. Only the active profile's conversations in rooms the room scope allows are
+    /// searched. With the SQLite backend
+    /// that is the whole history; otherwise the recent messages kept per room.
+    pub fn search_conversations(
+        &self,
+        workspace_id: &str,
+        room_id: Option<&str>,
+        query: &str,
+        limit: usize,
+    ) -> Vec<SearchHit> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
             return Vec::new();
         }
+        let profile = self.config_source.profile();
+        let rooms = |decorated: &str| {
+            let (room, room_profile) = split_conversation_room(decorated);
+            room_profile == profile
+                && room_id.is_none_or(|wanted| wanted == room)
+                && self.scope.allows(workspace_id, room)
+        };
+        #[cfg(feature = "sqlite")]
+        let stored = match &self.conversation_persistence {
+            Some(ConversationPersistence::Sqlite(db)) => db
+                .search(workspace_id, rooms, &terms, limit)
+                .map_err(|err| warn!("context search failed: {err:#}; searching recent messages"))
+                .ok(),
+            _ => None,
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let stored = None;
+        let mut hits = match stored {
+            Some(hits) => hits,
+            None => self
+                .conversations
+                .search(|key| key.0 == workspace_id && rooms(&key.1), &terms, limit),
+        };
+        for hit in &mut hits {
+            hit.room_id = split_conversation_room(&hit.room_id).0.to_string();
+        }
+        hits
+    }
 
-        match event.body {
-            ProtocolBody::Message(payload) => {
-                if !self.scope.allows(&payload.workspace_id, &payload.room_id) {
-                    return Vec::new();
+    /// The room's conversation under the active profile (every AI backend's) and the actions
+    /// run in it, as Markdown. Secrets are redacted.
+    pub fn export_markdown(&self, workspace_id: &str, room_id: &str) -> String {
+        let profile = self.config_source.profile();
+        let rooms = |decorated: &str| split_conversation_room(decorated) == (room_id, profile);
+        #[cfg(feature = "sqlite")]
+        let stored = match &self.conversation_persistence {
+            Some(ConversationPersistence::Sqlite(db)) => db
+                .room_messages(workspace_id, rooms)
+                .map_err(|err| warn!("context export failed: {err:#}; exporting recent messages"))
+                .ok(),
+            _ => None,
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let stored = None;
+        let messages = match stored {
+            Some(messages) => messages,
+            None => self
+                .conversations
+                .room_messages(|key| key.0 == workspace_id && rooms(&key.1)),
+        };
+        let mut actions = self
+            .history
+            .recent(Some(workspace_id), Some(room_id), usize::MAX);
+        actions.reverse();
+        let text =
+            crate::format::transcript_markdown(workspace_id, room_id, profile, &messages, &actions);
+        self.redactor.text(&text)
+    }
+
+    /// `export [path.md]`: writes `export_markdown` for this room to `path`, or to a new file
+    /// in the first allowed root. The path must be allowed by policy and not exist yet.
+    fn export_reply(&mut self, msg: &InboundMessage, path: Option<&str>) -> OutboundMessage {
+        if self.ctx.policy.read_only {
+            return self.reply(
+                msg,
+                "export: read-only mode is on; it writes a file",
+                "error",
+                serde_json::Value::Null,
+            );
+        }
+        let (workspace_id, room_id) = self.conversations.key_for(msg);
+        let path = match path {
+            Some(path) => clean_path(&expand_tilde(path)),
+            None => match self.ctx.policy.allowed_roots.first() {
+                Some(root) => {
+                    let room: String = room_id
+                        .chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                        .collect();
+                    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+                    root.join(format!("robit-{room}-{stamp}.md"))
                 }
-                if self.seen_messages.contains(&payload.message_id) {
-                    return Vec::new();
+                None => {
+                    return self.reply(
+                        msg,
+                        "no allowed root to export to; use export <path.md>",
+                        "error",
+                        serde_json::Value::Null,
+                    );
                 }
-                self.seen_messages.insert(payload.message_id.clone());
-                let convo_key = self.conversation_key_parts(&payload.workspace_id, &payload.room_id);
-                if payload
-                    .metadata
-                    .get("context_only")
-                    .and_then(|value| value.as_bool())
-                    == Some(true)
-                {
-                    let role = payload
-                        .metadata
-                        .get("role")
-                        .and_then(|value| value.as_str())
-                        .map(|value| value.to_lowercase())
-                        .map(|value| if value == "assistant" { AiChatRole::Assistant } else { AiChatRole::User })
-                        .unwrap_or(AiChatRole::User);
-                    self.record_context_and_persist(&convo_key, role, &payload.text);
-                    return Vec::new();
+            },
+        };
+        let written = self
+            .ctx
+            .policy
+            .check_path_allowed(&path)
+            .and_then(|()| {
+                if path.exists() {
+                    return Err(anyhow!("{} already exists", path.display()));
                 }
-                let room_cfg = self
-                    .config_store
-                    .effective_for(&payload.workspace_id, &payload.room_id);
-                let msg = InboundMessage {
-                    id: event.id,
-                    text: payload.text,
-                    sender: payload.sender_id,
-                    channel: payload.room_id,
-                    workspace_id: Some(payload.workspace_id),
-                    metadata: payload.metadata,
-                };
-                let replies = self.handle_message_with_config(msg, Some(room_cfg.clone()));
-                replies
-                    .into_iter()
-                    .map(|reply| self.wrap_response(reply))
-                    .collect()
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, self.export_markdown(&workspace_id, &room_id))?;
+                Ok(())
+            });
+        match written {
+            Ok(()) => self.reply(
+                msg,
+                format!("exported this room's transcript to {}", path.display()),
+                "info",
+                json!({"path": path}),
+            ),
+            Err(err) => self.reply(
+                msg,
+                format!("export failed: {err:#}"),
+                "error",
+                serde_json::Value::Null,
+            ),
+        }
+    }
+
+    /// Forgets the active profile's conversation in the room, as if it had just started;
+    /// returns how many messages went.
+    pub fn clear_conversation(&mut self, workspace_id: &str, room_id: &str) -> usize {
+        let profile = self.config_source.profile().map(str::to_string);
+        let rooms = |decorated: &str| {
+            split_conversation_room(decorated) == (room_id, profile.as_deref())
+        };
+        let removed = self
+            .conversations
+            .clear(|key| key.0 == workspace_id && rooms(&key.1));
+        match &mut self.conversation_persistence {
+            None => removed,
+            Some(ConversationPersistence::Json(_)) => {
+                self.conversations_dirty_since.get_or_insert_with(Instant::now);
+                removed
             }
-            ProtocolBody::ApprovalDecision(payload) => self.handle_approval_decision(payload),
-            ProtocolBody::RoomScope(payload) => {
-                self.scope.update(payload);
-                Vec::new()
+            #[cfg(feature = "sqlite")]
+            Some(ConversationPersistence::Sqlite(db)) => match db.clear(workspace_id, rooms) {
+                Ok(deleted) => removed.max(deleted),
+                Err(err) => {
+                    warn!("context clear failed: {err:#}");
+                    removed
+                }
+            },
+        }
+    }
+
+    /// Drops conversation messages older than `max_age` in every room and profile; returns
+    /// how many went. Messages stored before times were kept stay.
+    pub fn prune_conversations(&mut self, max_age: Duration) -> usize {
+        let cutoff = unix_now().saturating_sub(max_age.as_secs());
+        let removed = self.conversations.prune_before(cutoff);
+        match &mut self.conversation_persistence {
+            None => removed,
+            Some(ConversationPersistence::Json(_)) => {
+                if removed > 0 {
+                    self.conversations_dirty_since.get_or_insert_with(Instant::now);
+                }
+                removed
             }
-            ProtocolBody::ConfigUpdate(payload) => {
-                self.config_store.apply(payload);
-                Vec::new()
+            #[cfg(feature = "sqlite")]
+            Some(ConversationPersistence::Sqlite(db)) => match db.prune_before(cutoff) {
+                Ok(deleted) => removed.max(deleted),
+                Err(err) => {
+                    warn!("context prune failed: {err:#}");
+                    removed
+                }
+            },
+        }
+    }
+
+    /// Applies `[conversation] max_age`, if set.
+    fn apply_conversation_retention(&mut self) {
+        let Some(max_age) = self.conversation_max_age else {
+            return;
+        };
+        self.conversations_pruned_at = Some(Instant::now());
+        self.prune_conversations(Duration::from_secs(max_age));
+    }
+
+    /// `search [all] <query>`: matches in this room's conversation, or every room's.
+    fn search_reply(&mut self, msg: &InboundMessage, all_rooms: bool, query: &str) -> OutboundMessage {
+        let (workspace_id, room_id) = self.conversations.key_for(msg);
+        let room = (!all_rooms).then_some(room_id.as_str());
+        let hits = self.search_conversations(&workspace_id, room, query, SEARCH_LIMIT);
+        let text = if query.trim().is_empty() {
+            "usage: search [all] <words>".to_string()
+        } else if hits.is_empty() {
+            format!("nothing in the conversation history matches {:?}", query.trim())
+        } else {
+            let mut text = format!("{} match(es) for {:?}, newest first:\n", hits.len(), query.trim());
+            for hit in &hits {
+                let at = hit.at.map(crate::history::local_time).unwrap_or_else(|| "-".to_string());
+                let who = match hit.role {
+                    AiChatRole::User => "you",
+                    AiChatRole::Assistant => "robit",
+                };
+                let snippet: String = hit.content.split_whitespace().collect::<Vec<_>>().join(" ");
+                let mut line: String = snippet.chars().take(SEARCH_SNIPPET_CHARS).collect();
+                if line.len() < snippet.len() {
+                    line.push('…');
+                }
+                if all_rooms {
+                    text.push_str(&format!("  {at}  {}  {who}: {line}\n", hit.room_id));
+                } else {
+                    text.push_str(&format!("  {at}  {who}: {line}\n"));
+                }
             }
-            ProtocolBody::ActionListRequest(_) => {
-                let actions = self.registry.list_specs();
-                vec![ProtocolEvent::new(ProtocolBody::ActionListResult(
-                    ActionListResultPayload { actions },
-                ))]
+            text
+        };
+        self.reply(msg, text, "info", json!({"hits": hits}))
+    }
+
+    /// A room's conversation and its workspace's remembered facts, for `import_conversation`
+    /// on another engine.
+    pub fn export_conversation(&self, workspace_id: &str, room_id: &str) -> ConversationDocument {
+        let key = self.conversation_key_parts(workspace_id, room_id);
+        let facts = self
+            .memory
+            .iter()
+            .flat_map(|memory| memory.entries(workspace_id, MemoryKind::Fact))
+            .map(|entry| MemoryFact {
+                source: entry.source.clone(),
+                text: entry.text.clone(),
+                created_at: entry.created_at,
+            })
+            .collect();
+        ConversationDocument {
+            format: CONVERSATION_FORMAT.to_string(),
+            exported_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            workspace_id: workspace_id.to_string(),
+            room_id: room_id.to_string(),
+            messages: self.conversations.history_for(&key),
+            facts,
+        }
+    }
+
+    /// Loads an exported conversation into the request's target room. Messages are redacted
+    /// on the way in; facts are re-embedded, and skipped when memory is off.
+    pub fn import_conversation(&mut self, request: ImportRequestPayload) -> Result<ImportResultPayload> {
+        if request.document.format != CONVERSATION_FORMAT {
+            return Err(anyhow!(
+                "unsupported conversation format {:?}; expected {CONVERSATION_FORMAT}",
+                request.document.format
+            ));
+        }
+        let (workspace_id, room_id) = request.target();
+        let (workspace_id, room_id) = (workspace_id.to_string(), room_id.to_string());
+        let key = self.conversation_key_parts(&workspace_id, &room_id);
+        let messages = request
+            .document
+            .messages
+            .into_iter()
+            .filter(|message| !message.content.trim().is_empty())
+            .map(|message| AiChatMessage {
+                role: message.role,
+                content: self.redactor.text(message.content.trim()),
+            })
+            .collect();
+        let messages = self.conversations.import(&key, messages, request.replace);
+        self.persist_room_history(&key);
+
+        let mut facts = 0;
+        if let Some(memory) = self.memory.as_mut() {
+            for fact in request.document.facts {
+                let known = memory
+                    .entries(&workspace_id, MemoryKind::Fact)
+                    .any(|entry| entry.text == fact.text.trim());
+                if known {
+                    continue;
+                }
+                match memory.remember(MemoryKind::Fact, &workspace_id, &fact.source, &fact.text) {
+                    Ok(()) => facts += 1,
+                    Err(err) => warn!("memory save failed: {err}"),
+                }
             }
-            ProtocolBody::Ping(_) => vec![ProtocolEvent::new(ProtocolBody::Pong(
-                crate::protocol::PongPayload { in_reply_to: event.id },
-            ))],
-            _ => Vec::new(),
         }
+        Ok(ImportResultPayload {
+            workspace_id,
+            room_id,
+            messages,
+            facts,
+        })
     }
-    pub fn run_with_adapter<A: Adapter>(&mut self, adapter: &mut A) -> Result<()> {
-        loop {
-            let Some(msg) = adapter.recv()? else {
-                break;
+
+    /// What a supervisor needs to see the engine is alive and what it is doing.
+    pub fn status(&mut self) -> StatusResultPayload {
+        self.expire_approvals();
+        let ai_backend = self
+            .ai_backend
+            .as_ref()
+            .map(|_| self.ai_backend_label.clone().unwrap_or_else(|| "custom".to_string()));
+        StatusResultPayload {
+            uptime_secs: self.created_at.elapsed().as_secs(),
+            ai_backend,
+            active_model: self.active_model.clone(),
+            pending_approvals: self.approvals.pending.len(),
+            running_plans: self.plans.len(),
+            dry_run: self.ctx.dry_run,
+            actions: self.registry.list_specs().len(),
+            engine: format!("robit {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    /// Counters and latencies since the engine was created.
+    pub fn metrics(&self) -> EngineMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.uptime_secs = self.created_at.elapsed().as_secs();
+        metrics
+    }
+
+    /// Renders replies in the room's format and cuts ones over its character limit down to
+    /// their first page. JSON replies are never cut.
+    fn finish_replies(&mut self, replies: Vec<OutboundMessage>) -> Vec<OutboundMessage> {
+        replies
+            .into_iter()
+            .map(|mut reply| {
+                let workspace_id = reply.workspace_id.clone().unwrap_or_default();
+                let room_cfg = self.config_store.effective_for(&workspace_id, &reply.channel);
+                let reply_format = room_cfg
+                    .reply_format
+                    .or(self.reply_format)
+                    .unwrap_or_default();
+                format::render(&mut reply, reply_format, room_cfg.locale.unwrap_or(self.locale));
+                let limit = room_cfg.max_reply_chars.or(self.max_reply_chars);
+                let Some(limit) = limit.filter(|_| reply_format != ReplyFormat::Json) else {
+                    return reply;
+                };
+                if reply.text.chars().count() <= limit {
+                    return reply;
+                }
+                let retained = RetainedReply {
+                    id: reply.id.clone(),
+                    room: (workspace_id, reply.channel.clone()),
+                    rest: std::mem::take(&mut reply.text),
+                    page: 0,
+                };
+                self.fill_page(&mut reply, retained, limit);
+                reply
+            })
+            .collect()
+    }
+
+    /// Moves the next page of `retained` into `reply` and keeps the rest, if any.
+    fn fill_page(&mut self, reply: &mut OutboundMessage, mut retained: RetainedReply, limit: usize) {
+        retained.page += 1;
+        let footer = format!("\n… show more {}", retained.id);
+        let body_limit = limit
+            .saturating_sub(footer.chars().count())
+            .max(limit / 2)
+            .max(1);
+        let (page, rest) = split_page(&retained.rest, body_limit);
+        let has_more = !rest.is_empty();
+        reply.text = page.trim_end_matches('\n').to_string();
+        if reply.metadata.is_object() {
+            reply.metadata["page"] = json!({
+                "id": retained.id,
+                "page": retained.page,
+                "more": has_more,
+            });
+        }
+        if has_more {
+            reply.text.push_str(&footer);
+            retained.rest = rest.to_string();
+            self.retained_replies.insert(retained);
+        }
+    }
+
+    /// `show more [id]`: the next page of a long reply in this room.
+    /// Which rules blocked an action and the config keys behind them.
+    fn explain_text(&self, msg: &InboundMessage, id: Option<&str>) -> String {
+        let Some(blocked) = self.blocked.get(&room_key(msg), id) else {
+            return match id {
+                Some(id) => format!("no blocked action {id} in this room"),
+                None => "nothing has been blocked in this room".to_string(),
             };
-            if msg.text.trim().is_empty() {
-                continue;
-            }
-            let responses = self.handle_message(msg);
-            for response in responses {
-                adapter.send(response)?;
-            }
+        };
+        let report = &blocked.report;
+        let mut text = format!(
+            "{}: {} ({} risk) was blocked\n",
+            blocked.id,
+            report.action,
+            risk_label(report.risk)
+        );
+        for finding in &report.findings {
+            let severity = match finding.severity {
+                Severity::Warn => " (warning)",
+                Severity::Block => "",
+            };
+            text.push_str(&format!(
+                "- {}{severity}\n  set by {}\n",
+                finding.reason, finding.config_key
+            ));
         }
-        Ok(())
+        if !report.capabilities.is_empty() {
+            text.push_str(&format!("capabilities: {}\n", report.capabilities.join(", ")));
+        }
+        if !report.paths.is_empty() {
+            text.push_str(&format!("paths: {}\n", report.paths.join(", ")));
+        }
+        let from_preflight = report
+            .findings
+            .iter()
+            .any(|finding| finding.check != CheckId::Policy);
+        if from_preflight {
+            text.push_str("[preflight] strict = true blocks on these; with strict = false they are only logged\n");
+        }
+        text
     }
 
-    fn handle_message_with_config(
+    fn show_more(&mut self, msg: &InboundMessage, id: Option<&str>) -> OutboundMessage {
+        let room = room_key(msg);
+        let Some(retained) = self.retained_replies.take(&room, id) else {
+            return self.reply(msg, "nothing more to show", "info", serde_json::Value::Null);
+        };
+        let limit = self
+            .config_store
+            .effective_for(&room.0, &room.1)
+            .max_reply_chars
+            .or(self.max_reply_chars)
+            .unwrap_or(retained.rest.chars().count());
+        let mut reply = self.reply(msg, String::new(), "info", serde_json::Value::Null);
+        self.fill_page(&mut reply, retained, limit);
+        reply
+    }
+
+    fn dispatch_message(
         &mut self,
-        msg: InboundMessage,
+        mut msg: InboundMessage,
         room_cfg: Option<RoomConfig>,
     ) -> Vec<OutboundMessage> {
+        if let Some(expanded) = self.expand_alias(&msg) {
+            msg.text = expanded;
+        }
         let text = msg.text.trim();
         if text.is_empty() {
             return Vec::new();
         }
 
         let convo_key = self.conversation_key_for(&msg);
-        let room_cfg = room_cfg.unwrap_or_default();
+        let mut room_cfg = room_cfg.unwrap_or_default();
+        if let Some(dry_run) = self.session(&msg).and_then(|session| session.dry_run) {
+            room_cfg.dry_run_default = Some(dry_run);
+        }
 
         if let Some(response) = self.handle_control(&msg) {
-            self.record_exchange_and_persist(&convo_key, text, &[response.clone()]);
+            // Kept out of the conversation: every search would find the earlier ones, and a
+            // cleared room would start with the clear.
+            if !text.starts_with("search ") && export_command(text).is_none() && text != "history clear" {
+                self.record_exchange_and_persist(&convo_key, text, &[response.clone()]);
+            }
             return vec![response];
         }
 
+        if let Some(replies) = self.handle_plan_command(&msg, &convo_key, &room_cfg) {
+            self.record_exchange_and_persist(&convo_key, text, &replies);
+            return replies;
+        }
+
+        if text == "undo" {
+            let replies = self.undo_last(&msg, &room_cfg);
+            self.record_exchange_and_persist(&convo_key, text, &replies);
+            return replies;
+        }
+
         if let Some(response) = self.handle_approval(&msg) {
             self.record_exchange_and_persist(&convo_key, text, &response);
             return response;
         }
 
+        self.learn_facts(&msg);
+
         let mut pending_for_ai = None;
-        if let Some(pending) = self.pending_inputs.remove(&convo_key) {
+        if let Some(pending) = self.session_mut(&msg).pending_input.take() {
             let ctx = self.build_context(&room_cfg);
-            if let Some(request) = self.resolve_pending_input(&pending, text, &ctx) {
-                let replies = self.handle_action_request(&msg, request, Some(room_cfg.clone()));
+            let replies = match self.resolve_pending_input(&msg, pending, text, &ctx) {
+                PendingReply::Ready(request) => {
+                    self.handle_action_request(&msg, request, Some(room_cfg.clone()))
+                }
+                PendingReply::Ask(pending, prompt) => {
+                    let data = json!({"action": pending.action, "missing": pending.missing});
+                    self.session_mut(&msg).pending_input = Some(pending);
+                    vec![self.reply(&msg, prompt, "need_input", data)]
+                }
+                PendingReply::Cancelled => {
+                    let text = self.locale_for(&msg).catalog().input_cancelled;
+                    vec![self.reply(&msg, text, "cancelled", serde_json::Value::Null)]
+                }
+                PendingReply::Unresolved(pending) => {
+                    pending_for_ai = Some(pending);
+                    Vec::new()
+                }
+            };
+            if !replies.is_empty() {
                 self.record_exchange_and_persist(&convo_key, text, &replies);
                 return replies;
             }
-            pending_for_ai = Some(pending);
+        }
+
+        let replies = match self.planners.plan(&msg) {
+            PlannerResponse::Action(request) => {
+                self.handle_action_request(&msg, request, Some(room_cfg.clone()))
+            }
+            PlannerResponse::Plan(steps) => {
+                self.propose_plan(&msg, &convo_key, steps, room_cfg.clone())
+            }
+            PlannerResponse::NeedInput { prompt } => {
+                vec![self.reply(&msg, prompt, "need_input", serde_json::Value::Null)]
+            }
+            PlannerResponse::Unknown { message } => vec![self.unknown_reply(&msg, &message)],
+            PlannerResponse::Reply(reply) => {
+                vec![self.reply(&msg, reply, "info", serde_json::Value::Null)]
+            }
+            PlannerResponse::Pass => Vec::new(),
+        };
+        if !replies.is_empty() {
+            self.record_exchange_and_persist(&convo_key, text, &replies);
+            return replies;
         }
 
         let history = self.conversations.history_for(&convo_key);
-        if let Some(ai_backend) = &self.ai_backend {
+        let mut ai_wait = None;
+        let mut ai_error = None;
+        let planner = self.planning_backend_for(&room_cfg).filter(|_| {
+            ai_wait = self.ai_calls_wait(&msg, &room_cfg);
+            ai_wait.is_none()
+        });
+        if let Some(planner) = planner {
+            let ai_backend = planner.backend.clone();
             let ai_input =
                 self.build_ai_input(text, &msg, &room_cfg, pending_for_ai.as_ref(), &history);
-            match ai_backend.plan_with_history(&ai_input, &self.registry.list_specs(), &history) {
+            let ai_options = room_cfg.ai_options();
+            let decision = self.plan_routed(&planner, &room_cfg, &ai_input, &history, &ai_options);
+            match decision {
                 Ok(AiDecision::Action(request)) => {
                     let replies = self.handle_action_request(&msg, request, Some(room_cfg.clone()));
                     self.record_exchange_and_persist(&convo_key, text, &replies);
@@ -504,31 +3340,13 @@ impl Engine {
                     params,
                     missing,
                 }) => {
-                    if let Some(action) = action {
-                        if !missing.is_empty() {
-                            self.pending_inputs.insert(
-                                convo_key.clone(),
-                                PendingInput {
-                                    action,
-                                    params,
-                                    missing,
-                                    prompt: prompt.clone(),
-                                },
-                            );
-                        }
-                    }
-                    let reply = self.reply(
-                        &msg,
-                        prompt,
-                        "need_input",
-                        serde_json::Value::Null,
-                    );
+                    let reply = self.ask_for_input(&msg, prompt, action, params, missing);
                     self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
                     return vec![reply];
                 }
                 Ok(AiDecision::Chat { message }) => {
                     let reply_text = if message.trim().is_empty() {
-                        "我在这儿，可以继续说说你的需求。".to_string()
+                        self.locale_for(&msg).catalog().chat_idle.to_string()
                     } else {
                         message
                     };
@@ -543,7 +3361,7 @@ impl Engine {
                             replies.push(self.reply(&msg, note, "plan", serde_json::Value::Null));
                         }
                     }
-                    let plan_replies = self.handle_plan_request(&msg, steps, Some(room_cfg.clone()));
+                    let plan_replies = self.propose_plan(&msg, &convo_key, steps, room_cfg.clone());
                     replies.extend(plan_replies);
                     self.record_exchange_and_persist(&convo_key, text, &replies);
                     return replies;
@@ -552,19 +3370,27 @@ impl Engine {
                     if message == "AI response format invalid; please retry." {
                         if let Some(steps) = heuristic_plan_for(text) {
                             let plan_replies =
-                                self.handle_plan_request(&msg, steps, Some(room_cfg.clone()));
+                                self.propose_plan(&msg, &convo_key, steps, room_cfg.clone());
                             self.record_exchange_and_persist(&convo_key, text, &plan_replies);
                             return plan_replies;
                         }
                         let retry_input = format!(
                             "RETRY: Return valid JSON only (no prose). Keep it minimal. {}",
-                            ai_input
+                            self.redactor.text(&ai_input)
                         );
-                        if let Ok(retry_decision) = ai_backend.plan_with_history(
+                        let _span = info_span!("ai", task = "planning", retry = true).entered();
+                        let retry = ai_backend.plan_with_options(
                             &retry_input,
-                            &self.registry.list_specs(),
-                            &history,
-                        ) {
+                            &self.available_specs(),
+                            &self.redact_history(&history),
+                            &ai_options,
+                        );
+                        self.note_ai_call(AiTask::Planning, &retry);
+                        if let Ok(retry_decision) = retry {
+                            self.note_ai_usage(ai_backend.as_ref());
+                            if let Some(backend) = ai_backend.last_backend() {
+                                self.ai_answered_by = Some(backend);
+                            }
                             if !matches!(retry_decision, AiDecision::Unknown { .. }) {
                                 match retry_decision {
                                     AiDecision::Action(request) => {
@@ -577,25 +3403,8 @@ impl Engine {
                                         return replies;
                                     }
                                     AiDecision::NeedInput { prompt, action, params, missing } => {
-                                        if let Some(action) = action {
-                                            if !missing.is_empty() {
-                                                self.pending_inputs.insert(
-                                                    convo_key.clone(),
-                                                    PendingInput {
-                                                        action,
-                                                        params,
-                                                        missing,
-                                                        prompt: prompt.clone(),
-                                                    },
-                                                );
-                                            }
-                                        }
-                                        let reply = self.reply(
-                                            &msg,
-                                            prompt,
-                                            "need_input",
-                                            serde_json::Value::Null,
-                                        );
+                                        let reply =
+                                            self.ask_for_input(&msg, prompt, action, params, missing);
                                         self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
                                         return vec![reply];
                                     }
@@ -611,7 +3420,7 @@ impl Engine {
                                                 replies.push(self.reply(&msg, note, "plan", serde_json::Value::Null));
                                             }
                                         }
-                                        let plan_replies = self.handle_plan_request(&msg, steps, Some(room_cfg.clone()));
+                                        let plan_replies = self.propose_plan(&msg, &convo_key, steps, room_cfg.clone());
                                         replies.extend(plan_replies);
                                         self.record_exchange_and_persist(&convo_key, text, &replies);
                                         return replies;
@@ -626,7 +3435,7 @@ impl Engine {
                         }
                     }
                     let reply_text = if message.trim().is_empty() {
-                        "我暂时没把握这个请求，可以再具体一点吗？".to_string()
+                        self.locale_for(&msg).catalog().chat_unsure.to_string()
                     } else {
                         message
                     };
@@ -635,38 +3444,223 @@ impl Engine {
                     return vec![reply];
                 }
                 Err(err) => {
-                    eprintln!("robit ai error: {err}");
+                    warn!("ai error: {err}");
+                    ai_error = Some(self.redactor.text(&err.to_string()));
                 }
             }
         }
 
-        match self.planner.plan(text) {
-            PlannerResponse::Action(request) => {
-                let replies = self.handle_action_request(&msg, request, Some(room_cfg.clone()));
-                self.record_exchange_and_persist(&convo_key, text, &replies);
-                replies
-            }
-            PlannerResponse::NeedInput { prompt } => {
-                let reply = self.reply(&msg, prompt, "need_input", serde_json::Value::Null);
-                self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
-                vec![reply]
+        if let Some(wait) = ai_wait {
+            let reply = self.reply(
+                &msg,
+                format!(
+                    "this room has used its AI calls for the hour; try again in {}, or use action:xxx directly",
+                    format_age(wait)
+                ),
+                "rate_limited",
+                json!({"limit": "ai_calls_per_hour", "retry_after_secs": wait.as_secs()}),
+            );
+            self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
+            return vec![reply];
+        }
+        let reply = match ai_error {
+            Some(err) => self.error_reply(
+                &msg,
+                RobitError::AiUnavailable(format!(
+                    "AI unavailable: {err}; use action:xxx directly"
+                )),
+            ),
+            None => self.unknown_reply(&msg, "no planner matched"),
+        };
+        self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
+        vec![reply]
+    }
+
+    fn unknown_reply(&mut self, msg: &InboundMessage, message: &str) -> OutboundMessage {
+        let catalog = self.locale_for(msg).catalog();
+        self.reply(
+            msg,
+            fill(catalog.unknown_request, &[("message", &message)]),
+            "unknown",
+            serde_json::Value::Null,
+        )
+    }
+
+    fn handle_control(&mut self, msg: &InboundMessage) -> Option<OutboundMessage> {
+        let role = self.sender_role(msg);
+        if let Some(needed) = control_role(msg.text.trim()).filter(|needed| role < *needed) {
+            return Some(self.reply(
+                msg,
+                format!("`{}` needs the {needed} role; you are {role}", msg.text.trim()),
+                "error",
+                json!({"role": role, "needed": needed}),
+            ));
+        }
+        if msg.text.trim() == "role" {
+            return Some(self.reply(msg, format!("you are {role}"), "info", json!({"role": role})));
+        }
+        if let Some(fact) = msg.text.trim().strip_prefix("remember ") {
+            return Some(self.remember_fact(msg, fact));
+        }
+        if msg.text.trim() == "facts" {
+            let workspace_id = msg.workspace_id.clone().unwrap_or_else(|| "default".to_string());
+            let facts = self.facts(&workspace_id);
+            let text = if facts.is_empty() {
+                "no facts remembered in this workspace".to_string()
+            } else {
+                let mut text = String::from("facts used in every room of this workspace:\n");
+                for fact in &facts {
+                    text.push_str(&format!("  {}  {}  ({})\n", fact.id, fact.text, fact.source));
+                }
+                text
+            };
+            return Some(self.reply(msg, text, "info", json!({"facts": facts})));
+        }
+        if let Some(target) = forget_command(msg.text.trim()) {
+            let workspace_id = msg.workspace_id.clone().unwrap_or_else(|| "default".to_string());
+            return Some(match self.forget_fact(&workspace_id, target) {
+                Ok(0) => self.reply(msg, "no such fact", "error", serde_json::Value::Null),
+                Ok(removed) => self.reply(
+                    msg,
+                    format!("forgot {removed} fact(s)"),
+                    "info",
+                    json!({"removed": removed}),
+                ),
+                Err(err) => self.reply(
+                    msg,
+                    format!("forget failed: {err}"),
+                    "error",
+                    serde_json::Value::Null,
+                ),
+            });
+        }
+        if let Some(arg) = msg.text.trim().strip_prefix("trust ") {
+            return Some(self.set_trust(msg, arg.trim()));
+        }
+        if let Some(rest) = msg.text.trim().strip_prefix("explain") {
+            // `explain this code` is a request, not the command.
+            let id = rest.trim();
+            if id.is_empty() || (rest.starts_with(' ') && id.starts_with("blk-") && !id.contains(' ')) {
+                let text = self.explain_text(msg, (!id.is_empty()).then_some(id));
+                return Some(self.reply(msg, text, "info", serde_json::Value::Null));
             }
-            PlannerResponse::Unknown { message } => {
-                let reply = self.reply(
-                    &msg,
-                    format!(
-                        "我还没学会处理这个请求（{message}）。可以试试输入 actions 查看动作列表，或用 action:xxx 明确指令。",
-                    ),
-                    "unknown",
+        }
+        if let Some(rest) = msg.text.trim().strip_prefix("show more") {
+            let id = rest.trim();
+            return Some(self.show_more(msg, (!id.is_empty()).then_some(id)));
+        }
+        if let Some(assignment) = msg.text.trim().strip_prefix("set var ") {
+            return Some(self.set_var(msg, assignment));
+        }
+        if let Some(rest) = msg.text.trim().strip_prefix("alias ") {
+            return Some(self.alias_command(msg, rest.trim()));
+        }
+        if let Some(name) = msg.text.trim().strip_prefix("unalias ") {
+            return Some(self.alias_command(msg, &format!("{} =", name.trim())));
+        }
+        if msg.text.trim() == "get var" {
+            return Some(self.get_var(msg, ""));
+        }
+        if let Some(name) = msg.text.trim().strip_prefix("get var ") {
+            return Some(self.get_var(msg, name.trim()));
+        }
+        if msg.text.trim() == "history clear" {
+            let (workspace_id, room_id) = self.conversations.key_for(msg);
+            let removed = self.clear_conversation(&workspace_id, &room_id);
+            return Some(self.reply(
+                msg,
+                format!("cleared {removed} message(s) from this room's conversation"),
+                "info",
+                json!({"removed": removed}),
+            ));
+        }
+        if let Some(age) = msg.text.trim().strip_prefix("history prune ") {
+            return Some(match crate::schedule::parse_interval(age) {
+                Ok(secs) => {
+                    let removed = self.prune_conversations(Duration::from_secs(secs));
+                    self.reply(
+                        msg,
+                        format!("pruned {removed} conversation message(s) older than {}", age.trim()),
+                        "info",
+                        json!({"removed": removed}),
+                    )
+                }
+                Err(err) => self.reply(
+                    msg,
+                    format!("usage: history prune <age>, e.g. 30d ({err})"),
+                    "error",
                     serde_json::Value::Null,
-                );
-                self.record_exchange_and_persist(&convo_key, text, &[reply.clone()]);
-                vec![reply]
+                ),
+            });
+        }
+        if let Some(rest) = msg.text.trim().strip_prefix("history") {
+            let rest = rest.trim();
+            let limit = if rest.is_empty() { Some(10) } else { rest.parse().ok() };
+            if let Some(limit) = limit {
+                let text = self.history_text(msg, limit);
+                return Some(self.reply(msg, text, "info", serde_json::Value::Null));
             }
         }
-    }
-
-    fn handle_control(&mut self, msg: &InboundMessage) -> Option<OutboundMessage> {
+        if let Some(path) = export_command(msg.text.trim()) {
+            return Some(self.export_reply(msg, path));
+        }
+        if let Some(query) = msg.text.trim().strip_prefix("search ") {
+            return Some(match query.trim().strip_prefix("all ") {
+                Some(query) => self.search_reply(msg, true, query),
+                None => self.search_reply(msg, false, query),
+            });
+        }
+        if let Some(id) = msg.text.trim().strip_prefix("schedule delete ") {
+            let id = id.trim();
+            let removed = self
+                .schedules
+                .remove(msg.workspace_id.as_deref(), &msg.channel, id);
+            return Some(if removed {
+                self.reply(msg, format!("schedule {id} deleted"), "info", json!({"id": id}))
+            } else {
+                self.reply(
+                    msg,
+                    format!("no schedule {id} in this room"),
+                    "error",
+                    serde_json::Value::Null,
+                )
+            });
+        }
+        if let Some(name) = msg.text.trim().strip_prefix("profile use ") {
+            let name = name.trim();
+            let profile = (name != "default").then_some(name);
+            return Some(match self.use_profile(profile) {
+                Ok(()) => self.reply(
+                    msg,
+                    format!("switched to profile {name}"),
+                    "info",
+                    json!({"profile": profile}),
+                ),
+                Err(err) => self.reply(
+                    msg,
+                    format!("profile switch failed: {err:#}"),
+                    "error",
+                    serde_json::Value::Null,
+                ),
+            });
+        }
+        if let Some(name) = msg.text.trim().strip_prefix("model use ") {
+            let name = name.trim();
+            return Some(match self.use_ai_model(name) {
+                Ok(()) => self.reply(
+                    msg,
+                    format!("ai model switched to {name}"),
+                    "info",
+                    serde_json::Value::Null,
+                ),
+                Err(err) => self.reply(
+                    msg,
+                    format!("model switch failed: {err:#}"),
+                    "error",
+                    serde_json::Value::Null,
+                ),
+            });
+        }
         match msg.text.trim() {
             "help" => Some(self.reply(
                 msg,
@@ -686,23 +3680,467 @@ impl Engine {
                 "info",
                 serde_json::Value::Null,
             )),
+            "usage" => Some(self.reply(
+                msg,
+                self.usage_text(msg),
+                "info",
+                serde_json::Value::Null,
+            )),
+            "stats" => {
+                let metrics = self.metrics();
+                Some(self.reply(msg, metrics.text(), "info", json!(metrics)))
+            }
+            "quota" => {
+                let (workspace_id, room_id) = room_key(msg);
+                let quotas = self.config_store.effective_for(&workspace_id, &room_id).quotas;
+                let text = self
+                    .quotas
+                    .text(&(workspace_id, room_id), quotas.as_ref(), Instant::now());
+                Some(self.reply(msg, text, "info", json!({"quotas": quotas})))
+            }
+            "schedules" => Some(self.reply(
+                msg,
+                self.schedules_text(msg),
+                "info",
+                serde_json::Value::Null,
+            )),
+            "approvals" => {
+                self.expire_approvals();
+                Some(self.reply(
+                    msg,
+                    self.approvals_text(msg),
+                    "info",
+                    serde_json::Value::Null,
+                ))
+            }
             "dry-run on" => {
-                self.ctx.dry_run = true;
+                self.session_mut(msg).dry_run = Some(true);
                 Some(self.reply(msg, "dry-run enabled", "info", serde_json::Value::Null))
             }
             "dry-run off" => {
-                self.ctx.dry_run = false;
+                self.session_mut(msg).dry_run = Some(false);
                 Some(self.reply(msg, "dry-run disabled", "info", serde_json::Value::Null))
             }
+            "reload" => {
+                let (text, kind) = match self.reload_config() {
+                    Ok(()) => {
+                        let paths = self.config_source.paths();
+                        if paths.is_empty() {
+                            ("no config file; using the built-in policy".to_string(), "info")
+                        } else {
+                            let paths: Vec<String> =
+                                paths.iter().map(|path| path.display().to_string()).collect();
+                            (format!("reloaded policy and preflight from {}", paths.join(", ")), "info")
+                        }
+                    }
+                    Err(err) => (format!("reload failed: {err}"), "error"),
+                };
+                Some(self.reply(msg, text, kind, serde_json::Value::Null))
+            }
+            "profile" => {
+                let current = self.profile().unwrap_or("default");
+                let (text, known) = match self.profiles() {
+                    Ok(known) if known.is_empty() => (
+                        format!("profile: {current}; the config files define no [profiles.<name>]"),
+                        known,
+                    ),
+                    Ok(known) => (
+                        format!("profile: {current} (available: default, {})", known.join(", ")),
+                        known,
+                    ),
+                    Err(err) => (format!("profile: {current}; config unreadable: {err:#}"), Vec::new()),
+                };
+                Some(self.reply(
+                    msg,
+                    text,
+                    "info",
+                    json!({"profile": self.profile(), "profiles": known}),
+                ))
+            }
+            "config show" => Some(self.reply(
+                msg,
+                self.config_source.text(),
+                "info",
+                json!({"layers": self.config_source.layers_json()}),
+            )),
+            "read-only on" | "read-only off" => {
+                let read_only = msg.text.trim() == "read-only on";
+                self.ctx.policy.read_only = read_only;
+                let text = if read_only {
+                    "read-only mode on: only low-risk read actions run"
+                } else {
+                    "read-only mode off"
+                };
+                Some(self.reply(msg, text, "info", json!({"read_only": read_only})))
+            }
             _ => None,
         }
     }
 
+    /// `plan save <name>`, `plan run <name> [key=value ...]`, `plans`, and answers to the
+    /// placeholder prompts of a running template.
+    fn handle_plan_command(
+        &mut self,
+        msg: &InboundMessage,
+        convo_key: &(String, String),
+        room_cfg: &RoomConfig,
+    ) -> Option<Vec<OutboundMessage>> {
+        let text = msg.text.trim();
+        if let Some(name) = text.strip_prefix("plan save ") {
+            return Some(vec![self.save_plan_template(msg, convo_key, name.trim())]);
+        }
+        if let Some(rest) = text.strip_prefix("plan run ") {
+            self.session_mut(msg).plan_run = None;
+            return Some(self.run_plan_template(msg, convo_key, rest, room_cfg));
+        }
+        if text == "plans" {
+            let listed = match self.plan_templates.as_ref() {
+                Some(store) => store.list(),
+                None => Err(anyhow!("plan templates are not enabled")),
+            };
+            return Some(vec![match listed {
+                Ok(names) if names.is_empty() => {
+                    self.reply(msg, "no saved plans", "info", serde_json::Value::Null)
+                }
+                Ok(names) => self.reply(
+                    msg,
+                    format!("saved plans: {}", names.join(", ")),
+                    "info",
+                    json!({"plans": names}),
+                ),
+                Err(err) => self.reply(
+                    msg,
+                    format!("plan list failed: {err:#}"),
+                    "error",
+                    serde_json::Value::Null,
+                ),
+            }]);
+        }
+        let mut pending = self.session_mut(msg).plan_run.take()?;
+        let name = pending.missing.remove(0);
+        pending.values.insert(name, text.to_string());
+        Some(self.continue_plan_run(msg, convo_key, pending, room_cfg))
+    }
+
+    fn save_plan_template(
+        &mut self,
+        msg: &InboundMessage,
+        convo_key: &(String, String),
+        name: &str,
+    ) -> OutboundMessage {
+        let Some(store) = self.plan_templates.as_ref() else {
+            return self.reply(
+                msg,
+                "plan templates are not enabled",
+                "error",
+                serde_json::Value::Null,
+            );
+        };
+        let Some(steps) = self.last_plans.get(convo_key).cloned() else {
+            return self.reply(msg, "no plan to save yet", "error", serde_json::Value::Null);
+        };
+        let total = steps.len();
+        let template = PlanTemplate {
+            name: name.to_string(),
+            steps,
+        };
+        match store.save(&template) {
+            Ok(path) => self.reply(
+                msg,
+                format!("saved plan {name} ({total} steps) to {}", path.display()),
+                "info",
+                json!({"plan": name, "path": path.display().to_string()}),
+            ),
+            Err(err) => self.reply(
+                msg,
+                format!("plan save failed: {err:#}"),
+                "error",
+                serde_json::Value::Null,
+            ),
+        }
+    }
+
+    fn run_plan_template(
+        &mut self,
+        msg: &InboundMessage,
+        convo_key: &(String, String),
+        args: &str,
+        room_cfg: &RoomConfig,
+    ) -> Vec<OutboundMessage> {
+        let mut parts = args.split_whitespace();
+        let name = parts.next().unwrap_or_default();
+        let loaded = match self.plan_templates.as_ref() {
+            Some(store) => store.load(name),
+            None => Err(anyhow!("plan templates are not enabled")),
+        };
+        let template = match loaded {
+            Ok(template) => template,
+            Err(err) => {
+                return vec![self.reply(
+                    msg,
+                    format!("plan run failed: {err:#}"),
+                    "error",
+                    serde_json::Value::Null,
+                )];
+            }
+        };
+        let values: HashMap<String, String> = parts
+            .filter_map(|part| part.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let missing = template
+            .placeholders()
+            .into_iter()
+            .filter(|name| !values.contains_key(name))
+            .collect();
+        let pending = PendingPlanRun {
+            template,
+            values,
+            missing,
+        };
+        self.continue_plan_run(msg, convo_key, pending, room_cfg)
+    }
+
+    /// Asks for the next missing placeholder, or starts the plan once all are known.
+    fn continue_plan_run(
+        &mut self,
+        msg: &InboundMessage,
+        convo_key: &(String, String),
+        pending: PendingPlanRun,
+        room_cfg: &RoomConfig,
+    ) -> Vec<OutboundMessage> {
+        if let Some(next) = pending.missing.first() {
+            let prompt = format!("plan {}: value for {next}?", pending.template.name);
+            let data = json!({"plan": pending.template.name, "missing": pending.missing});
+            self.session_mut(msg).plan_run = Some(pending);
+            return vec![self.reply(msg, prompt, "need_input", data)];
+        }
+        match fill_plan_inputs(&pending.template.steps, &pending.values) {
+            Ok(steps) => self.propose_plan(msg, convo_key, steps, room_cfg.clone()),
+            Err(err) => vec![self.reply(
+                msg,
+                format!("plan run failed: {err:#}"),
+                "error",
+                serde_json::Value::Null,
+            )],
+        }
+    }
+
+    /// `trust 10m` lets the sender's medium-risk actions in this room run without approval
+    /// until the window closes; `trust off` closes it early.
+    fn set_trust(&mut self, msg: &InboundMessage, arg: &str) -> OutboundMessage {
+        if arg == "off" {
+            self.session_mut(msg).trust_until = None;
+            self.audit(json!({
+                "event": "trust_revoked",
+                "sender": msg.sender,
+                "workspace": msg.workspace_id,
+                "room": msg.channel,
+            }));
+            return self.reply(
+                msg,
+                "trust off: approvals required again",
+                "info",
+                serde_json::Value::Null,
+            );
+        }
+        let Some(window) = parse_trust_window(arg) else {
+            return self.reply(
+                msg,
+                "usage: trust <minutes>m | <hours>h | off",
+                "error",
+                serde_json::Value::Null,
+            );
+        };
+        let window = window.min(MAX_TRUST_WINDOW);
+        let expires_at = unix_now() + window.as_secs();
+        self.session_mut(msg).trust_until = Some(Instant::now() + window);
+        self.audit(json!({
+            "event": "trust_granted",
+            "sender": msg.sender,
+            "workspace": msg.workspace_id,
+            "room": msg.channel,
+            "expires_at": expires_at,
+        }));
+        self.reply(
+            msg,
+            format!(
+                "medium-risk actions from {} in this room run without approval for {}",
+                msg.sender,
+                format_age(window)
+            ),
+            "info",
+            json!({"expires_at": expires_at}),
+        )
+    }
+
+    /// When the sender's trust window covers `spec`, the instant it closes.
+    fn trust_until(&self, msg: &InboundMessage, spec: &ActionSpec) -> Option<Instant> {
+        if spec.risk != RiskLevel::Medium {
+            return None;
+        }
+        self.session(msg)
+            .and_then(|session| session.trust_until)
+            .filter(|until| *until > Instant::now())
+    }
+
+    fn session(&self, msg: &InboundMessage) -> Option<&Session> {
+        self.sessions.get(&session_key(msg))
+    }
+
+    fn session_mut(&mut self, msg: &InboundMessage) -> &mut Session {
+        self.sessions.entry(session_key(msg)).or_default()
+    }
+
+    /// Applies the room's per-sender and per-room message limits. `Some` means the message
+    /// is dropped; only the first one over a limit gets a reply so throttling doesn't flood.
+    fn throttle_messages(
+        &mut self,
+        msg: &InboundMessage,
+        room_cfg: &RoomConfig,
+    ) -> Option<Vec<OutboundMessage>> {
+        let limits = room_cfg.rate_limits.clone()?;
+        let (workspace_id, room_id) = room_key(msg);
+        let checks = [
+            (
+                limits.sender_messages_per_minute,
+                format!("sender:{workspace_id}/{room_id}/{}", msg.sender),
+                "sender_messages_per_minute",
+                "messages a minute from you in this room",
+            ),
+            (
+                limits.room_messages_per_minute,
+                format!("room:{workspace_id}/{room_id}"),
+                "room_messages_per_minute",
+                "messages a minute in this room",
+            ),
+        ];
+        let now = Instant::now();
+        for (limit, key, name, what) in &checks {
+            let Some(limit) = *limit else {
+                continue;
+            };
+            if let Some(wait) = self.limiter.wait(key, limit, Duration::from_secs(60), now) {
+                if !self.limiter.first_throttle(key) {
+                    return Some(Vec::new());
+                }
+                let text = format!(
+                    "slow down a little: up to {limit} {what}. try again in {}",
+                    format_age(wait)
+                );
+                let data = json!({"limit": name, "retry_after_secs": wait.as_secs().max(1)});
+                return Some(vec![self.reply(msg, text, "rate_limited", data)]);
+            }
+        }
+        for (limit, key, _, _) in &checks {
+            if limit.is_some() {
+                self.limiter.record(key, now);
+            }
+        }
+        None
+    }
+
+    /// Refuses a new plan while the room already has `concurrent_plans` open.
+    fn throttle_plans(
+        &mut self,
+        msg: &InboundMessage,
+        room_cfg: &RoomConfig,
+    ) -> Option<Vec<OutboundMessage>> {
+        let limit = room_cfg.rate_limits.as_ref()?.concurrent_plans?;
+        let room = room_key(msg);
+        let open = self.plans.values().filter(|plan| plan.room == room).count();
+        if open < limit as usize {
+            return None;
+        }
+        let text = format!(
+            "this room already has {open} plan(s) running or waiting for approval; finish or deny one first"
+        );
+        let data = json!({"limit": "concurrent_plans", "open_plans": open});
+        Some(vec![self.reply(msg, text, "rate_limited", data)])
+    }
+
+    /// How long until the room may call the AI backend again, if its hourly budget is spent.
+    fn ai_calls_wait(&mut self, msg: &InboundMessage, room_cfg: &RoomConfig) -> Option<Duration> {
+        let limit = room_cfg.rate_limits.as_ref()?.ai_calls_per_hour?;
+        let (workspace_id, room_id) = room_key(msg);
+        self.limiter.wait(
+            &format!("ai:{workspace_id}/{room_id}"),
+            limit,
+            Duration::from_secs(3600),
+            Instant::now(),
+        )
+    }
+
+    /// Closes lapsed trust windows and forgets sessions with nothing left in them.
+    fn prune_sessions(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| {
+            if session.trust_until.is_some_and(|until| until <= now) {
+                session.trust_until = None;
+            }
+            !session.is_empty()
+        });
+    }
+
+    fn audit_trusted(&self, msg: &InboundMessage, spec: &ActionSpec, until: Instant) {
+        self.audit(json!({
+            "event": "trust_auto_approved",
+            "action": spec.name,
+            "sender": msg.sender,
+            "workspace": msg.workspace_id,
+            "room": msg.channel,
+            "expires_at": unix_now() + until.saturating_duration_since(Instant::now()).as_secs(),
+        }));
+    }
+
+    fn audit(&self, event: serde_json::Value) {
+        info!(target: "robit::audit", %event, "audit");
+    }
+
+    /// `remember <fact>`: a user fact for every room of the workspace, and a memory entry
+    /// for recall when memory is on.
+    fn remember_fact(&mut self, msg: &InboundMessage, fact: &str) -> OutboundMessage {
+        let workspace_id = msg.workspace_id.clone().unwrap_or_else(|| "default".to_string());
+        if let Some(memory) = self.memory.as_mut() {
+            let indexed = memory.remember(MemoryKind::Fact, &workspace_id, &msg.sender, fact);
+            if let Err(err) = indexed {
+                warn!("memory save failed: {err}");
+            }
+        }
+        match self.add_fact(&workspace_id, fact, &msg.sender) {
+            Ok(Some(id)) => self.reply(msg, format!("remembered (fact {id})"), "info", json!({"id": id})),
+            Ok(None) => self.reply(msg, "already remembered", "info", serde_json::Value::Null),
+            Err(err) => self.reply(
+                msg,
+                format!("remember failed: {err}"),
+                "error",
+                serde_json::Value::Null,
+            ),
+        }
+    }
+
+    /// Keeps statements about the user from an ordinary message as workspace facts.
+    fn learn_facts(&mut self, msg: &InboundMessage) {
+        let text = msg.text.trim();
+        // A capped role marks a machine sender, whose text reports events rather than facts.
+        let capped = self.sender_role(msg) < self.ctx.policy.role_for(&msg.sender);
+        if !self.fact_extraction || capped || text.starts_with('/') || is_explicit_action(text) {
+            return;
+        }
+        let workspace_id = msg.workspace_id.clone().unwrap_or_else(|| "default".to_string());
+        for fact in extract_facts(text) {
+            if let Err(err) = self.add_fact_from(&workspace_id, &fact, "auto", &msg.sender) {
+                warn!("facts save failed: {err}");
+            }
+        }
+    }
+
     fn handle_approval(&mut self, msg: &InboundMessage) -> Option<Vec<OutboundMessage>> {
         let trimmed = msg.text.trim();
         if trimmed.is_empty() {
             return None;
         }
+        self.expire_approvals();
         let lower = trimmed.to_lowercase();
         let explicit = lower == "approve"
             || lower == "deny"
@@ -731,6 +4169,28 @@ impl Engine {
             )]);
         };
 
+        let deny = matches!(decision, ApprovalDecision::Deny(_));
+        if let ApprovalDecision::Approve(Some(overrides))
+        | ApprovalDecision::ApproveAll(Some(overrides)) = &decision
+            && let Err(text) = self.apply_param_overrides(&pending_id, &msg.sender, overrides)
+        {
+            return Some(vec![self.reply(msg, text, "error", serde_json::Value::Null)]);
+        }
+        match self.check_approver(&pending_id, &msg.sender, deny) {
+            ApproverCheck::Proceed => {}
+            ApproverCheck::Forbidden(text) => {
+                return Some(vec![self.reply(msg, text, "error", serde_json::Value::Null)]);
+            }
+            ApproverCheck::Waiting(text) => {
+                return Some(vec![self.reply(
+                    msg,
+                    text,
+                    "approval_pending",
+                    json!({"approval_id": pending_id}),
+                )]);
+            }
+        }
+
         let Some(pending) = self.approvals.take(&pending_id) else {
             return Some(vec![self.reply(
                 msg,
@@ -741,29 +4201,43 @@ impl Engine {
         };
 
         match decision {
-            ApprovalDecision::Deny => Some(vec![self.reply(
-                msg,
-                format!("action '{}' cancelled", pending.spec.name),
-                "cancelled",
-                serde_json::Value::Null,
-            )]),
-            ApprovalDecision::Approve | ApprovalDecision::ApproveAll => {
+            ApprovalDecision::Deny(reason) => {
+                self.metrics.approvals_denied += 1;
+                let mut replies = vec![self.reply(
+                    msg,
+                    format!("action '{}' cancelled", pending.spec.name),
+                    "cancelled",
+                    serde_json::Value::Null,
+                )];
+                if let Some(reason) = reason {
+                    replies.extend(self.replan_after_denial(msg, &pending, &reason));
+                }
+                Some(replies)
+            }
+            ApprovalDecision::Approve(_) | ApprovalDecision::ApproveAll(_) => {
+                self.metrics.approvals_granted += 1;
                 let mut plan_ctx = pending.plan;
                 let has_plan = plan_ctx.is_some();
-                if let (ApprovalDecision::ApproveAll, Some(plan)) = (&decision, plan_ctx.as_mut()) {
+                if let (ApprovalDecision::ApproveAll(_), Some(plan)) = (&decision, plan_ctx.as_mut()) {
                     plan.auto_approve = true;
                 }
-                let mut outcomes = self.execute_action(
+                let policy = plan_ctx.as_ref().map_or_else(StepPolicy::default, |plan| {
+                    StepPolicy::for_step(&plan.step, self.plan_time_left(&plan.plan_id))
+                });
+                let started = Instant::now();
+                let mut outcomes = self.execute_action_with_policy(
                     &pending.request,
                     &pending.spec,
                     msg,
                     Some(pending.config.clone()),
+                    &policy,
+                    plan_ctx.as_ref(),
                 );
                 if let Some(plan) = plan_ctx.as_ref() {
-                    if let Some(outcome) = extract_outcome_from_replies(&outcomes) {
-                        self.record_plan_result(&plan.plan_id, &pending.spec.name, &outcome);
-                    }
+                    self.add_plan_busy(&plan.plan_id, started.elapsed());
+                    self.record_approved_step(plan, &pending.spec.name, &outcomes);
                 }
+                let mut rollback = Vec::new();
                 if let Some(plan) = plan_ctx {
                     let succeeded = outcomes.iter().any(|reply| {
                         reply
@@ -783,12 +4257,17 @@ impl Engine {
                             plan.total_steps,
                         );
                         outcomes.append(&mut more);
-                    } else if let Some(summary) = self.finish_plan(&plan.plan_id, msg, true) {
-                        outcomes.push(summary);
+                    } else {
+                        rollback = self.offer_rollback(msg, &plan.plan_id, &pending.config);
+                        if let Some(summary) = self.finish_plan(&plan.plan_id, msg, true) {
+                            outcomes.push(summary);
+                        }
                     }
                 }
                 if has_plan {
-                    Some(filter_plan_result_replies(outcomes))
+                    let mut replies = filter_plan_result_replies(outcomes);
+                    replies.extend(rollback);
+                    Some(replies)
                 } else {
                     Some(outcomes)
                 }
@@ -796,10 +4275,187 @@ impl Engine {
         }
     }
 
-    fn handle_plan_request(
+    /// Hands a denied action and the user's reason back to the planner so it can propose
+    /// something else. Without an AI backend the denial just cancels.
+    fn replan_after_denial(
+        &mut self,
+        msg: &InboundMessage,
+        pending: &PendingAction,
+        reason: &str,
+    ) -> Vec<OutboundMessage> {
+        let room_cfg = pending.config.clone();
+        let Some(planner) = self.planning_backend_for(&room_cfg) else {
+            return Vec::new();
+        };
+        if let Some(plan) = &pending.plan {
+            self.plans.remove(&plan.plan_id);
+        }
+        let convo_key = self.conversation_key_for(msg);
+        let history = self.conversations.history_for(&convo_key);
+        let feedback = format!(
+            "The user denied {action} with params {params} for the request \"{request}\".\nReason: {reason}\nPropose a corrected action or plan for the original request.",
+            action = pending.spec.name,
+            params = pending.request.params,
+            request = pending.request.raw_input,
+        );
+        let ai_input = self.build_ai_input(&feedback, msg, &room_cfg, None, &history);
+        let ai_options = room_cfg.ai_options();
+        match self.plan_routed(&planner, &room_cfg, &ai_input, &history, &ai_options) {
+            Ok(AiDecision::Action(request)) => {
+                self.handle_action_request(msg, request, Some(room_cfg))
+            }
+            Ok(AiDecision::Plan { steps, message }) => {
+                let mut replies = Vec::new();
+                if let Some(note) = message.filter(|note| !note.trim().is_empty()) {
+                    replies.push(self.reply(msg, note, "plan", serde_json::Value::Null));
+                }
+                replies.extend(self.propose_plan(msg, &convo_key, steps, room_cfg));
+                replies
+            }
+            Ok(AiDecision::NeedInput {
+                prompt,
+                action,
+                params,
+                missing,
+            }) => {
+                vec![self.ask_for_input(msg, prompt, action, params, missing)]
+            }
+            Ok(AiDecision::Chat { message }) | Ok(AiDecision::Unknown { message }) => {
+                vec![self.reply(msg, message, "chat", serde_json::Value::Null)]
+            }
+            Err(err) => {
+                warn!("replan after denial failed: {err}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Patches a pending request's params and re-runs preflight and validation. On failure
+    /// the approval stays pending as it was.
+    fn apply_param_overrides(
+        &mut self,
+        id: &str,
+        approver: &str,
+        overrides: &serde_json::Value,
+    ) -> Result<(), String> {
+        let Some(pending) = self.approvals.pending.get(id) else {
+            return Ok(());
+        };
+        // Checked before anything is patched: a sender who may not approve must not be able to
+        // change what the next approval runs.
+        if let Some(refusal) = self.approve_refusal(pending, approver) {
+            return Err(refusal);
+        }
+        let Some(fields) = overrides.as_object().filter(|fields| !fields.is_empty()) else {
+            return Err("no param overrides recognized; use key=value or a JSON object".to_string());
+        };
+        let mut params = match &pending.request.params {
+            serde_json::Value::Object(map) => map.clone(),
+            _ => serde_json::Map::new(),
+        };
+        for (key, value) in fields {
+            params.insert(key.clone(), value.clone());
+        }
+        let params = serde_json::Value::Object(params);
+        let Some(action) = self.registry.get(&pending.spec.name) else {
+            return Err(format!("unknown action: {}", pending.spec.name));
+        };
+        let ctx = self.build_context(&pending.config);
+        let room = room_key(&pending.origin);
+        let report = match self.preflight.check(&pending.spec, &params, &ctx) {
+            Ok(report) => report,
+            Err(err) => {
+                let report = self.preflight.refusal(&pending.spec, &ctx);
+                return Err(self.blocked.keep(room, report, format!("preflight failed: {err}")));
+            }
+        };
+        self.log_preflight(&report);
+        if !report.allowed && self.preflight.config().strict {
+            let text = format!("preflight blocked: {}", report.summary());
+            return Err(self.blocked.keep(room, report, text));
+        }
+        action
+            .validate(&ctx, &params)
+            .map_err(|err| format!("validation failed: {err}"))?;
+        if let Some(pending) = self.approvals.pending.get_mut(id) {
+            pending.request.params = params;
+            // Earlier votes approved different params.
+            pending.approved_by.clear();
+        }
+        self.approvals.save();
+        Ok(())
+    }
+
+    /// Why `approver` may not approve `pending`, if they may not.
+    fn approve_refusal(&self, pending: &PendingAction, approver: &str) -> Option<String> {
+        if self.ctx.policy.role_for(approver) == Role::Guest {
+            return Some(format!("{approver} is a guest and may not approve"));
+        }
+        if !pending.config.allows_approver(approver) {
+            return Some(format!("{approver} is not an approver in this room"));
+        }
+        None
+    }
+
+    /// Enforces the room's approvers. Approving a high-risk action that needs several distinct
+    /// approvers records the vote and waits until enough have approved. The requester may
+    /// always deny their own action.
+    fn check_approver(&mut self, id: &str, approver: &str, deny: bool) -> ApproverCheck {
+        if !deny && self.ctx.policy.role_for(approver) == Role::Guest {
+            return ApproverCheck::Forbidden(format!("{approver} is a guest and may not approve"));
+        }
+        let Some(needed) = self
+            .approvals
+            .pending
+            .get(id)
+            .map(|pending| self.approvals_needed(&pending.spec, &pending.config))
+        else {
+            return ApproverCheck::Proceed;
+        };
+        let Some(pending) = self.approvals.pending.get_mut(id) else {
+            return ApproverCheck::Proceed;
+        };
+        let listed = pending.config.allows_approver(approver);
+        if deny {
+            if listed || pending.origin.sender == approver {
+                return ApproverCheck::Proceed;
+            }
+            return ApproverCheck::Forbidden(format!("{approver} may not deny {id}"));
+        }
+        if !listed {
+            return ApproverCheck::Forbidden(format!(
+                "{approver} is not an approver in this room"
+            ));
+        }
+        if !pending.approved_by.iter().any(|name| name == approver) {
+            pending.approved_by.push(approver.to_string());
+        }
+        let have = pending.approved_by.len();
+        if have >= needed {
+            return ApproverCheck::Proceed;
+        }
+        self.approvals.save();
+        ApproverCheck::Waiting(format!(
+            "approval {id} recorded ({have}/{needed}); waiting for another approver"
+        ))
+    }
+
+    /// Starts a plan and keeps its steps for `plan save`.
+    fn propose_plan(
         &mut self,
         msg: &InboundMessage,
+        convo_key: &(String, String),
         steps: Vec<PlanStep>,
+        room_cfg: RoomConfig,
+    ) -> Vec<OutboundMessage> {
+        self.last_plans.insert(convo_key.clone(), steps.clone());
+        self.handle_plan_request(msg, steps, Some(room_cfg))
+    }
+
+    fn handle_plan_request(
+        &mut self,
+        msg: &InboundMessage,
+        mut steps: Vec<PlanStep>,
         room_cfg: Option<RoomConfig>,
     ) -> Vec<OutboundMessage> {
         if steps.is_empty() {
@@ -810,9 +4466,34 @@ impl Engine {
                 serde_json::Value::Null,
             )];
         }
-        let plan_id = self.next_plan_id();
-        self.start_plan_progress(&plan_id, steps.len());
+        // Fix positional ids before foreach expansion shifts the positions.
+        for (idx, step) in steps.iter_mut().enumerate() {
+            step.id.get_or_insert_with(|| format!("s{}", idx + 1));
+        }
         let room_cfg = room_cfg.unwrap_or_default();
+        if room_cfg.plan_preview == Some(true) {
+            steps = match expand_known_foreach(steps) {
+                Ok(steps) => steps,
+                Err(err) => {
+                    return vec![self.error_reply(msg, RobitError::ValidationFailed(err.to_string()))];
+                }
+            };
+        }
+        if let Some(replies) = self.throttle_plans(msg, &room_cfg) {
+            return replies;
+        }
+        let plan_id = self.next_plan_id();
+        self.start_plan_progress(
+            &plan_id,
+            room_key(msg),
+            steps.len(),
+            room_cfg.plan_deadline_secs.map(Duration::from_secs),
+        );
+        if room_cfg.plan_preview == Some(true)
+            && let Some(replies) = self.preview_plan(msg, &plan_id, &steps, &room_cfg)
+        {
+            return replies;
+        }
         let total_steps = steps.len();
         self.execute_plan_steps(
             msg,
@@ -825,15 +4506,166 @@ impl Engine {
         )
     }
 
+    /// Checks every step up front and asks for one approval covering the whole plan.
+    /// Returns `None` when no step needs approval, so the plan just runs.
+    fn preview_plan(
+        &mut self,
+        msg: &InboundMessage,
+        plan_id: &str,
+        steps: &[PlanStep],
+        room_cfg: &RoomConfig,
+    ) -> Option<Vec<OutboundMessage>> {
+        let ctx = self.build_context(room_cfg);
+        let catalog = room_cfg.locale.unwrap_or(self.locale).catalog();
+        let mut needs_approval = false;
+        let mut lines = Vec::with_capacity(steps.len());
+        let mut preview = Vec::with_capacity(steps.len());
+        for (idx, step) in steps.iter().enumerate() {
+            let step_no = idx + 1;
+            let found = match self.registry.get(&step.action) {
+                None => Err(RobitError::ValidationFailed(format!(
+                    "unknown action in plan: {}",
+                    step.action
+                ))),
+                Some(action) => {
+                    let spec = action.spec();
+                    if !room_cfg.allows_action(&spec.name) {
+                        Err(RobitError::PolicyDenied(format!("action not allowed: {}", spec.name)))
+                    } else if let Some(refusal) = self.role_refusal(msg, &spec) {
+                        Err(RobitError::PolicyDenied(refusal))
+                    } else {
+                        Ok((action, spec))
+                    }
+                }
+            };
+            let (action, spec) = match found {
+                Ok(found) => found,
+                Err(err) => {
+                    self.plans.remove(plan_id);
+                    return Some(vec![self.error_reply(msg, err)]);
+                }
+            };
+            needs_approval |=
+                self.requires_approval(&spec, room_cfg) || step.requires_approval == Some(true);
+            // Params that reference earlier results are only known once those steps ran.
+            let deferred =
+                step.foreach.is_some() || step.params.to_string().contains("{{steps.");
+            let preflight_text = if deferred {
+                "deferred".to_string()
+            } else {
+                let mut report = match self.preflight.check(&spec, &step.params, &ctx) {
+                    Ok(report) => report,
+                    Err(err) => {
+                        self.plans.remove(plan_id);
+                        let report = self.preflight.refusal(&spec, &ctx);
+                        let text = format!("preflight failed: step {step_no}: {err}");
+                        let text = self.blocked.keep(room_key(msg), report, text);
+                        return Some(vec![self.error_reply(msg, RobitError::PolicyDenied(text))]);
+                    }
+                };
+                self.log_preflight(&report);
+                if !report.allowed && self.preflight.config().strict {
+                    self.plans.remove(plan_id);
+                    let text = format!("preflight blocked: step {step_no}: {}", report.summary());
+                    let text = self.blocked.keep(room_key(msg), report, text);
+                    return Some(vec![self.error_reply(msg, RobitError::PreflightBlocked(text))]);
+                }
+                // Warnings are overridden only by approving them.
+                needs_approval |= !report.warnings.is_empty();
+                if let Err(err) = action.validate(&ctx, &step.params) {
+                    self.plans.remove(plan_id);
+                    return Some(vec![self.error_reply(
+                        msg,
+                        RobitError::ValidationFailed(format!("validation failed: step {step_no}: {err}")),
+                    )]);
+                }
+                report.change = action.preview(&ctx, &step.params);
+                match &report.change {
+                    Some(change) => format!("{}; {}", report.summary(), change.summary),
+                    None => report.summary(),
+                }
+            };
+            let risk = risk_label(spec.risk);
+            let mut line = format!(
+                "{step_no}. {name}  {risk_field}  {preflight_field}\n   {params_field}",
+                name = spec.name,
+                risk_field = catalog.field(catalog.risk, risk),
+                preflight_field = catalog.field(catalog.preflight, &preflight_text),
+                params_field = catalog.field(catalog.params, format_params_compact(&step.params)),
+            );
+            if let Some(note) = step.note.as_deref().filter(|note| !note.trim().is_empty()) {
+                line.push_str(&format!("\n   {}", catalog.field(catalog.note, note)));
+            }
+            lines.push(line);
+            preview.push(json!({
+                "step": step_no,
+                "action": spec.name,
+                "risk": risk,
+                "params": step.params,
+                "preflight": preflight_text,
+            }));
+        }
+        if !needs_approval {
+            return None;
+        }
+
+        let first = steps[0].clone();
+        let step_key = first.id.clone().unwrap_or_else(|| "s1".to_string());
+        let params = match resolve_step_templates(&first.params, &[]) {
+            Ok(params) => params,
+            Err(err) => {
+                self.plans.remove(plan_id);
+                return Some(vec![self.error_reply(
+                    msg,
+                    RobitError::ValidationFailed(format!("plan step {step_key}: {err}")),
+                )]);
+            }
+        };
+        let spec = self.registry.get(&first.action)?.spec();
+        let plan_ctx = PlanContext {
+            plan_id: plan_id.to_string(),
+            step_key,
+            step: first.clone(),
+            remaining: steps[1..].to_vec(),
+            auto_approve: true,
+            completed_steps: 0,
+            total_steps: steps.len(),
+        };
+        let request = ActionRequest {
+            name: first.action,
+            params,
+            raw_input: msg.text.clone(),
+        };
+        let approval_id =
+            self.approvals
+                .create(msg, request, spec, room_cfg.clone(), Some(plan_ctx));
+        self.metrics.approvals_requested += 1;
+        let text = format!(
+            "{heading}\n{steps}\n{hint}",
+            heading = fill(
+                catalog.plan_approval,
+                &[("plan_id", &plan_id), ("total", &steps.len()), ("dry_run", &ctx.dry_run)],
+            ),
+            steps = lines.join("\n"),
+            hint = fill(catalog.plan_approve_hint, &[("id", &approval_id)]),
+        );
+        Some(vec![self.reply(
+            msg,
+            text,
+            "approval_request",
+            json!({"approval_id": approval_id, "plan_id": plan_id, "preview": preview}),
+        )])
+    }
+
     fn execute_plan_steps(
         &mut self,
         msg: &InboundMessage,
-        steps: Vec<PlanStep>,
+        mut steps: Vec<PlanStep>,
         room_cfg: RoomConfig,
         auto_approve: bool,
         plan_id: Option<String>,
         completed_steps: usize,
-        total_steps: usize,
+        mut total_steps: usize,
     ) -> Vec<OutboundMessage> {
         let mut replies = Vec::new();
         let mut completed = completed_steps;
@@ -845,159 +4677,375 @@ impl Engine {
         while index < steps.len() {
             let step = steps[index].clone();
             let step_no = completed + 1;
+            let step_key = step.id.clone().unwrap_or_else(|| format!("s{step_no}"));
+            let results = self
+                .plans
+                .get(&plan_label)
+                .map(|plan| plan.results.as_slice())
+                .unwrap_or(&[]);
+            if let Some(foreach) = &step.foreach {
+                let expanded = match expand_foreach(&step, &step_key, foreach, results) {
+                    Ok(expanded) => expanded,
+                    Err(err) => {
+                        let text = format!("plan step {step_key}: foreach: {err}");
+                        self.note_plan_failure(&plan_label, &step_key, &step.action, &text);
+                        replies.push(self.error_reply(msg, RobitError::ValidationFailed(text)));
+                        stopped_early = true;
+                        break;
+                    }
+                };
+                total_steps = total_steps + expanded.len() - 1;
+                if let Some(plan) = self.plans.get_mut(&plan_label) {
+                    plan.total_steps = total_steps;
+                }
+                steps.splice(index..=index, expanded);
+                continue;
+            }
+            let params = match resolve_step_templates(&step.params, results) {
+                Ok(params) => params,
+                Err(err) => {
+                    replies.push(self.error_reply(
+                        msg,
+                        RobitError::ValidationFailed(format!("plan step {step_key}: {err}")),
+                    ));
+                    stopped_early = true;
+                    break;
+                }
+            };
             let request = ActionRequest {
                 name: step.action.clone(),
-                params: step.params.clone(),
+                params: params.clone(),
                 raw_input: msg.text.clone(),
             };
             let Some(action) = self.registry.get(&request.name) else {
-                replies.push(self.reply(
+                replies.push(self.error_reply(
                     msg,
-                    format!("unknown action in plan: {}", request.name),
-                    "error",
-                    serde_json::Value::Null,
+                    RobitError::ValidationFailed(format!("unknown action in plan: {}", request.name)),
                 ));
                 break;
             };
             let spec = action.spec();
             if !room_cfg.allows_action(&spec.name) {
-                replies.push(self.reply(
+                replies.push(self.error_reply(
                     msg,
-                    format!("action not allowed: {}", spec.name),
-                    "error",
-                    serde_json::Value::Null,
+                    RobitError::PolicyDenied(format!("action not allowed: {}", spec.name)),
                 ));
                 break;
             }
+            if let Some(refusal) = self.role_refusal(msg, &spec) {
+                replies.push(self.error_reply(msg, RobitError::PolicyDenied(refusal)));
+                break;
+            }
             let mut needs_approval = self.requires_approval(&spec, &room_cfg);
+            let trusted_until = needs_approval
+                .then(|| self.trust_until(msg, &spec))
+                .flatten();
+            if trusted_until.is_some() {
+                needs_approval = false;
+            }
             if step.requires_approval == Some(true) {
                 needs_approval = true;
             }
             let ctx = self.build_context(&room_cfg);
-            let preflight = match self.preflight.check(&spec, &request.params, &ctx) {
+            let mut preflight = match self.preflight.check(&spec, &request.params, &ctx) {
                 Ok(report) => report,
                 Err(err) => {
-                    replies.push(self.reply(
-                        msg,
-                        format!("preflight failed: {err}"),
-                        "error",
-                        serde_json::Value::Null,
-                    ));
+                    let report = self.preflight.refusal(&spec, &ctx);
+                    let text =
+                        self.blocked.keep(room_key(msg), report, format!("preflight failed: {err}"));
+                    replies.push(self.error_reply(msg, RobitError::PolicyDenied(text)));
                     break;
                 }
             };
             self.log_preflight(&preflight);
             if !preflight.allowed && self.preflight.config().strict {
-                replies.push(self.reply(
-                    msg,
-                    format!("preflight blocked: {}", preflight.summary()),
-                    "error",
-                    serde_json::Value::Null,
-                ));
+                let text = format!("preflight blocked: {}", preflight.summary());
+                let text = self.blocked.keep(room_key(msg), preflight, text);
+                replies.push(self.error_reply(msg, RobitError::PreflightBlocked(text)));
                 break;
             }
+            let warned = !preflight.warnings.is_empty();
+            needs_approval |= warned;
             if let Err(err) = action.validate(&ctx, &request.params) {
-                replies.push(self.reply(
+                replies.push(self.error_reply(
                     msg,
-                    format!("validation failed: {err}"),
-                    "error",
-                    serde_json::Value::Null,
+                    RobitError::ValidationFailed(format!("validation failed: {err}")),
                 ));
                 break;
             }
 
-            if needs_approval && !auto_approve {
+            // Approve-all does not stand in for the extra approvers a step needs.
+            let auto_approved =
+                auto_approve && !warned && self.approvals_needed(&spec, &room_cfg) <= 1;
+            if needs_approval && !auto_approved {
+                preflight.change = action.preview(&ctx, &params);
                 let remaining = steps[index + 1..].to_vec();
                 let plan_ctx = PlanContext {
                     plan_id: plan_label.clone(),
+                    step_key: step_key.clone(),
+                    step: step.clone(),
                     remaining,
                     auto_approve: false,
                     completed_steps: completed,
                     total_steps,
                 };
                 let approval_id = self.approvals.create(
-                    &msg.sender,
+                    msg,
                     request,
                     spec.clone(),
                     room_cfg.clone(),
                     Some(plan_ctx),
                 );
+                self.metrics.approvals_requested += 1;
+                self.hooks.approval_created(&ApprovalEvent {
+                    approval_id: &approval_id,
+                    action: &spec.name,
+                    params: &params,
+                    risk: spec.risk,
+                    msg,
+                    plan_id: Some(&plan_label),
+                });
                 let hint = PlanApprovalHint {
                     plan_id: plan_label.clone(),
                     step_index: step_no,
                     total_steps,
                     allow_approve_all: true,
                 };
-                let text = format_approval_prompt(
+                let review = self.critique_action(msg, &spec, &params);
+                let prompt = format_approval_prompt(
                     &spec,
-                    &step.params,
+                    &params,
                     &ctx,
                     &approval_id,
                     Some(&preflight),
                     Some(hint),
+                    review.as_ref(),
                 );
-                replies.push(self.reply(
-                    msg,
-                    text,
-                    "approval_request",
-                    json!({"approval_id": approval_id, "plan_id": plan_label, "step": step_no}),
-                ));
+                let text = prompt.plain(self.locale_for(msg));
+                let mut data = json!({
+                    "approval_id": approval_id,
+                    "plan_id": plan_label,
+                    "step": step_no,
+                    "prompt": prompt,
+                });
+                if let Some(review) = &review {
+                    data["critic"] = json!(review);
+                }
+                replies.push(self.reply(msg, text, "approval_request", data));
                 awaiting_approval = true;
                 break;
             }
 
-            match action.execute(&ctx, &request.params) {
+            if let Some(until) = trusted_until.filter(|_| !needs_approval) {
+                self.audit_trusted(msg, &spec, until);
+            }
+            let event = ActionEvent {
+                action: &spec.name,
+                params: &request.params,
+                risk: spec.risk,
+                msg,
+                plan_id: (!plan_label.is_empty()).then_some(plan_label.as_str()),
+                step_id: Some(&step_key),
+            };
+            if let Some(reason) = self.hooks.action_start(&event) {
+                self.note_plan_failure(&plan_label, &step_key, &spec.name, &reason);
+                replies.push(self.error_reply(msg, RobitError::PolicyDenied(reason)));
+                stopped_early = true;
+                break;
+            }
+            let policy = StepPolicy::for_step(&step, self.plan_time_left(&plan_label))
+                .or_timeout(self.action_timeout);
+            let started = Instant::now();
+            let (result, attempts) = self.run_action(
+                &action,
+                msg,
+                &ctx,
+                &request.params,
+                &policy,
+                room_cfg.quotas.as_ref(),
+            );
+            self.add_plan_busy(&plan_label, started.elapsed());
+            self.finish_action(&event, &result, started.elapsed());
+            match result {
                 Ok(outcome) => {
-                    self.record_plan_result(&plan_label, &spec.name, &outcome);
+                    self.record_plan_result(&plan_label, &step_key, &spec.name, &outcome, attempts);
+                    if let Some(undo) = &step.undo {
+                        self.push_compensation(&plan_label, &step_key, undo);
+                    }
                     replies.push(self.reply_with_outcome(msg, outcome, &spec));
                     completed += 1;
                     index += 1;
                 }
                 Err(err) => {
-                    replies.push(self.reply(
-                        msg,
-                        format!("error: {err}"),
-                        "error",
-                        serde_json::Value::Null,
-                    ));
+                    let text = execution_error_text(&err, attempts);
+                    self.note_plan_failure(&plan_label, &step_key, &spec.name, &text);
+                    replies.push(self.error_reply(msg, RobitError::ActionFailed(text)));
                     stopped_early = true;
                     break;
                 }
             }
         }
 
+        let mut rollback = Vec::new();
         if !awaiting_approval && !plan_label.is_empty() {
+            if stopped_early {
+                rollback = self.offer_rollback(msg, &plan_label, &room_cfg);
+            }
             if let Some(summary) = self.finish_plan(&plan_label, msg, stopped_early) {
                 replies.push(summary);
             }
         }
 
-        if !plan_label.is_empty() {
+        let mut replies = if !plan_label.is_empty() {
             filter_plan_result_replies(replies)
         } else {
             replies
-        }
+        };
+        replies.extend(rollback);
+        replies
     }
 
-    fn start_plan_progress(&mut self, plan_id: &str, total_steps: usize) {
+    fn start_plan_progress(
+        &mut self,
+        plan_id: &str,
+        room: (String, String),
+        total_steps: usize,
+        deadline: Option<Duration>,
+    ) {
         self.plans.entry(plan_id.to_string()).or_insert(PlanProgress {
             id: plan_id.to_string(),
+            room,
             total_steps,
             results: Vec::new(),
+            failure: None,
+            compensations: Vec::new(),
+            deadline,
+            busy: Duration::ZERO,
         });
     }
 
-    fn record_plan_result(&mut self, plan_id: &str, action: &str, outcome: &ActionOutcome) {
+    fn record_plan_result(
+        &mut self,
+        plan_id: &str,
+        step: &str,
+        action: &str,
+        outcome: &ActionOutcome,
+        attempts: u32,
+    ) {
         let Some(plan) = self.plans.get_mut(plan_id) else {
             return;
         };
         plan.results.push(PlanResultItem {
+            step: step.to_string(),
             action: action.to_string(),
             summary: outcome.summary.clone(),
             data: outcome.data.clone(),
+            attempts,
         });
     }
 
+    /// `None` when the plan has no deadline.
+    fn plan_time_left(&self, plan_id: &str) -> Option<Duration> {
+        let plan = self.plans.get(plan_id)?;
+        plan.deadline
+            .map(|deadline| deadline.saturating_sub(plan.busy))
+    }
+
+    fn add_plan_busy(&mut self, plan_id: &str, elapsed: Duration) {
+        if let Some(plan) = self.plans.get_mut(plan_id) {
+            plan.busy += elapsed;
+        }
+    }
+
+    fn note_plan_failure(&mut self, plan_id: &str, step: &str, action: &str, error: &str) {
+        if let Some(plan) = self.plans.get_mut(plan_id) {
+            plan.failure = Some(format!("{step} ({action}) {error}"));
+        }
+    }
+
+    /// Queues a completed step's undo, resolved against the results so far.
+    fn push_compensation(&mut self, plan_id: &str, step: &str, undo: &UndoStep) {
+        let Some(plan) = self.plans.get_mut(plan_id) else {
+            return;
+        };
+        match resolve_step_templates(&undo.params, &plan.results) {
+            Ok(params) => plan.compensations.push(PlanStep {
+                id: Some(format!("undo-{step}")),
+                action: undo.action.clone(),
+                params,
+                note: Some(format!("undo {step}")),
+                requires_approval: Some(true),
+                retries: None,
+                retry_delay: None,
+                timeout_secs: None,
+                undo: None,
+                foreach: None,
+            }),
+            Err(err) => warn!("plan {plan_id}: undo for {step} dropped: {err}"),
+        }
+    }
+
+    /// After a plan fails, offers to run the undo steps of its completed steps, newest
+    /// first, as a new plan that asks for approval.
+    fn offer_rollback(
+        &mut self,
+        msg: &InboundMessage,
+        plan_id: &str,
+        room_cfg: &RoomConfig,
+    ) -> Vec<OutboundMessage> {
+        let Some(plan) = self.plans.get_mut(plan_id) else {
+            return Vec::new();
+        };
+        if std::mem::take(&mut self.timed_out_running) && !plan.compensations.is_empty() {
+            // Undoing steps while the timed-out one still changes things could leave a mix.
+            return vec![self.reply(
+                msg,
+                format!(
+                    "plan {plan_id} stopped; its timed-out step may still be running, so no \
+                     rollback is offered. Check its effects, then use `undo`."
+                ),
+                "info",
+                json!({"plan_id": plan_id}),
+            )];
+        }
+        let mut steps = std::mem::take(&mut plan.compensations);
+        if steps.is_empty() {
+            return Vec::new();
+        }
+        steps.reverse();
+        let mut replies = vec![self.reply(
+            msg,
+            format!(
+                "plan {plan_id} stopped; {} completed step(s) can be rolled back",
+                steps.len()
+            ),
+            "rollback_offer",
+            json!({"plan_id": plan_id, "steps": steps.len()}),
+        )];
+        replies.extend(self.handle_plan_request(msg, steps, Some(room_cfg.clone())));
+        replies
+    }
+
+    /// Records the outcome of a plan step that ran after approval.
+    fn record_approved_step(&mut self, plan: &PlanContext, action: &str, replies: &[OutboundMessage]) {
+        if let Some(outcome) = extract_outcome_from_replies(replies) {
+            let attempts = replies
+                .iter()
+                .find_map(|reply| reply.metadata.get("attempts")?.as_u64())
+                .unwrap_or(1) as u32;
+            self.record_plan_result(&plan.plan_id, &plan.step_key, action, &outcome, attempts);
+            if let Some(undo) = &plan.step.undo {
+                self.push_compensation(&plan.plan_id, &plan.step_key, undo);
+            }
+        } else if let Some(error) = replies
+            .iter()
+            .find(|reply| reply.metadata.get("kind").and_then(|v| v.as_str()) == Some("error"))
+        {
+            let text = error.text.clone();
+            self.note_plan_failure(&plan.plan_id, &plan.step_key, action, &text);
+        }
+    }
+
     fn finish_plan(
         &mut self,
         plan_id: &str,
@@ -1005,6 +5053,13 @@ impl Engine {
         stopped_early: bool,
     ) -> Option<OutboundMessage> {
         let plan = self.plans.remove(plan_id)?;
+        self.hooks.plan_finished(&PlanEvent {
+            plan_id,
+            msg,
+            steps_run: plan.results.len(),
+            total_steps: plan.total_steps,
+            stopped_early,
+        });
         if plan.results.is_empty() {
             return None;
         }
@@ -1013,7 +5068,7 @@ impl Engine {
         } else {
             "plan_completed"
         };
-        let summary_text = self.summarize_plan(&plan);
+        let summary_text = self.summarize_plan(&plan, self.locale_for(msg));
         Some(self.reply(
             msg,
             summary_text,
@@ -1022,70 +5077,185 @@ impl Engine {
         ))
     }
 
-    fn summarize_plan(&self, plan: &PlanProgress) -> String {
-        if let Some(summary) = summarize_system_status(plan) {
+    fn summarize_plan(&mut self, plan: &PlanProgress, locale: Locale) -> String {
+        if let Some(summary) = summarize_system_status(plan, locale) {
             return summary;
         }
         let details = plan_result_details(plan);
-        if let Some(ai_backend) = &self.ai_backend {
-            let prompt = format!(
+        if let Some(route) = self.ai_backend_for(AiTask::Summarize) {
+            let prompt = self.redactor.text(&format!(
                 "Summarize the following execution results for the user. Return type=chat only.\nResults:\n{details}"
-            );
-            if let Ok(decision) = ai_backend.plan_with_history(&prompt, &[], &[]) {
-                if let AiDecision::Chat { message } = decision {
-                    let trimmed = message.trim();
-                    if !trimmed.is_empty()
-                        && !trimmed.contains("[result]")
-                        && !trimmed.to_lowercase().contains("please provide")
-                    {
-                        return message;
-                    }
+            ));
+            let _span = info_span!("ai", task = "summarize", backend = route.label.as_deref()).entered();
+            let decision = route.backend.plan_with_history(&prompt, &[], &[]);
+            self.note_ai_call(AiTask::Summarize, &decision);
+            if decision.is_ok() {
+                self.note_ai_usage(route.backend.as_ref());
+            }
+            if let Ok(AiDecision::Chat { message }) = decision {
+                let trimmed = message.trim();
+                if !trimmed.is_empty()
+                    && !trimmed.contains("[result]")
+                    && !trimmed.to_lowercase().contains("please provide")
+                {
+                    return message;
                 }
             }
         }
-        format_plan_summary_fallback(plan)
+        format_plan_summary_fallback(plan, locale)
+    }
+
+    /// Asks the critic backend whether an AI-proposed action fits the user's request.
+    /// Actions typed explicitly with `action:` are not reviewed.
+    fn critique_action(
+        &mut self,
+        msg: &InboundMessage,
+        spec: &ActionSpec,
+        params: &serde_json::Value,
+    ) -> Option<CriticReview> {
+        if !self.critic_enabled || self.ai_answered_by.is_none() {
+            return None;
+        }
+        let route = self.ai_backend_for(AiTask::Critic)?;
+        let risk = risk_label(spec.risk);
+        let prompt = format!(
+            "Review an action proposed for the user's request before they approve it. Return type=chat only, with the message in exactly this form:\nVERDICT: ok|warn|reject\nCONFIDENCE: <0.0-1.0>\nREASON: <one sentence>\nFlag paths or targets the user did not mention, params that do not match the schema, and destructive commands the request does not call for.\nUser request: {request}\nAction: {name} - {desc} (risk: {risk})\nParams schema: {schema}\nProposed params: {params}",
+            request = msg.text,
+            name = spec.name,
+            desc = spec.description,
+            schema = spec.params_schema,
+        );
+        let prompt = self.redactor.text(&prompt);
+        let _span = info_span!("ai", task = "critic", backend = route.label.as_deref()).entered();
+        let decision = route.backend.plan_with_history(&prompt, &[], &[]);
+        self.note_ai_call(AiTask::Critic, &decision);
+        match decision {
+            Ok(AiDecision::Chat { message }) => {
+                self.note_ai_usage(route.backend.as_ref());
+                parse_critic_review(&message)
+            }
+            Ok(_) => {
+                self.note_ai_usage(route.backend.as_ref());
+                None
+            }
+            Err(err) => {
+                warn!("ai critic failed: {err}");
+                None
+            }
+        }
+    }
+
+    /// Remembers what an AI `need_input` decision still needs. With several missing fields
+    /// the first is asked for on its own, and the rest follow one reply at a time.
+    fn ask_for_input(
+        &mut self,
+        msg: &InboundMessage,
+        prompt: String,
+        action: Option<String>,
+        params: serde_json::Value,
+        missing: Vec<String>,
+    ) -> OutboundMessage {
+        let Some(action) = action.filter(|_| !missing.is_empty()) else {
+            return self.reply(msg, prompt, "need_input", serde_json::Value::Null);
+        };
+        let pending = PendingInput {
+            action,
+            params,
+            missing,
+            filled: Vec::new(),
+            prompt: prompt.clone(),
+        };
+        let text = if pending.missing.len() > 1 {
+            format!("{prompt}\n{}", self.field_prompt(msg, &pending))
+        } else {
+            prompt
+        };
+        let data = json!({"action": pending.action, "missing": pending.missing});
+        self.session_mut(msg).pending_input = Some(pending);
+        self.reply(msg, text, "need_input", data)
     }
 
+    /// Fills the next missing field from `text`, read as the type the action's schema gives
+    /// it.
     fn resolve_pending_input(
         &self,
-        pending: &PendingInput,
+        msg: &InboundMessage,
+        mut pending: PendingInput,
         text: &str,
         ctx: &ActionContext,
-    ) -> Option<ActionRequest> {
+    ) -> PendingReply {
         let trimmed = text.trim();
-        if trimmed.is_empty() {
-            return None;
+        if trimmed.is_empty() || pending.missing.is_empty() {
+            return PendingReply::Unresolved(pending);
         }
-        let mut params = pending.params.clone();
-        let mut filled = false;
-
-        let value = if is_current_directory(trimmed) {
+        if matches!(trimmed.to_lowercase().as_str(), "cancel" | "取消") {
+            return PendingReply::Cancelled;
+        }
+        let field = pending.missing[0].clone();
+        let raw = if is_path_key(&field) && is_current_directory(trimmed) {
             ctx.cwd.to_string_lossy().to_string()
         } else {
             trimmed.to_string()
         };
-
-        if pending.missing.len() == 1 {
-            params = insert_param(params, &pending.missing[0], &value);
-            filled = true;
-        } else {
-            for key in &pending.missing {
-                if is_path_key(key) {
-                    params = insert_param(params, key, &value);
-                    filled = true;
-                    break;
-                }
+        let schema = self.param_schema(&pending.action, &field);
+        let value = match coerce_param(&raw, schema.as_ref()) {
+            Ok(value) => value,
+            Err(expected) => {
+                let catalog = self.locale_for(msg).catalog();
+                let expected = match expected {
+                    Expected::Integer => catalog.expect_integer.to_string(),
+                    Expected::Number => catalog.expect_number.to_string(),
+                    Expected::Boolean => catalog.expect_boolean.to_string(),
+                    Expected::Object => catalog.expect_object.to_string(),
+                    Expected::OneOf(values) => {
+                        fill(catalog.expect_one_of, &[("values", &values.join(", "))])
+                    }
+                };
+                let prompt = format!(
+                    "{}\n{}",
+                    fill(catalog.bad_field, &[("field", &field), ("expected", &expected)]),
+                    self.field_prompt(msg, &pending)
+                );
+                return PendingReply::Ask(pending, prompt);
             }
+        };
+        pending.params = insert_param(pending.params, &field, value);
+        pending.filled.push(pending.missing.remove(0));
+        if !pending.missing.is_empty() {
+            let prompt = self.field_prompt(msg, &pending);
+            return PendingReply::Ask(pending, prompt);
         }
+        PendingReply::Ready(ActionRequest {
+            name: pending.action,
+            params: pending.params,
+            raw_input: trimmed.to_string(),
+        })
+    }
 
-        if filled {
-            return Some(ActionRequest {
-                name: pending.action.clone(),
-                params,
-                raw_input: trimmed.to_string(),
-            });
+    /// Asks for the first missing field, with its schema description when it has one.
+    fn field_prompt(&self, msg: &InboundMessage, pending: &PendingInput) -> String {
+        let catalog = self.locale_for(msg).catalog();
+        let Some(field) = pending.missing.first() else {
+            return pending.prompt.clone();
+        };
+        let step = pending.filled.len() + 1;
+        let total = pending.filled.len() + pending.missing.len();
+        let ask = fill(
+            catalog.ask_field,
+            &[("field", field), ("step", &step), ("total", &total)],
+        );
+        let description = self
+            .param_schema(&pending.action, field)
+            .and_then(|schema| schema.get("description")?.as_str().map(str::to_string));
+        match description {
+            Some(description) => catalog.field(&ask, description),
+            None => ask,
         }
-        None
+    }
+
+    fn param_schema(&self, action: &str, field: &str) -> Option<serde_json::Value> {
+        let spec = self.registry.get(action)?.spec();
+        spec.params_schema.pointer(&format!("/properties/{field}")).cloned()
     }
 
     fn build_ai_input(
@@ -1099,12 +5269,47 @@ impl Engine {
         let mut parts = Vec::new();
         let cwd = self.build_context(room_cfg).cwd;
         let home = std::env::var("HOME").unwrap_or_else(|_| "".to_string());
+        let workspace_id = msg.workspace_id.clone().unwrap_or_else(|| "default".to_string());
         parts.push(format!("Context:\n- cwd: {}\n- home: {}\n- room: {}\n- workspace: {}",
             cwd.to_string_lossy(),
             home,
             msg.channel,
-            msg.workspace_id.clone().unwrap_or_else(|| "default".to_string())
+            workspace_id
         ));
+        if let Some(vars) = self.vars.all(&workspace_id) {
+            let lines: Vec<String> = vars
+                .iter()
+                .map(|(name, value)| format!("- ${name} = {value}"))
+                .collect();
+            parts.push(format!(
+                "Variables (the user may write $name; use the value in params):\n{}",
+                lines.join("\n")
+            ));
+        }
+        let facts = self.prompt_facts(&workspace_id, &msg.sender);
+        if !facts.is_empty() {
+            let lines: Vec<String> = facts.iter().map(|fact| format!("- {}", fact.text)).collect();
+            parts.push(format!(
+                "Facts and preferences shared in this workspace (follow them unless asked otherwise):\n{}",
+                lines.join("\n")
+            ));
+        }
+        if let Some(memory) = &self.memory {
+            match memory.recall(&workspace_id, text) {
+                Ok(hits) if !hits.is_empty() => {
+                    let lines: Vec<String> = hits
+                        .iter()
+                        .map(|hit| {
+                            let snippet: String = hit.text.chars().take(600).collect();
+                            format!("- [{}] {}", hit.kind.as_str(), snippet)
+                        })
+                        .collect();
+                    parts.push(format!("Relevant memory:\n{}", lines.join("\n")));
+                }
+                Ok(_) => {}
+                Err(err) => warn!("memory recall failed: {err}"),
+            }
+        }
         if let Some(pending) = pending {
             if !pending.missing.is_empty() {
                 parts.push(format!(
@@ -1132,78 +5337,102 @@ impl Engine {
     fn handle_action_request(
         &mut self,
         msg: &InboundMessage,
-        request: ActionRequest,
+        mut request: ActionRequest,
         room_cfg: Option<RoomConfig>,
     ) -> Vec<OutboundMessage> {
+        if is_explicit_action(&request.raw_input) {
+            request.params = self.vars.substitute(&var_workspace(msg), &request.params);
+        }
         let Some(action) = self.registry.get(&request.name) else {
-            return vec![self.reply(
+            return vec![self.error_reply(
                 msg,
-                format!("unknown action: {}", request.name),
-                "error",
-                serde_json::Value::Null,
+                RobitError::ValidationFailed(format!("unknown action: {}", request.name)),
             )];
         };
         let spec = action.spec();
         let room_cfg = room_cfg.unwrap_or_default();
         if !room_cfg.allows_action(&spec.name) {
-            return vec![self.reply(
+            return vec![self.error_reply(
                 msg,
-                format!("action not allowed: {}", spec.name),
-                "error",
-                serde_json::Value::Null,
+                RobitError::PolicyDenied(format!("action not allowed: {}", spec.name)),
             )];
         }
-        let needs_approval = self.requires_approval(&spec, &room_cfg);
+        if let Some(refusal) = self.role_refusal(msg, &spec) {
+            return vec![self.error_reply(msg, RobitError::PolicyDenied(refusal))];
+        }
+        let mut needs_approval = self.requires_approval(&spec, &room_cfg);
+        let trusted_until = needs_approval
+            .then(|| self.trust_until(msg, &spec))
+            .flatten();
+        if trusted_until.is_some() {
+            needs_approval = false;
+        }
 
         let ctx = self.build_context(&room_cfg);
-        let preflight = match self.preflight.check(&spec, &request.params, &ctx) {
+        let mut preflight = match self.preflight.check(&spec, &request.params, &ctx) {
             Ok(report) => report,
             Err(err) => {
-                return vec![self.reply(
-                    msg,
-                    format!("preflight failed: {err}"),
-                    "error",
-                    serde_json::Value::Null,
-                )]
+                let report = self.preflight.refusal(&spec, &ctx);
+                let text =
+                    self.blocked.keep(room_key(msg), report, format!("preflight failed: {err}"));
+                return vec![self.error_reply(msg, RobitError::PolicyDenied(text))];
             }
         };
         self.log_preflight(&preflight);
         if !preflight.allowed && self.preflight.config().strict {
-            return vec![self.reply(
-                msg,
-                format!("preflight blocked: {}", preflight.summary()),
-                "error",
-                serde_json::Value::Null,
-            )];
+            let text = format!("preflight blocked: {}", preflight.summary());
+            let text = self.blocked.keep(room_key(msg), preflight, text);
+            return vec![self.error_reply(msg, RobitError::PreflightBlocked(text))];
         }
+        // Warnings are overridden only by approving them, even in a trust window.
+        needs_approval |= !preflight.warnings.is_empty();
         if let Err(err) = action.validate(&ctx, &request.params) {
-            return vec![self.reply(
+            return vec![self.error_reply(
                 msg,
-                format!("validation failed: {err}"),
-                "error",
-                serde_json::Value::Null,
+                RobitError::ValidationFailed(format!("validation failed: {err}")),
             )];
         }
 
         if needs_approval {
+            preflight.change = action.preview(&ctx, &request.params);
             let params_snapshot = request.params.clone();
             let approval_id = self.approvals.create(
-                &msg.sender,
+                msg,
                 request,
                 spec.clone(),
                 room_cfg.clone(),
                 None,
             );
-            let text =
-                format_approval_prompt(&spec, &params_snapshot, &ctx, &approval_id, Some(&preflight), None);
-            return vec![self.reply(
+            self.metrics.approvals_requested += 1;
+            self.hooks.approval_created(&ApprovalEvent {
+                approval_id: &approval_id,
+                action: &spec.name,
+                params: &params_snapshot,
+                risk: spec.risk,
                 msg,
-                text,
-                "approval_request",
-                json!({"approval_id": approval_id}),
-            )];
+                plan_id: None,
+            });
+            let review = self.critique_action(msg, &spec, &params_snapshot);
+            let prompt = format_approval_prompt(
+                &spec,
+                &params_snapshot,
+                &ctx,
+                &approval_id,
+                Some(&preflight),
+                None,
+                review.as_ref(),
+            );
+            let text = prompt.plain(self.locale_for(msg));
+            let mut data = json!({"approval_id": approval_id, "prompt": prompt});
+            if let Some(review) = &review {
+                data["critic"] = json!(review);
+            }
+            return vec![self.reply(msg, text, "approval_request", data)];
         }
 
+        if let Some(until) = trusted_until {
+            self.audit_trusted(msg, &spec, until);
+        }
         self.execute_action(&request, &spec, msg, Some(room_cfg))
     }
 
@@ -1213,13 +5442,23 @@ impl Engine {
         spec: &ActionSpec,
         msg: &InboundMessage,
         room_cfg: Option<RoomConfig>,
+    ) -> Vec<OutboundMessage> {
+        self.execute_action_with_policy(request, spec, msg, room_cfg, &StepPolicy::default(), None)
+    }
+
+    fn execute_action_with_policy(
+        &mut self,
+        request: &ActionRequest,
+        spec: &ActionSpec,
+        msg: &InboundMessage,
+        room_cfg: Option<RoomConfig>,
+        policy: &StepPolicy,
+        plan: Option<&PlanContext>,
     ) -> Vec<OutboundMessage> {
         let Some(action) = self.registry.get(&request.name) else {
-            return vec![self.reply(
+            return vec![self.error_reply(
                 msg,
-                format!("unknown action: {}", request.name),
-                "error",
-                serde_json::Value::Null,
+                RobitError::ValidationFailed(format!("unknown action: {}", request.name)),
             )];
         };
 
@@ -1228,44 +5467,71 @@ impl Engine {
         let preflight = match self.preflight.check(spec, &request.params, &ctx) {
             Ok(report) => report,
             Err(err) => {
-                return vec![self.reply(
-                    msg,
-                    format!("preflight failed: {err}"),
-                    "error",
-                    serde_json::Value::Null,
-                )]
+                let report = self.preflight.refusal(spec, &ctx);
+                let text =
+                    self.blocked.keep(room_key(msg), report, format!("preflight failed: {err}"));
+                return vec![self.error_reply(msg, RobitError::PolicyDenied(text))];
             }
         };
         self.log_preflight(&preflight);
         if !preflight.allowed && self.preflight.config().strict {
-            return vec![self.reply(
-                msg,
-                format!("preflight blocked: {}", preflight.summary()),
-                "error",
-                serde_json::Value::Null,
-            )];
+            let text = format!("preflight blocked: {}", preflight.summary());
+            let text = self.blocked.keep(room_key(msg), preflight, text);
+            return vec![self.error_reply(msg, RobitError::PreflightBlocked(text))];
         }
         if let Err(err) = action.validate(&ctx, &request.params) {
-            return vec![self.reply(
+            return vec![self.error_reply(
                 msg,
-                format!("validation failed: {err}"),
-                "error",
-                serde_json::Value::Null,
+                RobitError::ValidationFailed(format!("validation failed: {err}")),
             )];
         }
 
-        match action.execute(&ctx, &request.params) {
-            Ok(outcome) => vec![self.reply_with_outcome(msg, outcome, spec)],
-            Err(err) => vec![self.reply(
+        let event = ActionEvent {
+            action: &spec.name,
+            params: &request.params,
+            risk: spec.risk,
+            msg,
+            plan_id: plan.map(|plan| plan.plan_id.as_str()),
+            step_id: plan.map(|plan| plan.step_key.as_str()),
+        };
+        if let Some(reason) = self.hooks.action_start(&event) {
+            return vec![self.error_reply(msg, RobitError::PolicyDenied(reason))];
+        }
+        let policy = policy.clone().or_timeout(self.action_timeout);
+        let started = Instant::now();
+        let (result, attempts) = self.run_action(
+            &action,
+            msg,
+            &ctx,
+            &request.params,
+            &policy,
+            room_cfg.quotas.as_ref(),
+        );
+        self.finish_action(&event, &result, started.elapsed());
+        match result {
+            Ok(outcome) => {
+                let mut reply = self.reply_with_outcome(msg, outcome, spec);
+                if attempts > 1 && reply.metadata.is_object() {
+                    reply.metadata["attempts"] = json!(attempts);
+                }
+                vec![reply]
+            }
+            Err(err) => vec![self.error_reply(
                 msg,
-                format!("error: {err}"),
-                "error",
-                serde_json::Value::Null,
+                RobitError::ActionFailed(execution_error_text(&err, attempts)),
             )],
         }
     }
 
     fn reply(&mut self, msg: &InboundMessage, text: impl Into<String>, kind: &str, data: serde_json::Value) -> OutboundMessage {
+        let mut metadata = json!({
+            "kind": kind,
+            "data": data,
+        });
+        if kind == "error" {
+            self.metrics.errors += 1;
+            metadata["code"] = json!("invalid_request");
+        }
         let id = self.next_message_id();
         OutboundMessage {
             id,
@@ -1274,13 +5540,17 @@ impl Engine {
             recipient: msg.sender.clone(),
             channel: msg.channel.clone(),
             workspace_id: msg.workspace_id.clone(),
-            metadata: json!({
-                "kind": kind,
-                "data": data,
-            }),
+            metadata,
         }
     }
 
+    /// An `error` reply whose metadata carries the error's `code`.
+    fn error_reply(&mut self, msg: &InboundMessage, err: RobitError) -> OutboundMessage {
+        let mut reply = self.reply(msg, err.to_string(), "error", serde_json::Value::Null);
+        reply.metadata["code"] = json!(err.code());
+        reply
+    }
+
     fn reply_with_outcome(
         &mut self,
         msg: &InboundMessage,
@@ -1288,6 +5558,15 @@ impl Engine {
         spec: &ActionSpec,
     ) -> OutboundMessage {
         let id = self.next_message_id();
+        let attachments = Self::outcome_attachments(&outcome.data);
+        let mut metadata = json!({
+            "kind": "action_result",
+            "action": spec.name,
+            "data": outcome.data,
+        });
+        if !attachments.is_empty() {
+            metadata["attachments"] = json!(attachments);
+        }
         OutboundMessage {
             id,
             in_reply_to: Some(msg.id.clone()),
@@ -1295,11 +5574,7 @@ impl Engine {
             recipient: msg.sender.clone(),
             channel: msg.channel.clone(),
             workspace_id: msg.workspace_id.clone(),
-            metadata: json!({
-                "kind": "action_result",
-                "action": spec.name,
-                "data": outcome.data,
-            }),
+            metadata,
         }
     }
 
@@ -1321,9 +5596,44 @@ impl Engine {
         user_input: &str,
         replies: &[OutboundMessage],
     ) {
-        self.conversations
-            .record_exchange(key, user_input, replies);
-        self.persist_conversations();
+        let user_input = self.redactor.text(user_input);
+        let replies: Vec<OutboundMessage> = replies
+            .iter()
+            .map(|reply| OutboundMessage {
+                text: self.redactor.text(&reply.text),
+                metadata: self.redactor.value(&reply.metadata),
+                ..reply.clone()
+            })
+            .collect();
+        let added = self
+            .conversations
+            .record_exchange(key, &user_input, &replies);
+        self.persist_conversations(key, &added);
+        if self.ai_answered_by.is_some() {
+            self.remember_exchange(&key.0, &user_input, &replies);
+        }
+    }
+
+    fn remember_exchange(
+        &mut self,
+        workspace_id: &str,
+        user_input: &str,
+        replies: &[OutboundMessage],
+    ) {
+        let Some(memory) = self.memory.as_mut() else {
+            return;
+        };
+        let mut text = format!("User: {}", user_input.trim());
+        for reply in replies {
+            if !reply.text.trim().is_empty() {
+                text.push_str(&format!("\nAssistant: {}", reply.text.trim()));
+            }
+        }
+        if let Err(err) =
+            memory.remember(MemoryKind::Conversation, workspace_id, "conversation", &text)
+        {
+            warn!("memory save failed: {err}");
+        }
     }
 
     fn record_context_and_persist(
@@ -1332,16 +5642,63 @@ impl Engine {
         role: AiChatRole,
         content: &str,
     ) {
-        self.conversations.record_context(key, role, content);
-        self.persist_conversations();
+        let content = self.redactor.text(content);
+        if let Some(added) = self.conversations.record_context(key, role, &content) {
+            self.persist_conversations(key, &[added]);
+        }
+    }
+
+    /// Saves the messages just added to the room at `key`; the JSON file is only marked
+    /// for the next save.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn persist_conversations(&mut self, key: &(String, String), added: &[StoredMessage]) {
+        match &mut self.conversation_persistence {
+            None => {}
+            Some(ConversationPersistence::Json(_)) => {
+                self.conversations_dirty_since.get_or_insert_with(Instant::now);
+            }
+            #[cfg(feature = "sqlite")]
+            Some(ConversationPersistence::Sqlite(db)) => {
+                if let Err(err) = db.append(key, added) {
+                    warn!("context save failed: {err}");
+                }
+            }
+        }
     }
 
-    fn persist_conversations(&self) {
-        let Some(path) = &self.conversation_persist_path else {
+    /// Writes conversation changes still waiting for the JSON file. `tick` does this once
+    /// they are a couple of seconds old, and the engine does it when the adapter closes or
+    /// the engine is dropped; call it before exiting some other way.
+    pub fn flush_conversations(&mut self) {
+        let Some(ConversationPersistence::Json(path)) = &self.conversation_persistence else {
             return;
         };
+        if self.conversations_dirty_since.take().is_none() {
+            return;
+        }
+        if let Some(max_age) = self.conversation_max_age {
+            self.conversations.prune_before(unix_now().saturating_sub(max_age));
+        }
         if let Err(err) = self.conversations.save_to_path(path) {
-            eprintln!("robit context save failed: {err}");
+            warn!("context save failed: {err}");
+        }
+    }
+
+    /// Saves the room's whole history in place of what was stored for it.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn persist_room_history(&mut self, key: &(String, String)) {
+        match &mut self.conversation_persistence {
+            None => {}
+            Some(ConversationPersistence::Json(_)) => {
+                self.conversations_dirty_since.get_or_insert_with(Instant::now);
+            }
+            #[cfg(feature = "sqlite")]
+            Some(ConversationPersistence::Sqlite(db)) => {
+                let messages = self.conversations.history.get(key).map(Vec::as_slice);
+                if let Err(err) = db.replace(key, messages.unwrap_or_default()) {
+                    warn!("context save failed: {err}");
+                }
+            }
         }
     }
 
@@ -1351,26 +5708,92 @@ impl Engine {
         text.push_str("  help           show this help\n");
         text.push_str("  actions        list actions\n");
         text.push_str("  backend        show ai backend\n");
+        text.push_str("  model use <name> switch the ai model\n");
+        text.push_str("  usage          show ai token usage and cost\n");
         text.push_str("  dry-run on     enable dry-run mode\n");
         text.push_str("  dry-run off    disable dry-run mode\n");
+        text.push_str("  role           show your role (admin, operator or guest)\n");
+        text.push_str("  read-only on|off allow only low-risk read actions, for everyone\n");
+        text.push_str("  reload         re-read [policy] and [preflight] from the config files\n");
+        text.push_str("  config show    list the config files in merge order\n");
+        text.push_str("  profile        show the active profile and the others\n");
+        text.push_str("  profile use <name|default> switch to a [profiles.<name>] config\n");
+        text.push_str("  remember <fact> store a fact used in every room of this workspace\n");
+        text.push_str("  facts          list remembered facts; forget <id|all> removes them\n");
+        text.push_str("  trust <10m|off> skip approval for your medium-risk actions for a while\n");
+        text.push_str("  plan save <name> save the last plan as a template\n");
+        text.push_str("  plan run <name> [key=value ...] run a saved plan\n");
+        text.push_str("  plans          list saved plans\n");
+        text.push_str("  schedules      list this room's schedules\n");
+        text.push_str("  schedule delete <id> stop a schedule\n");
+        text.push_str("  show more [id] next page of a long reply\n");
+        text.push_str("  explain [id]   show which rules blocked an action\n");
+        text.push_str("  undo           reverse the last file change in this room\n");
+        text.push_str("  history [n]    list the last n actions run in this room\n");
+        text.push_str("  history clear  forget this room's conversation\n");
+        text.push_str("  history prune <age> drop conversation messages older than e.g. 30d\n");
+        text.push_str("  export [path.md] write this room's conversation and actions as Markdown\n");
+        text.push_str("  search [all] <words> find messages in this room's (or every room's) conversation\n");
+        text.push_str("  stats          show engine counters and latencies\n");
+        text.push_str("  quota          show this room's action quotas and usage\n");
+        text.push_str("  set var <name>=<value> set a workspace variable, used as $name\n");
+        text.push_str("  get var [name] show workspace variables\n");
+        text.push_str("  alias <name> = <text> define a shortcut for this workspace\n");
+        text.push_str("  alias list     list shortcuts; unalias <name> removes one\n");
+        text.push_str("  /help          list slash commands (/read, /run, /organize, /search)\n");
+        text.push_str("  approvals      list pending approvals with their age\n");
         text.push_str("  approve <id>   approve pending action\n");
         text.push_str("  approve-all <id> approve this and remaining plan steps\n");
         text.push_str("  deny <id>      deny pending action\n\n");
         text.push_str("examples:\n");
         text.push_str("  action:fs.write_file {\"path\":\"./notes.txt\",\"content\":\"hello world\"}\n");
         text.push_str("  action:fs.read_file path=./notes.txt\n");
+        text.push_str("  /read ./notes.txt\n");
         text.push_str("  action:fs.replace_text {\"path\":\"./notes.txt\",\"find\":\"hello\",\"replace\":\"hi\"}\n");
         text.push_str("  action:fs.list_dir path=./\n");
         text.push_str("  action:shell.run command=\"ls -la\"\n");
         text.push_str("  action:web.fetch_url url=https://example.com\n");
+        text.push_str("  action:schedule.create cron=\"0 22 * * *\" text=\"整理下载文件夹\"\n");
         text.push_str("  整理桌面\n");
         text
     }
 
+    /// Actions the policy's capability rules allow; the others are hidden from lists and the AI.
+    fn available_specs(&self) -> Vec<ActionSpec> {
+        self.registry
+            .list_specs()
+            .into_iter()
+            .filter(|spec| self.ctx.policy.check_capabilities(spec).is_ok())
+            .filter(|spec| self.ctx.policy.check_read_only(spec).is_ok())
+            .collect()
+    }
+
+    /// The policy's role for the sender, lowered to the message's `sender_role` metadata if
+    /// that is lower. Adapters set it for machine senders such as CI webhooks.
+    fn sender_role(&self, msg: &InboundMessage) -> Role {
+        let role = self.ctx.policy.role_for(&msg.sender);
+        msg.metadata
+            .get("sender_role")
+            .and_then(|value| serde_json::from_value::<Role>(value.clone()).ok())
+            .map_or(role, |cap| role.min(cap))
+    }
+
+    /// Guests may only run low-risk actions.
+    fn role_refusal(&self, msg: &InboundMessage, spec: &ActionSpec) -> Option<String> {
+        if self.sender_role(msg) != Role::Guest || spec.risk == RiskLevel::Low {
+            return None;
+        }
+        Some(format!(
+            "{} is a guest here and may only run low-risk actions; {} is {} risk",
+            msg.sender,
+            spec.name,
+            risk_label(spec.risk)
+        ))
+    }
+
     fn actions_text(&self) -> String {
         let mut lines: Vec<String> = self
-            .registry
-            .list_specs()
+            .available_specs()
             .into_iter()
             .map(|spec| format!("{} v{} - {}", spec.name, spec.version, spec.description))
             .collect();
@@ -1379,12 +5802,33 @@ impl Engine {
     }
 
     fn backend_text(&self) -> String {
-        match (&self.ai_backend, &self.ai_backend_label) {
+        let mut text = match (&self.ai_backend, &self.ai_backend_label) {
             (Some(_), Some(label)) => format!("ai backend: {label}"),
             (Some(_), None) => "ai backend: custom".to_string(),
             (None, Some(label)) => format!("ai backend: {label}"),
             (None, None) => "ai backend: none".to_string(),
+        };
+        for task in [AiTask::Planning, AiTask::Chat, AiTask::Summarize, AiTask::Critic] {
+            if let Some(route) = self.ai_routes.get(&task) {
+                let label = route.label.as_deref().unwrap_or("custom");
+                text.push_str(&format!("\n{} -> {label}", task.as_str()));
+            }
         }
+        if !self.ai_models.is_empty() {
+            let models = self
+                .model_names()
+                .into_iter()
+                .map(|name| {
+                    if self.active_model.as_deref() == Some(name.as_str()) {
+                        format!("{name} (active)")
+                    } else {
+                        name
+                    }
+                })
+                .collect::<Vec<_>>();
+            text.push_str(&format!("\nmodels: {}", models.join(", ")));
+        }
+        text
     }
 
     fn build_context(&self, room_cfg: &RoomConfig) -> ActionContext {
@@ -1392,6 +5836,9 @@ impl Engine {
         if let Some(dry_run) = room_cfg.dry_run_default {
             ctx.dry_run = dry_run;
         }
+        ctx.dry_run |= self.force_dry_run;
+        let preflight = self.preflight.config();
+        ctx.preflight = preflight.enabled.then(|| std::sync::Arc::new(preflight.clone()));
         ctx
     }
 
@@ -1399,6 +5846,10 @@ impl Engine {
         if spec.requires_approval {
             return true;
         }
+        let dry_run = room_cfg.dry_run_default.unwrap_or(self.ctx.dry_run);
+        if self.ctx.policy.window_approvals(spec, dry_run) > 0 {
+            return true;
+        }
 
         if let Some(policy) = &room_cfg.risk_policy {
             if policy.low_auto_execute && spec.risk == RiskLevel::Low {
@@ -1412,13 +5863,184 @@ impl Engine {
             .requires_approval(spec.risk, spec.requires_approval)
     }
 
-    fn wrap_response(&mut self, reply: OutboundMessage) -> ProtocolEvent {
+    /// The room's approver count, raised by any open policy time window.
+    fn approvals_needed(&self, spec: &ActionSpec, room_cfg: &RoomConfig) -> usize {
+        let dry_run = room_cfg.dry_run_default.unwrap_or(self.ctx.dry_run);
+        room_cfg
+            .approvals_needed(spec.risk)
+            .max(self.ctx.policy.window_approvals(spec, dry_run))
+    }
+
+    /// Saves inline attachments and fills in what the client left out. A path-only
+    /// attachment is passed on as given; actions reading it still go through the policy.
+    fn receive_attachments(
+        &self,
+        message_id: &str,
+        attachments: Vec<Attachment>,
+    ) -> Result<Vec<Attachment>> {
+        let mut received = Vec::with_capacity(attachments.len());
+        for mut attachment in attachments {
+            // Only the file name is used, so `../` cannot leave the message's directory.
+            let name = Path::new(&attachment.name)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| format!("attachment-{}", received.len() + 1));
+            if let Some(bytes) = attachment.bytes()? {
+                let size = bytes.len() as u64;
+                if size > self.inline_attachment_bytes {
+                    return Err(anyhow!(
+                        "attachment {name} is {}, over the inline limit of {}; send a path instead",
+                        format_bytes(size),
+                        format_bytes(self.inline_attachment_bytes)
+                    ));
+                }
+                let dir = self
+                    .attachment_dir
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("inline attachments are not enabled; send a path instead"))?
+                    .join(file_safe(message_id));
+                fs::create_dir_all(&dir)?;
+                let path = dir.join(&name);
+                fs::write(&path, &bytes).with_context(|| format!("save {}", path.display()))?;
+                attachment.path = Some(path.to_string_lossy().to_string());
+                attachment.size = Some(size);
+                attachment.data = None;
+            }
+            let Some(path) = attachment.path.clone() else {
+                return Err(anyhow!("attachment {name} has neither a path nor data"));
+            };
+            let path = expand_tilde(&path);
+            if attachment.size.is_none() {
+                attachment.size = fs::metadata(&path).ok().map(|meta| meta.len());
+            }
+            attachment.mime.get_or_insert_with(|| guess_mime(&path).to_string());
+            attachment.name = name;
+            received.push(attachment);
+        }
+        Ok(received)
+    }
+
+    /// Files an action result names in `data.attachments`, as paths or `{"path": ...}`.
+    fn outcome_attachments(data: &serde_json::Value) -> Vec<Attachment> {
+        let Some(items) = data.get("attachments").and_then(|items| items.as_array()) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter_map(|item| item.as_str().or_else(|| item.get("path")?.as_str()))
+            .map(|raw| {
+                let path = expand_tilde(raw);
+                Attachment {
+                    name: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    path: Some(path.to_string_lossy().to_string()),
+                    mime: Some(guess_mime(&path).to_string()),
+                    size: fs::metadata(&path).ok().map(|meta| meta.len()),
+                    data: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Inlines small attachments the policy lets actions read; the rest keep only their path.
+    fn inline_attachments(&self, attachments: &mut [Attachment]) {
+        for attachment in attachments {
+            let Some(path) = attachment.path.as_deref().map(expand_tilde) else {
+                continue;
+            };
+            if attachment.data.is_some()
+                || attachment.size.is_none_or(|size| size > self.inline_attachment_bytes)
+                || self.ctx.policy.check_path_allowed(&clean_path(&path)).is_err()
+            {
+                continue;
+            }
+            match Attachment::from_path(&path, self.inline_attachment_bytes) {
+                Ok(inlined) => attachment.data = inlined.data,
+                Err(err) => warn!("attachment failed: {err:#}"),
+            }
+        }
+    }
+
+    /// Remembers what the client supports and answers with what the engine does.
+    fn handle_hello(&mut self, in_reply_to: String, hello: HelloPayload) -> ProtocolEvent {
+        let schema_version = SCHEMA_VERSIONS
+            .iter()
+            .find(|version| {
+                hello.schema_versions.is_empty()
+                    || hello.schema_versions.iter().any(|theirs| theirs == *version)
+            })
+            .map(|version| version.to_string());
+        let mut features = vec!["attachments".to_string()];
+        if self.attachment_dir.is_some() {
+            features.push("inline_attachments".to_string());
+        }
+        self.peer = Some(hello);
+        ProtocolEvent::new(ProtocolBody::Capabilities(CapabilitiesPayload {
+            in_reply_to,
+            schema_version,
+            schema_versions: SCHEMA_VERSIONS.iter().map(|version| version.to_string()).collect(),
+            events: ProtocolBody::EVENT_TYPES
+                .iter()
+                .map(|event| event.to_string())
+                .collect(),
+            features,
+            engine: format!("robit {}", env!("CARGO_PKG_VERSION")),
+        }))
+    }
+
+    /// An `Error` event, or a `Response` with kind `error` for clients that said they
+    /// do not understand `Error`.
+    fn error_event(&self, error: ErrorPayload) -> ProtocolEvent {
+        if self.peer.as_ref().is_none_or(|peer| peer.accepts_event("Error")) {
+            return ProtocolEvent::new(ProtocolBody::Error(error));
+        }
+        let mut metadata = error.metadata;
+        if !metadata.is_object() {
+            metadata = json!({"kind": "error"});
+        }
+        metadata["code"] = json!(error.code);
+        ProtocolEvent::new(ProtocolBody::Response(ResponsePayload {
+            in_reply_to: error.in_reply_to,
+            room_id: error.room_id,
+            workspace_id: error.workspace_id,
+            kind: "error".to_string(),
+            text: error.message,
+            metadata,
+            attachments: Vec::new(),
+        }))
+    }
+
+    fn wrap_response(&mut self, mut reply: OutboundMessage) -> ProtocolEvent {
         let kind = reply
             .metadata
             .get("kind")
             .and_then(|value| value.as_str())
             .unwrap_or("info")
             .to_string();
+        if kind == "error" {
+            let code = reply.metadata["code"].as_str().unwrap_or("invalid_request").to_string();
+            return self.error_event(ErrorPayload {
+                in_reply_to: reply.in_reply_to.unwrap_or_default(),
+                room_id: reply.channel,
+                workspace_id: reply.workspace_id.unwrap_or_else(|| "default".to_string()),
+                code,
+                message: reply.text,
+                metadata: reply.metadata,
+            });
+        }
+        let mut attachments: Vec<Attachment> = reply
+            .metadata
+            .as_object_mut()
+            .and_then(|metadata| metadata.remove("attachments"))
+            .and_then(|value| serde_json::from_value(value).ok())
+            .unwrap_or_default();
+        if self.peer.as_ref().is_some_and(|peer| !peer.has_feature("attachments")) {
+            attachments.clear();
+        }
+        self.inline_attachments(&mut attachments);
         ProtocolEvent::new(ProtocolBody::Response(ResponsePayload {
             in_reply_to: reply.in_reply_to.unwrap_or_default(),
             room_id: reply.channel,
@@ -1426,6 +6048,7 @@ impl Engine {
             kind,
             text: reply.text,
             metadata: reply.metadata,
+            attachments,
         }))
     }
 
@@ -1433,9 +6056,7 @@ impl Engine {
         &mut self,
         payload: ApprovalDecisionPayload,
     ) -> Vec<ProtocolEvent> {
-        let Some(pending) = self.approvals.take(&payload.approval_id) else {
-            return Vec::new();
-        };
+        self.expire_approvals();
         let msg = InboundMessage {
             id: payload.in_reply_to.clone(),
             text: String::new(),
@@ -1444,8 +6065,46 @@ impl Engine {
             workspace_id: Some(payload.workspace_id),
             metadata: serde_json::Value::Null,
         };
+        if !matches!(
+            payload.decision.as_str(),
+            "approve" | "approve_all" | "approve-all" | "deny"
+        ) {
+            let error = RobitError::InvalidRequest(format!(
+                "unknown decision {:?}; use approve, approve_all or deny",
+                payload.decision
+            ));
+            let reply = self.error_reply(&msg, error);
+            return vec![self.wrap_response(reply)];
+        }
+        let deny = payload.decision == "deny";
+        if let Some(overrides) = payload.params.as_ref().filter(|_| !deny)
+            && let Err(text) =
+                self.apply_param_overrides(&payload.approval_id, &msg.sender, overrides)
+        {
+            let reply = self.reply(&msg, text, "error", serde_json::Value::Null);
+            return vec![self.wrap_response(reply)];
+        }
+        let reply = match self.check_approver(&payload.approval_id, &msg.sender, deny) {
+            ApproverCheck::Proceed => None,
+            ApproverCheck::Forbidden(text) => {
+                Some(self.reply(&msg, text, "error", serde_json::Value::Null))
+            }
+            ApproverCheck::Waiting(text) => Some(self.reply(
+                &msg,
+                text,
+                "approval_pending",
+                json!({"approval_id": payload.approval_id}),
+            )),
+        };
+        if let Some(reply) = reply {
+            return vec![self.wrap_response(reply)];
+        }
+        let Some(pending) = self.approvals.take(&payload.approval_id) else {
+            return Vec::new();
+        };
         match payload.decision.as_str() {
             "approve" | "approve_all" | "approve-all" => {
+                self.metrics.approvals_granted += 1;
                 let mut plan_ctx = pending.plan;
                 let has_plan = plan_ctx.is_some();
                 if payload.decision != "approve" {
@@ -1453,8 +6112,23 @@ impl Engine {
                         plan.auto_approve = true;
                     }
                 }
-                let mut replies = self
-                    .execute_action(&pending.request, &pending.spec, &msg, Some(pending.config.clone()));
+                let policy = plan_ctx.as_ref().map_or_else(StepPolicy::default, |plan| {
+                    StepPolicy::for_step(&plan.step, self.plan_time_left(&plan.plan_id))
+                });
+                let started = Instant::now();
+                let mut replies = self.execute_action_with_policy(
+                    &pending.request,
+                    &pending.spec,
+                    &msg,
+                    Some(pending.config.clone()),
+                    &policy,
+                    plan_ctx.as_ref(),
+                );
+                if let Some(plan) = plan_ctx.as_ref() {
+                    self.add_plan_busy(&plan.plan_id, started.elapsed());
+                    self.record_approved_step(plan, &pending.spec.name, &replies);
+                }
+                let mut rollback = Vec::new();
                 if let Some(plan) = plan_ctx {
                     let succeeded = replies.iter().any(|reply| {
                         reply
@@ -1474,32 +6148,149 @@ impl Engine {
                             plan.total_steps,
                         );
                         replies.append(&mut more);
+                    } else {
+                        rollback = self.offer_rollback(&msg, &plan.plan_id, &pending.config);
+                        if let Some(summary) = self.finish_plan(&plan.plan_id, &msg, true) {
+                            replies.push(summary);
+                        }
                     }
                 }
-                let filtered = if has_plan {
+                let mut filtered = if has_plan {
                     filter_plan_result_replies(replies)
                 } else {
                     replies
                 };
-                filtered
+                filtered.extend(rollback);
+                self.middleware
+                    .outbound(filtered)
                     .into_iter()
                     .map(|reply| self.wrap_response(reply))
                     .collect()
             }
             "deny" => {
-                let reply = self.reply(
+                self.metrics.approvals_denied += 1;
+                let mut replies = vec![self.reply(
                     &msg,
                     format!("action '{}' cancelled", pending.spec.name),
                     "cancelled",
                     serde_json::Value::Null,
-                );
-                vec![self.wrap_response(reply)]
+                )];
+                if let Some(reason) = payload.reason.filter(|reason| !reason.trim().is_empty()) {
+                    replies.extend(self.replan_after_denial(&msg, &pending, &reason));
+                }
+                self.middleware
+                    .outbound(replies)
+                    .into_iter()
+                    .map(|reply| self.wrap_response(reply))
+                    .collect()
             }
             _ => Vec::new(),
         }
     }
 }
 
+/// Adds a loader for each `[[ai.models]]` entry and returns the names added; entries that
+/// cannot be used are skipped.
+fn load_ai_models(
+    entries: Vec<config::AiModelEntry>,
+    ai_models: &mut HashMap<String, AiModelLoader>,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry.name.clone();
+        match model_loader_for(entry) {
+            Ok(loader) => {
+                ai_models.insert(name.clone(), loader);
+                names.push(name);
+            }
+            Err(err) => warn!("ai model {name} skipped: {err}"),
+        }
+    }
+    names
+}
+
+pub(crate) fn model_loader_for(entry: config::AiModelEntry) -> Result<AiModelLoader> {
+    match entry.provider.as_str() {
+        #[cfg(feature = "ai-omnix-mlx")]
+        "omnix-mlx" | "mlx" => {
+            let model_dir = entry
+                .model_dir
+                .ok_or_else(|| anyhow!("provider omnix-mlx needs model_dir"))?;
+            let temperature = entry.temperature.unwrap_or(0.2) as f32;
+            Ok(Box::new(move || {
+                let client = crate::ai::MlxQwenClient::new(crate::ai::MlxQwenConfig {
+                    model_dir: model_dir.clone(),
+                    temperature,
+                    max_tokens: 1024,
+                    context_window: None,
+                    stop_token_ids: Vec::new(),
+                    stop_strings: Vec::new(),
+                    seed: None,
+                })?;
+                Ok(std::sync::Arc::new(client) as std::sync::Arc<dyn AiPlanner>)
+            }))
+        }
+        #[cfg(feature = "ai-http")]
+        name @ ("openai" | "deepseek" | "anthropic" | "claude") => {
+            let provider: crate::ai::AiProvider = serde_json::from_value(json!(name))?;
+            let model = entry
+                .model
+                .ok_or_else(|| anyhow!("provider {name} needs model"))?;
+            let api_key_env = entry.api_key_env.unwrap_or_else(|| match provider {
+                crate::ai::AiProvider::OpenAI => "OPENAI_API_KEY".to_string(),
+                crate::ai::AiProvider::DeepSeek => "DEEPSEEK_API_KEY".to_string(),
+                crate::ai::AiProvider::Anthropic => "ANTHROPIC_API_KEY".to_string(),
+            });
+            let base_url = entry.base_url;
+            let temperature = entry.temperature;
+            Ok(Box::new(move || {
+                let api_key = std::env::var(&api_key_env)
+                    .with_context(|| format!("{api_key_env} is not set"))?;
+                let client = crate::ai::AiClient::new(crate::ai::AiConfig {
+                    provider,
+                    api_key,
+                    model: model.clone(),
+                    base_url: base_url.clone(),
+                    temperature,
+                    organization: None,
+                    extra_headers: Vec::new(),
+                    tool_calling: false,
+                    structured_output: false,
+                    context_window: None,
+                })?;
+                Ok(std::sync::Arc::new(client) as std::sync::Arc<dyn AiPlanner>)
+            }))
+        }
+        other => Err(anyhow!("unsupported provider: {other}")),
+    }
+}
+
+#[cfg(feature = "async")]
+fn blocking_section<T>(f: impl FnOnce() -> T) -> T {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+fn context_only_role(metadata: &serde_json::Value) -> Option<AiChatRole> {
+    if metadata.get("context_only").and_then(|value| value.as_bool()) != Some(true) {
+        return None;
+    }
+    let role = metadata
+        .get("role")
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_lowercase());
+    if role.as_deref() == Some("assistant") {
+        Some(AiChatRole::Assistant)
+    } else {
+        Some(AiChatRole::User)
+    }
+}
+
 fn parse_approval_command(input: &str) -> Option<(ApprovalDecision, Option<String>)> {
     let trimmed = input.trim();
     if trimmed.is_empty() {
@@ -1508,37 +6299,103 @@ fn parse_approval_command(input: &str) -> Option<(ApprovalDecision, Option<Strin
 
     let lower = trimmed.to_lowercase();
     if lower == "yes" || lower == "y" || lower == "approve" {
-        return Some((ApprovalDecision::Approve, None));
+        return Some((ApprovalDecision::Approve(None), None));
     }
     if is_affirmation(&lower) || is_followup_reference(&lower) {
-        return Some((ApprovalDecision::ApproveAll, None));
+        return Some((ApprovalDecision::ApproveAll(None), None));
     }
     if lower == "approve-all" || lower == "approve all" || lower == "approve plan" {
-        return Some((ApprovalDecision::ApproveAll, None));
+        return Some((ApprovalDecision::ApproveAll(None), None));
     }
     if lower == "no" || lower == "n" || lower == "deny" || lower == "reject" {
-        return Some((ApprovalDecision::Deny, None));
+        return Some((ApprovalDecision::Deny(None), None));
     }
 
-    if let Some(rest) = lower.strip_prefix("approve ") {
-        return Some((ApprovalDecision::Approve, Some(rest.trim().to_string())));
-    }
-    if let Some(rest) = lower.strip_prefix("approve-all ") {
-        return Some((ApprovalDecision::ApproveAll, Some(rest.trim().to_string())));
-    }
-    if let Some(rest) = lower.strip_prefix("approve all ") {
-        return Some((ApprovalDecision::ApproveAll, Some(rest.trim().to_string())));
-    }
-    if let Some(rest) = lower.strip_prefix("approve plan ") {
-        return Some((ApprovalDecision::ApproveAll, Some(rest.trim().to_string())));
+    for (prefix, all) in [
+        ("approve-all ", true),
+        ("approve all ", true),
+        ("approve plan ", true),
+        ("approve ", false),
+    ] {
+        if lower.starts_with(prefix) {
+            let (id, overrides) = split_approval_target(&trimmed[prefix.len()..]);
+            let decision = if all {
+                ApprovalDecision::ApproveAll(overrides)
+            } else {
+                ApprovalDecision::Approve(overrides)
+            };
+            return Some((decision, id));
+        }
     }
-    if let Some(rest) = lower.strip_prefix("deny ") {
-        return Some((ApprovalDecision::Deny, Some(rest.trim().to_string())));
+    if lower.starts_with("deny ") {
+        // `deny appr-3 wrong directory`, or `deny wrong directory` for the latest approval.
+        let rest = trimmed["deny ".len()..].trim();
+        let (first, reason) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(first, reason)| (first, reason.trim()));
+        if first.to_lowercase().starts_with("appr-") {
+            let reason = (!reason.is_empty()).then(|| reason.to_string());
+            return Some((ApprovalDecision::Deny(reason), Some(first.to_lowercase())));
+        }
+        return Some((ApprovalDecision::Deny(Some(rest.to_string())), None));
     }
 
     None
 }
 
+/// Splits `appr-2 path=~/Documents` into the approval id and param overrides (`key=value`
+/// pairs or a JSON object). Without an id the overrides apply to the latest approval.
+fn split_approval_target(rest: &str) -> (Option<String>, Option<serde_json::Value>) {
+    let rest = rest.trim();
+    let (first, tail) = rest
+        .split_once(char::is_whitespace)
+        .map_or((rest, ""), |(first, tail)| (first, tail.trim()));
+    let (id, tail) = if first.contains('=') || first.starts_with('{') {
+        (None, rest)
+    } else {
+        (Some(first.to_lowercase()), tail)
+    };
+    let overrides = if tail.is_empty() {
+        None
+    } else if tail.starts_with('{') {
+        Some(serde_json::from_str(tail).unwrap_or_else(|_| json!({})))
+    } else {
+        Some(parse_kv_params(tail))
+    };
+    (id, overrides)
+}
+
+#[derive(Serialize)]
+struct CriticReview {
+    verdict: String,
+    confidence: Option<f64>,
+    justification: String,
+}
+
+/// Reads the `VERDICT:` / `CONFIDENCE:` / `REASON:` lines of a critic reply.
+fn parse_critic_review(message: &str) -> Option<CriticReview> {
+    let mut verdict = None;
+    let mut confidence = None;
+    let mut justification = String::new();
+    for line in message.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_uppercase().as_str() {
+            "VERDICT" => verdict = Some(value.to_lowercase()),
+            "CONFIDENCE" => confidence = value.parse::<f64>().ok().map(|v| v.clamp(0.0, 1.0)),
+            "REASON" => justification = value.to_string(),
+            _ => {}
+        }
+    }
+    Some(CriticReview {
+        verdict: verdict?,
+        confidence,
+        justification,
+    })
+}
+
 struct PlanApprovalHint {
     plan_id: String,
     step_index: usize,
@@ -1546,6 +6403,34 @@ struct PlanApprovalHint {
     allow_approve_all: bool,
 }
 
+/// How an incoming attachment is shown to planners and the AI.
+fn attachment_line(attachment: &Attachment) -> String {
+    let mut details = vec![attachment.mime.clone().unwrap_or_default()];
+    if let Some(size) = attachment.size {
+        details.push(format_bytes(size));
+    }
+    format!(
+        "[attached: {} ({})]",
+        attachment.path.as_deref().unwrap_or(&attachment.name),
+        details.join(", ")
+    )
+}
+
+/// `id` with everything but letters, digits, `-` and `_` replaced, for use as a file name.
+fn file_safe(id: &str) -> String {
+    id.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' { ch } else { '_' })
+        .collect()
+}
+
+fn risk_label(risk: RiskLevel) -> &'static str {
+    match risk {
+        RiskLevel::Low => "low",
+        RiskLevel::Medium => "medium",
+        RiskLevel::High => "high",
+    }
+}
+
 fn format_approval_prompt(
     spec: &ActionSpec,
     params: &serde_json::Value,
@@ -1553,44 +6438,137 @@ fn format_approval_prompt(
     approval_id: &str,
     preflight: Option<&PreflightReport>,
     plan_hint: Option<PlanApprovalHint>,
-) -> String {
-    let risk = match spec.risk {
-        RiskLevel::Low => "low",
-        RiskLevel::Medium => "medium",
-        RiskLevel::High => "high",
+    critic: Option<&CriticReview>,
+) -> ApprovalPrompt {
+    ApprovalPrompt {
+        approval_id: approval_id.to_string(),
+        action: spec.name.clone(),
+        description: spec.description.clone(),
+        risk: risk_label(spec.risk).to_string(),
+        dry_run: ctx.dry_run,
+        preflight: preflight
+            .map(|report| report.summary())
+            .unwrap_or_else(|| "n/a".to_string()),
+        params: format_params_compact(params),
+        change: preflight.and_then(|report| report.change.clone()),
+        critic: critic.map(|review| CriticNote {
+            verdict: review.verdict.clone(),
+            confidence: review.confidence,
+            reason: review.justification.clone(),
+        }),
+        plan: plan_hint.map(|hint| PlanNote {
+            plan_id: hint.plan_id,
+            step: hint.step_index,
+            total: hint.total_steps,
+            approve_all: hint.allow_approve_all,
+        }),
+    }
+}
+
+/// Variables are shared by the rooms of a workspace.
+fn var_workspace(msg: &InboundMessage) -> String {
+    msg.workspace_id
+        .clone()
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// Typed as `action:<name> ...` rather than planned.
+fn is_explicit_action(raw_input: &str) -> bool {
+    let trimmed = raw_input.trim_start();
+    trimmed.starts_with("action:") || trimmed.starts_with("action ")
+}
+
+fn room_key(msg: &InboundMessage) -> (String, String) {
+    (
+        msg.workspace_id.clone().unwrap_or_default(),
+        msg.channel.clone(),
+    )
+}
+
+fn session_key(msg: &InboundMessage) -> (String, String, String) {
+    (
+        msg.workspace_id.clone().unwrap_or_default(),
+        msg.channel.clone(),
+        msg.sender.clone(),
+    )
+}
+
+/// `10m`, `2h`, `90s`, or a bare number of minutes.
+fn parse_trust_window(arg: &str) -> Option<Duration> {
+    let arg = arg.trim().to_lowercase();
+    let (number, unit) = match arg.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(idx) => arg.split_at(idx),
+        None => (arg.as_str(), "m"),
     };
-    let params_text = format_params_compact(params);
-    let preflight_text = preflight
-        .map(|report| report.summary())
-        .unwrap_or_else(|| "n/a".to_string());
-    let mut text = format!(
-        "需要审批：{name}\n描述：{desc}\n风险：{risk}  |  dry-run：{dry_run}\n预检：{preflight}\n参数：{params}",
-        name = spec.name,
-        desc = spec.description,
-        risk = risk,
-        dry_run = ctx.dry_run,
-        preflight = preflight_text,
-        params = params_text,
-    );
-    if let Some(hint) = plan_hint {
-        text.push_str(&format!(
-            "\n计划：{plan}  |  步骤：{step}/{total}",
-            plan = hint.plan_id,
-            step = hint.step_index,
-            total = hint.total_steps
-        ));
-        if hint.allow_approve_all {
-            text.push_str(&format!(
-                "\n回复 approve-all {id} 一次性同意后续步骤",
-                id = approval_id
-            ));
-        }
+    let value: u64 = number.parse().ok().filter(|value| *value > 0)?;
+    let secs = match unit.trim() {
+        "s" | "sec" | "secs" => value,
+        "m" | "min" | "mins" => value * 60,
+        "h" | "hr" | "hrs" => value * 3600,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Coarse duration for messages, e.g. `45s`, `12m`, `3h`.
+pub(crate) fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
     }
-    text.push_str(&format!(
-        "\n回复 approve {id} 执行，或 deny {id} 取消",
-        id = approval_id
-    ));
-    text
+}
+
+/// `export` or `export <path.md>`; `export the photos to ...` is a request, not the command.
+fn export_command(text: &str) -> Option<Option<&str>> {
+    if text == "export" {
+        return Some(None);
+    }
+    let path = text.strip_prefix("export ")?.trim();
+    (path.ends_with(".md") && !path.contains(char::is_whitespace)).then_some(Some(path))
+}
+
+/// `forget <id>` or `forget all`; `forget about it` is not the command.
+fn forget_command(text: &str) -> Option<Option<u64>> {
+    match text.strip_prefix("forget ")?.trim() {
+        "all" => Some(None),
+        id => id.parse().ok().map(Some),
+    }
+}
+
+/// The role a control command needs, when it changes more than the sender's view.
+fn control_role(text: &str) -> Option<Role> {
+    if text.starts_with("model use ")
+        || text.starts_with("profile use ")
+        || text.starts_with("history prune ")
+        || text == "read-only on"
+        || text == "read-only off"
+        || text == "reload"
+    {
+        return Some(Role::Admin);
+    }
+    if text == "alias list" {
+        return None;
+    }
+    if export_command(text).is_some()
+        || forget_command(text).is_some()
+        || text.starts_with("remember ")
+    {
+        return Some(Role::Operator);
+    }
+    let operator = ["dry-run on", "dry-run off", "history clear", "trust ", "set var ", "alias ", "unalias ", "schedule delete "];
+    operator
+        .iter()
+        .any(|prefix| text.starts_with(prefix))
+        .then_some(Role::Operator)
 }
 
 fn format_params_compact(params: &serde_json::Value) -> String {
@@ -1626,6 +6604,231 @@ fn compact_value(value: &serde_json::Value) -> String {
     }
 }
 
+/// Replaces `{{steps.<id>.<path>}}` references in plan step params with earlier results
+/// (`summary`, `action`, or `data.<field>`). A string that is only a reference keeps the
+/// referenced value's JSON type; other `{{...}}` text is left as is.
+/// Fills `{{input.<name>}}` placeholders of a plan template.
+fn fill_plan_inputs(steps: &[PlanStep], values: &HashMap<String, String>) -> Result<Vec<PlanStep>> {
+    let filled = substitute_templates(&serde_json::to_value(steps)?, &|expr| {
+        Ok(expr
+            .strip_prefix("input.")
+            .and_then(|name| values.get(name.trim()))
+            .map(|value| serde_json::Value::String(value.clone())))
+    })?;
+    Ok(serde_json::from_value(filled)?)
+}
+
+fn resolve_step_templates(
+    params: &serde_json::Value,
+    results: &[PlanResultItem],
+) -> Result<serde_json::Value> {
+    substitute_templates(params, &|expr| lookup_step_reference(expr, results))
+}
+
+/// Replaces every `{{expr}}` that `lookup` knows in strings nested anywhere in `value`.
+fn substitute_templates(
+    value: &serde_json::Value,
+    lookup: &dyn Fn(&str) -> Result<Option<serde_json::Value>>,
+) -> Result<serde_json::Value> {
+    Ok(match value {
+        serde_json::Value::String(text) => resolve_template_string(text, lookup)?,
+        serde_json::Value::Array(items) => serde_json::Value::Array(
+            items
+                .iter()
+                .map(|item| substitute_templates(item, lookup))
+                .collect::<Result<_>>()?,
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| Ok((key.clone(), substitute_templates(value, lookup)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+fn resolve_template_string(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Result<Option<serde_json::Value>>,
+) -> Result<serde_json::Value> {
+    if let Some(expr) = text
+        .trim()
+        .strip_prefix("{{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+        && !expr.contains("{{")
+        && let Some(value) = lookup(expr.trim())?
+    {
+        return Ok(value);
+    }
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let expr = &rest[start + 2..start + len];
+        out.push_str(&rest[..start]);
+        match lookup(expr.trim())? {
+            Some(serde_json::Value::String(value)) => out.push_str(&value),
+            Some(value) => out.push_str(&value.to_string()),
+            None => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    Ok(serde_json::Value::String(out))
+}
+
+/// `None` for expressions that are not step references.
+fn lookup_step_reference(
+    expr: &str,
+    results: &[PlanResultItem],
+) -> Result<Option<serde_json::Value>> {
+    let Some(path) = expr.strip_prefix("steps.") else {
+        return Ok(None);
+    };
+    let mut parts = path.split('.');
+    let step = parts.next().unwrap_or_default();
+    let Some(result) = results.iter().rev().find(|result| result.step == step) else {
+        return Err(anyhow!("no result for step {step} in {{{{{expr}}}}}"));
+    };
+    let current = json!({
+        "action": result.action,
+        "summary": result.summary,
+        "data": result.data,
+    });
+    walk_value(current, parts)
+        .map(Some)
+        .ok_or_else(|| anyhow!("{{{{{expr}}}}} not found in step {step} result"))
+}
+
+fn walk_value<'a>(
+    mut current: serde_json::Value,
+    parts: impl Iterator<Item = &'a str>,
+) -> Option<serde_json::Value> {
+    for part in parts {
+        let next = match &current {
+            serde_json::Value::Array(items) => {
+                part.parse::<usize>().ok().and_then(|idx| items.get(idx))
+            }
+            value => value.get(part),
+        };
+        current = next.cloned()?;
+    }
+    Some(current)
+}
+
+/// Most copies a single foreach step may expand into.
+const MAX_FOREACH_ITEMS: usize = 200;
+
+/// Replaces a foreach step with one step per item, with ids `<key>-1`, `<key>-2`, ….
+fn expand_foreach(
+    step: &PlanStep,
+    step_key: &str,
+    foreach: &ForEach,
+    results: &[PlanResultItem],
+) -> Result<Vec<PlanStep>> {
+    let items = match (&foreach.glob, &foreach.items) {
+        (Some(pattern), None) => expand_glob(pattern)?
+            .into_iter()
+            .map(|path| serde_json::Value::String(path.display().to_string()))
+            .collect(),
+        (None, Some(items)) => match resolve_step_templates(items, results)? {
+            serde_json::Value::Array(items) => items,
+            other => return Err(anyhow!("items must be an array, got {other}")),
+        },
+        _ => return Err(anyhow!("set exactly one of glob and items")),
+    };
+    if items.len() > MAX_FOREACH_ITEMS {
+        return Err(anyhow!(
+            "{} items, more than the limit of {MAX_FOREACH_ITEMS}",
+            items.len()
+        ));
+    }
+    let var = foreach.var.as_str();
+    items
+        .iter()
+        .enumerate()
+        .map(|(idx, item)| {
+            let lookup = |expr: &str| lookup_foreach_item(expr, var, item);
+            let undo = match &step.undo {
+                Some(undo) => Some(UndoStep {
+                    action: undo.action.clone(),
+                    params: substitute_templates(&undo.params, &lookup)?,
+                }),
+                None => None,
+            };
+            Ok(PlanStep {
+                id: Some(format!("{step_key}-{}", idx + 1)),
+                params: substitute_templates(&step.params, &lookup)?,
+                undo,
+                foreach: None,
+                ..step.clone()
+            })
+        })
+        .collect()
+}
+
+/// Expands foreach steps whose items do not depend on earlier results, so a plan preview
+/// can list every call.
+fn expand_known_foreach(steps: Vec<PlanStep>) -> Result<Vec<PlanStep>> {
+    let mut expanded = Vec::with_capacity(steps.len());
+    for step in steps {
+        let known = step.foreach.as_ref().is_some_and(|foreach| {
+            foreach
+                .items
+                .as_ref()
+                .is_none_or(|items| !items.to_string().contains("{{steps."))
+        });
+        match (&step.foreach, known) {
+            (Some(foreach), true) => {
+                let key = step.id.clone().unwrap_or_default();
+                expanded.extend(
+                    expand_foreach(&step, &key, foreach, &[])
+                        .with_context(|| format!("plan step {key}: foreach"))?,
+                );
+            }
+            _ => expanded.push(step),
+        }
+    }
+    Ok(expanded)
+}
+
+/// `None` for expressions that are not about the foreach variable.
+fn lookup_foreach_item(
+    expr: &str,
+    var: &str,
+    item: &serde_json::Value,
+) -> Result<Option<serde_json::Value>> {
+    if expr == var {
+        return Ok(Some(item.clone()));
+    }
+    let Some(path) = expr
+        .strip_prefix(var)
+        .and_then(|rest| rest.strip_prefix('.'))
+    else {
+        return Ok(None);
+    };
+    if let serde_json::Value::String(text) = item {
+        let path_ref = Path::new(text);
+        let part = match path {
+            "name" => path_ref.file_name(),
+            "stem" => path_ref.file_stem(),
+            "ext" => path_ref.extension(),
+            "dir" => path_ref.parent().map(|dir| dir.as_os_str()),
+            _ => None,
+        };
+        if let Some(part) = part {
+            return Ok(Some(serde_json::Value::String(
+                part.to_string_lossy().into_owned(),
+            )));
+        }
+    }
+    walk_value(item.clone(), path.split('.'))
+        .map(Some)
+        .ok_or_else(|| anyhow!("{{{{{expr}}}}} not found in item {item}"))
+}
+
 fn extract_outcome_from_replies(replies: &[OutboundMessage]) -> Option<ActionOutcome> {
     for reply in replies {
         let kind = reply
@@ -1651,10 +6854,157 @@ fn extract_outcome_from_replies(replies: &[OutboundMessage]) -> Option<ActionOut
     None
 }
 
+/// Retries and time limits for executing one plan step.
+#[derive(Clone, Default)]
+struct StepPolicy {
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Option<Duration>,
+    /// When the plan deadline runs out.
+    deadline: Option<Instant>,
+}
+
+impl StepPolicy {
+    fn for_step(step: &PlanStep, time_left: Option<Duration>) -> Self {
+        Self {
+            retries: step.retries.unwrap_or(0),
+            retry_delay: Duration::from_secs(step.retry_delay.unwrap_or(0)),
+            timeout: step.timeout_secs.map(Duration::from_secs),
+            deadline: time_left.map(|left| Instant::now() + left),
+        }
+    }
+
+    fn or_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = self.timeout.or(timeout);
+        self
+    }
+}
+
+fn run_step(
+    action: &std::sync::Arc<dyn ActionHandler>,
+    ctx: &ActionContext,
+    params: &serde_json::Value,
+    policy: &StepPolicy,
+) -> (Result<ActionOutcome>, u32) {
+    if policy.deadline.is_some_and(|at| at <= Instant::now()) {
+        return (Err(anyhow!("plan deadline exceeded")), 0);
+    }
+    run_with_retries(policy.retries, policy.retry_delay, || {
+        execute_with_timeout(action, ctx, params, policy)
+    })
+}
+
+/// A step that ran past its time limit or the plan deadline. Never retried: the first attempt
+/// may still be running.
+#[derive(Debug)]
+struct StepTimedOut {
+    message: String,
+    /// The action had not returned even after `TIMEOUT_GRACE`.
+    still_running: bool,
+}
+
+impl std::fmt::Display for StepTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)?;
+        if self.still_running {
+            f.write_str("; the action is still running in the background")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for StepTimedOut {}
+
+/// How long an action that overran gets to stop, e.g. `shell.run` killing its command.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
+
+/// Runs the action on its own thread when a time limit applies. `shell.run` stops its command
+/// at the limit; other actions that overrun are left to finish in the background and their
+/// result is dropped.
+fn execute_with_timeout(
+    action: &std::sync::Arc<dyn ActionHandler>,
+    ctx: &ActionContext,
+    params: &serde_json::Value,
+    policy: &StepPolicy,
+) -> Result<ActionOutcome> {
+    let time_left = policy
+        .deadline
+        .map(|at| at.saturating_duration_since(Instant::now()));
+    let (limit, deadline_bound) = match (policy.timeout, time_left) {
+        (Some(timeout), Some(left)) if left < timeout => (left, true),
+        (None, Some(left)) => (left, true),
+        (Some(timeout), _) => (timeout, false),
+        (None, None) => return action.execute(ctx, params),
+    };
+    if limit.is_zero() {
+        return Err(anyhow!("plan deadline exceeded"));
+    }
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (action, mut ctx, params) = (action.clone(), ctx.clone(), params.clone());
+    ctx.deadline = Some(Instant::now() + limit);
+    std::thread::spawn(move || {
+        let _ = tx.send(action.execute(&ctx, &params));
+    });
+    if let Ok(result) = rx.recv_timeout(limit) {
+        return result;
+    }
+    let message = if deadline_bound {
+        "plan deadline exceeded".to_string()
+    } else {
+        format!("timed out after {}s", limit.as_secs())
+    };
+    let still_running = rx.recv_timeout(TIMEOUT_GRACE).is_err();
+    Err(StepTimedOut {
+        message,
+        still_running,
+    }
+    .into())
+}
+
+/// Runs `f` up to `retries + 1` times, sleeping `delay` after each failure.
+fn run_with_retries<T>(
+    retries: u32,
+    delay: Duration,
+    mut f: impl FnMut() -> Result<T>,
+) -> (Result<T>, u32) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match f() {
+            Err(err) if attempts <= retries && err.downcast_ref::<StepTimedOut>().is_none() => {
+                warn!("step attempt {attempts} failed, retrying: {err}");
+                std::thread::sleep(delay);
+            }
+            result => return (result, attempts),
+        }
+    }
+}
+
+fn execution_error_text(err: &anyhow::Error, attempts: u32) -> String {
+    if attempts > 1 {
+        format!("error after {attempts} attempts: {err}")
+    } else {
+        format!("error: {err}")
+    }
+}
+
+fn attempts_note(attempts: u32) -> String {
+    if attempts > 1 {
+        format!(" ({attempts} attempts)")
+    } else {
+        String::new()
+    }
+}
+
 fn plan_result_details(plan: &PlanProgress) -> String {
     let mut lines = Vec::new();
     for (idx, item) in plan.results.iter().enumerate() {
-        let prefix = format!("Step {} ({})", idx + 1, item.action);
+        let prefix = format!(
+            "Step {} ({}){}",
+            idx + 1,
+            item.action,
+            attempts_note(item.attempts)
+        );
         if item.action == "shell.run" {
             let command = item
                 .data
@@ -1673,15 +7023,18 @@ fn plan_result_details(plan: &PlanProgress) -> String {
             lines.push(format!("{prefix}: {}", item.summary));
         }
     }
+    if let Some(failure) = &plan.failure {
+        lines.push(format!("Stopped at {failure}"));
+    }
     lines.join("\n")
 }
 
-fn format_plan_summary_fallback(plan: &PlanProgress) -> String {
+fn format_plan_summary_fallback(plan: &PlanProgress, locale: Locale) -> String {
+    let catalog = locale.catalog();
     let mut lines = Vec::new();
-    lines.push(format!(
-        "完成计划（{}/{} 步）：",
-        plan.results.len(),
-        plan.total_steps
+    lines.push(fill(
+        catalog.plan_done,
+        &[("done", &plan.results.len()), ("total", &plan.total_steps)],
     ));
     for item in &plan.results {
         if item.action == "shell.run" {
@@ -1696,21 +7049,27 @@ fn format_plan_summary_fallback(plan: &PlanProgress) -> String {
                 .and_then(|v| v.as_str())
                 .unwrap_or("");
             let snippet = truncate_text(stdout, 1200);
-            lines.push(format!("- command: {command}\n{snippet}"));
+            lines.push(format!(
+                "- command: {command}{}\n{snippet}",
+                attempts_note(item.attempts)
+            ));
         } else {
-            lines.push(format!("- {}: {}", item.action, item.summary));
+            lines.push(format!(
+                "- {}{}: {}",
+                item.action,
+                attempts_note(item.attempts),
+                item.summary
+            ));
         }
     }
+    if let Some(failure) = &plan.failure {
+        lines.push(fill(catalog.plan_failed, &[("failure", failure)]));
+    }
     lines.join("\n")
 }
 
-fn summarize_system_status(plan: &PlanProgress) -> Option<String> {
-    let mut uptime = None;
-    let mut vm_stat = None;
-    let mut df = None;
-    let mut ps = None;
-    let mut ifconfig = None;
-
+fn summarize_system_status(plan: &PlanProgress, locale: Locale) -> Option<String> {
+    let mut outputs = Vec::new();
     for item in &plan.results {
         if item.action != "shell.run" {
             continue;
@@ -1725,73 +7084,88 @@ fn summarize_system_status(plan: &PlanProgress) -> Option<String> {
             .get("stdout")
             .and_then(|v| v.as_str())
             .unwrap_or("");
-        match command {
-            "uptime" => uptime = Some(stdout.to_string()),
-            "vm_stat" => vm_stat = Some(stdout.to_string()),
-            "df -h" => df = Some(stdout.to_string()),
-            cmd if cmd.contains("ps aux") => ps = Some(stdout.to_string()),
-            "ifconfig" => ifconfig = Some(stdout.to_string()),
-            _ => {}
+        if let Some((os, probe)) = match_probe(command) {
+            outputs.push((os, probe, command, stdout));
         }
     }
-
-    if uptime.is_none() && vm_stat.is_none() && df.is_none() && ps.is_none() && ifconfig.is_none() {
+    if outputs.is_empty() {
         return None;
     }
 
     let mut lines = Vec::new();
-    lines.push("系统状态摘要：".to_string());
-    if let Some(uptime_out) = &uptime {
-        let summary = parse_uptime_summary(uptime_out);
-        lines.push(format!("- Uptime/Load: {summary}"));
+    lines.push(locale.catalog().status_summary.to_string());
+    for (os, probe, _, stdout) in &outputs {
+        if let Some(line) = summarize_probe(*os, *probe, stdout) {
+            lines.push(line);
+        }
     }
-    if let Some(vm_stat_out) = &vm_stat {
-        if let Some(mem) = parse_vm_stat_summary(vm_stat_out) {
-            lines.push(format!(
+
+    lines.push(format!("\n{}", locale.catalog().raw_output));
+    for (_, probe, command, stdout) in &outputs {
+        let limit = match probe {
+            Probe::Memory | Probe::Processes => 1600,
+            _ => 1200,
+        };
+        lines.push(format!("[{command}]\n{}", truncate_text(stdout, limit)));
+    }
+
+    Some(lines.join("\n"))
+}
+
+fn summarize_probe(os: HostOs, probe: Probe, output: &str) -> Option<String> {
+    match (probe, os) {
+        (Probe::Load, HostOs::Windows) => {
+            let load = output.trim();
+            (!load.is_empty()).then(|| format!("- CPU load: {load}%"))
+        }
+        (Probe::Load, _) => Some(format!("- Uptime/Load: {}", parse_uptime_summary(output))),
+        (Probe::Memory, os) => {
+            let mem = match os {
+                HostOs::MacOs => parse_vm_stat_summary(output),
+                HostOs::Linux => parse_free_summary(output),
+                HostOs::Windows => parse_win_memory_summary(output),
+            }?;
+            Some(format!(
                 "- Memory: used {} / total {} (free {})",
                 mem.used_gib, mem.total_gib, mem.free_gib
-            ));
+            ))
         }
-    }
-    if let Some(df_out) = &df {
-        if let Some(disk) = parse_df_summary(df_out) {
-            lines.push(format!(
+        (Probe::Disk, HostOs::Windows) => None,
+        (Probe::Disk, _) => {
+            let disk = parse_df_summary(output)?;
+            Some(format!(
                 "- Disk {}: used {} / {} (avail {}, {} used)",
                 disk.mount, disk.used, disk.size, disk.avail, disk.capacity
-            ));
+            ))
         }
-    }
-    if let Some(ps_out) = &ps {
-        let top = parse_ps_summary(ps_out);
-        if !top.is_empty() {
-            lines.push(format!("- Top processes: {}", top));
+        (Probe::Processes, os) => {
+            let top = match os {
+                HostOs::Windows => output
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .take(5)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => parse_ps_summary(output),
+            };
+            (!top.is_empty()).then(|| format!("- Top processes: {top}"))
         }
-    }
-    if let Some(if_out) = &ifconfig {
-        let count = if_out.lines().filter(|line| !line.starts_with('\t') && line.contains(':')).count();
-        if count > 0 {
-            lines.push(format!("- Network interfaces: {}", count));
+        (Probe::Network, os) => {
+            let count = match os {
+                HostOs::MacOs => output
+                    .lines()
+                    .filter(|line| !line.starts_with('\t') && line.contains(':'))
+                    .count(),
+                HostOs::Linux => output.lines().filter(|line| !line.trim().is_empty()).count(),
+                HostOs::Windows => output
+                    .lines()
+                    .filter(|line| !line.starts_with(' ') && line.trim_end().ends_with(':'))
+                    .count(),
+            };
+            (count > 0).then(|| format!("- Network interfaces: {count}"))
         }
     }
-
-    lines.push("\n原始输出：".to_string());
-    if let Some(uptime_out) = uptime {
-        lines.push(format!("[uptime]\n{}", truncate_text(&uptime_out, 1200)));
-    }
-    if let Some(vm_stat_out) = vm_stat {
-        lines.push(format!("[vm_stat]\n{}", truncate_text(&vm_stat_out, 1600)));
-    }
-    if let Some(df_out) = df {
-        lines.push(format!("[df -h]\n{}", truncate_text(&df_out, 1200)));
-    }
-    if let Some(ps_out) = ps {
-        lines.push(format!("[ps aux]\n{}", truncate_text(&ps_out, 1600)));
-    }
-    if let Some(if_out) = ifconfig {
-        lines.push(format!("[ifconfig]\n{}", truncate_text(&if_out, 1200)));
-    }
-
-    Some(lines.join("\n"))
 }
 
 struct MemSummary {
@@ -1856,6 +7230,42 @@ fn parse_vm_stat_summary(output: &str) -> Option<MemSummary> {
     })
 }
 
+fn gib_summary(used: u64, free: u64, total: u64) -> MemSummary {
+    let to_gib = |bytes: u64| format!("{:.2} GiB", bytes as f64 / 1024.0 / 1024.0 / 1024.0);
+    MemSummary {
+        used_gib: to_gib(used),
+        free_gib: to_gib(free),
+        total_gib: to_gib(total),
+    }
+}
+
+/// `free -b`; "free" is the available column, which counts reclaimable cache.
+fn parse_free_summary(output: &str) -> Option<MemSummary> {
+    let row = output.lines().find(|line| line.starts_with("Mem:"))?;
+    let values: Vec<u64> = row
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    let total = *values.first()?;
+    let used = *values.get(1)?;
+    let free = values.get(5).or(values.get(2)).copied()?;
+    (total > 0).then(|| gib_summary(used, free, total))
+}
+
+/// `Format-List TotalVisibleMemorySize,FreePhysicalMemory`, both in KiB.
+fn parse_win_memory_summary(output: &str) -> Option<MemSummary> {
+    let value = |key: &str| {
+        output.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            (name.trim() == key).then(|| value.trim().parse::<u64>().ok())?
+        })
+    };
+    let total = value("TotalVisibleMemorySize")? * 1024;
+    let free = value("FreePhysicalMemory")? * 1024;
+    (total > 0).then(|| gib_summary(total.saturating_sub(free), free, total))
+}
+
 struct DiskSummary {
     mount: String,
     size: String,
@@ -1871,11 +7281,9 @@ fn parse_df_summary(output: &str) -> Option<DiskSummary> {
         if parts.len() < 6 {
             continue;
         }
+        // macOS adds inode columns before the mount point; the first five line up everywhere.
         let mount = parts[parts.len() - 1];
-        let capacity = parts[parts.len() - 3];
-        let avail = parts[parts.len() - 4];
-        let used = parts[parts.len() - 5];
-        let size = parts[parts.len() - 6];
+        let (size, used, avail, capacity) = (parts[1], parts[2], parts[3], parts[4]);
         let summary = DiskSummary {
             mount: mount.to_string(),
             size: size.to_string(),
@@ -1895,11 +7303,15 @@ fn parse_df_summary(output: &str) -> Option<DiskSummary> {
 
 fn parse_uptime_summary(output: &str) -> String {
     let line = output.lines().next().unwrap_or("").trim();
-    if let Some(idx) = line.find("load averages:") {
-        let load = line[idx + "load averages:".len()..].trim();
-        return format!("{line} (load {load})");
+    // macOS says "load averages:", Linux (uptime and top) "load average:".
+    let load = line
+        .find("load average")
+        .and_then(|idx| line[idx..].split_once(':'))
+        .map(|(_, load)| load.trim());
+    match load {
+        Some(load) => format!("{line} (load {load})"),
+        None => line.to_string(),
     }
-    line.to_string()
 }
 
 fn parse_ps_summary(output: &str) -> String {
@@ -1915,6 +7327,20 @@ fn parse_ps_summary(output: &str) -> String {
     names.join(", ")
 }
 
+/// Splits off at most `limit` characters, preferring to end at a line break in the second
+/// half of the page.
+fn split_page(text: &str, limit: usize) -> (&str, &str) {
+    let Some((cut, _)) = text.char_indices().nth(limit) else {
+        return (text, "");
+    };
+    let cut = text[..cut]
+        .rfind('\n')
+        .filter(|newline| *newline >= cut / 2)
+        .map(|newline| newline + 1)
+        .unwrap_or(cut);
+    text.split_at(cut)
+}
+
 fn truncate_text(text: &str, limit: usize) -> String {
     if text.len() <= limit {
         return text.to_string();
@@ -1962,16 +7388,81 @@ fn filter_plan_result_replies(replies: Vec<OutboundMessage>) -> Vec<OutboundMess
     keep
 }
 
-fn insert_param(mut params: serde_json::Value, key: &str, value: &str) -> serde_json::Value {
+fn insert_param(
+    mut params: serde_json::Value,
+    key: &str,
+    value: serde_json::Value,
+) -> serde_json::Value {
     match &mut params {
         serde_json::Value::Object(map) => {
-            map.insert(key.to_string(), serde_json::Value::String(value.to_string()));
+            map.insert(key.to_string(), value);
             params
         }
         _ => serde_json::json!({ key: value }),
     }
 }
 
+/// What a reply to a typed field should have been.
+enum Expected {
+    Integer,
+    Number,
+    Boolean,
+    Object,
+    OneOf(Vec<String>),
+}
+
+/// Reads a reply as the JSON type `schema` declares.
+fn coerce_param(
+    text: &str,
+    schema: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, Expected> {
+    use serde_json::Value;
+    let kind = schema
+        .and_then(|schema| schema.get("type"))
+        .and_then(Value::as_str)
+        .unwrap_or("string");
+    let value = match kind {
+        "integer" => text
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| Expected::Integer)?,
+        "number" => text
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .ok_or(Expected::Number)?,
+        "boolean" => match text.to_lowercase().as_str() {
+            "true" | "yes" | "y" | "on" | "1" | "是" | "要" => Value::Bool(true),
+            "false" | "no" | "n" | "off" | "0" | "否" | "不" | "不要" => Value::Bool(false),
+            _ => return Err(Expected::Boolean),
+        },
+        "array" => Value::Array(
+            text.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        "object" => serde_json::from_str::<Value>(text)
+            .ok()
+            .filter(Value::is_object)
+            .ok_or(Expected::Object)?,
+        _ => Value::String(text.to_string()),
+    };
+    let allowed = schema
+        .and_then(|schema| schema.get("enum"))
+        .and_then(Value::as_array);
+    if let Some(allowed) = allowed.filter(|allowed| !allowed.contains(&value)) {
+        let names: Vec<String> = allowed
+            .iter()
+            .map(|item| item.as_str().map_or_else(|| item.to_string(), str::to_string))
+            .collect();
+        return Err(Expected::OneOf(names));
+    }
+    Ok(value)
+}
+
 fn is_path_key(key: &str) -> bool {
     matches!(
         key.to_lowercase().as_str(),
@@ -2027,81 +7518,61 @@ fn last_user_message(history: &[AiChatMessage]) -> Option<String> {
         .map(|msg| msg.content.clone())
 }
 
-fn heuristic_plan_for(text: &str) -> Option<Vec<PlanStep>> {
-    let lower = text.to_lowercase();
-    let mut steps = Vec::new();
-    let wants_status = lower.contains("系统状态")
-        || lower.contains("system status")
-        || lower.contains("status")
-        || lower.contains("状态");
-    let wants_cpu = lower.contains("cpu") || lower.contains("负载") || lower.contains("load");
-    let wants_mem = lower.contains("内存") || lower.contains("memory");
-    let wants_disk = lower.contains("磁盘") || lower.contains("disk");
-    let wants_proc = lower.contains("进程") || lower.contains("process");
-    let wants_net = lower.contains("网络") || lower.contains("network");
-
-    if wants_status || wants_cpu {
-        steps.push(PlanStep {
-            id: Some("s1".to_string()),
-            action: "shell.run".to_string(),
-            params: json!({ "command": "uptime" }),
-            note: Some("Check uptime / load".to_string()),
-            requires_approval: Some(true),
-        });
-    }
-    if wants_status || wants_mem {
-        steps.push(PlanStep {
-            id: Some("s2".to_string()),
-            action: "shell.run".to_string(),
-            params: json!({ "command": "vm_stat" }),
-            note: Some("Check memory stats".to_string()),
-            requires_approval: Some(true),
-        });
-    }
-    if wants_status || wants_disk {
-        steps.push(PlanStep {
-            id: Some("s3".to_string()),
-            action: "shell.run".to_string(),
-            params: json!({ "command": "df -h" }),
-            note: Some("Check disk usage".to_string()),
-            requires_approval: Some(true),
-        });
-    }
-    if wants_status || wants_proc {
-        steps.push(PlanStep {
-            id: Some("s4".to_string()),
-            action: "shell.run".to_string(),
-            params: json!({ "command": "ps aux | sort -nrk 3,3 | head -5" }),
-            note: Some("Check top processes".to_string()),
-            requires_approval: Some(true),
-        });
-    }
-    if wants_net {
-        steps.push(PlanStep {
-            id: Some("s5".to_string()),
-            action: "shell.run".to_string(),
-            params: json!({ "command": "ifconfig" }),
-            note: Some("Check network interfaces".to_string()),
-            requires_approval: Some(true),
-        });
-    }
-
-    if steps.is_empty() {
-        None
-    } else {
-        Some(steps)
-    }
-}
-
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 struct RoomConfig {
     risk_policy: Option<RiskPolicyConfig>,
     action_allowlist: Option<HashSet<String>>,
     action_denylist: Option<HashSet<String>>,
     dry_run_default: Option<bool>,
+    provider_binding: Option<ProviderBinding>,
+    system_prompt_extra: Option<String>,
+    plan_preview: Option<bool>,
+    plan_deadline_secs: Option<u64>,
+    approval_ttl_secs: Option<u64>,
+    approvers: Option<HashSet<String>>,
+    high_risk_approvals: Option<u32>,
+    rate_limits: Option<RateLimits>,
+    quotas: Option<Quotas>,
+    max_reply_chars: Option<usize>,
+    reply_format: Option<ReplyFormat>,
+    locale: Option<Locale>,
 }
 
 impl RoomConfig {
+    fn ai_options(&self) -> AiRequestOptions {
+        let binding = self.provider_binding.as_ref();
+        AiRequestOptions {
+            model: binding
+                .map(|binding| binding.model.trim().to_string())
+                .filter(|model| !model.is_empty()),
+            temperature: binding.and_then(|binding| binding.temperature),
+            system_prompt_extra: self.system_prompt_extra.clone(),
+        }
+    }
+
+    fn allows_approver(&self, approver: &str) -> bool {
+        self.approvers
+            .as_ref()
+            .is_none_or(|approvers| approvers.contains(approver))
+    }
+
+    fn approvals_needed(&self, risk: RiskLevel) -> usize {
+        match risk {
+            RiskLevel::High => self.high_risk_approvals.unwrap_or(1).max(1) as usize,
+            _ => 1,
+        }
+    }
+
+    /// `None` when approvals never expire.
+    fn approval_ttl(&self) -> Option<Duration> {
+        match self.approval_ttl_secs {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(DEFAULT_APPROVAL_TTL),
+        }
+    }
+
     fn allows_action(&self, name: &str) -> bool {
         if let Some(deny) = &self.action_denylist {
             if deny.contains(name) {
@@ -2127,23 +7598,169 @@ impl RoomConfig {
         if other.dry_run_default.is_some() {
             self.dry_run_default = other.dry_run_default;
         }
+        if other.provider_binding.is_some() {
+            self.provider_binding = other.provider_binding.clone();
+        }
+        if other.plan_preview.is_some() {
+            self.plan_preview = other.plan_preview;
+        }
+        if other.plan_deadline_secs.is_some() {
+            self.plan_deadline_secs = other.plan_deadline_secs;
+        }
+        if other.approval_ttl_secs.is_some() {
+            self.approval_ttl_secs = other.approval_ttl_secs;
+        }
+        if other.approvers.is_some() {
+            self.approvers = other.approvers.clone();
+        }
+        if other.high_risk_approvals.is_some() {
+            self.high_risk_approvals = other.high_risk_approvals;
+        }
+        if other.rate_limits.is_some() {
+            self.rate_limits = other.rate_limits.clone();
+        }
+        if other.quotas.is_some() {
+            self.quotas = other.quotas.clone();
+        }
+        if other.max_reply_chars.is_some() {
+            self.max_reply_chars = other.max_reply_chars;
+        }
+        if other.reply_format.is_some() {
+            self.reply_format = other.reply_format;
+        }
+        if other.locale.is_some() {
+            self.locale = other.locale;
+        }
+        // Narrower scopes add to the prompt rather than replacing broader house rules.
+        if let Some(extra) = &other.system_prompt_extra {
+            self.system_prompt_extra = Some(match self.system_prompt_extra.take() {
+                Some(base) => format!("{base}\n{extra}"),
+                None => extra.clone(),
+            });
+        }
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 struct RiskPolicyConfig {
     low_auto_execute: bool,
     approval_for: Vec<RiskLevel>,
 }
 
+/// Sliding-window hit counters behind the room `rate_limits`.
+#[derive(Default)]
+struct RateLimiter {
+    hits: HashMap<String, VecDeque<Instant>>,
+    /// Keys already told they are throttled, until a hit gets through again.
+    throttled: HashSet<String>,
+}
+
+impl RateLimiter {
+    /// `None` when another hit fits under `limit` within `window`, otherwise the time until
+    /// the oldest hit leaves the window.
+    fn wait(&mut self, key: &str, limit: u32, window: Duration, now: Instant) -> Option<Duration> {
+        let hits = self.hits.get_mut(key)?;
+        while hits.front().is_some_and(|at| now.duration_since(*at) >= window) {
+            hits.pop_front();
+        }
+        if hits.len() < limit as usize {
+            return None;
+        }
+        hits.front()
+            .map(|oldest| window.saturating_sub(now.duration_since(*oldest)))
+    }
+
+    fn record(&mut self, key: &str, now: Instant) {
+        self.hits.entry(key.to_string()).or_default().push_back(now);
+        self.throttled.remove(key);
+    }
+
+    fn first_throttle(&mut self, key: &str) -> bool {
+        self.throttled.insert(key.to_string())
+    }
+
+    /// Drops hits older than the longest window.
+    fn prune(&mut self, now: Instant) {
+        self.hits.retain(|_, hits| {
+            while hits
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= Duration::from_secs(3600))
+            {
+                hits.pop_front();
+            }
+            !hits.is_empty()
+        });
+    }
+}
+
 #[derive(Default)]
 struct ConfigStore {
+    /// `[ai] system_prompt_extra` from the config files; under `global` and never saved.
+    file_prompt_extra: Option<String>,
     global: RoomConfig,
     workspaces: HashMap<String, RoomConfig>,
     rooms: HashMap<(String, String), RoomConfig>,
+    persist_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedConfigStore {
+    global: RoomConfig,
+    #[serde(default)]
+    workspaces: HashMap<String, RoomConfig>,
+    #[serde(default)]
+    rooms: Vec<PersistedRoomConfig>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedRoomConfig {
+    workspace_id: String,
+    room_id: String,
+    config: RoomConfig,
 }
 
 impl ConfigStore {
+    fn enable_persistence(&mut self, path: PathBuf) -> Result<()> {
+        if path.exists() {
+            let store: PersistedConfigStore = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            self.global = store.global;
+            self.workspaces = store.workspaces;
+            self.rooms = store
+                .rooms
+                .into_iter()
+                .map(|room| ((room.workspace_id, room.room_id), room.config))
+                .collect();
+        }
+        self.persist_path = Some(path);
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut rooms: Vec<PersistedRoomConfig> = self
+            .rooms
+            .iter()
+            .map(|((workspace_id, room_id), config)| PersistedRoomConfig {
+                workspace_id: workspace_id.clone(),
+                room_id: room_id.clone(),
+                config: config.clone(),
+            })
+            .collect();
+        rooms.sort_by(|a, b| (&a.workspace_id, &a.room_id).cmp(&(&b.workspace_id, &b.room_id)));
+        let store = PersistedConfigStore {
+            global: self.global.clone(),
+            workspaces: self.workspaces.clone(),
+            rooms,
+        };
+        fs::write(path, serde_json::to_string_pretty(&store)?)?;
+        Ok(())
+    }
+
     fn apply(&mut self, payload: ConfigUpdatePayload) {
         let (mode, scope) = (payload.mode.unwrap_or(ConfigMode::Merge), payload.scope);
         let new_config = RoomConfig {
@@ -2158,6 +7775,20 @@ impl ConfigStore {
                 .action_denylist
                 .map(|items| items.into_iter().collect()),
             dry_run_default: payload.dry_run_default,
+            provider_binding: payload.provider_binding,
+            system_prompt_extra: payload.system_prompt_extra,
+            plan_preview: payload.plan_preview,
+            plan_deadline_secs: payload.plan_deadline_secs,
+            approval_ttl_secs: payload.approval_ttl_secs,
+            approvers: payload
+                .approvers
+                .map(|items| items.into_iter().collect()),
+            high_risk_approvals: payload.high_risk_approvals,
+            rate_limits: payload.rate_limits,
+            quotas: payload.quotas,
+            max_reply_chars: payload.max_reply_chars.filter(|limit| *limit > 0),
+            reply_format: payload.reply_format,
+            locale: payload.locale.as_deref().and_then(Locale::parse),
         };
 
         match scope {
@@ -2215,10 +7846,51 @@ impl ConfigStore {
         if new_config.dry_run_default.is_some() {
             base.dry_run_default = new_config.dry_run_default;
         }
+        if new_config.provider_binding.is_some() {
+            base.provider_binding = new_config.provider_binding;
+        }
+        if new_config.system_prompt_extra.is_some() {
+            base.system_prompt_extra = new_config.system_prompt_extra;
+        }
+        if new_config.plan_preview.is_some() {
+            base.plan_preview = new_config.plan_preview;
+        }
+        if new_config.plan_deadline_secs.is_some() {
+            base.plan_deadline_secs = new_config.plan_deadline_secs;
+        }
+        if new_config.approval_ttl_secs.is_some() {
+            base.approval_ttl_secs = new_config.approval_ttl_secs;
+        }
+        // Replaced rather than extended, so a merge never widens who may approve.
+        if new_config.approvers.is_some() {
+            base.approvers = new_config.approvers;
+        }
+        if new_config.high_risk_approvals.is_some() {
+            base.high_risk_approvals = new_config.high_risk_approvals;
+        }
+        if new_config.rate_limits.is_some() {
+            base.rate_limits = new_config.rate_limits;
+        }
+        if new_config.quotas.is_some() {
+            base.quotas = new_config.quotas;
+        }
+        if new_config.max_reply_chars.is_some() {
+            base.max_reply_chars = new_config.max_reply_chars;
+        }
+        if new_config.reply_format.is_some() {
+            base.reply_format = new_config.reply_format;
+        }
+        if new_config.locale.is_some() {
+            base.locale = new_config.locale;
+        }
     }
 
     fn effective_for(&self, workspace_id: &str, room_id: &str) -> RoomConfig {
-        let mut config = self.global.clone();
+        let mut config = RoomConfig {
+            system_prompt_extra: self.file_prompt_extra.clone(),
+            ..RoomConfig::default()
+        };
+        config.apply_override(&self.global);
         if let Some(ws) = self.workspaces.get(workspace_id) {
             config.apply_override(ws);
         }
@@ -2257,4 +7929,15 @@ impl RoomScope {
         self.allowed
             .contains(&(workspace_id.to_string(), room_id.to_string()))
     }
+
+    /// A room's settings need the room in scope, a workspace's one of its rooms.
+    fn allows_config(&self, scope: &ConfigScope) -> bool {
+        let workspace_id = scope.workspace_id.as_deref().unwrap_or_default();
+        match &scope.room_id {
+            Some(room_id) => self.allows(workspace_id, room_id),
+            None => {
+                !self.enforced || self.allowed.iter().any(|(workspace, _)| workspace == workspace_id)
+            }
+        }
+    }
 }
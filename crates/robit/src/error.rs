@@ -0,0 +1,65 @@
+use std::fmt;
+
+/// Why a message could not be handled. The display text is what the room sees; `code` is a
+/// stable name clients can match on, sent as `code` in reply metadata and `Error` events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RobitError {
+    /// Refused by a `[policy]` rule, the sender's role, the room's action list or a hook.
+    PolicyDenied(String),
+    /// Blocked by a preflight finding.
+    PreflightBlocked(String),
+    /// Unknown action, or params the action rejects.
+    ValidationFailed(String),
+    /// The action ran and failed.
+    ActionFailed(String),
+    /// The AI backend could not be reached or answered with an error.
+    AiUnavailable(String),
+    /// Anything else, e.g. a malformed command.
+    InvalidRequest(String),
+}
+
+impl RobitError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PolicyDenied(_) => "policy_denied",
+            Self::PreflightBlocked(_) => "preflight_blocked",
+            Self::ValidationFailed(_) => "validation_failed",
+            Self::ActionFailed(_) => "action_failed",
+            Self::AiUnavailable(_) => "ai_unavailable",
+            Self::InvalidRequest(_) => "invalid_request",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::PolicyDenied(message)
+            | Self::PreflightBlocked(message)
+            | Self::ValidationFailed(message)
+            | Self::ActionFailed(message)
+            | Self::AiUnavailable(message)
+            | Self::InvalidRequest(message) => message,
+        }
+    }
+
+    /// The error for a `code` and message, e.g. from an `Error` event; unknown codes give
+    /// `InvalidRequest`.
+    pub fn from_code(code: &str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        match code {
+            "policy_denied" => Self::PolicyDenied(message),
+            "preflight_blocked" => Self::PreflightBlocked(message),
+            "validation_failed" => Self::ValidationFailed(message),
+            "action_failed" => Self::ActionFailed(message),
+            "ai_unavailable" => Self::AiUnavailable(message),
+            _ => Self::InvalidRequest(message),
+        }
+    }
+}
+
+impl fmt::Display for RobitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for RobitError {}
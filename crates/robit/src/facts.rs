@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{open_text, seal_text, Record, SealMark, StorageKey};
+
+/// Facts kept per workspace; the oldest go first, so the prompt stays small.
+const FACT_LIMIT: usize = 50;
+/// Longer sentences are more likely a request than a fact about the user.
+const FACT_MAX_CHARS: usize = 200;
+
+/// Something durable about the user, e.g. "I prefer English replies".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserFact {
+    pub id: u64,
+    /// In the user's words.
+    pub text: String,
+    /// The sender who said it; `auto` marks facts picked out of ordinary messages.
+    pub source: String,
+    /// Who shared it, `auto` facts included. Empty for `auto` facts saved before senders were
+    /// kept; those are listed but left out of prompts.
+    #[serde(default)]
+    pub sender: String,
+    /// Unix seconds.
+    pub created_at: u64,
+}
+
+/// Facts about the user, per workspace and shared by all of its rooms, unlike the per-room
+/// conversation. Each keeps its sender, so a prompt only lists the facts its sender may rely
+/// on (see `Engine::prompt_facts`).
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct FactStore {
+    next_id: u64,
+    workspaces: BTreeMap<String, Vec<UserFact>>,
+    #[serde(skip)]
+    persist_path: Option<PathBuf>,
+    #[serde(skip)]
+    key: Option<StorageKey>,
+}
+
+impl FactStore {
+    pub(crate) fn set_storage_key(&mut self, key: StorageKey) {
+        self.key = Some(key);
+    }
+
+    pub(crate) fn enable_persistence(&mut self, path: PathBuf) -> Result<()> {
+        if path.exists() {
+            let mark = SealMark::for_store(&path);
+            let content = fs::read_to_string(&path)?;
+            let record = Record::whole("facts");
+            let content = open_text(self.key.as_ref(), record, &content, mark.is_set())
+                .with_context(|| mark.refused(&path))?;
+            let mut stored: FactStore = serde_json::from_str(&content)?;
+            for fact in stored.workspaces.values_mut().flatten() {
+                if fact.sender.is_empty() && fact.source != "auto" {
+                    fact.sender = fact.source.clone();
+                }
+            }
+            self.next_id = stored.next_id;
+            self.workspaces = stored.workspaces;
+        }
+        self.persist_path = Some(path);
+        Ok(())
+    }
+
+    /// Returns the new fact's id, or `None` if `sender` already shared it in the workspace.
+    pub(crate) fn add(
+        &mut self,
+        workspace_id: &str,
+        text: &str,
+        source: &str,
+        sender: &str,
+        now: u64,
+    ) -> Result<Option<u64>> {
+        let text = text.trim().trim_end_matches(['.', '。']).trim();
+        let facts = self.workspaces.entry(workspace_id.to_string()).or_default();
+        let known = facts
+            .iter()
+            .any(|fact| fact.sender == sender && fact.text.eq_ignore_ascii_case(text));
+        if text.is_empty() || known {
+            return Ok(None);
+        }
+        self.next_id += 1;
+        facts.push(UserFact {
+            id: self.next_id,
+            text: text.to_string(),
+            source: source.to_string(),
+            sender: sender.to_string(),
+            created_at: now,
+        });
+        if facts.len() > FACT_LIMIT {
+            facts.remove(0);
+        }
+        self.persist()?;
+        Ok(Some(self.next_id))
+    }
+
+    /// Oldest first.
+    pub(crate) fn list(&self, workspace_id: &str) -> &[UserFact] {
+        self.workspaces.get(workspace_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Removes one fact by id, or all of the workspace's with `None`; returns how many went.
+    pub(crate) fn forget(&mut self, workspace_id: &str, id: Option<u64>) -> Result<usize> {
+        let Some(facts) = self.workspaces.get_mut(workspace_id) else {
+            return Ok(0);
+        };
+        let before = facts.len();
+        facts.retain(|fact| id.is_some_and(|id| fact.id != id));
+        let removed = before - facts.len();
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    fn persist(&self) -> Result<()> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, seal_text(self.key.as_ref(), Record::whole("facts"), data)?)?;
+        SealMark::for_store(path).update(self.key.is_some())
+    }
+}
+
+/// Sentences of `text` that state a lasting preference or fact about the user ("I prefer
+/// ...", "my projects live in ..."). Questions are skipped, and so are instructions such as
+/// "always run ...": those only become facts through `remember`.
+pub(crate) fn extract_facts(text: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(
+            r"(?ix)^(
+                (i|we)\s+(prefer|like|use|usually|always|never|work|live)\b
+                | (i\s+am|i'm)\s+(a|an|based|located|working|using)\b
+                | my\s+[\w\s-]{1,30}?\s+(is|are|live|lives)\b
+                | call\s+me\s+\w+
+                | 我(更)?喜欢 | 我习惯 | 我的.{1,20}(是|在) | 我住在
+            )",
+        )
+        .expect("fact pattern")
+    });
+    // A bare `.` would split paths such as `notes.md`.
+    text.replace(". ", "\n")
+        .split(['!', '\n', '。', '！'])
+        .map(|sentence| sentence.trim().trim_end_matches('.'))
+        .filter(|sentence| !sentence.is_empty() && sentence.chars().count() <= FACT_MAX_CHARS)
+        .filter(|sentence| !sentence.ends_with(['?', '？']) && !is_trivial(sentence))
+        .filter(|sentence| pattern.is_match(sentence))
+        .map(str::to_string)
+        .collect()
+}
+
+/// "never mind", "I like it" and the like.
+fn is_trivial(sentence: &str) -> bool {
+    let words: Vec<String> = sentence
+        .split_whitespace()
+        .map(|word| word.to_ascii_lowercase())
+        .collect();
+    words.len() <= 3
+        && words
+            .last()
+            .is_some_and(|last| ["it", "this", "that", "mind"].contains(&last.as_str()))
+}
@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ai::AiChatRole;
+use crate::engine::{unix_now, StoredMessage};
+use crate::history::{local_time, ActionRecord};
+use crate::i18n::{fill, Locale};
+use crate::preflight::ChangePreview;
+use crate::types::OutboundMessage;
+
+/// How reply text is rendered for a transport. The engine writes plain text and keeps the
+/// structured parts in `metadata`; other formats are derived from those.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyFormat {
+    /// Terminals, email.
+    #[default]
+    Plain,
+    /// Chat clients such as Matrix or Slack: tables for approvals, code fences for data.
+    Markdown,
+    /// Machine consumers: the text becomes a JSON object of the reply's kind, text and data.
+    Json,
+}
+
+/// What an approval request shows, kept in the reply's `data.prompt`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApprovalPrompt {
+    pub approval_id: String,
+    pub action: String,
+    pub description: String,
+    pub risk: String,
+    pub dry_run: bool,
+    pub preflight: String,
+    pub params: String,
+    #[serde(default)]
+    pub critic: Option<CriticNote>,
+    #[serde(default)]
+    pub plan: Option<PlanNote>,
+    #[serde(default)]
+    pub change: Option<ChangePreview>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CriticNote {
+    pub verdict: String,
+    pub confidence: Option<f64>,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanNote {
+    pub plan_id: String,
+    pub step: usize,
+    pub total: usize,
+    pub approve_all: bool,
+}
+
+impl ApprovalPrompt {
+    pub fn plain(&self, locale: Locale) -> String {
+        let t = locale.catalog();
+        let mut lines = vec![
+            t.field(t.approval_needed, &self.action),
+            t.field(t.description, &self.description),
+            format!(
+                "{}  |  {}",
+                t.field(t.risk, &self.risk),
+                t.field("dry-run", self.dry_run)
+            ),
+            t.field(t.preflight, &self.preflight),
+            t.field(t.params, &self.params),
+        ];
+        if let Some(change) = &self.change {
+            lines.push(t.field(t.change, &change.summary));
+            if !change.diff.is_empty() {
+                lines.push(change.diff.clone());
+            }
+        }
+        if let Some(critic) = &self.critic {
+            lines.push(format!(
+                "{}  |  {}",
+                t.field(t.review, &critic.verdict),
+                t.field(t.confidence, critic.confidence_text())
+            ));
+            lines.push(t.field(t.reason, &critic.reason));
+        }
+        if let Some(plan) = &self.plan {
+            lines.push(format!(
+                "{}  |  {}",
+                t.field(t.plan, &plan.plan_id),
+                t.field(t.step, format!("{}/{}", plan.step, plan.total))
+            ));
+            if plan.approve_all {
+                lines.push(fill(t.approve_all_hint, &[("id", &self.approval_id)]));
+            }
+        }
+        lines.push(fill(t.approve_hint, &[("id", &self.approval_id)]));
+        lines.join("\n")
+    }
+
+    pub fn markdown(&self, locale: Locale) -> String {
+        let t = locale.catalog();
+        let mut text = format!(
+            "**{}**\n{desc}\n\n| | |\n|---|---|\n",
+            t.field(t.approval_needed, format!("`{}`", self.action)),
+            desc = self.description,
+        );
+        let mut row = |label: &str, value: &str| {
+            text.push_str(&format!("| {label} | {} |\n", table_cell(value)));
+        };
+        row(t.risk, &self.risk);
+        row("dry-run", &self.dry_run.to_string());
+        row(t.preflight, &self.preflight);
+        row(t.params, &format!("`{}`", self.params));
+        if let Some(change) = &self.change {
+            row(t.change, &change.summary);
+        }
+        if let Some(plan) = &self.plan {
+            row(t.plan, &format!("{} ({}/{})", plan.plan_id, plan.step, plan.total));
+        }
+        if let Some(critic) = &self.critic {
+            row(
+                t.review,
+                &format!("{} ({}): {}", critic.verdict, critic.confidence_text(), critic.reason),
+            );
+        }
+        if let Some(change) = self.change.as_ref().filter(|change| !change.diff.is_empty()) {
+            text.push_str(&format!("\n```diff\n{}\n```\n", change.diff));
+        }
+        text.push('\n');
+        text.push_str(&fill(t.approve_hint_md, &[("id", &self.approval_id)]));
+        if self.plan.as_ref().is_some_and(|plan| plan.approve_all) {
+            text.push_str(&fill(t.approve_all_hint_md, &[("id", &self.approval_id)]));
+        }
+        text
+    }
+}
+
+impl CriticNote {
+    fn confidence_text(&self) -> String {
+        self.confidence
+            .map(|value| format!("{value:.2}"))
+            .unwrap_or_else(|| "n/a".to_string())
+    }
+}
+
+/// Rewrites `reply.text` for `format`. Plain text is left as the engine wrote it.
+pub fn render(reply: &mut OutboundMessage, format: ReplyFormat, locale: Locale) {
+    match format {
+        ReplyFormat::Plain => {}
+        ReplyFormat::Markdown => reply.text = markdown(reply, locale),
+        ReplyFormat::Json => {
+            let mut value = match &reply.metadata {
+                Value::Object(map) => Value::Object(map.clone()),
+                _ => Value::Object(Default::default()),
+            };
+            value["text"] = Value::String(std::mem::take(&mut reply.text));
+            reply.text = value.to_string();
+        }
+    }
+}
+
+fn markdown(reply: &OutboundMessage, locale: Locale) -> String {
+    let kind = reply.metadata.get("kind").and_then(Value::as_str);
+    match kind {
+        Some("approval_request") => reply
+            .metadata
+            .pointer("/data/prompt")
+            .and_then(|prompt| ApprovalPrompt::deserialize(prompt).ok())
+            .map(|prompt| prompt.markdown(locale))
+            .unwrap_or_else(|| reply.text.clone()),
+        Some("action_result") => {
+            let data = reply.metadata.get("data").filter(|data| has_content(data));
+            match data.and_then(|data| serde_json::to_string_pretty(data).ok()) {
+                Some(json) => format!("{}\n```json\n{json}\n```", reply.text),
+                None => reply.text.clone(),
+            }
+        }
+        Some("error") => format!("**{}**", reply.text.trim()),
+        // Lists such as `help` and `approvals` are aligned for a monospace font.
+        Some("info") if reply.text.trim_end().contains('\n') => {
+            format!("```text\n{}\n```", reply.text.trim_end())
+        }
+        _ => reply.text.clone(),
+    }
+}
+
+fn has_content(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => true,
+    }
+}
+
+fn table_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+/// A room's conversation and the actions run in it as a Markdown document, for `export`.
+/// Both lists are oldest first.
+pub(crate) fn transcript_markdown(
+    workspace_id: &str,
+    room_id: &str,
+    profile: Option<&str>,
+    messages: &[StoredMessage],
+    actions: &[ActionRecord],
+) -> String {
+    let mut text = format!("# Robit transcript: {room_id}\n\n");
+    text.push_str(&format!("- Workspace: `{workspace_id}`\n"));
+    if let Some(profile) = profile {
+        text.push_str(&format!("- Profile: `{profile}`\n"));
+    }
+    text.push_str(&format!("- Exported: {}\n", local_time(unix_now())));
+    text.push_str("\n## Conversation\n\n");
+    if messages.is_empty() {
+        text.push_str("_No messages._\n");
+    }
+    for stored in messages {
+        let who = match stored.message.role {
+            AiChatRole::User => "User",
+            AiChatRole::Assistant => "Robit",
+        };
+        let mut heading = format!("**{who}**");
+        if let Some(at) = stored.at {
+            heading.push_str(&format!(" · {}", local_time(at)));
+        }
+        match &stored.action {
+            Some(action) => text.push_str(&format!(
+                "{heading} · `{action}` result\n\n```json\n{}\n```\n\n",
+                stored.message.content.trim()
+            )),
+            None => text.push_str(&format!("{heading}\n\n{}\n\n", stored.message.content.trim())),
+        }
+    }
+    text.push_str("## Actions\n\n");
+    if actions.is_empty() {
+        text.push_str("_No actions ran._\n");
+        return text;
+    }
+    text.push_str("| Started | Action | Params | Result | Outcome |\n");
+    text.push_str("| --- | --- | --- | --- | --- |\n");
+    for record in actions {
+        let status = if record.ok { "ok" } else { "failed" };
+        text.push_str(&format!(
+            "| {} | `{}` | {} | {status} ({}s) | {} |\n",
+            local_time(record.started_at),
+            record.action,
+            table_cell(&record.params),
+            record.finished_at.saturating_sub(record.started_at),
+            table_cell(&record.summary),
+        ));
+    }
+    text
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::encryption::{is_sealed, open_text, seal_text, Record, SealMark, StorageKey};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Records kept in memory; the file keeps everything.
+const HISTORY_CAPACITY: usize = 1000;
+/// Lines are appended in order and carry no key of their own, so all share one record.
+const RECORD: Record<'static> = Record::whole("history");
+
+/// One executed action, as listed by `history`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionRecord {
+    pub workspace_id: String,
+    pub room_id: String,
+    pub sender: String,
+    pub action: String,
+    /// Compact `key=value` form, truncated.
+    pub params: String,
+    pub ok: bool,
+    /// The outcome summary, or the error.
+    pub summary: String,
+    #[serde(default)]
+    pub plan_id: Option<String>,
+    /// Unix seconds.
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+/// Executed actions, appended as JSON lines to a file when persistence is enabled. With a
+/// storage key each line is sealed on its own.
+#[derive(Default)]
+pub(crate) struct ActionHistory {
+    records: VecDeque<ActionRecord>,
+    persist_path: Option<PathBuf>,
+    key: Option<StorageKey>,
+}
+
+impl ActionHistory {
+    pub(crate) fn set_storage_key(&mut self, key: StorageKey) {
+        self.key = Some(key);
+    }
+
+    /// Loads the latest records from `path` and appends new ones to it. A file with sealed
+    /// lines and no key to open them is left alone, and nothing is appended.
+    pub(crate) fn enable_persistence(&mut self, path: PathBuf) -> Result<()> {
+        let mark = SealMark::for_store(&path);
+        if !path.exists() {
+            mark.update(self.key.is_some())?;
+            self.persist_path = Some(path);
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)?;
+        let lines = || content.lines().filter(|line| !line.trim().is_empty());
+        // Sealed lines mean the file was sealed before, with or without the mark.
+        let sealed_before = mark.is_set() || lines().any(is_sealed);
+        let mut plain_lines = false;
+        for line in lines() {
+            plain_lines |= !is_sealed(line);
+            let line = open_text(self.key.as_ref(), RECORD, line, sealed_before)
+                .with_context(|| mark.refused(&path))?;
+            match serde_json::from_str(&line) {
+                Ok(record) => self.remember(record),
+                Err(err) => warn!("history: skipped bad line: {err}"),
+            }
+        }
+        if plain_lines && self.key.is_some() {
+            self.seal_file(&path, &content)?;
+        }
+        mark.update(self.key.is_some())?;
+        self.persist_path = Some(path);
+        Ok(())
+    }
+
+    /// Rewrites lines written before the storage key was set, sealed.
+    fn seal_file(&self, path: &PathBuf, content: &str) -> Result<()> {
+        let mut sealed = String::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            if is_sealed(line) {
+                sealed.push_str(line);
+            } else {
+                sealed.push_str(&seal_text(self.key.as_ref(), RECORD, line.to_string())?);
+            }
+            sealed.push('\n');
+        }
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, sealed)?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, record: ActionRecord) {
+        let key = self.key.as_ref();
+        let saved = self.persist_path.as_ref().map(|path| append_line(path, &record, key));
+        if let Some(Err(err)) = saved {
+            warn!("history save failed: {err}");
+        }
+        self.remember(record);
+    }
+
+    /// Newest first; both filters are optional.
+    pub(crate) fn recent(
+        &self,
+        workspace_id: Option<&str>,
+        room_id: Option<&str>,
+        limit: usize,
+    ) -> Vec<ActionRecord> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|record| workspace_id.is_none_or(|ws| record.workspace_id == ws))
+            .filter(|record| room_id.is_none_or(|room| record.room_id == room))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn remember(&mut self, record: ActionRecord) {
+        if self.records.len() == HISTORY_CAPACITY {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+}
+
+fn append_line(path: &PathBuf, record: &ActionRecord, key: Option<&StorageKey>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", seal_text(key, RECORD, serde_json::to_string(record)?)?)?;
+    Ok(())
+}
+
+impl ActionRecord {
+    pub fn line(&self) -> String {
+        let status = if self.ok { "ok" } else { "failed" };
+        format!(
+            "{at}  {action} [{status}, {secs}s]  {params}\n    {summary}",
+            at = local_time(self.started_at),
+            action = self.action,
+            secs = self.finished_at.saturating_sub(self.started_at),
+            params = self.params,
+            summary = self.summary,
+        )
+    }
+}
+
+pub(crate) fn local_time(unix: u64) -> String {
+    Local
+        .timestamp_opt(unix as i64, 0)
+        .single()
+        .map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| unix.to_string())
+}
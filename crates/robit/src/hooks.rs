@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::types::{ActionOutcome, InboundMessage, RiskLevel};
+
+pub enum HookFlow {
+    Continue,
+    Veto(String),
+}
+
+/// An action about to run, or one that just ran.
+pub struct ActionEvent<'a> {
+    pub action: &'a str,
+    pub params: &'a Value,
+    pub risk: RiskLevel,
+    pub msg: &'a InboundMessage,
+    /// Set when the action runs as a plan step.
+    pub plan_id: Option<&'a str>,
+    pub step_id: Option<&'a str>,
+}
+
+pub struct ApprovalEvent<'a> {
+    pub approval_id: &'a str,
+    pub action: &'a str,
+    pub params: &'a Value,
+    pub risk: RiskLevel,
+    pub msg: &'a InboundMessage,
+    pub plan_id: Option<&'a str>,
+}
+
+pub struct PlanEvent<'a> {
+    pub plan_id: &'a str,
+    pub msg: &'a InboundMessage,
+    pub steps_run: usize,
+    pub total_steps: usize,
+    pub stopped_early: bool,
+}
+
+/// Observes the engine for metrics, notifications or custom vetoes. Every method defaults
+/// to doing nothing.
+pub trait EngineHook: Send {
+    fn name(&self) -> &'static str;
+
+    /// A message that got past the middleware.
+    fn on_message(&mut self, _msg: &InboundMessage) {}
+
+    /// Called after approval and preflight, right before the action executes. A veto stops
+    /// the action, and the plan it belongs to.
+    fn on_action_start(&mut self, _event: &ActionEvent) -> HookFlow {
+        HookFlow::Continue
+    }
+
+    fn on_action_finish(
+        &mut self,
+        _event: &ActionEvent,
+        _result: &Result<ActionOutcome>,
+        _elapsed: Duration,
+    ) {
+    }
+
+    fn on_approval_created(&mut self, _event: &ApprovalEvent) {}
+
+    /// A plan ran its last step or stopped on a failure.
+    fn on_plan_finished(&mut self, _event: &PlanEvent) {}
+}
+
+#[derive(Default)]
+pub(crate) struct HookChain {
+    hooks: Vec<Box<dyn EngineHook>>,
+}
+
+impl HookChain {
+    pub(crate) fn push(&mut self, hook: Box<dyn EngineHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub(crate) fn message(&mut self, msg: &InboundMessage) {
+        for hook in &mut self.hooks {
+            hook.on_message(msg);
+        }
+    }
+
+    /// The first veto wins and later hooks are not asked.
+    pub(crate) fn action_start(&mut self, event: &ActionEvent) -> Option<String> {
+        self.hooks
+            .iter_mut()
+            .find_map(|hook| match hook.on_action_start(event) {
+                HookFlow::Continue => None,
+                HookFlow::Veto(reason) => Some(format!("vetoed by {}: {reason}", hook.name())),
+            })
+    }
+
+    pub(crate) fn action_finish(
+        &mut self,
+        event: &ActionEvent,
+        result: &Result<ActionOutcome>,
+        elapsed: Duration,
+    ) {
+        for hook in &mut self.hooks {
+            hook.on_action_finish(event, result, elapsed);
+        }
+    }
+
+    pub(crate) fn approval_created(&mut self, event: &ApprovalEvent) {
+        for hook in &mut self.hooks {
+            hook.on_approval_created(event);
+        }
+    }
+
+    pub(crate) fn plan_finished(&mut self, event: &PlanEvent) {
+        for hook in &mut self.hooks {
+            hook.on_plan_finished(event);
+        }
+    }
+}
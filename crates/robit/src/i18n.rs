@@ -0,0 +1,170 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Language of the text the engine writes itself: approval prompts, plan summaries and
+/// fallback replies. Control command output stays in English.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    En,
+    #[default]
+    Zh,
+}
+
+impl Locale {
+    /// Accepts language tags such as `en`, `en-US` or `zh_CN`; only the language part counts.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let lang = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match lang.as_str() {
+            "en" => Some(Self::En),
+            "zh" => Some(Self::Zh),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn catalog(self) -> &'static Catalog {
+        match self {
+            Self::En => &EN,
+            Self::Zh => &ZH,
+        }
+    }
+}
+
+/// Messages for one locale. Templates name their arguments in braces; see `fill`.
+pub(crate) struct Catalog {
+    /// Separates a label from its value.
+    pub colon: &'static str,
+    pub approval_needed: &'static str,
+    pub description: &'static str,
+    pub risk: &'static str,
+    pub preflight: &'static str,
+    pub params: &'static str,
+    pub change: &'static str,
+    pub review: &'static str,
+    pub confidence: &'static str,
+    pub reason: &'static str,
+    pub plan: &'static str,
+    pub step: &'static str,
+    pub note: &'static str,
+    /// `{id}`
+    pub approve_hint: &'static str,
+    pub approve_all_hint: &'static str,
+    pub approve_hint_md: &'static str,
+    pub approve_all_hint_md: &'static str,
+    /// `{plan_id}`, `{total}`, `{dry_run}`
+    pub plan_approval: &'static str,
+    /// `{id}`
+    pub plan_approve_hint: &'static str,
+    pub chat_idle: &'static str,
+    pub chat_unsure: &'static str,
+    /// `{message}`
+    pub unknown_request: &'static str,
+    /// `{done}`, `{total}`
+    pub plan_done: &'static str,
+    /// `{failure}`
+    pub plan_failed: &'static str,
+    pub status_summary: &'static str,
+    pub raw_output: &'static str,
+    /// `{field}`, `{step}`, `{total}`
+    pub ask_field: &'static str,
+    /// `{field}`, `{expected}`
+    pub bad_field: &'static str,
+    pub expect_integer: &'static str,
+    pub expect_number: &'static str,
+    pub expect_boolean: &'static str,
+    pub expect_object: &'static str,
+    /// `{values}`
+    pub expect_one_of: &'static str,
+    pub input_cancelled: &'static str,
+}
+
+static EN: Catalog = Catalog {
+    colon: ": ",
+    approval_needed: "Approval needed",
+    description: "Description",
+    risk: "Risk",
+    preflight: "Preflight",
+    params: "Params",
+    change: "Change",
+    review: "Review",
+    confidence: "Confidence",
+    reason: "Reason",
+    plan: "Plan",
+    step: "Step",
+    note: "Note",
+    approve_hint: "Reply approve {id} to run it, or deny {id} to cancel",
+    approve_all_hint: "Reply approve-all {id} to approve the remaining steps at once",
+    approve_hint_md: "Reply `approve {id}` to run it, or `deny {id}` to cancel",
+    approve_all_hint_md: "; `approve-all {id}` approves the remaining steps at once",
+    plan_approval: "Plan needs approval: {plan_id} ({total} steps)  |  dry-run: {dry_run}",
+    plan_approve_hint: "Reply approve {id} to run every step, or deny {id} to cancel",
+    chat_idle: "I'm here. Tell me more about what you need.",
+    chat_unsure: "I'm not sure about this request yet. Could you be more specific?",
+    unknown_request: "I don't know how to handle this request yet ({message}). Try actions to list what I can do, or name one with action:xxx.",
+    plan_done: "Plan finished ({done}/{total} steps):",
+    plan_failed: "Failed: {failure}",
+    status_summary: "System status:",
+    raw_output: "Raw output:",
+    ask_field: "Please provide {field} ({step}/{total})",
+    bad_field: "{field} should be {expected}.",
+    expect_integer: "an integer",
+    expect_number: "a number",
+    expect_boolean: "yes or no",
+    expect_object: "a JSON object",
+    expect_one_of: "one of {values}",
+    input_cancelled: "Cancelled.",
+};
+
+static ZH: Catalog = Catalog {
+    colon: "：",
+    approval_needed: "需要审批",
+    description: "描述",
+    risk: "风险",
+    preflight: "预检",
+    params: "参数",
+    change: "变更",
+    review: "审查",
+    confidence: "置信度",
+    reason: "理由",
+    plan: "计划",
+    step: "步骤",
+    note: "说明",
+    approve_hint: "回复 approve {id} 执行，或 deny {id} 取消",
+    approve_all_hint: "回复 approve-all {id} 一次性同意后续步骤",
+    approve_hint_md: "回复 `approve {id}` 执行，或 `deny {id}` 取消",
+    approve_all_hint_md: "；`approve-all {id}` 一次性同意后续步骤",
+    plan_approval: "需要审批计划：{plan_id}（{total} 步）  |  dry-run：{dry_run}",
+    plan_approve_hint: "回复 approve {id} 执行全部步骤，或 deny {id} 取消",
+    chat_idle: "我在这儿，可以继续说说你的需求。",
+    chat_unsure: "我暂时没把握这个请求，可以再具体一点吗？",
+    unknown_request: "我还没学会处理这个请求（{message}）。可以试试输入 actions 查看动作列表，或用 action:xxx 明确指令。",
+    plan_done: "完成计划（{done}/{total} 步）：",
+    plan_failed: "失败：{failure}",
+    status_summary: "系统状态摘要：",
+    raw_output: "原始输出：",
+    ask_field: "请提供 {field}（{step}/{total}）",
+    bad_field: "{field} 需要是 {expected}。",
+    expect_integer: "整数",
+    expect_number: "数字",
+    expect_boolean: "是或否",
+    expect_object: "JSON 对象",
+    expect_one_of: "以下之一：{values}",
+    input_cancelled: "已取消。",
+};
+
+impl Catalog {
+    /// `label` followed by `value`, e.g. `风险：high`.
+    pub(crate) fn field(&self, label: &str, value: impl Display) -> String {
+        format!("{label}{}{value}", self.colon)
+    }
+}
+
+/// Replaces each `{name}` in `template` with its value.
+pub(crate) fn fill(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in args {
+        text = text.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+    text
+}
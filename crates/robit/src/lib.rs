@@ -1,33 +1,90 @@
 pub mod adapter;
 pub mod actions;
 pub mod ai;
+pub mod ai_cache;
+pub mod ai_chain;
+pub mod builder;
 pub mod config;
+pub mod conformance;
+#[cfg(feature = "sqlite")]
+pub(crate) mod conversation_db;
+pub(crate) mod diff;
+pub mod doctor;
+pub mod encryption;
 pub mod engine;
+pub mod error;
+pub(crate) mod facts;
+pub mod format;
+pub mod history;
+pub mod hooks;
+pub mod i18n;
+#[cfg(feature = "logging")]
+pub mod logging;
+pub mod memory;
+pub mod metrics;
+pub mod paths;
+pub mod middleware;
+pub mod plan_template;
 pub mod protocol;
+pub(crate) mod quota;
+pub(crate) mod redact;
+pub mod replay;
+#[cfg(feature = "async")]
+pub mod runtime;
 pub mod planner;
+pub mod schedule;
+pub mod testing;
 pub mod policy;
 pub mod preflight;
 pub mod types;
 pub mod utils;
+pub(crate) mod vars;
 
 pub use actions::{ActionHandler, ActionRegistry};
 pub use actions::default_registry;
-pub use ai::{AiChatMessage, AiChatRole, AiDecision, AiPlanner};
+pub use ai::{
+    AiChatMessage, AiChatRole, AiDecision, AiPlanner, AiRequestOptions, AiTask, AiUsage,
+};
+pub use ai_cache::AiPlannerCache;
+pub use ai_chain::AiPlannerChain;
 #[cfg(feature = "ai-http")]
-pub use ai::{AiClient, AiConfig, AiProvider};
+pub use ai::{AiClient, AiConfig, AiHttpError, AiProvider};
 #[cfg(feature = "ai-omnix-mlx")]
 pub use ai::{MlxQwenClient, MlxQwenConfig};
-pub use engine::Engine;
-pub use preflight::{PreflightConfig, PreflightEngine, PreflightReport};
+pub use builder::EngineBuilder;
+pub use engine::{AiModelLoader, Engine};
+pub use encryption::StorageKey;
+pub use error::RobitError;
+pub use facts::UserFact;
+pub use format::{ApprovalPrompt, ReplyFormat};
+pub use history::ActionRecord;
+pub use hooks::{ActionEvent, ApprovalEvent, EngineHook, HookFlow, PlanEvent};
+pub use i18n::Locale;
+pub use memory::{AiEmbedder, MemoryIndex, MemoryKind};
+pub use metrics::{ActionStats, EngineMetrics, Latency};
+pub use paths::RobitPaths;
+pub use middleware::{Middleware, MiddlewareChain, MiddlewareFlow};
+pub use plan_template::{PlanTemplate, PlanTemplateStore};
+pub use replay::{RecordedTurn, ReplayReport};
+#[cfg(feature = "async")]
+pub use runtime::{EngineFuture, EngineHandle, EnginePool};
+pub use schedule::{Recurrence, Schedule};
+pub use preflight::{
+    ChangePreview, CheckId, PreflightCheck, PreflightConfig, PreflightEngine, PreflightFinding,
+    PreflightReport, Severity,
+};
 pub use protocol::{
-    ActionListRequestPayload, ActionListResultPayload, ApprovalDecisionPayload, ConfigMode,
-    ConfigScope, ConfigUpdatePayload, MessagePayload, PingPayload, PongPayload, ProtocolBody,
-    ProtocolEvent, ProviderBinding, ResponsePayload, RiskPolicy, RoomScopePayload, RoomScopeItem,
-    WorkspaceScope,
+    ActionListRequestPayload, ActionListResultPayload, ApprovalDecisionPayload, Attachment,
+    CapabilitiesPayload, ConfigMode, ConfigQueryPayload, ConfigResultPayload,
+    ConfigReloadedPayload, ConfigScope, ConfigUpdatePayload, ConversationDocument, ErrorPayload, ExportRequestPayload,
+    HistoryRequestPayload, HelloPayload, HistoryResultPayload, ImportRequestPayload,
+    ImportResultPayload, MemoryFact, MessagePayload, PingPayload, PongPayload, ProtocolBody,
+    ProtocolEvent, ProviderBinding, Quotas, RateLimits, ResponsePayload, RiskPolicy, RoomScopePayload, RoomScopeItem,
+    RoomUsage, SearchHit, SearchRequestPayload, SearchResultPayload, StatusRequestPayload, StatusResultPayload, UsageRequestPayload, UsageResultPayload, WorkspaceScope,
 };
-pub use planner::RulePlanner;
-pub use policy::{ActionContext, Policy};
+pub use planner::{CommandPlanner, Planner, PlannerChain, RuleConfig, RulePlanner, SlashPlanner};
+pub use policy::{ActionContext, Policy, Role};
 pub use types::{
-    ActionOutcome, ActionRequest, ActionSpec, InboundMessage, OutboundMessage, PlannerResponse,
-    PlanStep, RiskLevel,
+    ActionOutcome, ActionRequest, ActionSpec, ForEach, InboundMessage, OutboundMessage,
+    PlannerResponse, PlanStep, RiskLevel, UndoStep,
 };
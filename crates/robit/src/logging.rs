@@ -0,0 +1,62 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Used when `ROBIT_LOG` is unset: robit's own events down to `info`, other crates' warnings.
+const DEFAULT_FILTER: &str = "warn,robit=info";
+
+/// Installs the global `tracing` subscriber the `robit` binary logs through.
+///
+/// - `ROBIT_LOG` filters events with `RUST_LOG` syntax, e.g. `debug` or `robit::engine=trace`.
+/// - `ROBIT_LOG_FILE` also appends every event as one JSON object per line, with the message,
+///   action and AI call spans it happened in.
+///
+/// Embedders that install their own subscriber get the same events and spans without calling
+/// this.
+pub fn init() -> Result<()> {
+    let filter = match env::var("ROBIT_LOG") {
+        Ok(directives) if !directives.trim().is_empty() => {
+            EnvFilter::try_new(&directives).with_context(|| format!("ROBIT_LOG={directives}"))?
+        }
+        _ => EnvFilter::new(DEFAULT_FILTER),
+    };
+    let json_layer = match env::var_os("ROBIT_LOG_FILE").filter(|path| !path.is_empty()) {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("ROBIT_LOG_FILE {}", path.display()))?;
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(Mutex::new(file)),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_ansi(std::io::stderr().is_terminal())
+                .with_writer(std::io::stderr),
+        )
+        .with(json_layer)
+        .try_init()
+        .map_err(|err| anyhow!("logging already initialized: {err}"))
+}
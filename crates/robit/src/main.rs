@@ -1,22 +1,188 @@
-use anyhow::Result;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
 
 use robit::adapter::stdin::StdinAdapter;
-use robit::{default_registry, Engine, Policy, RulePlanner};
-use std::path::PathBuf;
+use robit::config::ai_backend_from_config;
+use robit::{default_registry, Engine, RobitPaths, StorageKey};
 
 fn main() -> Result<()> {
+    #[cfg(feature = "logging")]
+    robit::logging::init()?;
     let registry = default_registry();
+    let action_names = registry
+        .list_specs()
+        .into_iter()
+        .map(|spec| spec.name)
+        .collect();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let profile = match profile_arg(&args)? {
+        Some(profile) => Some(profile),
+        None => std::env::var("ROBIT_PROFILE").ok().filter(|name| !name.is_empty()),
+    };
+    if args.iter().any(|arg| arg == "check-config") {
+        return check_config(registry, profile);
+    }
+    if args.iter().any(|arg| arg == "new-storage-key") {
+        println!("{}", StorageKey::generate()?);
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "doctor") {
+        let ping = !args.iter().any(|arg| arg == "--no-ping");
+        return doctor(registry, profile, ping);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "conformance") {
+        let dir = args.get(index + 1).filter(|arg| !arg.starts_with("--")).map(PathBuf::from);
+        return conformance(profile, dir);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "replay") {
+        let path = args.get(index + 1).ok_or_else(|| anyhow!("replay needs a session file"))?;
+        return replay(registry, profile, PathBuf::from(path));
+    }
 
-    let planner = RulePlanner::new();
-    let policy = Policy::default_with_home();
-    let mut engine = Engine::new(registry, planner, policy)?;
-    if let Some(home) = std::env::var_os("HOME") {
-        let path = PathBuf::from(home).join(".robit/contexts/stdin.json");
-        engine.enable_conversation_persistence(path);
+    let mut builder = Engine::builder().registry(registry);
+    if let Some(profile) = &profile {
+        builder = builder.profile(profile.clone());
+    }
+    // Refuses to start rather than write plain files when the key cannot be read.
+    if let Some(key) = StorageKey::from_env()? {
+        builder = builder.storage_key(key);
+    }
+    let mut adapter = StdinAdapter::new().with_action_names(action_names);
+    if let Some(paths) = RobitPaths::from_env() {
+        // Each profile keeps its own state under profiles/<name> in the data directory.
+        let paths = match &profile {
+            Some(profile) => paths.for_profile(profile),
+            None => paths,
+        };
+        builder = builder
+            .conversation_path(paths.conversation_path("stdin"))
+            .plan_templates_dir(paths.plan_templates_dir())
+            .backup_dir(paths.data_file("backups"))
+            .approvals_path(paths.approvals_path())
+            .schedules_path(paths.data_file("schedules.json"))
+            .action_history_path(paths.data_file("actions.jsonl"))
+            .vars_path(paths.data_file("vars.json"))
+            .facts_path(paths.data_file("facts.json"))
+            .rules_path(paths.data_file("rules.toml"))
+            .aliases_path(paths.data_file("aliases.json"))
+            .config_state_path(paths.data_file("config-state.json"));
+        #[cfg(feature = "sqlite")]
+        {
+            builder = builder.conversation_db_path(paths.data_file("conversations.db"));
+        }
+        adapter = adapter.with_history_path(paths.data_file("history.txt"));
+    }
+    match ai_backend_from_config(profile.as_deref()) {
+        Ok(Some((backend, label))) => builder = builder.ai_backend(backend, Some(label)),
+        Ok(None) => {}
+        Err(err) => warn!("ai backend not loaded: {err:#}"),
     }
+    if let Some(path) = std::env::var_os("ROBIT_RECORD_SESSION").filter(|path| !path.is_empty()) {
+        builder = builder.session_log_path(PathBuf::from(path));
+    }
+    let mut engine = builder.build()?;
 
     println!("robit stdin ready. type 'help' for commands. ctrl-d to exit.");
 
-    let mut adapter = StdinAdapter::new();
     engine.run_with_adapter(&mut adapter)
 }
+
+/// `--profile <name>` or `--profile=<name>`.
+fn profile_arg(args: &[String]) -> Result<Option<String>> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Ok(Some(name.to_string()));
+        }
+        if arg == "--profile" {
+            return match args.next() {
+                Some(name) => Ok(Some(name.clone())),
+                None => Err(anyhow!("--profile needs a name")),
+            };
+        }
+    }
+    Ok(None)
+}
+
+/// `robit doctor [--no-ping]`: checks the config, allowed roots, AI backends, state directory
+/// and external programs, and fails if a check does.
+fn doctor(registry: robit::ActionRegistry, profile: Option<String>, ping: bool) -> Result<()> {
+    let mut builder = Engine::builder().registry(registry);
+    if let Some(profile) = &profile {
+        builder = builder.profile(profile.clone());
+    }
+    let engine = builder.build()?;
+    let paths = RobitPaths::from_env().map(|paths| match &profile {
+        Some(profile) => paths.for_profile(profile),
+        None => paths,
+    });
+    let report = robit::doctor::run(&engine, paths.as_ref(), ping);
+    println!("{report}");
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(anyhow!("doctor found problems"))
+    }
+}
+
+/// `robit replay <file>`: runs a recorded session against a fresh engine with the current
+/// config, actions as dry runs and no model, and fails if a turn decides differently.
+fn replay(registry: robit::ActionRegistry, profile: Option<String>, path: PathBuf) -> Result<()> {
+    let key = StorageKey::from_env()?;
+    let turns = robit::replay::load_session(&path, key.as_ref())?;
+    let mut builder = Engine::builder().registry(registry);
+    if let Some(profile) = profile {
+        builder = builder.profile(profile);
+    }
+    let mut engine = builder.build()?;
+    let report = robit::replay::replay(&mut engine, &turns);
+    println!("{report}");
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(anyhow!("replay of {} differs", path.display()))
+    }
+}
+
+/// `robit conformance [dir]`: round-trips the protocol fixtures, the shipped ones or those in
+/// `dir`, and checks the engine's replies to them, each against a fresh engine.
+fn conformance(profile: Option<String>, dir: Option<PathBuf>) -> Result<()> {
+    let fixtures = match &dir {
+        Some(dir) => robit::conformance::load_fixtures(dir)?,
+        None => robit::conformance::builtin_fixtures()?,
+    };
+    let report = robit::conformance::check(&fixtures, || {
+        let mut builder = Engine::builder().registry(default_registry());
+        if let Some(profile) = &profile {
+            builder = builder.profile(profile.clone());
+        }
+        builder.build()
+    });
+    println!("{report}");
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(anyhow!("protocol conformance failed"))
+    }
+}
+
+/// `robit check-config`: lists every problem in the config files and fails if there is one.
+fn check_config(registry: robit::ActionRegistry, profile: Option<String>) -> Result<()> {
+    let mut builder = Engine::builder().registry(registry);
+    if let Some(profile) = profile {
+        builder = builder.profile(profile);
+    }
+    let engine = builder.build()?;
+    let problems = engine.validate_config();
+    if problems.is_empty() {
+        println!("config ok");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("- {problem}");
+    }
+    Err(anyhow!("{} config problem(s)", problems.len()))
+}
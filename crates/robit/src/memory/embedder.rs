@@ -0,0 +1,139 @@
+use anyhow::Result;
+
+pub trait AiEmbedder: Send + Sync {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+    /// Vectors from different models are not comparable, so entries are tagged with this.
+    fn model_name(&self) -> &str;
+}
+
+#[cfg(feature = "ai-http")]
+mod http {
+    use std::time::Duration;
+
+    use anyhow::{anyhow, Context, Result};
+    use reqwest::blocking::Client;
+    use serde_json::{json, Value};
+
+    use super::AiEmbedder;
+
+    fn http_client() -> Result<Client> {
+        Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .context("failed to build http client")
+    }
+
+    fn parse_vectors(value: &Value) -> Vec<Vec<f32>> {
+        value
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|item| {
+                        item.as_array()
+                            .map(|nums| {
+                                nums.iter()
+                                    .filter_map(|n| n.as_f64())
+                                    .map(|n| n as f32)
+                                    .collect()
+                            })
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// OpenAI-compatible `/embeddings` endpoint.
+    pub struct OpenAiEmbedder {
+        client: Client,
+        api_key: String,
+        base_url: String,
+        model: String,
+    }
+
+    impl OpenAiEmbedder {
+        pub fn new(api_key: String, model: String, base_url: Option<String>) -> Result<Self> {
+            if api_key.trim().is_empty() {
+                return Err(anyhow!("api key is empty"));
+            }
+            Ok(Self {
+                client: http_client()?,
+                api_key,
+                base_url: base_url.unwrap_or_else(|| "https://api.openai.com/v1".to_string()),
+                model,
+            })
+        }
+    }
+
+    impl AiEmbedder for OpenAiEmbedder {
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let url = format!("{}/embeddings", self.base_url.trim_end_matches('/'));
+            let resp = self
+                .client
+                .post(url)
+                .bearer_auth(&self.api_key)
+                .json(&json!({"model": self.model, "input": texts}))
+                .send()
+                .context("failed to send embeddings request")?;
+            let status = resp.status();
+            let value: Value = resp.json().context("failed to parse embeddings response")?;
+            if !status.is_success() {
+                return Err(anyhow!("embeddings http error {status}: {value}"));
+            }
+            let mut data = value["data"].as_array().cloned().unwrap_or_default();
+            data.sort_by_key(|item| item["index"].as_u64().unwrap_or(0));
+            let embeddings: Vec<Value> = data
+                .into_iter()
+                .map(|item| item["embedding"].clone())
+                .collect();
+            Ok(parse_vectors(&Value::Array(embeddings)))
+        }
+
+        fn model_name(&self) -> &str {
+            &self.model
+        }
+    }
+
+    /// Local Ollama `/api/embed` endpoint.
+    pub struct OllamaEmbedder {
+        client: Client,
+        base_url: String,
+        model: String,
+    }
+
+    impl OllamaEmbedder {
+        pub fn new(model: String, base_url: Option<String>) -> Result<Self> {
+            Ok(Self {
+                client: http_client()?,
+                base_url: base_url.unwrap_or_else(|| "http://localhost:11434".to_string()),
+                model,
+            })
+        }
+    }
+
+    impl AiEmbedder for OllamaEmbedder {
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let url = format!("{}/api/embed", self.base_url.trim_end_matches('/'));
+            let resp = self
+                .client
+                .post(url)
+                .json(&json!({"model": self.model, "input": texts}))
+                .send()
+                .context("failed to send embeddings request")?;
+            let status = resp.status();
+            let value: Value = resp.json().context("failed to parse embeddings response")?;
+            if !status.is_success() {
+                return Err(anyhow!("embeddings http error {status}: {value}"));
+            }
+            Ok(parse_vectors(&value["embeddings"]))
+        }
+
+        fn model_name(&self) -> &str {
+            &self.model
+        }
+    }
+}
+
+#[cfg(feature = "ai-http")]
+pub use http::{OllamaEmbedder, OpenAiEmbedder};
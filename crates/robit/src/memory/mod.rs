@@ -0,0 +1,208 @@
+mod embedder;
+mod store;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::encryption::StorageKey;
+
+pub use embedder::AiEmbedder;
+#[cfg(feature = "ai-http")]
+pub use embedder::{OllamaEmbedder, OpenAiEmbedder};
+pub use store::{MemoryEntry, MemoryHit, MemoryKind, VectorStore};
+
+/// Characters per indexed file chunk.
+const FILE_CHUNK_CHARS: usize = 1200;
+
+/// Embeds and recalls past conversations, facts, and file snippets for the planner prompt.
+pub struct MemoryIndex {
+    embedder: Box<dyn AiEmbedder>,
+    store: VectorStore,
+    path: Option<PathBuf>,
+    key: Option<StorageKey>,
+    /// Hits scoring below this cosine similarity are not injected.
+    pub min_score: f32,
+    pub top_k: usize,
+}
+
+impl MemoryIndex {
+    pub fn new(embedder: Box<dyn AiEmbedder>) -> Self {
+        Self {
+            embedder,
+            store: VectorStore::default(),
+            path: None,
+            key: None,
+            min_score: 0.35,
+            top_k: 4,
+        }
+    }
+
+    /// Loads (and later saves) the store at `path`, e.g. `~/.robit/memory/vectors.json`.
+    pub fn with_path(mut self, path: PathBuf) -> Result<Self> {
+        self.store = VectorStore::load_sealed(&path, self.key.as_ref())?;
+        let existed = path.exists();
+        self.path = Some(path);
+        // Seals a file written before the key was set.
+        if existed && self.key.is_some() {
+            self.save()?;
+        }
+        Ok(self)
+    }
+
+    /// Encrypts the store at rest; set it before `with_path`.
+    pub fn with_storage_key(mut self, key: StorageKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Gives an index without a key of its own `key`, after `with_path`: the store is read
+    /// again with it, so a plain copy in a sealed store is refused, and saved sealed.
+    pub(crate) fn adopt_storage_key(&mut self, key: &StorageKey) -> Result<()> {
+        if self.key.is_some() {
+            return Ok(());
+        }
+        self.key = Some(key.clone());
+        if let Some(path) = &self.path {
+            self.store = VectorStore::load_sealed(path, self.key.as_ref())?;
+            if path.exists() {
+                self.save()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    /// Stored entries of `kind` in a workspace, oldest first.
+    pub fn entries(&self, workspace_id: &str, kind: MemoryKind) -> impl Iterator<Item = &MemoryEntry> {
+        self.store
+            .entries()
+            .iter()
+            .filter(move |entry| entry.workspace_id == workspace_id && entry.kind == kind)
+    }
+
+    pub fn remember(
+        &mut self,
+        kind: MemoryKind,
+        workspace_id: &str,
+        source: &str,
+        text: &str,
+    ) -> Result<()> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        let embedding = self.embed_one(text)?;
+        self.store.insert(
+            kind,
+            workspace_id,
+            source,
+            text,
+            self.embedder.model_name(),
+            embedding,
+        );
+        self.save()
+    }
+
+    /// Splits a text file into chunks and indexes each as a file snippet.
+    pub fn index_file(&mut self, workspace_id: &str, path: &Path) -> Result<usize> {
+        let content = fs::read_to_string(path)?;
+        let source = path.to_string_lossy().to_string();
+        self.store.remove_source(&source);
+        let chunks = chunk_text(&content, FILE_CHUNK_CHARS);
+        if chunks.is_empty() {
+            return Ok(0);
+        }
+        let embeddings = self.embedder.embed(&chunks)?;
+        if embeddings.len() != chunks.len() {
+            return Err(anyhow!(
+                "embedder returned {} vectors for {} chunks",
+                embeddings.len(),
+                chunks.len()
+            ));
+        }
+        let model = self.embedder.model_name().to_string();
+        for (chunk, embedding) in chunks.iter().zip(embeddings) {
+            self.store.insert(
+                MemoryKind::File,
+                workspace_id,
+                &source,
+                chunk,
+                &model,
+                embedding,
+            );
+        }
+        self.save()?;
+        Ok(chunks.len())
+    }
+
+    pub fn recall(&self, workspace_id: &str, query: &str) -> Result<Vec<MemoryHit>> {
+        if self.store.is_empty() || query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let embedding = self.embed_one(query)?;
+        Ok(self
+            .store
+            .search(
+                &embedding,
+                workspace_id,
+                self.embedder.model_name(),
+                self.top_k,
+            )
+            .into_iter()
+            .filter(|hit| hit.score >= self.min_score)
+            .collect())
+    }
+
+    fn embed_one(&self, text: &str) -> Result<Vec<f32>> {
+        self.embedder
+            .embed(&[text.to_string()])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("embedder returned no vector"))
+    }
+
+    fn save(&self) -> Result<()> {
+        match &self.path {
+            Some(path) => self.store.save_sealed(path, self.key.as_ref()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn chunk_text(content: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in content.split("\n\n") {
+        let paragraph = paragraph.trim();
+        if paragraph.is_empty() {
+            continue;
+        }
+        if !current.is_empty() && current.chars().count() + paragraph.chars().count() > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if paragraph.chars().count() > max_chars {
+            let chars: Vec<char> = paragraph.chars().collect();
+            for piece in chars.chunks(max_chars) {
+                chunks.push(piece.iter().collect());
+            }
+            continue;
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{open_text, seal_text, Record, SealMark, StorageKey};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryKind {
+    Conversation,
+    Fact,
+    File,
+}
+
+impl MemoryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryKind::Conversation => "conversation",
+            MemoryKind::Fact => "fact",
+            MemoryKind::File => "file",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemoryEntry {
+    pub id: u64,
+    pub kind: MemoryKind,
+    pub workspace_id: String,
+    pub source: String,
+    pub text: String,
+    pub model: String,
+    pub created_at: u64,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryHit {
+    pub kind: MemoryKind,
+    pub source: String,
+    pub text: String,
+    pub score: f32,
+}
+
+/// Flat in-memory vector store persisted as JSON; brute-force cosine search is plenty
+/// for a personal assistant's history.
+#[derive(Default, Serialize, Deserialize)]
+pub struct VectorStore {
+    next_id: u64,
+    entries: Vec<MemoryEntry>,
+}
+
+impl VectorStore {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_sealed(path, None)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.save_sealed(path, None)
+    }
+
+    /// Like `load`, for a file `save_sealed` wrote with `key`; see `SealMark` for plain ones.
+    pub fn load_sealed(path: &Path, key: Option<&StorageKey>) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mark = SealMark::for_store(path);
+        let content = fs::read_to_string(path)?;
+        let content = open_text(key, Record::whole("memory"), &content, mark.is_set())
+            .with_context(|| mark.refused(path))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save_sealed(&self, path: &Path, key: Option<&StorageKey>) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, seal_text(key, Record::whole("memory"), serde_json::to_string(self)?)?)?;
+        SealMark::for_store(path).update(key.is_some())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[MemoryEntry] {
+        &self.entries
+    }
+
+    pub fn insert(
+        &mut self,
+        kind: MemoryKind,
+        workspace_id: &str,
+        source: &str,
+        text: &str,
+        model: &str,
+        embedding: Vec<f32>,
+    ) -> u64 {
+        self.next_id += 1;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.entries.push(MemoryEntry {
+            id: self.next_id,
+            kind,
+            workspace_id: workspace_id.to_string(),
+            source: source.to_string(),
+            text: text.to_string(),
+            model: model.to_string(),
+            created_at,
+            embedding,
+        });
+        self.next_id
+    }
+
+    pub fn remove_source(&mut self, source: &str) {
+        self.entries.retain(|entry| entry.source != source);
+    }
+
+    /// Best matches within a workspace, restricted to vectors from the same model.
+    pub fn search(
+        &self,
+        query: &[f32],
+        workspace_id: &str,
+        model: &str,
+        k: usize,
+    ) -> Vec<MemoryHit> {
+        let mut hits: Vec<MemoryHit> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.workspace_id == workspace_id && entry.model == model)
+            .map(|entry| MemoryHit {
+                kind: entry.kind,
+                source: entry.source.clone(),
+                text: entry.text.clone(),
+                score: cosine_similarity(query, &entry.embedding),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        hits
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
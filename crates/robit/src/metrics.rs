@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Counters since the engine was created, from `Engine::metrics` or the `stats` command.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EngineMetrics {
+    pub uptime_secs: u64,
+    /// Inbound messages, including ones the middleware rejected.
+    pub messages: u64,
+    /// AI calls that returned a decision.
+    pub ai_calls: u64,
+    pub approvals_requested: u64,
+    pub approvals_granted: u64,
+    pub approvals_denied: u64,
+    /// Replies of kind `error`.
+    pub errors: u64,
+    /// Time to handle one inbound message, approvals and AI calls included.
+    pub message_latency: Latency,
+    /// By action name.
+    pub actions: BTreeMap<String, ActionStats>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ActionStats {
+    pub runs: u64,
+    pub failures: u64,
+    pub latency: Latency,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Latency {
+    pub count: u64,
+    pub total_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Latency {
+    pub fn record(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        self.count += 1;
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    pub fn avg_ms(&self) -> u64 {
+        self.total_ms.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+impl EngineMetrics {
+    pub(crate) fn record_action(&mut self, action: &str, ok: bool, elapsed: Duration) {
+        let stats = self.actions.entry(action.to_string()).or_default();
+        stats.runs += 1;
+        if !ok {
+            stats.failures += 1;
+        }
+        stats.latency.record(elapsed);
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = format!(
+            "uptime {uptime}s\nmessages {messages} (avg {avg}ms, max {max}ms)\nai calls {ai}\napprovals {requested} requested, {granted} granted, {denied} denied\nerrors {errors}\n",
+            uptime = self.uptime_secs,
+            messages = self.messages,
+            avg = self.message_latency.avg_ms(),
+            max = self.message_latency.max_ms,
+            ai = self.ai_calls,
+            requested = self.approvals_requested,
+            granted = self.approvals_granted,
+            denied = self.approvals_denied,
+            errors = self.errors,
+        );
+        if self.actions.is_empty() {
+            text.push_str("no actions run yet\n");
+            return text;
+        }
+        text.push_str("actions:\n");
+        for (name, stats) in &self.actions {
+            text.push_str(&format!(
+                "  {name}  {runs} runs, {failures} failed, avg {avg}ms, max {max}ms\n",
+                runs = stats.runs,
+                failures = stats.failures,
+                avg = stats.latency.avg_ms(),
+                max = stats.latency.max_ms,
+            ));
+        }
+        text
+    }
+}
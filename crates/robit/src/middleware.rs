@@ -0,0 +1,63 @@
+use crate::types::{InboundMessage, OutboundMessage};
+
+pub enum MiddlewareFlow {
+    Continue(InboundMessage),
+    Reject(String),
+    Drop,
+}
+
+pub trait Middleware: Send {
+    fn name(&self) -> &'static str;
+
+    fn on_inbound(&mut self, msg: InboundMessage) -> MiddlewareFlow {
+        MiddlewareFlow::Continue(msg)
+    }
+
+    fn on_outbound(&mut self, msg: OutboundMessage) -> Option<OutboundMessage> {
+        Some(msg)
+    }
+}
+
+#[derive(Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn push(&mut self, middleware: Box<dyn Middleware>) {
+        self.layers.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Runs inbound layers in registration order; the first non-`Continue` result wins.
+    pub fn inbound(&mut self, msg: InboundMessage) -> MiddlewareFlow {
+        let mut current = msg;
+        for layer in &mut self.layers {
+            match layer.on_inbound(current) {
+                MiddlewareFlow::Continue(next) => current = next,
+                other => return other,
+            }
+        }
+        MiddlewareFlow::Continue(current)
+    }
+
+    /// Runs outbound layers in reverse registration order, so the first layer added
+    /// sees the final message.
+    pub fn outbound(&mut self, replies: Vec<OutboundMessage>) -> Vec<OutboundMessage> {
+        if self.layers.is_empty() {
+            return replies;
+        }
+        replies
+            .into_iter()
+            .filter_map(|reply| {
+                self.layers
+                    .iter_mut()
+                    .rev()
+                    .try_fold(reply, |current, layer| layer.on_outbound(current))
+            })
+            .collect()
+    }
+}
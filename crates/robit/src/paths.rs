@@ -0,0 +1,122 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where robit keeps its user config and its state (conversations, plans, approvals and the
+/// other persisted files).
+///
+/// `ROBIT_HOME` puts both in one directory, and `ROBIT_CONFIG_DIR` / `ROBIT_DATA_DIR` set each
+/// on its own. Otherwise an existing `~/.robit` is kept, so older installs find their files;
+/// new ones follow the platform:
+///
+/// - Linux and other Unix: `$XDG_CONFIG_HOME/robit` (`~/.config/robit`) and
+///   `$XDG_DATA_HOME/robit` (`~/.local/share/robit`)
+/// - macOS: `~/Library/Application Support/robit` for both
+/// - Windows: `%APPDATA%\robit` for both
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RobitPaths {
+    /// Holds the user `config.toml`.
+    pub config_dir: PathBuf,
+    /// Holds the persisted state.
+    pub data_dir: PathBuf,
+}
+
+impl RobitPaths {
+    /// `None` when neither an override nor a home directory is set.
+    pub fn from_env() -> Option<Self> {
+        let home = env::var_os("ROBIT_HOME").filter(|dir| !dir.is_empty());
+        let config_dir = env_dir("ROBIT_CONFIG_DIR").or_else(|| home.clone().map(PathBuf::from));
+        let data_dir = env_dir("ROBIT_DATA_DIR").or_else(|| home.map(PathBuf::from));
+        if let (Some(config_dir), Some(data_dir)) = (&config_dir, &data_dir) {
+            return Some(Self {
+                config_dir: config_dir.clone(),
+                data_dir: data_dir.clone(),
+            });
+        }
+        let defaults = Self::platform_default()?;
+        Some(Self {
+            config_dir: config_dir.unwrap_or(defaults.config_dir),
+            data_dir: data_dir.unwrap_or(defaults.data_dir),
+        })
+    }
+
+    /// The same config, with state under `profiles/<name>` so profiles do not share files.
+    pub fn for_profile(&self, name: &str) -> Self {
+        Self {
+            config_dir: self.config_dir.clone(),
+            data_dir: self.data_dir.join("profiles").join(name),
+        }
+    }
+
+    /// The user config layer.
+    pub fn config_file(&self) -> PathBuf {
+        self.config_dir.join("config.toml")
+    }
+
+    /// Conversation history for one adapter, e.g. `contexts/stdin.json`.
+    pub fn conversation_path(&self, adapter: &str) -> PathBuf {
+        self.data_dir.join("contexts").join(format!("{adapter}.json"))
+    }
+
+    pub fn plan_templates_dir(&self) -> PathBuf {
+        self.data_dir.join("plans").join("templates")
+    }
+
+    pub fn approvals_path(&self) -> PathBuf {
+        self.data_dir.join("approvals.json")
+    }
+
+    /// Any other state file, e.g. `vars.json`.
+    pub fn data_file(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    fn platform_default() -> Option<Self> {
+        let home = home_dir()?;
+        let legacy = home.join(".robit");
+        if legacy.is_dir() {
+            return Some(Self {
+                config_dir: legacy.clone(),
+                data_dir: legacy,
+            });
+        }
+        if cfg!(target_os = "macos") {
+            let dir = home.join("Library/Application Support/robit");
+            return Some(Self {
+                config_dir: dir.clone(),
+                data_dir: dir,
+            });
+        }
+        if cfg!(windows) {
+            let dir = env_dir("APPDATA")
+                .unwrap_or_else(|| home.join("AppData").join("Roaming"))
+                .join("robit");
+            return Some(Self {
+                config_dir: dir.clone(),
+                data_dir: dir,
+            });
+        }
+        Some(Self {
+            config_dir: xdg_dir("XDG_CONFIG_HOME")
+                .unwrap_or_else(|| home.join(".config"))
+                .join("robit"),
+            data_dir: xdg_dir("XDG_DATA_HOME")
+                .unwrap_or_else(|| home.join(".local/share"))
+                .join("robit"),
+        })
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env_dir("HOME").or_else(|| if cfg!(windows) { env_dir("USERPROFILE") } else { None })
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The XDG spec says relative paths are to be ignored.
+fn xdg_dir(name: &str) -> Option<PathBuf> {
+    env_dir(name).filter(|dir| dir.is_absolute())
+}
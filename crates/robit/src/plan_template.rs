@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::PlanStep;
+
+/// A saved plan. Step params may contain `{{input.<name>}}` placeholders that are filled
+/// in when the template runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanTemplate {
+    pub name: String,
+    pub steps: Vec<PlanStep>,
+}
+
+impl PlanTemplate {
+    /// Placeholder names in order of first use.
+    pub fn placeholders(&self) -> Vec<String> {
+        let text = serde_json::to_string(&self.steps).unwrap_or_default();
+        let mut names: Vec<String> = Vec::new();
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("{{input.") {
+            rest = &rest[start + "{{input.".len()..];
+            let Some(end) = rest.find("}}") else {
+                break;
+            };
+            let name = rest[..end].trim().to_string();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+            rest = &rest[end + 2..];
+        }
+        names
+    }
+}
+
+/// One JSON file per template, e.g. `~/.robit/plans/templates/morning-briefing.json`.
+pub struct PlanTemplateStore {
+    dir: PathBuf,
+}
+
+impl PlanTemplateStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn save(&self, template: &PlanTemplate) -> Result<PathBuf> {
+        let path = self.path_for(&template.name)?;
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("create {}", self.dir.display()))?;
+        fs::write(&path, serde_json::to_string_pretty(template)?)
+            .with_context(|| format!("write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(&self, name: &str) -> Result<PlanTemplate> {
+        let path = self.path_for(name)?;
+        if !path.exists() {
+            return Err(anyhow!("no saved plan named {name}"));
+        }
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        let mut template: PlanTemplate = serde_json::from_str(&content)
+            .with_context(|| format!("parse {}", path.display()))?;
+        template.name = name.to_string();
+        Ok(template)
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                names.push(stem.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn path_for(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+        if !valid {
+            return Err(anyhow!(
+                "plan names may only use letters, digits, '-' and '_': {name}"
+            ));
+        }
+        Ok(self.dir.join(format!("{name}.json")))
+    }
+}
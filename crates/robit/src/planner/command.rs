@@ -0,0 +1,60 @@
+use serde_json::json;
+
+use super::rule::parse_kv_params;
+use super::Planner;
+use crate::types::{ActionRequest, InboundMessage, PlannerResponse};
+
+/// Explicit commands: `action:fs.list_dir path=~` or `action fs.read_file {"path": "a.txt"}`.
+#[derive(Default)]
+pub struct CommandPlanner;
+
+impl CommandPlanner {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn parse(&self, input: &str) -> Option<ActionRequest> {
+        let trimmed = input.trim();
+        let rest = if let Some(rest) = trimmed.strip_prefix("action:") {
+            rest.trim()
+        } else if let Some(rest) = trimmed.strip_prefix("action ") {
+            rest.trim()
+        } else {
+            return None;
+        };
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut parts = rest.splitn(2, char::is_whitespace);
+        let name = parts.next()?.trim();
+        let params_raw = parts.next().unwrap_or("").trim();
+        let params = if params_raw.is_empty() {
+            json!({})
+        } else if params_raw.starts_with('{') {
+            serde_json::from_str(params_raw).unwrap_or_else(|_| json!({}))
+        } else {
+            parse_kv_params(params_raw)
+        };
+
+        Some(ActionRequest {
+            name: name.to_string(),
+            params,
+            raw_input: trimmed.to_string(),
+        })
+    }
+}
+
+impl Planner for CommandPlanner {
+    fn name(&self) -> &'static str {
+        "commands"
+    }
+
+    fn plan(&mut self, msg: &InboundMessage) -> PlannerResponse {
+        match self.parse(&msg.text) {
+            Some(request) => PlannerResponse::Action(request),
+            None => PlannerResponse::Pass,
+        }
+    }
+}
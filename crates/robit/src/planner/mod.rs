@@ -1,3 +1,49 @@
+mod command;
 mod rule;
+mod rules;
+mod slash;
+mod status;
 
+pub use command::CommandPlanner;
 pub use rule::RulePlanner;
+pub use rules::RuleConfig;
+pub use slash::SlashPlanner;
+pub(crate) use rule::parse_kv_params;
+pub(crate) use status::{heuristic_plan_for, match_probe, HostOs, Probe};
+
+use crate::types::{InboundMessage, PlannerResponse};
+
+/// Turns a message into an action without the AI. Return `PlannerResponse::Pass` to let the
+/// next planner in the chain try.
+pub trait Planner: Send {
+    fn name(&self) -> &'static str;
+
+    fn plan(&mut self, msg: &InboundMessage) -> PlannerResponse;
+}
+
+/// Planners asked in order before the AI, which only sees messages all of them passed on.
+#[derive(Default)]
+pub struct PlannerChain {
+    planners: Vec<Box<dyn Planner>>,
+}
+
+impl PlannerChain {
+    pub fn push(&mut self, planner: Box<dyn Planner>) {
+        self.planners.push(planner);
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.planners.iter().map(|planner| planner.name()).collect()
+    }
+
+    /// The first answer that isn't `Pass`.
+    pub fn plan(&mut self, msg: &InboundMessage) -> PlannerResponse {
+        for planner in &mut self.planners {
+            match planner.plan(msg) {
+                PlannerResponse::Pass => continue,
+                response => return response,
+            }
+        }
+        PlannerResponse::Pass
+    }
+}
@@ -1,24 +1,53 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
 use serde_json::{json, Value};
 
-use crate::types::{ActionRequest, PlannerResponse};
+use super::rules::UserRules;
+use super::status::heuristic_plan_for;
+use super::Planner;
+use crate::types::{ActionRequest, InboundMessage, PlannerResponse};
 
-pub struct RulePlanner;
+#[derive(Default)]
+pub struct RulePlanner {
+    user_rules: Option<UserRules>,
+}
 
 impl RulePlanner {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Adds the rules in a TOML file (see `RuleConfig`), checked before the built-in ones.
+    pub fn load_rules(&mut self, path: PathBuf) -> Result<()> {
+        self.user_rules = Some(UserRules::load(path)?);
+        Ok(())
     }
 
+    /// Picks up edits to the rules file since it was last read.
+    pub fn refresh_rules(&mut self) {
+        if let Some(rules) = &mut self.user_rules {
+            rules.refresh();
+        }
+    }
+
+    /// The rules file first, then the built-in rules; `Pass` when none match.
     pub fn plan(&self, input: &str) -> PlannerResponse {
         let trimmed = input.trim();
         if trimmed.is_empty() {
-            return PlannerResponse::Unknown {
-                message: "empty input".to_string(),
-            };
+            return PlannerResponse::Pass;
+        }
+
+        if let Some(response) = self.user_rules.as_ref().and_then(|rules| rules.plan(trimmed)) {
+            return response;
         }
 
-        if let Some(request) = self.parse_explicit_action(trimmed) {
-            return PlannerResponse::Action(request);
+        let status_steps = self
+            .matches_system_status(trimmed)
+            .then(|| heuristic_plan_for(trimmed))
+            .flatten();
+        if let Some(steps) = status_steps {
+            return PlannerResponse::Plan(steps);
         }
 
         if self.matches_desktop_organize(trimmed) {
@@ -32,41 +61,11 @@ impl RulePlanner {
             });
         }
 
-        PlannerResponse::Unknown {
-            message: "no rule matched".to_string(),
-        }
+        PlannerResponse::Pass
     }
 
-    fn parse_explicit_action(&self, input: &str) -> Option<ActionRequest> {
-        let trimmed = input.trim();
-        let rest = if let Some(rest) = trimmed.strip_prefix("action:") {
-            rest.trim()
-        } else if let Some(rest) = trimmed.strip_prefix("action ") {
-            rest.trim()
-        } else {
-            return None;
-        };
-
-        if rest.is_empty() {
-            return None;
-        }
-
-        let mut parts = rest.splitn(2, char::is_whitespace);
-        let name = parts.next()?.trim();
-        let params_raw = parts.next().unwrap_or("").trim();
-        let params = if params_raw.is_empty() {
-            json!({})
-        } else if params_raw.starts_with('{') {
-            serde_json::from_str(params_raw).unwrap_or_else(|_| json!({}))
-        } else {
-            parse_kv_params(params_raw)
-        };
-
-        Some(ActionRequest {
-            name: name.to_string(),
-            params,
-            raw_input: trimmed.to_string(),
-        })
+    fn matches_system_status(&self, input: &str) -> bool {
+        input.contains("系统状态") || input.to_lowercase().contains("system status")
     }
 
     fn matches_desktop_organize(&self, input: &str) -> bool {
@@ -75,7 +74,18 @@ impl RulePlanner {
     }
 }
 
-fn parse_kv_params(input: &str) -> Value {
+impl Planner for RulePlanner {
+    fn name(&self) -> &'static str {
+        "rules"
+    }
+
+    fn plan(&mut self, msg: &InboundMessage) -> PlannerResponse {
+        self.refresh_rules();
+        RulePlanner::plan(self, &msg.text)
+    }
+}
+
+pub(crate) fn parse_kv_params(input: &str) -> Value {
     let mut map = serde_json::Map::new();
     for token in input.split_whitespace() {
         let Some((key, value)) = token.split_once('=') else {
@@ -86,7 +96,7 @@ fn parse_kv_params(input: &str) -> Value {
     Value::Object(map)
 }
 
-fn parse_value(raw: &str) -> Value {
+pub(super) fn parse_value(raw: &str) -> Value {
     let trimmed = raw.trim_matches('"');
     if trimmed.eq_ignore_ascii_case("true") {
         return Value::Bool(true);
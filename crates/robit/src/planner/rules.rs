@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context, Result};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
+use super::rule::parse_value;
+use crate::types::{ActionRequest, PlanStep, PlannerResponse};
+
+/// One `[[rule]]` from the rules file:
+///
+/// ```toml
+/// [[rule]]
+/// pattern = '^deploy (?P<env>\w+)$'
+/// action = "shell.run"
+/// params = { command = "make deploy ENV=${env}" }
+/// ```
+///
+/// Strings in `params` may use `$1`, `${1}` or `${name}` for capture groups and `$0` for the
+/// whole match. A param that is only a reference is typed like `key=value` params.
+///
+/// A rule can list `[[rule.steps]]` (each a `PlanStep`) instead of one action; they are
+/// proposed as a plan, with captures filled in the same way.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RuleConfig {
+    pub pattern: String,
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleConfig>,
+}
+
+struct UserRule {
+    regex: Regex,
+    config: RuleConfig,
+}
+
+/// Rules from a TOML file, re-read when the file changes. First match wins.
+pub(crate) struct UserRules {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    rules: Vec<UserRule>,
+}
+
+impl UserRules {
+    /// A missing file is fine; rules are picked up once it appears.
+    pub(crate) fn load(path: PathBuf) -> Result<Self> {
+        let mut rules = Self {
+            path,
+            modified: None,
+            rules: Vec::new(),
+        };
+        rules.reload()?;
+        Ok(rules)
+    }
+
+    /// Re-reads the file if it changed since the last load. A broken file keeps the old rules.
+    pub(crate) fn refresh(&mut self) {
+        if modified_at(&self.path) == self.modified {
+            return;
+        }
+        if let Err(err) = self.reload() {
+            warn!("rules reload failed: {err}");
+        }
+    }
+
+    pub(crate) fn plan(&self, input: &str) -> Option<PlannerResponse> {
+        self.rules.iter().find_map(|rule| {
+            let caps = rule.regex.captures(input)?;
+            let config = &rule.config;
+            if !config.steps.is_empty() {
+                let steps = config
+                    .steps
+                    .iter()
+                    .map(|step| PlanStep {
+                        params: fill_params(&step.params, &caps),
+                        ..step.clone()
+                    })
+                    .collect();
+                return Some(PlannerResponse::Plan(steps));
+            }
+            Some(PlannerResponse::Action(ActionRequest {
+                name: config.action.clone(),
+                params: fill_params(&config.params, &caps),
+                raw_input: input.to_string(),
+            }))
+        })
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let modified = modified_at(&self.path);
+        if modified.is_none() {
+            self.modified = None;
+            self.rules.clear();
+            return Ok(());
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("read {}", self.path.display()))?;
+        // Remember the attempt so a broken file is reported once, not on every message.
+        self.modified = modified;
+        let file: RulesFile = toml::from_str(&content)
+            .with_context(|| format!("parse {}", self.path.display()))?;
+        let mut rules = Vec::with_capacity(file.rule.len());
+        for config in file.rule {
+            let regex = Regex::new(&config.pattern)
+                .map_err(|err| anyhow!("rule {:?}: {err}", config.pattern))?;
+            if config.action.trim().is_empty() == config.steps.is_empty() {
+                return Err(anyhow!(
+                    "rule {:?} needs either an action or steps",
+                    config.pattern
+                ));
+            }
+            rules.push(UserRule { regex, config });
+        }
+        self.rules = rules;
+        Ok(())
+    }
+}
+
+fn modified_at(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn fill_params(template: &Value, caps: &Captures) -> Value {
+    match template {
+        Value::String(text) => {
+            if let Some(value) = single_capture(text, caps) {
+                return parse_value(value);
+            }
+            let mut out = String::new();
+            caps.expand(text, &mut out);
+            Value::String(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| fill_params(item, caps)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| (key.clone(), fill_params(item, caps)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// The captured text when `text` is nothing but `$1`, `${1}`, `$name` or `${name}`.
+fn single_capture<'t>(text: &str, caps: &Captures<'t>) -> Option<&'t str> {
+    let reference = text.strip_prefix('$')?;
+    let reference = reference
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(reference);
+    if reference.is_empty() || !reference.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+        return None;
+    }
+    let group = match reference.parse::<usize>() {
+        Ok(index) => caps.get(index),
+        Err(_) => caps.name(reference),
+    };
+    group.map(|group| group.as_str())
+}
@@ -0,0 +1,133 @@
+use serde_json::{json, Map, Value};
+
+use super::rule::parse_value;
+use super::Planner;
+use crate::types::{ActionRequest, InboundMessage, PlannerResponse};
+
+struct SlashCommand {
+    name: &'static str,
+    action: &'static str,
+    /// The param that takes the text after the command.
+    arg: &'static str,
+    /// Params that may trail the argument as `key=value`.
+    options: &'static [&'static str],
+    /// Params always sent.
+    fixed: &'static [(&'static str, &'static str)],
+    usage: &'static str,
+}
+
+const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "read",
+        action: "fs.read_file",
+        arg: "path",
+        options: &["max_chars"],
+        fixed: &[],
+        usage: "/read <path> [max_chars=N]    read a text file",
+    },
+    SlashCommand {
+        name: "run",
+        action: "shell.run",
+        arg: "command",
+        options: &[],
+        fixed: &[],
+        usage: "/run <command>                run a shell command",
+    },
+    SlashCommand {
+        name: "organize",
+        action: "fs.organize_directory",
+        arg: "path",
+        options: &[],
+        fixed: &[("mode", "extension")],
+        usage: "/organize <dir>               sort a folder's files by extension",
+    },
+    SlashCommand {
+        name: "search",
+        action: "web.search_brave",
+        arg: "query",
+        options: &["count"],
+        fixed: &[],
+        usage: "/search <query> [count=N]     search the web (needs BRAVE_API_KEY)",
+    },
+];
+
+/// Chat shortcuts such as `/read notes.txt` or `/run ls -la`; `/help` lists them. Other
+/// `/...` input, like a bare path, is passed on.
+#[derive(Default)]
+pub struct SlashPlanner;
+
+impl SlashPlanner {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn help_text() -> String {
+        let mut text = String::from("slash commands:\n");
+        for command in COMMANDS {
+            text.push_str(&format!("  {}\n", command.usage));
+        }
+        text.push_str("  /help                         show this list");
+        text
+    }
+}
+
+impl Planner for SlashPlanner {
+    fn name(&self) -> &'static str {
+        "slash"
+    }
+
+    fn plan(&mut self, msg: &InboundMessage) -> PlannerResponse {
+        let Some(rest) = msg.text.trim().strip_prefix('/') else {
+            return PlannerResponse::Pass;
+        };
+        let (name, arg) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(name, arg)| (name, arg.trim()));
+        if name == "help" {
+            return PlannerResponse::Reply(Self::help_text());
+        }
+        let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+            return PlannerResponse::Pass;
+        };
+        let mut params = Map::new();
+        for (key, value) in command.fixed {
+            params.insert(key.to_string(), json!(value));
+        }
+        let arg = take_options(arg, command.options, &mut params);
+        if arg.is_empty() {
+            return PlannerResponse::Reply(format!("usage: {}", command.usage));
+        }
+        params.insert(command.arg.to_string(), Value::String(unquote(arg).to_string()));
+        PlannerResponse::Action(ActionRequest {
+            name: command.action.to_string(),
+            params: Value::Object(params),
+            raw_input: msg.text.trim().to_string(),
+        })
+    }
+}
+
+/// Moves trailing `key=value` words for known options into `params`.
+fn take_options<'a>(
+    mut arg: &'a str,
+    options: &[&str],
+    params: &mut Map<String, Value>,
+) -> &'a str {
+    while let Some((head, last)) = arg.rsplit_once(char::is_whitespace) {
+        let Some((key, value)) = last.split_once('=') else {
+            break;
+        };
+        if !options.contains(&key) {
+            break;
+        }
+        params.insert(key.to_string(), parse_value(value));
+        arg = head.trim_end();
+    }
+    arg
+}
+
+fn unquote(text: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| text.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(text)
+}
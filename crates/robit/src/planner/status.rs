@@ -0,0 +1,143 @@
+use serde_json::json;
+
+use crate::types::PlanStep;
+
+/// Probe steps for a status question ("系统状态", "memory", "disk", ...), or `None` when the
+/// text asks about none of them.
+pub(crate) fn heuristic_plan_for(text: &str) -> Option<Vec<PlanStep>> {
+    let lower = text.to_lowercase();
+    let wants_status = lower.contains("系统状态")
+        || lower.contains("system status")
+        || lower.contains("status")
+        || lower.contains("状态");
+    let wants_cpu = lower.contains("cpu") || lower.contains("负载") || lower.contains("load");
+    let wants_mem = lower.contains("内存") || lower.contains("memory");
+    let wants_disk = lower.contains("磁盘") || lower.contains("disk");
+    let wants_proc = lower.contains("进程") || lower.contains("process");
+    let wants_net = lower.contains("网络") || lower.contains("network");
+
+    let steps: Vec<PlanStep> = HostOs::current()
+        .probes()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (probe, _))| match probe {
+            Probe::Load => wants_status || wants_cpu,
+            Probe::Memory => wants_status || wants_mem,
+            Probe::Disk => wants_status || wants_disk,
+            Probe::Processes => wants_status || wants_proc,
+            Probe::Network => wants_net,
+        })
+        .map(|(idx, (probe, command))| PlanStep {
+            id: Some(format!("s{}", idx + 1)),
+            action: "shell.run".to_string(),
+            params: json!({ "command": command }),
+            note: Some(probe.note().to_string()),
+            requires_approval: Some(true),
+            retries: None,
+            retry_delay: None,
+            timeout_secs: None,
+            undo: None,
+            foreach: None,
+        })
+        .collect();
+
+    if steps.is_empty() {
+        None
+    } else {
+        Some(steps)
+    }
+}
+
+/// What one system status command looks at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Probe {
+    Load,
+    Memory,
+    Disk,
+    Processes,
+    Network,
+}
+
+impl Probe {
+    fn note(self) -> &'static str {
+        match self {
+            Self::Load => "Check uptime / load",
+            Self::Memory => "Check memory stats",
+            Self::Disk => "Check disk usage",
+            Self::Processes => "Check top processes",
+            Self::Network => "Check network interfaces",
+        }
+    }
+}
+
+/// Platforms with their own status commands; other Unixes use the Linux set.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HostOs {
+    MacOs,
+    Linux,
+    Windows,
+}
+
+impl HostOs {
+    pub(crate) fn current() -> Self {
+        match std::env::consts::OS {
+            "macos" | "ios" => Self::MacOs,
+            "windows" => Self::Windows,
+            _ => Self::Linux,
+        }
+    }
+
+    /// Status commands in the order the plan runs them.
+    pub(crate) fn probes(self) -> [(Probe, &'static str); 5] {
+        match self {
+            Self::MacOs => [
+                (Probe::Load, "uptime"),
+                (Probe::Memory, "vm_stat"),
+                (Probe::Disk, "df -h"),
+                (Probe::Processes, "ps aux | sort -nrk 3,3 | head -5"),
+                (Probe::Network, "ifconfig"),
+            ],
+            Self::Linux => [
+                (Probe::Load, "top -bn1 | head -5"),
+                (Probe::Memory, "free -b"),
+                (Probe::Disk, "df -h"),
+                (Probe::Processes, "ps aux --sort=-%cpu | head -6"),
+                (Probe::Network, "ip -brief address"),
+            ],
+            Self::Windows => [
+                (
+                    Probe::Load,
+                    "powershell -NoProfile -Command \"(Get-CimInstance Win32_Processor | Measure-Object LoadPercentage -Average).Average\"",
+                ),
+                (
+                    Probe::Memory,
+                    "powershell -NoProfile -Command \"Get-CimInstance Win32_OperatingSystem | Format-List TotalVisibleMemorySize,FreePhysicalMemory\"",
+                ),
+                (
+                    Probe::Disk,
+                    "powershell -NoProfile -Command \"Get-PSDrive -PSProvider FileSystem\"",
+                ),
+                (
+                    Probe::Processes,
+                    "powershell -NoProfile -Command \"Get-Process | Sort-Object CPU -Descending | Select-Object -First 5 -ExpandProperty ProcessName\"",
+                ),
+                (Probe::Network, "ipconfig"),
+            ],
+        }
+    }
+}
+
+/// Which probe set ran `command`, so its output is read the right way.
+pub(crate) fn match_probe(command: &str) -> Option<(HostOs, Probe)> {
+    for os in [HostOs::MacOs, HostOs::Linux, HostOs::Windows] {
+        if let Some((probe, _)) = os.probes().into_iter().find(|(_, cmd)| *cmd == command) {
+            return Some((os, probe));
+        }
+    }
+    match command {
+        "uptime" => Some((HostOs::Linux, Probe::Load)),
+        "df -h" => Some((HostOs::Linux, Probe::Disk)),
+        cmd if cmd.contains("ps aux") => Some((HostOs::Linux, Probe::Processes)),
+        _ => None,
+    }
+}
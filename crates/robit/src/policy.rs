@@ -1,21 +1,163 @@
 use anyhow::{anyhow, Result};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::types::RiskLevel;
-use crate::utils::expand_tilde;
+use crate::paths::RobitPaths;
+use crate::preflight::PreflightConfig;
+use crate::types::{ActionSpec, RiskLevel};
+use crate::utils::{expand_tilde, format_bytes};
+
+/// Default for each of the `max_*_bytes` limits.
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PolicyConfig {
     pub allowed_roots: Option<Vec<String>>,
+    /// Added to the built-in denied roots.
+    pub denied_roots: Option<Vec<String>>,
     pub approval_risk_levels: Option<Vec<String>>,
+    pub allowed_capabilities: Option<Vec<String>>,
+    pub denied_capabilities: Option<Vec<String>>,
+    pub time_windows: Option<Vec<TimeWindowConfig>>,
+    pub read_only: Option<bool>,
+    pub shell: Option<ShellSandbox>,
+    /// Sender id to role.
+    pub roles: Option<HashMap<String, Role>>,
+    pub default_role: Option<Role>,
+    /// Byte limits; 0 removes the limit.
+    pub max_read_bytes: Option<u64>,
+    pub max_write_bytes: Option<u64>,
+    pub max_fetch_bytes: Option<u64>,
+}
+
+/// What a sender may do. Guests only run low-risk actions and cannot approve or change
+/// settings; operators run anything subject to approval and change their own session
+/// settings; admins also switch models and read-only mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Guest,
+    Operator,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Guest => "guest",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+/// `[policy.shell]`: how `shell.run` starts its child process.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShellSandbox {
+    /// Leave out environment variables whose names look like secrets (`*KEY*`, `*TOKEN*`,
+    /// `*SECRET*`, `*PASSWORD*`, `AWS_*`, ...). On by default.
+    pub scrub_env: bool,
+    /// Passed through even when they look like secrets.
+    pub keep_env: Vec<String>,
+    /// Replaces `PATH` for the command.
+    pub path: Option<String>,
+    /// CPU seconds, applied with `ulimit -t`.
+    pub max_cpu_secs: Option<u64>,
+    /// Virtual memory in MiB, applied with `ulimit -v`.
+    pub max_memory_mb: Option<u64>,
+    /// Runs the command without network access: `unshare -rn` on Linux, `sandbox-exec` on
+    /// macOS. Where neither works the command fails rather than running with network.
+    pub deny_network: bool,
+}
+
+impl Default for ShellSandbox {
+    fn default() -> Self {
+        Self {
+            scrub_env: true,
+            keep_env: Vec::new(),
+            path: None,
+            max_cpu_secs: None,
+            max_memory_mb: None,
+            deny_network: false,
+        }
+    }
+}
+
+/// One `[[policy.time_windows]]` entry:
+///
+/// ```toml
+/// [[policy.time_windows]]
+/// actions = ["shell.run"]
+/// start = "23:00"
+/// end = "07:00"
+/// ```
+///
+/// Matches actions by name or risk level (either list may be empty, not both). Inside the
+/// window they are refused, or need `approvals` distinct approvers when it is set. A window
+/// whose end is before its start runs past midnight; `days` name the day it starts on.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TimeWindowConfig {
+    #[serde(default)]
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub risk_levels: Vec<String>,
+    pub start: String,
+    pub end: String,
+    /// `mon` .. `sun`; empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub approvals: Option<u32>,
+    /// Dry runs are exempt unless this is set.
+    #[serde(default)]
+    pub include_dry_run: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimeWindow {
+    pub actions: Vec<String>,
+    pub risk_levels: Vec<RiskLevel>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub days: Vec<Weekday>,
+    /// `None` refuses matching actions outright.
+    pub approvals: Option<u32>,
+    pub include_dry_run: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct Policy {
     pub allowed_roots: Vec<PathBuf>,
+    /// Never readable or writable by actions, even inside an allowed root. Checked by each
+    /// action's own path validation, so it holds with preflight disabled.
+    pub denied_roots: Vec<PathBuf>,
     pub approval_risk_levels: Vec<RiskLevel>,
+    /// Capabilities an action may declare (`filesystem`, `network`, `shell`, `process`,
+    /// `browser`, ...); `None` allows all. Checked for every action, even with preflight off.
+    pub allowed_capabilities: Option<Vec<String>>,
+    /// Wins over `allowed_capabilities`.
+    pub denied_capabilities: Vec<String>,
+    /// Hours when matching actions are refused or need extra approvers.
+    pub time_windows: Vec<TimeWindow>,
+    /// Only low-risk actions without shell or process capabilities run, approved or not.
+    /// Toggled at runtime with `read-only on|off`.
+    pub read_only: bool,
+    pub shell: ShellSandbox,
+    pub roles: HashMap<String, Role>,
+    /// For senders not in `roles`; admin unless configured, so unconfigured setups are unchanged.
+    pub default_role: Role,
+    /// Largest file `fs.read_file` reads; `None` for no limit.
+    pub max_read_bytes: Option<u64>,
+    /// Largest `content` `fs.write_file` writes.
+    pub max_write_bytes: Option<u64>,
+    /// Largest response body `web.fetch_url` downloads.
+    pub max_fetch_bytes: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -23,21 +165,134 @@ pub struct ActionContext {
     pub cwd: PathBuf,
     pub dry_run: bool,
     pub policy: Policy,
+    /// Where file actions copy what they overwrite, so `undo` can restore it. Without one,
+    /// overwrites cannot be undone.
+    pub backup_dir: Option<PathBuf>,
+    /// When the step's time limit runs out. Actions that start processes stop them then.
+    pub deadline: Option<Instant>,
+    /// The preflight settings when preflight is on. `web.fetch_url` applies their domain and
+    /// scheme lists to every redirect, which preflight never sees.
+    pub preflight: Option<Arc<PreflightConfig>>,
 }
 
 impl Policy {
     pub fn default_with_home() -> Self {
         let mut roots = Vec::new();
+        let mut denied = Vec::new();
         if let Ok(cwd) = env::current_dir() {
             roots.push(cwd);
         }
         if let Ok(home) = env::var("HOME") {
-            roots.push(PathBuf::from(home));
+            let home = PathBuf::from(home);
+            for dir in [".ssh", ".aws", ".gnupg", ".robit"] {
+                denied.push(home.join(dir));
+            }
+            roots.push(home);
+        }
+        if let Some(paths) = RobitPaths::from_env() {
+            denied.push(paths.config_dir);
+            denied.push(paths.data_dir);
         }
         Self {
             allowed_roots: roots,
+            denied_roots: denied,
             approval_risk_levels: vec![RiskLevel::Medium, RiskLevel::High],
+            allowed_capabilities: None,
+            denied_capabilities: Vec::new(),
+            time_windows: Vec::new(),
+            read_only: false,
+            shell: ShellSandbox::default(),
+            roles: HashMap::new(),
+            default_role: Role::Admin,
+            max_read_bytes: Some(DEFAULT_MAX_BYTES),
+            max_write_bytes: Some(DEFAULT_MAX_BYTES),
+            max_fetch_bytes: Some(DEFAULT_MAX_BYTES),
+        }
+    }
+
+    /// Fails when `spec` declares a denied capability or one outside the allowed set.
+    pub fn check_capabilities(&self, spec: &ActionSpec) -> Result<()> {
+        for cap in &spec.capabilities {
+            if self
+                .denied_capabilities
+                .iter()
+                .any(|denied| denied.eq_ignore_ascii_case(cap))
+            {
+                return Err(anyhow!("{}: capability {cap} is denied by policy", spec.name));
+            }
+            let allowed = self.allowed_capabilities.as_ref();
+            if allowed.is_some_and(|allowed| !allowed.iter().any(|ok| ok.eq_ignore_ascii_case(cap)))
+            {
+                return Err(anyhow!("{}: capability {cap} is not allowed by policy", spec.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn role_for(&self, sender: &str) -> Role {
+        self.roles.get(sender).copied().unwrap_or(self.default_role)
+    }
+
+    /// Fails when `bytes` of `what` is over `limit`, one of the `max_*_bytes` fields named
+    /// `setting`.
+    pub fn check_size(&self, limit: Option<u64>, setting: &str, what: &str, bytes: u64) -> Result<()> {
+        match limit {
+            Some(limit) if bytes > limit => Err(anyhow!(
+                "{what} is {}, over the {setting} limit of {}",
+                format_bytes(bytes),
+                format_bytes(limit)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails in read-only mode for anything but low-risk actions that only read.
+    pub fn check_read_only(&self, spec: &ActionSpec) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
         }
+        let runs_processes = spec
+            .capabilities
+            .iter()
+            .any(|cap| matches!(cap.as_str(), "shell" | "process" | "system_control"));
+        if spec.risk != RiskLevel::Low || runs_processes {
+            return Err(anyhow!("{}: read-only mode is on", spec.name));
+        }
+        Ok(())
+    }
+
+    /// Fails when a refusing time window is open for `spec`.
+    pub fn check_time_window(&self, spec: &ActionSpec, dry_run: bool) -> Result<()> {
+        let now = Local::now().naive_local();
+        match self.open_windows(spec, dry_run, now).find(|window| window.approvals.is_none()) {
+            Some(window) => Err(anyhow!(
+                "{} is not allowed between {} and {} by policy",
+                spec.name,
+                window.start.format("%H:%M"),
+                window.end.format("%H:%M")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Approvers the open time windows ask for `spec`; 0 when none applies.
+    pub fn window_approvals(&self, spec: &ActionSpec, dry_run: bool) -> usize {
+        let now = Local::now().naive_local();
+        self.open_windows(spec, dry_run, now)
+            .filter_map(|window| window.approvals)
+            .max()
+            .map_or(0, |approvals| approvals.max(1) as usize)
+    }
+
+    fn open_windows<'a>(
+        &'a self,
+        spec: &'a ActionSpec,
+        dry_run: bool,
+        now: NaiveDateTime,
+    ) -> impl Iterator<Item = &'a TimeWindow> {
+        self.time_windows
+            .iter()
+            .filter(move |window| window.matches(spec, dry_run) && window.is_open(now))
     }
 
     pub fn requires_approval(&self, risk: RiskLevel, explicit: bool) -> bool {
@@ -47,6 +302,18 @@ impl Policy {
         self.approval_risk_levels.iter().any(|level| *level == risk)
     }
 
+    /// The denied root `path` is under, if any.
+    pub fn denied_root(&self, path: &Path) -> Option<&Path> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        // Resolve through the nearest existing parent too, so `..` or a symlinked directory
+        // cannot reach a denied root by a path that does not exist yet.
+        let resolved = resolve_existing_prefix(&canonical);
+        self.denied_roots
+            .iter()
+            .find(|root| canonical.starts_with(root) || resolved.starts_with(resolve_existing_prefix(root)))
+            .map(PathBuf::as_path)
+    }
+
     pub fn check_path_allowed(&self, path: &Path) -> Result<()> {
         let canonical = if path.exists() {
             path.canonicalize()
@@ -55,6 +322,10 @@ impl Policy {
             path.to_path_buf()
         };
 
+        if self.denied_root(&canonical).is_some() {
+            return Err(anyhow!("path denied by policy: {}", canonical.display()));
+        }
+
         for root in &self.allowed_roots {
             let root_canonical = if root.exists() {
                 root.canonicalize()
@@ -78,6 +349,11 @@ impl Policy {
         if let Some(roots) = config.allowed_roots {
             policy.allowed_roots = roots.into_iter().map(|root| expand_tilde(&root)).collect();
         }
+        if let Some(roots) = config.denied_roots {
+            policy
+                .denied_roots
+                .extend(roots.into_iter().map(|root| expand_tilde(&root)));
+        }
         if let Some(levels) = config.approval_risk_levels {
             let mut parsed = Vec::new();
             for level in levels {
@@ -85,11 +361,131 @@ impl Policy {
             }
             policy.approval_risk_levels = parsed;
         }
+        if let Some(caps) = config.allowed_capabilities {
+            policy.allowed_capabilities = Some(caps);
+        }
+        if let Some(caps) = config.denied_capabilities {
+            policy.denied_capabilities = caps;
+        }
+        if let Some(read_only) = config.read_only {
+            policy.read_only = read_only;
+        }
+        if let Some(shell) = config.shell {
+            policy.shell = shell;
+        }
+        if let Some(roles) = config.roles {
+            policy.roles = roles;
+        }
+        if let Some(role) = config.default_role {
+            policy.default_role = role;
+        }
+        let limit = |bytes: u64| (bytes > 0).then_some(bytes);
+        if let Some(bytes) = config.max_read_bytes {
+            policy.max_read_bytes = limit(bytes);
+        }
+        if let Some(bytes) = config.max_write_bytes {
+            policy.max_write_bytes = limit(bytes);
+        }
+        if let Some(bytes) = config.max_fetch_bytes {
+            policy.max_fetch_bytes = limit(bytes);
+        }
+        if let Some(windows) = config.time_windows {
+            let mut parsed = Vec::new();
+            for window in windows {
+                parsed.push(TimeWindow::from_config(window)?);
+            }
+            policy.time_windows = parsed;
+        }
         Ok(policy)
     }
 }
 
-fn parse_risk_level(raw: &str) -> Result<RiskLevel> {
+impl TimeWindow {
+    pub(crate) fn from_config(config: TimeWindowConfig) -> Result<Self> {
+        if config.actions.is_empty() && config.risk_levels.is_empty() {
+            return Err(anyhow!("time window needs actions or risk_levels"));
+        }
+        let parse_time = |raw: &str| {
+            NaiveTime::parse_from_str(raw.trim(), "%H:%M")
+                .map_err(|_| anyhow!("time window: expected HH:MM, got {raw:?}"))
+        };
+        let mut risk_levels = Vec::new();
+        for level in &config.risk_levels {
+            risk_levels.push(parse_risk_level(level)?);
+        }
+        let mut days = Vec::new();
+        for day in &config.days {
+            days.push(
+                day.trim()
+                    .parse::<Weekday>()
+                    .map_err(|_| anyhow!("time window: unknown day {day:?}"))?,
+            );
+        }
+        Ok(Self {
+            actions: config.actions,
+            risk_levels,
+            start: parse_time(&config.start)?,
+            end: parse_time(&config.end)?,
+            days,
+            approvals: config.approvals,
+            include_dry_run: config.include_dry_run,
+        })
+    }
+
+    fn matches(&self, spec: &ActionSpec, dry_run: bool) -> bool {
+        if dry_run && !self.include_dry_run {
+            return false;
+        }
+        self.actions.contains(&spec.name) || self.risk_levels.contains(&spec.risk)
+    }
+
+    fn is_open(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+        let mut day = now.weekday();
+        let open = if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else if time >= self.start {
+            true
+        } else if time < self.end {
+            // Past midnight: the window belongs to the day before.
+            day = day.pred();
+            true
+        } else {
+            false
+        };
+        open && (self.days.is_empty() || self.days.contains(&day))
+    }
+}
+
+/// `path` with its longest existing prefix canonicalized and the rest appended as written,
+/// minus `.` and `..` components.
+fn resolve_existing_prefix(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            let mut resolved = canonical;
+            for part in rest.iter().rev() {
+                match part {
+                    Component::ParentDir => {
+                        resolved.pop();
+                    }
+                    Component::CurDir => {}
+                    other => resolved.push(other),
+                }
+            }
+            return resolved;
+        }
+        let (Some(parent), Some(last)) = (existing.parent(), existing.components().next_back())
+        else {
+            return path.to_path_buf();
+        };
+        rest.push(last);
+        existing = parent;
+    }
+}
+
+pub(crate) fn parse_risk_level(raw: &str) -> Result<RiskLevel> {
     match raw.trim().to_lowercase().as_str() {
         "low" => Ok(RiskLevel::Low),
         "medium" => Ok(RiskLevel::Medium),
@@ -1,13 +1,15 @@
 use std::collections::HashSet;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::policy::ActionContext;
 use crate::types::{ActionSpec, RiskLevel};
-use crate::utils::{clean_path, expand_tilde};
+use crate::utils::{clean_path, expand_tilde, format_bytes};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PreflightConfig {
@@ -18,6 +20,74 @@ pub struct PreflightConfig {
     pub blocked_roots: Vec<PathBuf>,
     pub enforce_policy_roots: bool,
     pub path_keys: Vec<String>,
+    /// Hosts that actions with `network` or `browser` capability may reach; a domain covers
+    /// its subdomains. Empty allows any host.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Wins over `allowed_domains`.
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    /// URL schemes those actions may use, e.g. `https`. Empty allows any scheme.
+    #[serde(default)]
+    pub allowed_schemes: Vec<String>,
+    #[serde(default)]
+    pub denied_schemes: Vec<String>,
+    /// Params holding URLs.
+    #[serde(default = "default_url_keys")]
+    pub url_keys: Vec<String>,
+    /// Checks whose findings only warn: `denied_capabilities`, `blocked_roots`,
+    /// `allowed_domains` and the other keys above, or the name of a custom check. A warning
+    /// does not block; it is shown in the approval prompt and the action always asks for
+    /// approval. `[policy]` findings, including `enforce_policy_roots`, always block.
+    #[serde(default)]
+    pub warn_checks: Vec<String>,
+    /// Any preflight finding for an action with one of these capabilities only warns.
+    #[serde(default)]
+    pub warn_capabilities: Vec<String>,
+}
+
+impl PreflightConfig {
+    fn url_findings(&self, url: &str) -> Vec<PreflightFinding> {
+        let config = self;
+        let (scheme, host) = split_url(url);
+        let shown = if host.is_empty() { url } else { host.as_str() };
+        let listed = |list: &[String], value: &str| list.iter().any(|item| item.eq_ignore_ascii_case(value));
+        let mut findings = Vec::new();
+        if listed(&config.denied_schemes, &scheme) {
+            findings.push(PreflightFinding::preflight(
+                format!("scheme denied: {url}"),
+                "denied_schemes",
+            ));
+        } else if !config.allowed_schemes.is_empty() && !listed(&config.allowed_schemes, &scheme) {
+            findings.push(PreflightFinding::preflight(
+                format!("scheme not allowed: {url}"),
+                "allowed_schemes",
+            ));
+        }
+        if config.denied_domains.iter().any(|domain| host_matches(&host, domain)) {
+            findings.push(PreflightFinding::preflight(
+                format!("domain denied: {shown}"),
+                "denied_domains",
+            ));
+        } else if !config.allowed_domains.is_empty()
+            && !config.allowed_domains.iter().any(|domain| host_matches(&host, domain))
+        {
+            findings.push(PreflightFinding::preflight(
+                format!("domain not allowed: {shown}"),
+                "allowed_domains",
+            ));
+        }
+        findings
+    }
+
+    /// Why the domain and scheme lists refuse `url`, e.g. a redirect target, if they do.
+    pub fn url_refusal(&self, url: &str) -> Option<String> {
+        self.url_findings(url).into_iter().next().map(|finding| finding.reason)
+    }
+}
+
+fn default_url_keys() -> Vec<String> {
+    vec!["url".to_string(), "urls".to_string()]
 }
 
 impl Default for PreflightConfig {
@@ -40,7 +110,19 @@ impl Default for PreflightConfig {
                 "dst".to_string(),
                 "source".to_string(),
                 "destination".to_string(),
+                "from".to_string(),
+                "to".to_string(),
+                "paths".to_string(),
+                "remove_dirs".to_string(),
+                "backup".to_string(),
             ],
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+            allowed_schemes: Vec::new(),
+            denied_schemes: Vec::new(),
+            url_keys: default_url_keys(),
+            warn_checks: Vec::new(),
+            warn_capabilities: Vec::new(),
         }
     }
 }
@@ -51,15 +133,101 @@ pub struct PreflightReport {
     pub risk: RiskLevel,
     pub requires_approval: bool,
     pub allowed: bool,
+    /// Findings that block the action.
     pub reasons: Vec<String>,
+    /// Findings that only warn; approving the action overrides them.
+    #[serde(default)]
+    pub warnings: Vec<String>,
     pub capabilities: Vec<String>,
     pub paths: Vec<String>,
+    /// Bytes the action will read or write: the `content` param, else the size of the files
+    /// it names.
+    #[serde(default)]
+    pub expected_bytes: Option<u64>,
+    /// The rules behind `reasons`, for `explain`.
+    #[serde(default)]
+    pub findings: Vec<PreflightFinding>,
+    /// Filled in by the engine from `ActionHandler::preview` once the action validates.
+    #[serde(default)]
+    pub change: Option<ChangePreview>,
+}
+
+/// What an action would change, e.g. a file's line diff.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChangePreview {
+    /// E.g. `+3 -1 lines` or `2 replacements`.
+    pub summary: String,
+    /// `+` / `-` lines with some context; empty when there is nothing to show.
+    #[serde(default)]
+    pub diff: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Shown in the approval prompt; approval overrides it.
+    Warn,
+    /// Stops the action in strict mode.
+    #[default]
+    Block,
+}
+
+/// The rule behind a finding, which decides whether `warn_checks` and `warn_capabilities` apply.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "name")]
+pub enum CheckId {
+    /// A `[preflight]` list, by its key, e.g. `blocked_roots`.
+    Preflight(String),
+    /// A custom `PreflightCheck`, by its name.
+    Custom(String),
+    /// A `[policy]` rule, including the policy roots preflight enforces. These always block.
+    #[default]
+    Policy,
+}
+
+/// A rule that flagged an action and the config key that controls it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreflightFinding {
+    pub reason: String,
+    /// E.g. `[preflight] denied_capabilities`.
+    pub config_key: String,
+    #[serde(default)]
+    pub check: CheckId,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+impl PreflightFinding {
+    fn new(reason: impl Into<String>, config_key: &str, check: CheckId) -> Self {
+        Self {
+            reason: reason.into(),
+            config_key: config_key.to_string(),
+            check,
+            severity: Severity::Block,
+        }
+    }
+
+    fn preflight(reason: impl Into<String>, key: &str) -> Self {
+        Self::new(reason, &format!("[preflight] {key}"), CheckId::Preflight(key.to_string()))
+    }
+
+    fn policy(reason: impl Into<String>, config_key: &str) -> Self {
+        Self::new(reason, config_key, CheckId::Policy)
+    }
 }
 
 impl PreflightReport {
     pub fn summary(&self) -> String {
         if self.allowed {
-            "ok".to_string()
+            let mut text = match self.expected_bytes {
+                Some(bytes) => format!("ok ({})", format_bytes(bytes)),
+                None => "ok".to_string(),
+            };
+            if !self.warnings.is_empty() {
+                let label = if self.warnings.len() == 1 { "warning" } else { "warnings" };
+                text.push_str(&format!("; {label}: {}", self.warnings.join("; ")));
+            }
+            text
         } else if self.reasons.is_empty() {
             "blocked".to_string()
         } else {
@@ -68,14 +236,50 @@ impl PreflightReport {
     }
 }
 
-#[derive(Clone, Debug)]
+/// An organization's own preflight rule, e.g. "writes under /srv/prod need a ticket number".
+/// Runs after the built-in checks whenever preflight is enabled; an error becomes a finding
+/// of the check's severity.
+pub trait PreflightCheck: Send + Sync {
+    /// Shown by `explain` as the source of the finding.
+    fn name(&self) -> &str;
+    fn check(&self, spec: &ActionSpec, params: &Value, ctx: &ActionContext) -> Result<()>;
+    /// `warn_checks` and `warn_capabilities` can still turn a blocking check into a warning.
+    fn severity(&self) -> Severity {
+        Severity::Block
+    }
+}
+
+#[derive(Clone)]
 pub struct PreflightEngine {
     config: PreflightConfig,
+    checks: Vec<Arc<dyn PreflightCheck>>,
+}
+
+impl fmt::Debug for PreflightEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checks: Vec<&str> = self.checks.iter().map(|check| check.name()).collect();
+        f.debug_struct("PreflightEngine")
+            .field("config", &self.config)
+            .field("checks", &checks)
+            .finish()
+    }
 }
 
 impl PreflightEngine {
     pub fn new(config: PreflightConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            checks: Vec::new(),
+        }
+    }
+
+    /// Adds a check run after the built-in ones, in registration order.
+    pub fn register_check<C: PreflightCheck + 'static>(&mut self, check: C) {
+        self.checks.push(Arc::new(check));
+    }
+
+    pub(crate) fn register_boxed_check(&mut self, check: Box<dyn PreflightCheck>) {
+        self.checks.push(Arc::from(check));
     }
 
     pub fn config(&self) -> &PreflightConfig {
@@ -92,6 +296,9 @@ impl PreflightEngine {
         params: &Value,
         ctx: &ActionContext,
     ) -> Result<PreflightReport> {
+        if let Some(refusal) = policy_findings(spec, ctx).into_iter().next() {
+            return Err(anyhow!(refusal.reason));
+        }
         if !self.config.enabled {
             return Ok(PreflightReport {
                 action: spec.name.clone(),
@@ -99,8 +306,12 @@ impl PreflightEngine {
                 requires_approval: spec.requires_approval,
                 allowed: true,
                 reasons: Vec::new(),
+                warnings: Vec::new(),
                 capabilities: spec.capabilities.clone(),
                 paths: Vec::new(),
+                expected_bytes: None,
+                findings: Vec::new(),
+                change: None,
             });
         }
 
@@ -117,42 +328,84 @@ impl PreflightEngine {
             .map(|cap| cap.to_lowercase())
             .collect();
 
-        let mut reasons = Vec::new();
+        let mut findings = Vec::new();
 
         for cap in &spec.capabilities {
             let cap_norm = cap.to_lowercase();
             if denied_set.contains(&cap_norm) {
-                reasons.push(format!("capability denied: {cap}"));
+                findings.push(PreflightFinding::preflight(
+                    format!("capability denied: {cap}"),
+                    "denied_capabilities",
+                ));
             }
             if !allowed_set.is_empty() && !allowed_set.contains(&cap_norm) {
-                reasons.push(format!("capability not allowed: {cap}"));
+                findings.push(PreflightFinding::preflight(
+                    format!("capability not allowed: {cap}"),
+                    "allowed_capabilities",
+                ));
             }
         }
 
         let paths = collect_paths(params, &self.config.path_keys);
         let mut normalized_paths = Vec::new();
         for raw in &paths {
-            let expanded = expand_tilde(raw);
-            let normalized = clean_path(&expanded);
+            let normalized = clean_path(&ctx.cwd.join(expand_tilde(raw)));
             normalized_paths.push(normalized.clone());
 
             for blocked in &self.config.blocked_roots {
                 let blocked_norm = clean_path(&expand_tilde(&blocked.to_string_lossy()));
                 if is_under(&normalized, &blocked_norm) {
-                    reasons.push(format!(
-                        "path blocked by policy: {}",
-                        normalized.display()
+                    findings.push(PreflightFinding::preflight(
+                        format!("path blocked by policy: {}", normalized.display()),
+                        "blocked_roots",
                     ));
                 }
             }
 
-            if self.config.enforce_policy_roots {
-                if let Err(err) = ctx.policy.check_path_allowed(&normalized) {
-                    reasons.push(format!("path not allowed: {}", err));
-                }
+            // Backups robit wrote itself may sit outside the allowed roots.
+            let in_backups = ctx.backup_dir.as_ref().is_some_and(|dir| is_under(&normalized, dir));
+            if self.config.enforce_policy_roots
+                && !in_backups
+                && let Err(err) = ctx.policy.check_path_allowed(&normalized)
+            {
+                let key = if ctx.policy.denied_root(&normalized).is_some() {
+                    "[policy] denied_roots"
+                } else {
+                    "[policy] allowed_roots"
+                };
+                findings.push(PreflightFinding::policy(format!("path not allowed: {}", err), key));
+            }
+        }
+
+        let reaches_network = spec
+            .capabilities
+            .iter()
+            .any(|cap| cap.eq_ignore_ascii_case("network") || cap.eq_ignore_ascii_case("browser"));
+        if reaches_network {
+            for url in collect_paths(params, &self.config.url_keys) {
+                findings.extend(self.config.url_findings(&url));
+            }
+        }
+
+        for check in &self.checks {
+            if let Err(err) = check.check(spec, params, ctx) {
+                let mut finding = PreflightFinding::new(
+                    format!("{}: {err}", check.name()),
+                    &format!("preflight check {}", check.name()),
+                    CheckId::Custom(check.name().to_string()),
+                );
+                finding.severity = check.severity();
+                findings.push(finding);
+            }
+        }
+        for finding in &mut findings {
+            if self.only_warns(finding, spec) {
+                finding.severity = Severity::Warn;
             }
         }
 
+        let expected_bytes = expected_bytes(params, &normalized_paths);
+        let (reasons, warnings) = split_reasons(&findings);
         let allowed = reasons.is_empty();
         let report = PreflightReport {
             action: spec.name.clone(),
@@ -160,15 +413,134 @@ impl PreflightEngine {
             requires_approval: spec.requires_approval,
             allowed,
             reasons,
+            warnings,
             capabilities: spec.capabilities.clone(),
             paths: normalized_paths
                 .iter()
                 .map(|path| path.to_string_lossy().to_string())
                 .collect(),
+            expected_bytes,
+            findings,
+            change: None,
         };
 
         Ok(report)
     }
+
+    fn only_warns(&self, finding: &PreflightFinding, spec: &ActionSpec) -> bool {
+        let check = match &finding.check {
+            CheckId::Preflight(name) | CheckId::Custom(name) => name,
+            CheckId::Policy => return false,
+        };
+        let listed = |list: &[String], value: &str| list.iter().any(|item| item.eq_ignore_ascii_case(value));
+        listed(&self.config.warn_checks, check)
+            || spec
+                .capabilities
+                .iter()
+                .any(|cap| listed(&self.config.warn_capabilities, cap))
+    }
+
+    /// The report for an action `check` refused outright, naming the policy rules that did.
+    pub fn refusal(&self, spec: &ActionSpec, ctx: &ActionContext) -> PreflightReport {
+        let findings = policy_findings(spec, ctx);
+        PreflightReport {
+            action: spec.name.clone(),
+            risk: spec.risk,
+            requires_approval: spec.requires_approval,
+            allowed: findings.is_empty(),
+            reasons: findings.iter().map(|finding| finding.reason.clone()).collect(),
+            warnings: Vec::new(),
+            capabilities: spec.capabilities.clone(),
+            paths: Vec::new(),
+            expected_bytes: None,
+            findings,
+            change: None,
+        }
+    }
+}
+
+/// `[policy]` rules that refuse `spec` whatever the preflight settings.
+fn policy_findings(spec: &ActionSpec, ctx: &ActionContext) -> Vec<PreflightFinding> {
+    let policy = &ctx.policy;
+    let mut findings = Vec::new();
+    if let Err(err) = policy.check_capabilities(spec) {
+        let denied = spec.capabilities.iter().any(|cap| {
+            policy
+                .denied_capabilities
+                .iter()
+                .any(|denied| denied.eq_ignore_ascii_case(cap))
+        });
+        let key = if denied {
+            "[policy] denied_capabilities"
+        } else {
+            "[policy] allowed_capabilities"
+        };
+        findings.push(PreflightFinding::policy(err.to_string(), key));
+    }
+    if let Err(err) = policy.check_read_only(spec) {
+        findings.push(PreflightFinding::policy(
+            err.to_string(),
+            "[policy] read_only, or `read-only off`",
+        ));
+    }
+    if let Err(err) = policy.check_time_window(spec, ctx.dry_run) {
+        findings.push(PreflightFinding::policy(err.to_string(), "[[policy.time_windows]]"));
+    }
+    findings
+}
+
+/// Reasons of the blocking findings, then of the warnings.
+fn split_reasons(findings: &[PreflightFinding]) -> (Vec<String>, Vec<String>) {
+    let (blocking, warning): (Vec<&PreflightFinding>, Vec<&PreflightFinding>) = findings
+        .iter()
+        .partition(|finding| finding.severity == Severity::Block);
+    let reasons = |findings: Vec<&PreflightFinding>| {
+        findings.into_iter().map(|finding| finding.reason.clone()).collect()
+    };
+    (reasons(blocking), reasons(warning))
+}
+
+fn expected_bytes(params: &Value, paths: &[PathBuf]) -> Option<u64> {
+    if let Some(content) = params.get("content").and_then(Value::as_str) {
+        return Some(content.len() as u64);
+    }
+    let sizes: Vec<u64> = paths
+        .iter()
+        .filter_map(|path| path.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .collect();
+    (!sizes.is_empty()).then(|| sizes.iter().sum())
+}
+
+/// Lowercased scheme and host of `url`; either is empty when missing.
+fn split_url(url: &str) -> (String, String) {
+    let url = url.trim();
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest),
+        None => match url.split_once(':') {
+            // `mailto:`, `file:`, `javascript:` ... but not `host:8080/path`.
+            Some((scheme, rest)) if !rest.starts_with(|ch: char| ch.is_ascii_digit()) => (scheme, ""),
+            _ => ("", url),
+        },
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host_port.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(""),
+        None => host_port.split(':').next().unwrap_or(""),
+    };
+    (
+        scheme.to_ascii_lowercase(),
+        host.trim_end_matches('.').to_ascii_lowercase(),
+    )
+}
+
+/// `domain` itself or one of its subdomains; a leading `*.` or `.` is ignored.
+fn host_matches(host: &str, domain: &str) -> bool {
+    let domain = domain.trim().trim_start_matches("*.").trim_start_matches('.').to_ascii_lowercase();
+    !domain.is_empty()
+        && (host == domain || host.strip_suffix(&domain).is_some_and(|rest| rest.ends_with('.')))
 }
 
 fn collect_paths(value: &Value, path_keys: &[String]) -> Vec<String> {
@@ -1,13 +1,31 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use uuid::Uuid;
 
+use crate::ai::{AiChatMessage, AiChatRole};
+use crate::error::RobitError;
+use crate::format::ReplyFormat;
+use crate::history::ActionRecord;
+use crate::i18n::Locale;
 use crate::types::{ActionSpec, RiskLevel};
+use crate::utils::guess_mime;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProtocolEvent {
     pub schema_version: String,
+    /// `evt-` and a UUIDv7, so ids sort by creation time.
     pub id: String,
+    /// RFC 3339, set when the event is created.
     pub timestamp: Option<String>,
+    /// The `id` of the event this one answers; set on every reply the engine sends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
     #[serde(flatten)]
     pub body: ProtocolBody,
 }
@@ -15,26 +33,147 @@ pub struct ProtocolEvent {
 impl ProtocolEvent {
     pub fn new(body: ProtocolBody) -> Self {
         Self {
-            schema_version: "robit.v1".to_string(),
-            id: format!("evt-{}", uuid()),
-            timestamp: None,
+            schema_version: SCHEMA_VERSION.to_string(),
+            id: format!("evt-{}", Uuid::now_v7()),
+            timestamp: Some(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+            in_reply_to: None,
             body,
         }
     }
+
+    /// A new event answering `self`.
+    pub fn reply(&self, body: ProtocolBody) -> Self {
+        let mut event = Self::new(body);
+        event.in_reply_to = Some(self.id.clone());
+        event
+    }
+
+    /// The id this event answers: the envelope's `in_reply_to`, else the payload's for
+    /// `Response`, `Error`, `Pong` and `Capabilities`.
+    pub fn correlation_id(&self) -> Option<&str> {
+        if let Some(id) = &self.in_reply_to {
+            return Some(id);
+        }
+        match &self.body {
+            ProtocolBody::Response(payload) => Some(&payload.in_reply_to),
+            ProtocolBody::Error(payload) => Some(&payload.in_reply_to),
+            ProtocolBody::Pong(payload) => Some(&payload.in_reply_to),
+            ProtocolBody::Capabilities(payload) => Some(&payload.in_reply_to),
+            _ => None,
+        }
+    }
+
+    pub fn is_reply_to(&self, request: &ProtocolEvent) -> bool {
+        self.correlation_id() == Some(request.id.as_str())
+    }
 }
 
+/// The schema version events are sent with.
+pub const SCHEMA_VERSION: &str = "robit.v1";
+/// Every version the engine reads, newest first.
+pub const SCHEMA_VERSIONS: &[&str] = &[SCHEMA_VERSION];
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum ProtocolBody {
     Message(MessagePayload),
     Response(ResponsePayload),
+    /// A reply that reports a failure, in place of a `Response` with kind `error`.
+    Error(ErrorPayload),
     ConfigUpdate(ConfigUpdatePayload),
     RoomScope(RoomScopePayload),
     ActionListRequest(ActionListRequestPayload),
     ActionListResult(ActionListResultPayload),
     ApprovalDecision(ApprovalDecisionPayload),
+    UsageRequest(UsageRequestPayload),
+    UsageResult(UsageResultPayload),
+    HistoryRequest(HistoryRequestPayload),
+    HistoryResult(HistoryResultPayload),
     Ping(PingPayload),
     Pong(PongPayload),
+    /// Sent by a client to learn what the engine supports; answered with `Capabilities`.
+    Hello(HelloPayload),
+    Capabilities(CapabilitiesPayload),
+    StatusRequest(StatusRequestPayload),
+    StatusResult(StatusResultPayload),
+    ConfigQuery(ConfigQueryPayload),
+    ConfigResult(ConfigResultPayload),
+    /// Asks for a room's conversation as a `ConversationDocument`, sent back as `ExportResult`.
+    ExportRequest(ExportRequestPayload),
+    ExportResult(ConversationDocument),
+    ImportRequest(ImportRequestPayload),
+    ImportResult(ImportResultPayload),
+    /// Searches stored conversation history; answered with `SearchResult`.
+    SearchRequest(SearchRequestPayload),
+    SearchResult(SearchResultPayload),
+    /// Sent from `tick_protocol` after the engine re-read its changed config files.
+    ConfigReloaded(ConfigReloadedPayload),
+}
+
+impl ProtocolBody {
+    /// Every event type, as tagged in `type`.
+    pub const EVENT_TYPES: &'static [&'static str] = &[
+        "Message",
+        "Response",
+        "Error",
+        "ConfigUpdate",
+        "RoomScope",
+        "ActionListRequest",
+        "ActionListResult",
+        "ApprovalDecision",
+        "UsageRequest",
+        "UsageResult",
+        "HistoryRequest",
+        "HistoryResult",
+        "Ping",
+        "Pong",
+        "Hello",
+        "Capabilities",
+        "StatusRequest",
+        "StatusResult",
+        "ConfigQuery",
+        "ConfigResult",
+        "ExportRequest",
+        "ExportResult",
+        "ImportRequest",
+        "ImportResult",
+        "SearchRequest",
+        "SearchResult",
+        "ConfigReloaded",
+    ];
+
+    /// The `type` tag of this event.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::Message(_) => "Message",
+            Self::Response(_) => "Response",
+            Self::Error(_) => "Error",
+            Self::ConfigUpdate(_) => "ConfigUpdate",
+            Self::RoomScope(_) => "RoomScope",
+            Self::ActionListRequest(_) => "ActionListRequest",
+            Self::ActionListResult(_) => "ActionListResult",
+            Self::ApprovalDecision(_) => "ApprovalDecision",
+            Self::UsageRequest(_) => "UsageRequest",
+            Self::UsageResult(_) => "UsageResult",
+            Self::HistoryRequest(_) => "HistoryRequest",
+            Self::HistoryResult(_) => "HistoryResult",
+            Self::Ping(_) => "Ping",
+            Self::Pong(_) => "Pong",
+            Self::Hello(_) => "Hello",
+            Self::Capabilities(_) => "Capabilities",
+            Self::StatusRequest(_) => "StatusRequest",
+            Self::StatusResult(_) => "StatusResult",
+            Self::ConfigQuery(_) => "ConfigQuery",
+            Self::ConfigResult(_) => "ConfigResult",
+            Self::ExportRequest(_) => "ExportRequest",
+            Self::ExportResult(_) => "ExportResult",
+            Self::ImportRequest(_) => "ImportRequest",
+            Self::ImportResult(_) => "ImportResult",
+            Self::SearchRequest(_) => "SearchRequest",
+            Self::SearchResult(_) => "SearchResult",
+            Self::ConfigReloaded(_) => "ConfigReloaded",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -48,6 +187,8 @@ pub struct MessagePayload {
     pub event_kind: Option<String>,
     #[serde(default)]
     pub metadata: Value,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -59,6 +200,78 @@ pub struct ResponsePayload {
     pub text: String,
     #[serde(default)]
     pub metadata: Value,
+    /// Files the reply hands back, e.g. ones an action generated.
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A file sent with a message or reply: a `path` both sides can read, the content inline as
+/// base64 `data`, or both.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub mime: Option<String>,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+impl Attachment {
+    /// Describes the file at `path`, with its content inline when it is at most
+    /// `inline_limit` bytes.
+    pub fn from_path(path: &Path, inline_limit: u64) -> Result<Self> {
+        let size = fs::metadata(path)
+            .with_context(|| format!("attachment {}", path.display()))?
+            .len();
+        let data = if size <= inline_limit {
+            Some(BASE64.encode(fs::read(path)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path: Some(path.to_string_lossy().to_string()),
+            mime: Some(guess_mime(path).to_string()),
+            size: Some(size),
+            data,
+        })
+    }
+
+    /// The decoded inline content, if any.
+    pub fn bytes(&self) -> Result<Option<Vec<u8>>> {
+        let Some(data) = &self.data else {
+            return Ok(None);
+        };
+        BASE64
+            .decode(data.trim())
+            .map(Some)
+            .map_err(|err| anyhow!("attachment {}: invalid base64: {err}", self.name))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorPayload {
+    pub in_reply_to: String,
+    pub room_id: String,
+    pub workspace_id: String,
+    /// One of `RobitError`'s codes, e.g. `preflight_blocked`.
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+impl ErrorPayload {
+    pub fn error(&self) -> RobitError {
+        RobitError::from_code(&self.code, self.message.clone())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -72,14 +285,78 @@ pub struct ConfigUpdatePayload {
     pub dry_run_default: Option<bool>,
     pub locale: Option<String>,
     pub timezone: Option<String>,
+    #[serde(default)]
+    pub system_prompt_extra: Option<String>,
+    /// Ask once for the whole plan before running any step, instead of per step.
+    #[serde(default)]
+    pub plan_preview: Option<bool>,
+    /// Total execution time allowed for a plan; time spent waiting for approval is not counted.
+    #[serde(default)]
+    pub plan_deadline_secs: Option<u64>,
+    /// Only these sender ids may approve (or deny others') actions in the room.
+    #[serde(default)]
+    pub approvers: Option<Vec<String>>,
+    /// Distinct approvers a high-risk action needs; defaults to 1.
+    #[serde(default)]
+    pub high_risk_approvals: Option<u32>,
+    /// Seconds a pending approval stays valid; 0 keeps it until answered. Defaults to 30 minutes.
+    #[serde(default)]
+    pub approval_ttl_secs: Option<u64>,
+    /// Switches the engine's main AI backend to a registered model (see `model use`).
+    #[serde(default)]
+    pub ai_model: Option<String>,
+    #[serde(default)]
+    pub rate_limits: Option<RateLimits>,
+    #[serde(default)]
+    pub quotas: Option<Quotas>,
+    /// Longer replies are cut into pages; the rest is fetched with `show more <id>`.
+    #[serde(default)]
+    pub max_reply_chars: Option<usize>,
+    /// `plain`, `markdown` or `json`; see `ReplyFormat`.
+    #[serde(default)]
+    pub reply_format: Option<ReplyFormat>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ConfigScope {
     pub workspace_id: Option<String>,
     pub room_id: Option<String>,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConfigQueryPayload {
+    /// Without `room_id` the workspace's settings; without a scope the global ones.
+    #[serde(default)]
+    pub scope: Option<ConfigScope>,
+}
+
+/// The settings the engine applies in a scope: the room's over the workspace's over the
+/// global `config_update`s, with the engine's own defaults where none of them say.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigResultPayload {
+    pub scope: ConfigScope,
+    /// From `[policy] approval_risk_levels` unless a `config_update` set one.
+    pub risk_policy: RiskPolicy,
+    /// `None` allows every action not denied.
+    pub action_allowlist: Option<Vec<String>>,
+    pub action_denylist: Vec<String>,
+    pub dry_run_default: bool,
+    pub provider_binding: Option<ProviderBinding>,
+    pub system_prompt_extra: Option<String>,
+    pub plan_preview: bool,
+    pub plan_deadline_secs: Option<u64>,
+    /// `None` when approvals never expire.
+    pub approval_ttl_secs: Option<u64>,
+    /// `None` lets anyone approve.
+    pub approvers: Option<Vec<String>>,
+    pub high_risk_approvals: u32,
+    pub rate_limits: Option<RateLimits>,
+    pub quotas: Option<Quotas>,
+    pub max_reply_chars: Option<usize>,
+    pub reply_format: ReplyFormat,
+    pub locale: Locale,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ConfigMode {
@@ -89,8 +366,13 @@ pub enum ConfigMode {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProviderBinding {
+    /// Model override; empty keeps the backend's configured model.
+    #[serde(default)]
     pub model: String,
     pub temperature: Option<f64>,
+    /// Name of a backend registered with `Engine::register_ai_backend`.
+    #[serde(default)]
+    pub backend: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -99,6 +381,38 @@ pub struct RiskPolicy {
     pub approval_for: Option<Vec<RiskLevel>>,
 }
 
+/// Throttles for a room; limits left unset don't apply.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Messages one sender may send in the room per minute.
+    #[serde(default)]
+    pub sender_messages_per_minute: Option<u32>,
+    /// Messages the whole room may send per minute.
+    #[serde(default)]
+    pub room_messages_per_minute: Option<u32>,
+    /// Plans running or waiting for approval in the room at once.
+    #[serde(default)]
+    pub concurrent_plans: Option<u32>,
+    /// AI backend calls made for the room per hour.
+    #[serde(default)]
+    pub ai_calls_per_hour: Option<u32>,
+}
+
+/// Caps on what actions may do in a room, checked before each run; limits left unset don't
+/// apply. Dry runs are not counted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Quotas {
+    /// Runs of file-changing actions (filesystem capability, above low risk) per hour.
+    #[serde(default)]
+    pub writes_per_hour: Option<u32>,
+    /// Runs of actions with the `shell` capability per day.
+    #[serde(default)]
+    pub shell_runs_per_day: Option<u32>,
+    /// Bytes file actions report writing per day.
+    #[serde(default)]
+    pub bytes_written_per_day: Option<u64>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RoomScopePayload {
     pub mode: Option<ConfigMode>,
@@ -134,6 +448,54 @@ pub struct ApprovalDecisionPayload {
     pub workspace_id: String,
     pub sender_id: String,
     pub in_reply_to: String,
+    /// Why a `deny` was given; the planner uses it to propose something else.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Params patched into the request before an approval runs.
+    #[serde(default)]
+    pub params: Option<Value>,
+}
+
+/// Filters are optional; an empty request returns every room.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsageRequestPayload {
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    #[serde(default)]
+    pub room_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsageResultPayload {
+    pub rooms: Vec<RoomUsage>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RoomUsage {
+    pub workspace_id: String,
+    pub room_id: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Sum over calls whose model has a known price.
+    pub estimated_cost_usd: f64,
+}
+
+/// The room's most recent executed actions; `limit` defaults to 10.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryRequestPayload {
+    pub workspace_id: String,
+    pub room_id: String,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryResultPayload {
+    pub workspace_id: String,
+    pub room_id: String,
+    /// Newest first.
+    pub actions: Vec<ActionRecord>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -144,11 +506,176 @@ pub struct PongPayload {
     pub in_reply_to: String,
 }
 
-fn uuid() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_nanos();
-    format!("{now:x}")
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatusRequestPayload {}
+
+/// A health check of a running engine.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusResultPayload {
+    pub uptime_secs: u64,
+    /// The default AI backend's label, `custom` when it has none; `None` without a backend.
+    pub ai_backend: Option<String>,
+    /// Set when the backend was picked with `use model`.
+    pub active_model: Option<String>,
+    pub pending_approvals: usize,
+    /// Plans started and not yet finished, including ones waiting on an approval.
+    pub running_plans: usize,
+    /// Whether actions only report what they would do, unless a room says otherwise.
+    pub dry_run: bool,
+    /// Registered actions.
+    pub actions: usize,
+    /// `robit <version>`.
+    pub engine: String,
+}
+
+/// What a client supports. Empty lists mean "not said": the engine then sends everything.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HelloPayload {
+    /// E.g. `my-ui 1.2`.
+    #[serde(default)]
+    pub client: Option<String>,
+    #[serde(default)]
+    pub schema_versions: Vec<String>,
+    /// Event types the client understands. Without `Error`, failures come as a `Response`
+    /// with kind `error`.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// E.g. `attachments`; without it replies carry no attachments.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl HelloPayload {
+    pub fn accepts_event(&self, event_type: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|event| event == event_type)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.is_empty() || self.features.iter().any(|item| item == feature)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapabilitiesPayload {
+    pub in_reply_to: String,
+    /// The newest version both sides support; `None` when they share none. Events in other
+    /// versions are answered with an `Error`.
+    pub schema_version: Option<String>,
+    pub schema_versions: Vec<String>,
+    pub events: Vec<String>,
+    /// E.g. `attachments`, `inline_attachments`.
+    pub features: Vec<String>,
+    /// `robit <version>`.
+    pub engine: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigReloadedPayload {
+    /// The files read, in merge order.
+    pub paths: Vec<String>,
+    /// Why the new settings were not applied; the previous ones stay. `None` when they were.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// The `format` of documents written by this version.
+pub const CONVERSATION_FORMAT: &str = "robit.conversation.v1";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportRequestPayload {
+    pub workspace_id: String,
+    pub room_id: String,
+}
+
+/// A room's conversation and its workspace's remembered facts, for moving them to another
+/// engine. Facts carry no embeddings; the importing engine embeds them with its own model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConversationDocument {
+    pub format: String,
+    /// RFC 3339.
+    pub exported_at: String,
+    pub workspace_id: String,
+    pub room_id: String,
+    /// Oldest first.
+    pub messages: Vec<AiChatMessage>,
+    #[serde(default)]
+    pub facts: Vec<MemoryFact>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemoryFact {
+    /// Who asked for it to be remembered.
+    pub source: String,
+    pub text: String,
+    /// Unix seconds.
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportRequestPayload {
+    pub document: ConversationDocument,
+    /// Import into this room instead of the document's.
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    #[serde(default)]
+    pub room_id: Option<String>,
+    /// Drop the room's current conversation instead of keeping it after the imported one.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl ImportRequestPayload {
+    /// The (workspace, room) the document goes into.
+    pub fn target(&self) -> (&str, &str) {
+        (
+            self.workspace_id.as_deref().unwrap_or(&self.document.workspace_id),
+            self.room_id.as_deref().unwrap_or(&self.document.room_id),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchRequestPayload {
+    pub workspace_id: String,
+    /// Every room in the workspace when `None`.
+    #[serde(default)]
+    pub room_id: Option<String>,
+    /// Messages must contain every word, ignoring case.
+    pub query: String,
+    /// 20 when unset.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchResultPayload {
+    pub workspace_id: String,
+    #[serde(default)]
+    pub room_id: Option<String>,
+    pub query: String,
+    /// Newest first.
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub workspace_id: String,
+    pub room_id: String,
+    pub role: AiChatRole,
+    pub content: String,
+    /// Unix seconds; `None` for messages stored before timestamps were kept.
+    #[serde(default)]
+    pub at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportResultPayload {
+    pub workspace_id: String,
+    pub room_id: String,
+    /// Messages now in the room's conversation.
+    pub messages: usize,
+    /// Facts added to memory; ones already remembered, or all of them when memory is off,
+    /// are skipped.
+    pub facts: usize,
 }
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::engine::format_age;
+use crate::protocol::Quotas;
+use crate::types::{ActionOutcome, ActionSpec, RiskLevel};
+
+const HOUR: Duration = Duration::from_secs(3600);
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+/// What a room's `quotas` count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum QuotaKind {
+    /// Runs of filesystem actions above low risk.
+    Writes,
+    /// Runs of actions with the `shell` capability.
+    ShellRuns,
+    /// `bytes` reported by filesystem actions.
+    BytesWritten,
+}
+
+const KINDS: [QuotaKind; 3] = [QuotaKind::Writes, QuotaKind::ShellRuns, QuotaKind::BytesWritten];
+
+impl QuotaKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Writes => "writes_per_hour",
+            Self::ShellRuns => "shell_runs_per_day",
+            Self::BytesWritten => "bytes_written_per_day",
+        }
+    }
+
+    fn what(self) -> &'static str {
+        match self {
+            Self::Writes => "file writes an hour",
+            Self::ShellRuns => "shell runs a day",
+            Self::BytesWritten => "bytes written a day",
+        }
+    }
+
+    fn window(self) -> Duration {
+        match self {
+            Self::Writes => HOUR,
+            Self::ShellRuns | Self::BytesWritten => DAY,
+        }
+    }
+
+    fn limit(self, quotas: &Quotas) -> Option<u64> {
+        match self {
+            Self::Writes => quotas.writes_per_hour.map(u64::from),
+            Self::ShellRuns => quotas.shell_runs_per_day.map(u64::from),
+            Self::BytesWritten => quotas.bytes_written_per_day,
+        }
+    }
+
+    fn applies_to(self, spec: &ActionSpec) -> bool {
+        let has = |cap: &str| spec.capabilities.iter().any(|item| item == cap);
+        match self {
+            Self::Writes | Self::BytesWritten => has("filesystem") && spec.risk != RiskLevel::Low,
+            Self::ShellRuns => has("shell"),
+        }
+    }
+}
+
+/// When each counted run happened and how much it counted.
+type Usage = VecDeque<(Instant, u64)>;
+
+/// Per-room usage within each quota's window. Kept for every room, so `quota` can show it
+/// before any limit is set; dry runs are not counted.
+#[derive(Default)]
+pub(crate) struct QuotaTracker {
+    usage: HashMap<((String, String), QuotaKind), Usage>,
+}
+
+impl QuotaTracker {
+    /// Fails when running `spec` with `params` would go over one of the room's quotas.
+    pub(crate) fn check(
+        &mut self,
+        room: &(String, String),
+        quotas: &Quotas,
+        spec: &ActionSpec,
+        params: &Value,
+        now: Instant,
+    ) -> Result<()> {
+        for kind in KINDS {
+            let Some(limit) = kind.limit(quotas).filter(|_| kind.applies_to(spec)) else {
+                continue;
+            };
+            let used = self.used(room, kind, now);
+            let adding = match kind {
+                QuotaKind::BytesWritten => content_bytes(params),
+                _ => 1,
+            };
+            if used + adding > limit {
+                let retry = self
+                    .reset_in(room, kind, now)
+                    .map(|wait| format!("; try again in {}", format_age(wait)))
+                    .unwrap_or_default();
+                return Err(anyhow!(
+                    "quota exceeded: {} ({used}/{limit} {} in this room){retry}",
+                    kind.name(),
+                    kind.what()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        room: &(String, String),
+        spec: &ActionSpec,
+        outcome: Option<&ActionOutcome>,
+        now: Instant,
+    ) {
+        for kind in KINDS.into_iter().filter(|kind| kind.applies_to(spec)) {
+            let amount = match kind {
+                QuotaKind::BytesWritten => outcome
+                    .and_then(|outcome| outcome.data.get("bytes"))
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0),
+                _ => 1,
+            };
+            if amount > 0 {
+                self.usage
+                    .entry((room.clone(), kind))
+                    .or_default()
+                    .push_back((now, amount));
+            }
+        }
+    }
+
+    /// Usage against each quota, for the `quota` command.
+    pub(crate) fn text(&mut self, room: &(String, String), quotas: Option<&Quotas>, now: Instant) -> String {
+        let mut text = String::from("quotas for this room:\n");
+        for kind in KINDS {
+            let used = self.used(room, kind, now);
+            let limit = quotas
+                .and_then(|quotas| kind.limit(quotas))
+                .map_or_else(|| "unlimited".to_string(), |limit| limit.to_string());
+            text.push_str(&format!("  {}  {used}/{limit}", kind.name()));
+            if let Some(wait) = self.reset_in(room, kind, now).filter(|_| used > 0) {
+                text.push_str(&format!(" (oldest expires in {})", format_age(wait)));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Drops usage older than its window.
+    pub(crate) fn prune(&mut self, now: Instant) {
+        self.usage.retain(|(_, kind), entries| {
+            while entries
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) >= kind.window())
+            {
+                entries.pop_front();
+            }
+            !entries.is_empty()
+        });
+    }
+
+    fn used(&mut self, room: &(String, String), kind: QuotaKind, now: Instant) -> u64 {
+        let Some(entries) = self.usage.get_mut(&(room.clone(), kind)) else {
+            return 0;
+        };
+        while entries
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= kind.window())
+        {
+            entries.pop_front();
+        }
+        entries.iter().map(|(_, amount)| amount).sum()
+    }
+
+    fn reset_in(&self, room: &(String, String), kind: QuotaKind, now: Instant) -> Option<Duration> {
+        let (oldest, _) = self.usage.get(&(room.clone(), kind))?.front()?;
+        Some(kind.window().saturating_sub(now.duration_since(*oldest)))
+    }
+}
+
+/// Size of the `content` a write is about to put on disk.
+fn content_bytes(params: &Value) -> u64 {
+    params
+        .get("content")
+        .and_then(Value::as_str)
+        .map_or(0, |content| content.len() as u64)
+}
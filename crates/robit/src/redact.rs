@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Patterns for common secrets. A `secret` group limits the replacement to that part, so
+/// `OPENAI_API_KEY=sk-...` keeps its name.
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+    r"\bsk-[A-Za-z0-9_-]{20,}",
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}",
+    r"\bxox[abprs]-[A-Za-z0-9-]{10,}",
+    r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9._~+/=-]{16,})",
+    r#"(?i)\b[a-z0-9_]*(?:api_?key|secret|token|passw(?:or)?d)[a-z0-9_]*["']?\s*[:=]\s*["']?(?P<secret>[^\s"',;]{8,})"#,
+];
+
+/// Masks secrets in text the engine keeps or sends on: action results, history,
+/// conversation context, preflight logs and AI prompts.
+pub(crate) struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        let patterns = DEFAULT_PATTERNS
+            .iter()
+            .filter_map(|pattern| Regex::new(pattern).ok())
+            .collect();
+        Self { patterns }
+    }
+}
+
+impl Redactor {
+    pub(crate) fn add_pattern(&mut self, pattern: &str) -> Result<()> {
+        let regex =
+            Regex::new(pattern).map_err(|err| anyhow!("redaction pattern {pattern:?}: {err}"))?;
+        self.patterns.push(regex);
+        Ok(())
+    }
+
+    pub(crate) fn text(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for pattern in &self.patterns {
+            if pattern.is_match(&out) {
+                out = pattern.replace_all(&out, mask).into_owned();
+            }
+        }
+        out
+    }
+
+    /// Redacts every string inside `value`; object keys are kept.
+    pub(crate) fn value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.text(text)),
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.value(item)).collect()),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, item)| (key.clone(), self.value(item)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+fn mask(caps: &Captures) -> String {
+    let (Some(whole), Some(secret)) = (caps.get(0), caps.name("secret")) else {
+        return REDACTED.to_string();
+    };
+    let text = whole.as_str();
+    let (start, end) = (secret.start() - whole.start(), secret.end() - whole.start());
+    format!("{}{REDACTED}{}", &text[..start], &text[end..])
+}
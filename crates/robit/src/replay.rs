@@ -0,0 +1,366 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ai::{AiChatMessage, AiDecision, AiPlanner, AiTask};
+use crate::encryption::{is_sealed, open_text, seal_text, Record, SealMark, StorageKey};
+use crate::engine::Engine;
+use crate::protocol::{ProtocolBody, ProtocolEvent};
+use crate::types::{ActionSpec, InboundMessage, OutboundMessage};
+
+/// One turn of a recorded session: what the engine was sent, what its AI backends answered
+/// meanwhile, and what it decided.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedTurn {
+    pub input: ReplayInput,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ai_calls: Vec<RecordedAiCall>,
+    /// One per reply, in order.
+    #[serde(default)]
+    pub decisions: Vec<ReplyDecision>,
+}
+
+/// A protocol event, or a message from an adapter that talks to `handle_message`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayInput {
+    Event(Box<ProtocolEvent>),
+    Message(InboundMessage),
+}
+
+impl ReplayInput {
+    fn label(&self) -> String {
+        match self {
+            Self::Event(event) => match &event.body {
+                ProtocolBody::Message(message) => format!("{} {:?}", event.id, message.text),
+                body => format!("{} {}", event.id, body.event_type()),
+            },
+            Self::Message(msg) => format!("{} {:?}", msg.id, msg.text),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedAiCall {
+    pub task: AiTask,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<AiDecision>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RecordedAiCall {
+    pub(crate) fn new(task: AiTask, decision: &Result<AiDecision>) -> Self {
+        match decision {
+            Ok(decision) => Self {
+                task,
+                decision: Some(decision.clone()),
+                error: None,
+            },
+            Err(err) => Self {
+                task,
+                decision: None,
+                error: Some(format!("{err:#}")),
+            },
+        }
+    }
+}
+
+/// What a reply decided: its kind (`action_result`, `approval_request`, `chat`, ...), the
+/// action it names and, for errors, the code. The text is kept for reports but not compared,
+/// since dry runs and model wording change it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplyDecision {
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl ReplyDecision {
+    pub fn from_reply(reply: &OutboundMessage) -> Self {
+        let metadata = &reply.metadata;
+        Self {
+            kind: metadata["kind"].as_str().unwrap_or("info").to_string(),
+            action: action_of(metadata),
+            code: metadata["code"].as_str().map(str::to_string),
+            text: reply.text.clone(),
+        }
+    }
+
+    pub fn from_event(event: &ProtocolEvent) -> Self {
+        match &event.body {
+            ProtocolBody::Response(response) => Self {
+                kind: response.kind.clone(),
+                action: action_of(&response.metadata),
+                code: None,
+                text: response.text.clone(),
+            },
+            ProtocolBody::Error(error) => Self {
+                kind: "error".to_string(),
+                action: None,
+                code: Some(error.code.clone()),
+                text: error.message.clone(),
+            },
+            body => Self {
+                kind: body.event_type().to_string(),
+                ..Self::default()
+            },
+        }
+    }
+
+    pub fn same_as(&self, other: &ReplyDecision) -> bool {
+        self.kind == other.kind && self.action == other.action && self.code == other.code
+    }
+}
+
+/// Results name the action at the top of the metadata, approval requests in their prompt.
+fn action_of(metadata: &Value) -> Option<String> {
+    metadata["action"]
+        .as_str()
+        .or_else(|| metadata["data"]["prompt"]["action"].as_str())
+        .map(str::to_string)
+}
+
+impl fmt::Display for ReplyDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.kind)?;
+        if let Some(action) = &self.action {
+            write!(f, "({action})")?;
+        }
+        if let Some(code) = &self.code {
+            write!(f, "[{code}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends each turn the engine handles as a JSON line, sealed when a storage key is set.
+pub(crate) struct SessionLog {
+    path: PathBuf,
+    key: Option<StorageKey>,
+}
+
+impl SessionLog {
+    pub(crate) fn new(path: PathBuf, key: Option<StorageKey>) -> Self {
+        Self { path, key }
+    }
+
+    pub(crate) fn append(&self, turn: &RecordedTurn) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let turn = serde_json::to_string(turn)?;
+        writeln!(file, "{}", seal_text(self.key.as_ref(), Record::whole("session"), turn)?)?;
+        SealMark::for_store(&self.path).update(self.key.is_some())
+    }
+}
+
+/// Reads a session recorded with `Engine::record_session`. A plain log of protocol events, one
+/// per line, works too: `Response` and `Error` events become the decisions of the event named
+/// by their `in_reply_to`, and every other event is an input.
+pub fn load_session(path: &Path, key: Option<&StorageKey>) -> Result<Vec<RecordedTurn>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let mark = SealMark::for_store(path);
+    // Sealed lines mean the log was sealed before, with or without the mark.
+    let sealed_before = mark.is_set() || content.lines().any(is_sealed);
+    let mut turns: Vec<RecordedTurn> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let at = || format!("{}:{}", path.display(), number + 1);
+        let line = open_text(key, Record::whole("session"), line, sealed_before)
+            .with_context(|| mark.refused(path))?;
+        let value: Value = serde_json::from_str(&line).with_context(at)?;
+        if value.get("input").is_some() {
+            turns.push(serde_json::from_value(value).with_context(at)?);
+            continue;
+        }
+        let event: ProtocolEvent = serde_json::from_value(value).with_context(at)?;
+        if !matches!(event.body, ProtocolBody::Response(_) | ProtocolBody::Error(_)) {
+            turns.push(RecordedTurn {
+                input: ReplayInput::Event(Box::new(event)),
+                ai_calls: Vec::new(),
+                decisions: Vec::new(),
+            });
+            continue;
+        }
+        let answered = turns.iter_mut().rev().find(|turn| match &turn.input {
+            ReplayInput::Event(input) => event.in_reply_to.as_deref() == Some(input.id.as_str()),
+            ReplayInput::Message(_) => false,
+        });
+        match answered {
+            Some(turn) => turn.decisions.push(ReplyDecision::from_event(&event)),
+            None => return Err(anyhow!("{}: reply to an event that is not in the log", at())),
+        }
+    }
+    Ok(turns)
+}
+
+/// A turn whose replay decided differently.
+#[derive(Clone, Debug)]
+pub struct ReplayMismatch {
+    /// 1-based.
+    pub turn: usize,
+    pub input: String,
+    pub expected: Vec<ReplyDecision>,
+    pub actual: Vec<ReplyDecision>,
+    /// Recorded AI answers the replay never asked for.
+    pub unused_ai_calls: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayReport {
+    pub turns: usize,
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replayed {} turn(s): {} mismatch(es)",
+            self.turns,
+            self.mismatches.len()
+        )?;
+        for mismatch in &self.mismatches {
+            write!(
+                f,
+                "\nturn {} {}\n  expected: {}\n  actual:   {}",
+                mismatch.turn,
+                mismatch.input,
+                decision_list(&mismatch.expected),
+                decision_list(&mismatch.actual)
+            )?;
+            if mismatch.unused_ai_calls > 0 {
+                write!(f, "\n  {} recorded AI answer(s) not asked for", mismatch.unused_ai_calls)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decision_list(decisions: &[ReplyDecision]) -> String {
+    if decisions.is_empty() {
+        return "(no replies)".to_string();
+    }
+    decisions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Feeds recorded turns back through `engine` and compares its decisions with the recorded
+/// ones. Every action runs as a dry run, and the AI backends are replaced by one that gives
+/// each turn's recorded answers back in order, so no model is called. Start from a fresh
+/// engine with the recording's config: approval ids and other state build up turn by turn.
+pub fn replay(engine: &mut Engine, turns: &[RecordedTurn]) -> ReplayReport {
+    engine.set_force_dry_run(true);
+    let planner = Arc::new(ReplayPlanner::default());
+    let tasks: HashSet<AiTask> = turns
+        .iter()
+        .flat_map(|turn| &turn.ai_calls)
+        .map(|call| call.task)
+        .collect();
+    if !tasks.is_empty() {
+        engine.set_ai_backend(Some(planner.clone()));
+    }
+    if tasks.contains(&AiTask::Chat) {
+        engine.set_ai_route(AiTask::Chat, Some(planner.clone()), None);
+    }
+    let mut report = ReplayReport {
+        turns: turns.len(),
+        mismatches: Vec::new(),
+    };
+    for (index, turn) in turns.iter().enumerate() {
+        planner.load(&turn.ai_calls);
+        let actual: Vec<ReplyDecision> = match &turn.input {
+            ReplayInput::Event(event) => engine
+                .handle_protocol_event(event.as_ref().clone())
+                .iter()
+                .map(ReplyDecision::from_event)
+                .collect(),
+            ReplayInput::Message(msg) => engine
+                .handle_message(msg.clone())
+                .iter()
+                .map(ReplyDecision::from_reply)
+                .collect(),
+        };
+        let same = actual.len() == turn.decisions.len()
+            && actual
+                .iter()
+                .zip(&turn.decisions)
+                .all(|(actual, expected)| actual.same_as(expected));
+        let unused_ai_calls = planner.remaining();
+        if !same || unused_ai_calls > 0 {
+            report.mismatches.push(ReplayMismatch {
+                turn: index + 1,
+                input: turn.input.label(),
+                expected: turn.decisions.clone(),
+                actual,
+                unused_ai_calls,
+            });
+        }
+    }
+    report
+}
+
+/// Answers with the current turn's recorded AI calls, in order.
+#[derive(Default)]
+struct ReplayPlanner {
+    calls: Mutex<VecDeque<RecordedAiCall>>,
+}
+
+impl ReplayPlanner {
+    fn load(&self, calls: &[RecordedAiCall]) {
+        if let Ok(mut queue) = self.calls.lock() {
+            *queue = calls.iter().cloned().collect();
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.calls.lock().map(|queue| queue.len()).unwrap_or(0)
+    }
+}
+
+impl AiPlanner for ReplayPlanner {
+    fn plan_with_history(
+        &self,
+        _input: &str,
+        _actions: &[ActionSpec],
+        _history: &[AiChatMessage],
+    ) -> Result<AiDecision> {
+        let call = self
+            .calls
+            .lock()
+            .map_err(|_| anyhow!("replay planner lock poisoned"))?
+            .pop_front()
+            .ok_or_else(|| anyhow!("replay: no recorded AI answer left for this turn"))?;
+        match (call.decision, call.error) {
+            (Some(decision), _) => Ok(decision),
+            (None, error) => Err(anyhow!(
+                error.unwrap_or_else(|| "recorded AI call failed".to_string())
+            )),
+        }
+    }
+}
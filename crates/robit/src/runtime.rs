@@ -0,0 +1,351 @@
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::adapter::{AdapterPoll, AsyncAdapter};
+use crate::engine::Engine;
+use crate::protocol::{
+    ProtocolBody, ProtocolEvent, RoomUsage, SearchHit, SearchResultPayload, UsageResultPayload,
+};
+use crate::types::{InboundMessage, OutboundMessage};
+
+/// How often `run` checks for finished replies while messages are in flight.
+const REPLY_POLL: Duration = Duration::from_millis(50);
+
+type Job = Box<dyn FnOnce(&mut Engine) + Send>;
+
+pub type EngineFuture<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/// An `Engine` running on its own thread. Model calls, web fetches and shell commands
+/// block that thread instead of the async host, which keeps receiving messages and queues
+/// them for the engine. Clones share the same engine.
+#[derive(Clone)]
+pub struct EngineHandle {
+    jobs: mpsc::UnboundedSender<Job>,
+    tick_interval: Duration,
+}
+
+impl EngineHandle {
+    pub fn spawn(engine: Engine) -> Result<Self> {
+        let tick_interval = engine.tick_interval();
+        let (jobs, mut queue) = mpsc::unbounded_channel::<Job>();
+        std::thread::Builder::new()
+            .name("robit-engine".to_string())
+            .spawn(move || {
+                let mut engine = engine;
+                while let Some(job) = queue.blocking_recv() {
+                    job(&mut engine);
+                }
+            })?;
+        Ok(Self {
+            jobs,
+            tick_interval,
+        })
+    }
+
+    /// Queues `f` for the engine thread right away, so calls run in the order they were
+    /// made even when the returned futures are awaited out of order.
+    pub fn call<T, F>(&self, f: F) -> impl Future<Output = Result<T>> + Send + use<T, F>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Engine) -> T + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let queued = self
+            .jobs
+            .send(Box::new(move |engine| {
+                let _ = tx.send(f(engine));
+            }))
+            .map_err(|_| anyhow!("engine thread stopped"));
+        async move {
+            queued?;
+            rx.await.map_err(|_| anyhow!("engine thread stopped"))
+        }
+    }
+
+    pub fn handle_message(&self, msg: InboundMessage) -> EngineFuture<Vec<OutboundMessage>> {
+        Box::pin(self.call(move |engine| engine.handle_message(msg)))
+    }
+
+    pub fn handle_protocol_event(&self, event: ProtocolEvent) -> EngineFuture<Vec<ProtocolEvent>> {
+        Box::pin(self.call(move |engine| engine.handle_protocol_event(event)))
+    }
+
+    pub fn tick(&self) -> EngineFuture<Vec<OutboundMessage>> {
+        Box::pin(self.call(Engine::tick))
+    }
+
+    /// Serves `adapter` until it closes; see `serve`.
+    pub async fn run<A: AsyncAdapter>(&self, adapter: &mut A) -> Result<()> {
+        let (limit, format) = (adapter.max_message_chars(), adapter.reply_format());
+        self.call(move |engine| engine.fit_adapter(limit, format))
+            .await?;
+        serve(
+            adapter,
+            self.tick_interval,
+            |msg| self.handle_message(msg),
+            || self.tick(),
+        )
+        .await
+    }
+}
+
+/// Several engines, each on its own thread. A (workspace, room) always lands on the same
+/// shard, so one room's messages are handled in order while rooms on other shards carry on.
+#[derive(Clone)]
+pub struct EnginePool {
+    shards: Vec<EngineHandle>,
+    tick_interval: Duration,
+}
+
+impl EnginePool {
+    /// `factory(index)` builds each shard's engine. Shards share nothing, so give each its
+    /// own conversation and approval persistence paths. Workspace state such as user facts
+    /// is per shard too: a fact remembered in one room only reaches the workspace's rooms
+    /// on the same shard.
+    pub fn spawn(shards: usize, mut factory: impl FnMut(usize) -> Result<Engine>) -> Result<Self> {
+        let shards = (0..shards.max(1))
+            .map(|index| factory(index).and_then(EngineHandle::spawn))
+            .collect::<Result<Vec<_>>>()?;
+        let tick_interval = shards
+            .iter()
+            .map(|shard| shard.tick_interval)
+            .min()
+            .unwrap_or(Duration::from_secs(1));
+        Ok(Self {
+            shards,
+            tick_interval,
+        })
+    }
+
+    pub fn shard_for(&self, workspace_id: &str, room_id: &str) -> &EngineHandle {
+        let mut hasher = DefaultHasher::new();
+        (workspace_id, room_id).hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    pub fn handle_message(&self, msg: InboundMessage) -> EngineFuture<Vec<OutboundMessage>> {
+        let workspace_id = msg.workspace_id.clone().unwrap_or_default();
+        self.shard_for(&workspace_id, &msg.channel)
+            .handle_message(msg)
+    }
+
+    /// Room traffic goes to the room's shard; scope and config updates reach every shard,
+    /// and usage is collected from all of them.
+    pub fn handle_protocol_event(&self, event: ProtocolEvent) -> EngineFuture<Vec<ProtocolEvent>> {
+        let request_id = event.id.clone();
+        let replies = self.route_protocol_event(event);
+        // Merged results are new events, so they need the request's id too.
+        Box::pin(async move {
+            let mut replies = replies.await?;
+            for reply in &mut replies {
+                reply.in_reply_to.get_or_insert_with(|| request_id.clone());
+            }
+            Ok(replies)
+        })
+    }
+
+    fn route_protocol_event(&self, event: ProtocolEvent) -> EngineFuture<Vec<ProtocolEvent>> {
+        match &event.body {
+            ProtocolBody::Message(payload) => {
+                let shard = self.shard_for(&payload.workspace_id, &payload.room_id);
+                shard.handle_protocol_event(event)
+            }
+            ProtocolBody::ApprovalDecision(payload) => {
+                let shard = self.shard_for(&payload.workspace_id, &payload.room_id);
+                shard.handle_protocol_event(event)
+            }
+            ProtocolBody::HistoryRequest(payload) => {
+                let shard = self.shard_for(&payload.workspace_id, &payload.room_id);
+                shard.handle_protocol_event(event)
+            }
+            ProtocolBody::ExportRequest(payload) => {
+                let shard = self.shard_for(&payload.workspace_id, &payload.room_id);
+                shard.handle_protocol_event(event)
+            }
+            ProtocolBody::ImportRequest(payload) => {
+                let (workspace_id, room_id) = payload.target();
+                let shard = self.shard_for(workspace_id, room_id);
+                shard.handle_protocol_event(event)
+            }
+            ProtocolBody::SearchRequest(payload) if payload.room_id.is_some() => {
+                let room_id = payload.room_id.as_deref().unwrap_or_default();
+                let shard = self.shard_for(&payload.workspace_id, room_id);
+                shard.handle_protocol_event(event)
+            }
+            // Rooms are spread over the shards, so each searches its own and the hits merge.
+            ProtocolBody::SearchRequest(payload) => {
+                let request = payload.clone();
+                let limit = request.limit.unwrap_or(crate::engine::SEARCH_LIMIT);
+                let replies =
+                    self.broadcast(move |engine| engine.handle_protocol_event(event.clone()));
+                Box::pin(async move {
+                    let mut hits: Vec<SearchHit> = Vec::new();
+                    for reply in replies.await?.into_iter().flatten() {
+                        if let ProtocolBody::SearchResult(result) = reply.body {
+                            hits.extend(result.hits);
+                        }
+                    }
+                    hits.sort_by_key(|hit| std::cmp::Reverse(hit.at));
+                    hits.truncate(limit);
+                    Ok(vec![ProtocolEvent::new(ProtocolBody::SearchResult(
+                        SearchResultPayload {
+                            workspace_id: request.workspace_id,
+                            room_id: None,
+                            query: request.query,
+                            hits,
+                        },
+                    ))])
+                })
+            }
+            // Every shard answers to the client, so every shard learns what it supports.
+            ProtocolBody::Hello(_) => {
+                let replies =
+                    self.broadcast(move |engine| engine.handle_protocol_event(event.clone()));
+                Box::pin(async move { Ok(replies.await?.into_iter().next().unwrap_or_default()) })
+            }
+            ProtocolBody::RoomScope(_) | ProtocolBody::ConfigUpdate(_) => {
+                let replies =
+                    self.broadcast(move |engine| engine.handle_protocol_event(event.clone()));
+                Box::pin(async move { Ok(replies.await?.into_iter().flatten().collect()) })
+            }
+            ProtocolBody::UsageRequest(_) => {
+                let replies =
+                    self.broadcast(move |engine| engine.handle_protocol_event(event.clone()));
+                Box::pin(async move {
+                    let mut rooms: Vec<RoomUsage> = Vec::new();
+                    for reply in replies.await?.into_iter().flatten() {
+                        if let ProtocolBody::UsageResult(result) = reply.body {
+                            rooms.extend(result.rooms);
+                        }
+                    }
+                    rooms.sort_by(|a, b| {
+                        (&a.workspace_id, &a.room_id).cmp(&(&b.workspace_id, &b.room_id))
+                    });
+                    Ok(vec![ProtocolEvent::new(ProtocolBody::UsageResult(
+                        UsageResultPayload { rooms },
+                    ))])
+                })
+            }
+            ProtocolBody::StatusRequest(_) => {
+                let statuses = self.broadcast(Engine::status);
+                Box::pin(async move {
+                    let mut statuses = statuses.await?.into_iter();
+                    let Some(mut status) = statuses.next() else {
+                        return Ok(Vec::new());
+                    };
+                    for shard in statuses {
+                        status.pending_approvals += shard.pending_approvals;
+                        status.running_plans += shard.running_plans;
+                    }
+                    Ok(vec![ProtocolEvent::new(ProtocolBody::StatusResult(status))])
+                })
+            }
+            _ => self.shards[0].handle_protocol_event(event),
+        }
+    }
+
+    pub fn tick(&self) -> EngineFuture<Vec<OutboundMessage>> {
+        let notices = self.broadcast(Engine::tick);
+        Box::pin(async move { Ok(notices.await?.into_iter().flatten().collect()) })
+    }
+
+    pub async fn run<A: AsyncAdapter>(&self, adapter: &mut A) -> Result<()> {
+        let (limit, format) = (adapter.max_message_chars(), adapter.reply_format());
+        self.broadcast(move |engine| engine.fit_adapter(limit, format))
+            .await?;
+        serve(
+            adapter,
+            self.tick_interval,
+            |msg| self.handle_message(msg),
+            || self.tick(),
+        )
+        .await
+    }
+
+    /// Queues `f` on every shard and gathers the results in shard order.
+    fn broadcast<T, F>(&self, f: F) -> EngineFuture<Vec<T>>
+    where
+        T: Send + 'static,
+        F: Fn(&mut Engine) -> T + Clone + Send + 'static,
+    {
+        let calls: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| shard.call(f.clone()))
+            .collect();
+        Box::pin(async move {
+            let mut results = Vec::with_capacity(calls.len());
+            for call in calls {
+                results.push(call.await?);
+            }
+            Ok(results)
+        })
+    }
+}
+
+/// Polls `adapter` and hands each message to `handle`, whose future runs as its own task,
+/// so the adapter keeps being polled and replies go out as they complete.
+async fn serve<A, H, T>(adapter: &mut A, tick_interval: Duration, handle: H, tick: T) -> Result<()>
+where
+    A: AsyncAdapter,
+    H: Fn(InboundMessage) -> EngineFuture<Vec<OutboundMessage>>,
+    T: Fn() -> EngineFuture<Vec<OutboundMessage>>,
+{
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel::<Vec<OutboundMessage>>();
+    let mut in_flight = 0usize;
+    let mut last_tick = Instant::now();
+    loop {
+        let wait = if in_flight > 0 {
+            tick_interval.min(REPLY_POLL)
+        } else {
+            tick_interval
+        };
+        let poll = adapter.recv_timeout(wait).await?;
+        while let Ok(responses) = done_rx.try_recv() {
+            in_flight -= 1;
+            for response in responses {
+                adapter.send(response).await?;
+            }
+        }
+        match poll {
+            AdapterPoll::Message(msg) if !msg.text.trim().is_empty() => {
+                in_flight += 1;
+                let reply = handle(msg);
+                let done = done_tx.clone();
+                tokio::spawn(async move {
+                    let responses = reply.await.unwrap_or_else(|err| {
+                        error!("engine: {err}");
+                        Vec::new()
+                    });
+                    let _ = done.send(responses);
+                });
+            }
+            AdapterPoll::Message(_) | AdapterPoll::Idle => {}
+            AdapterPoll::Closed => break,
+        }
+        // Ticks wait for idle engines so they never queue behind a slow message.
+        if in_flight == 0 && last_tick.elapsed() >= tick_interval {
+            last_tick = Instant::now();
+            for notice in tick().await? {
+                adapter.send(notice).await?;
+            }
+        }
+    }
+    while in_flight > 0 {
+        let Some(responses) = done_rx.recv().await else {
+            break;
+        };
+        in_flight -= 1;
+        for response in responses {
+            adapter.send(response).await?;
+        }
+    }
+    Ok(())
+}
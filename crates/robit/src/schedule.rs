@@ -0,0 +1,517 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use crate::policy::ActionContext;
+use crate::types::{ActionOutcome, ActionSpec, InboundMessage, RiskLevel};
+
+pub const SCHEDULE_CREATE: &str = "schedule.create";
+
+/// How often a schedule fires: `every 30m` (also `s`, `h`, `d`) or a five-field cron
+/// expression (`minute hour day-of-month month day-of-week`) in local time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    Every(u64),
+    Cron(CronExpr),
+}
+
+impl Recurrence {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        match raw.strip_prefix("every ") {
+            Some(interval) => parse_interval(interval).map(Recurrence::Every),
+            None => CronExpr::parse(raw).map(Recurrence::Cron),
+        }
+    }
+
+    /// The first run strictly after `after` (unix seconds).
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        match self {
+            Recurrence::Every(secs) => Some(after + secs),
+            Recurrence::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: String) -> Result<Self> {
+        Recurrence::parse(&raw)
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> Self {
+        recurrence.to_string()
+    }
+}
+
+impl std::fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recurrence::Every(secs) if secs % 86_400 == 0 => write!(f, "every {}d", secs / 86_400),
+            Recurrence::Every(secs) if secs % 3600 == 0 => write!(f, "every {}h", secs / 3600),
+            Recurrence::Every(secs) if secs % 60 == 0 => write!(f, "every {}m", secs / 60),
+            Recurrence::Every(secs) => write!(f, "every {secs}s"),
+            Recurrence::Cron(cron) => f.write_str(&cron.source),
+        }
+    }
+}
+
+/// `30m`, `2h`, `1d`; at least a minute.
+pub(crate) fn parse_interval(raw: &str) -> Result<u64> {
+    let raw = raw.trim().to_lowercase();
+    let idx = raw
+        .find(|ch: char| !ch.is_ascii_digit())
+        .ok_or_else(|| anyhow!("interval needs a unit (s, m, h or d): {raw}"))?;
+    let (number, unit) = raw.split_at(idx);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid interval: {raw}"))?;
+    let secs = match unit.trim() {
+        "s" | "sec" | "secs" => value,
+        "m" | "min" | "mins" => value * 60,
+        "h" | "hr" | "hrs" => value * 3600,
+        "d" | "day" | "days" => value * 86_400,
+        _ => return Err(anyhow!("invalid interval unit: {raw}")),
+    };
+    if secs < 60 {
+        return Err(anyhow!("intervals must be at least a minute: {raw}"));
+    }
+    Ok(secs)
+}
+
+/// Fields are bit sets of the allowed values. Day-of-week 0 and 7 are both Sunday.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronExpr {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron matches either day field when both are restricted.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let fields: Vec<&str> = raw.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!("cron expressions have five fields: {raw}"));
+        };
+        let mut weekdays = parse_cron_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            source: fields.join(" "),
+            minutes: parse_cron_field(minute, 0, 59)?,
+            hours: parse_cron_field(hour, 0, 23)?,
+            days: parse_cron_field(day, 1, 31)?,
+            months: parse_cron_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Walks forward field by field; gives up after four years without a match (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let start = Local.timestamp_opt(after as i64, 0).single()?.naive_local();
+        let mut at = start.with_second(0)?.checked_add_signed(chrono::Duration::minutes(1))?;
+        let limit = start.checked_add_signed(chrono::Duration::days(4 * 366))?;
+        while at < limit {
+            let date = at.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                at = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.matches_day(date) {
+                at = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if self.hours & (1 << at.hour()) == 0 {
+                at = start_of_hour(at)? + chrono::Duration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << at.minute()) == 0 {
+                at += chrono::Duration::minutes(1);
+                continue;
+            }
+            // Times skipped by a DST change don't exist locally; move on to the next minute.
+            match Local.from_local_datetime(&at).earliest() {
+                Some(time) if time.timestamp() > after as i64 => return Some(time.timestamp() as u64),
+                _ => at += chrono::Duration::minutes(1),
+            }
+        }
+        None
+    }
+}
+
+fn start_of_hour(at: NaiveDateTime) -> Option<NaiveDateTime> {
+    at.date().and_hms_opt(at.hour(), 0, 0)
+}
+
+/// `*`, `5`, `1-5`, `*/15`, `0-30/10`, and comma lists of those.
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| anyhow!("invalid cron step: {part}"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_cron_value(start)?, parse_cron_value(end)?),
+                None if step > 1 => (parse_cron_value(range)?, max),
+                None => {
+                    let value = parse_cron_value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(anyhow!("cron field out of range {min}-{max}: {part}"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_cron_value(raw: &str) -> Result<u32> {
+    raw.parse()
+        .map_err(|_| anyhow!("invalid cron value: {raw}"))
+}
+
+/// A message the engine sends to itself on a timer, as if `sender` had typed `text` in the
+/// room. Scheduled runs go through the same planning, approval and trust checks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub when: Recurrence,
+    pub text: String,
+    pub workspace_id: Option<String>,
+    pub room_id: String,
+    pub sender: String,
+    /// Unix seconds.
+    pub next_run: u64,
+    #[serde(default)]
+    pub last_run: Option<u64>,
+}
+
+impl Schedule {
+    pub(crate) fn message(&self, now: u64) -> InboundMessage {
+        InboundMessage {
+            id: format!("{}-{now}", self.id),
+            text: self.text.clone(),
+            sender: self.sender.clone(),
+            channel: self.room_id.clone(),
+            workspace_id: self.workspace_id.clone(),
+            metadata: json!({"schedule_id": self.id}),
+        }
+    }
+}
+
+/// `[[schedules]]` entry of the config file. Set one of `every` and `cron`, and one of
+/// `text` and `plan` (a saved plan name).
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ScheduleEntry {
+    pub(crate) id: String,
+    #[serde(default)]
+    pub(crate) every: Option<String>,
+    #[serde(default)]
+    pub(crate) cron: Option<String>,
+    #[serde(default)]
+    pub(crate) text: Option<String>,
+    #[serde(default)]
+    pub(crate) plan: Option<String>,
+    #[serde(default)]
+    pub(crate) workspace: Option<String>,
+    pub(crate) room: String,
+    pub(crate) sender: String,
+}
+
+#[derive(Deserialize)]
+struct ScheduleCreateParams {
+    every: Option<String>,
+    cron: Option<String>,
+    text: Option<String>,
+    plan: Option<String>,
+}
+
+/// The parts shared by `schedule.create` and config entries.
+fn parse_schedule_parts(
+    every: Option<&str>,
+    cron: Option<&str>,
+    text: Option<&str>,
+    plan: Option<&str>,
+) -> Result<(Recurrence, String)> {
+    let when = match (every, cron) {
+        (Some(every), None) => Recurrence::Every(parse_interval(every)?),
+        (None, Some(cron)) => Recurrence::Cron(CronExpr::parse(cron)?),
+        _ => return Err(anyhow!("set exactly one of every and cron")),
+    };
+    let text = match (text, plan) {
+        (Some(text), None) if !text.trim().is_empty() => text.trim().to_string(),
+        (None, Some(plan)) if !plan.trim().is_empty() => format!("plan run {}", plan.trim()),
+        _ => return Err(anyhow!("set exactly one of text and plan")),
+    };
+    Ok((when, text))
+}
+
+impl ScheduleEntry {
+    pub(crate) fn into_schedule(self, now: u64) -> Result<Schedule> {
+        let (when, text) = parse_schedule_parts(
+            self.every.as_deref(),
+            self.cron.as_deref(),
+            self.text.as_deref(),
+            self.plan.as_deref(),
+        )
+        .with_context(|| format!("schedule {}", self.id))?;
+        let next_run = when
+            .next_after(now)
+            .ok_or_else(|| anyhow!("schedule {} never runs", self.id))?;
+        Ok(Schedule {
+            id: self.id,
+            when,
+            text,
+            workspace_id: self.workspace,
+            room_id: self.room,
+            sender: self.sender,
+            next_run,
+            last_run: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedSchedules {
+    next_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+/// Schedules created at runtime are saved to `persist_path`; ones from the config file are
+/// not, since the file stays their source.
+pub(crate) struct ScheduleBook {
+    next_id: u64,
+    schedules: Vec<Schedule>,
+    configured: Vec<Schedule>,
+    persist_path: Option<PathBuf>,
+}
+
+impl ScheduleBook {
+    pub(crate) fn new(configured: Vec<Schedule>) -> Self {
+        Self {
+            next_id: 1,
+            schedules: Vec::new(),
+            configured,
+            persist_path: None,
+        }
+    }
+
+    /// Loads saved schedules; runs missed while the engine was down fire once on the next tick.
+    pub(crate) fn enable_persistence(&mut self, path: PathBuf) -> Result<()> {
+        self.persist_path = Some(path.clone());
+        if !path.exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)?;
+        let persisted: PersistedSchedules = serde_json::from_str(&content)?;
+        self.next_id = self.next_id.max(persisted.next_id);
+        self.schedules = persisted.schedules;
+        Ok(())
+    }
+
+    pub(crate) fn create(
+        &mut self,
+        msg: &InboundMessage,
+        params: &serde_json::Value,
+        now: u64,
+    ) -> Result<Schedule> {
+        let (when, text) = parse_create_params(params)?;
+        let next_run = when
+            .next_after(now)
+            .ok_or_else(|| anyhow!("schedule never runs: {when}"))?;
+        let schedule = Schedule {
+            id: format!("sched-{}", self.next_id),
+            when,
+            text,
+            workspace_id: msg.workspace_id.clone(),
+            room_id: msg.channel.clone(),
+            sender: msg.sender.clone(),
+            next_run,
+            last_run: None,
+        };
+        self.next_id += 1;
+        self.schedules.push(schedule.clone());
+        self.save();
+        Ok(schedule)
+    }
+
+    /// Removes a runtime schedule of this room.
+    pub(crate) fn remove(&mut self, workspace_id: Option<&str>, room_id: &str, id: &str) -> bool {
+        let before = self.schedules.len();
+        self.schedules.retain(|schedule| {
+            !(schedule.id == id
+                && schedule.workspace_id.as_deref() == workspace_id
+                && schedule.room_id == room_id)
+        });
+        let removed = self.schedules.len() != before;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub(crate) fn in_room(&self, workspace_id: Option<&str>, room_id: &str) -> Vec<&Schedule> {
+        self.configured
+            .iter()
+            .chain(&self.schedules)
+            .filter(|schedule| {
+                schedule.workspace_id.as_deref() == workspace_id && schedule.room_id == room_id
+            })
+            .collect()
+    }
+
+    /// Schedules due at `now`, with their next run already moved forward. Ones that will
+    /// never run again are dropped.
+    pub(crate) fn take_due(&mut self, now: u64) -> Vec<Schedule> {
+        let mut due = Vec::new();
+        let mut changed = false;
+        for (list, persisted) in [(&mut self.configured, false), (&mut self.schedules, true)] {
+            list.retain_mut(|schedule| {
+                if schedule.next_run > now {
+                    return true;
+                }
+                changed |= persisted;
+                schedule.last_run = Some(now);
+                due.push(schedule.clone());
+                match schedule.when.next_after(now) {
+                    Some(next) => {
+                        schedule.next_run = next;
+                        true
+                    }
+                    None => false,
+                }
+            });
+        }
+        if changed {
+            self.save();
+        }
+        due
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.persist_path else {
+            return;
+        };
+        if let Err(err) = self.save_to_path(path) {
+            warn!("schedules save failed: {err}");
+        }
+    }
+
+    fn save_to_path(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let persisted = PersistedSchedules {
+            next_id: self.next_id,
+            schedules: self.schedules.clone(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&persisted)?)?;
+        Ok(())
+    }
+}
+
+fn parse_create_params(params: &serde_json::Value) -> Result<(Recurrence, String)> {
+    let params: ScheduleCreateParams = serde_json::from_value(params.clone())
+        .map_err(|err| anyhow!("invalid params: {err}"))?;
+    parse_schedule_parts(
+        params.every.as_deref(),
+        params.cron.as_deref(),
+        params.text.as_deref(),
+        params.plan.as_deref(),
+    )
+}
+
+/// Registered by the engine, which runs it itself since the schedule belongs to the room
+/// and sender that asked for it.
+#[derive(Default)]
+pub struct ScheduleCreateAction;
+
+impl crate::actions::ActionHandler for ScheduleCreateAction {
+    fn name(&self) -> &'static str {
+        SCHEDULE_CREATE
+    }
+
+    fn spec(&self) -> ActionSpec {
+        ActionSpec {
+            name: self.name().to_string(),
+            version: "1".to_string(),
+            description: "Run a request or saved plan in this room on a schedule (an interval \
+                          like every=\"1h\", or a cron expression like cron=\"0 22 * * *\")."
+                .to_string(),
+            params_schema: json!({
+                "type": "object",
+                "properties": {
+                    "every": { "type": "string" },
+                    "cron": { "type": "string" },
+                    "text": { "type": "string" },
+                    "plan": { "type": "string" }
+                }
+            }),
+            result_schema: json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "when": { "type": "string" },
+                    "text": { "type": "string" },
+                    "next_run": { "type": "integer" }
+                }
+            }),
+            risk: RiskLevel::Medium,
+            requires_approval: false,
+            capabilities: vec!["schedule".to_string()],
+        }
+    }
+
+    fn validate(&self, _ctx: &ActionContext, params: &serde_json::Value) -> Result<()> {
+        parse_create_params(params).map(|_| ())
+    }
+
+    fn execute(&self, _ctx: &ActionContext, _params: &serde_json::Value) -> Result<ActionOutcome> {
+        Err(anyhow!("{SCHEDULE_CREATE} only runs inside the engine"))
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::actions::ActionHandler;
+use crate::adapter::Adapter;
+use crate::ai::{AiChatMessage, AiDecision, AiPlanner};
+use crate::format::ReplyFormat;
+use crate::policy::{ActionContext, Policy};
+use crate::preflight::{PreflightConfig, PreflightEngine, PreflightReport};
+use crate::protocol::{ApprovalDecisionPayload, MessagePayload, ProtocolBody, ProtocolEvent};
+use crate::types::{ActionOutcome, ActionRequest, ActionSpec, InboundMessage, OutboundMessage};
+
+// Test support: a scripted AI backend, an adapter that keeps messages in memory, builders for
+// messages and protocol events, and a temp directory to run single actions in.
+
+/// Workspace, room and sender of the built messages unless given.
+pub const TEST_WORKSPACE: &str = "test";
+pub const TEST_ROOM: &str = "test-room";
+pub const TEST_SENDER: &str = "tester";
+
+/// An AI backend that answers from a queue of scripted decisions, in order, and keeps the
+/// inputs it was asked about. With the queue empty it fails, like an unreachable model.
+#[derive(Default)]
+pub struct MockAiPlanner {
+    answers: Mutex<VecDeque<Result<AiDecision, String>>>,
+    inputs: Mutex<Vec<String>>,
+}
+
+impl MockAiPlanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_decisions(decisions: impl IntoIterator<Item = AiDecision>) -> Self {
+        let planner = Self::new();
+        for decision in decisions {
+            planner.push(decision);
+        }
+        planner
+    }
+
+    pub fn push(&self, decision: AiDecision) {
+        self.answer(Ok(decision));
+    }
+
+    /// Makes the next call fail with `message`.
+    pub fn push_error(&self, message: impl Into<String>) {
+        self.answer(Err(message.into()));
+    }
+
+    fn answer(&self, answer: Result<AiDecision, String>) {
+        if let Ok(mut answers) = self.answers.lock() {
+            answers.push_back(answer);
+        }
+    }
+
+    /// Drops the decisions not asked for yet.
+    pub fn clear(&self) {
+        if let Ok(mut answers) = self.answers.lock() {
+            answers.clear();
+        }
+    }
+
+    /// Decisions not asked for yet.
+    pub fn remaining(&self) -> usize {
+        self.answers.lock().map(|answers| answers.len()).unwrap_or(0)
+    }
+
+    /// What the engine sent on each call so far: the user's request with the context the
+    /// engine adds (facts, pending input, memory).
+    pub fn inputs(&self) -> Vec<String> {
+        self.inputs.lock().map(|inputs| inputs.clone()).unwrap_or_default()
+    }
+}
+
+impl AiPlanner for MockAiPlanner {
+    fn plan_with_history(
+        &self,
+        input: &str,
+        _actions: &[ActionSpec],
+        _history: &[AiChatMessage],
+    ) -> Result<AiDecision> {
+        if let Ok(mut inputs) = self.inputs.lock() {
+            inputs.push(input.to_string());
+        }
+        let answer = self
+            .answers
+            .lock()
+            .map_err(|_| anyhow!("mock planner lock poisoned"))?
+            .pop_front()
+            .ok_or_else(|| anyhow!("MockAiPlanner has no scripted decision left"))?;
+        answer.map_err(|message| anyhow!(message))
+    }
+}
+
+/// An `Action` decision for `name` with `params`.
+pub fn ai_action(name: &str, params: Value) -> AiDecision {
+    AiDecision::Action(ActionRequest {
+        name: name.to_string(),
+        params,
+        raw_input: String::new(),
+    })
+}
+
+/// A `Chat` decision answering with `text`.
+pub fn ai_chat(text: &str) -> AiDecision {
+    AiDecision::Chat {
+        message: text.to_string(),
+    }
+}
+
+/// An adapter over in-memory queues: `recv` hands out the queued messages and then reports
+/// the adapter closed, so `Engine::run_with_adapter` returns once they are handled.
+#[derive(Default)]
+pub struct MemoryAdapter {
+    inbox: VecDeque<InboundMessage>,
+    sent: Vec<OutboundMessage>,
+    max_message_chars: Option<usize>,
+    reply_format: ReplyFormat,
+}
+
+impl MemoryAdapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_messages(messages: impl IntoIterator<Item = InboundMessage>) -> Self {
+        Self {
+            inbox: messages.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Pages replies longer than `limit` characters.
+    pub fn with_max_message_chars(mut self, limit: usize) -> Self {
+        self.max_message_chars = Some(limit);
+        self
+    }
+
+    pub fn with_reply_format(mut self, format: ReplyFormat) -> Self {
+        self.reply_format = format;
+        self
+    }
+
+    pub fn push(&mut self, msg: InboundMessage) {
+        self.inbox.push_back(msg);
+    }
+
+    /// Everything the engine sent, in order.
+    pub fn sent(&self) -> &[OutboundMessage] {
+        &self.sent
+    }
+
+    pub fn take_sent(&mut self) -> Vec<OutboundMessage> {
+        std::mem::take(&mut self.sent)
+    }
+}
+
+impl Adapter for MemoryAdapter {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn max_message_chars(&self) -> Option<usize> {
+        self.max_message_chars
+    }
+
+    fn reply_format(&self) -> ReplyFormat {
+        self.reply_format
+    }
+
+    fn recv(&mut self) -> Result<Option<InboundMessage>> {
+        Ok(self.inbox.pop_front())
+    }
+
+    fn send(&mut self, msg: OutboundMessage) -> Result<()> {
+        self.sent.push(msg);
+        Ok(())
+    }
+}
+
+/// `msg-1`, `msg-2`, ...: unique within the process, so the engine's duplicate check never
+/// drops a built message.
+fn next_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!("msg-{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A message from `TEST_SENDER` in `TEST_ROOM`.
+pub fn message(text: &str) -> InboundMessage {
+    message_from(TEST_SENDER, TEST_ROOM, text)
+}
+
+pub fn message_from(sender: &str, room: &str, text: &str) -> InboundMessage {
+    InboundMessage {
+        id: next_id(),
+        text: text.to_string(),
+        sender: sender.to_string(),
+        channel: room.to_string(),
+        workspace_id: Some(TEST_WORKSPACE.to_string()),
+        metadata: json!({}),
+    }
+}
+
+/// A `Message` event from `TEST_SENDER` in `TEST_ROOM`.
+pub fn message_event(text: &str) -> ProtocolEvent {
+    message_event_from(TEST_SENDER, TEST_ROOM, text)
+}
+
+pub fn message_event_from(sender: &str, room: &str, text: &str) -> ProtocolEvent {
+    ProtocolEvent::new(ProtocolBody::Message(MessagePayload {
+        message_id: next_id(),
+        room_id: room.to_string(),
+        workspace_id: TEST_WORKSPACE.to_string(),
+        sender_id: sender.to_string(),
+        text: text.to_string(),
+        event_kind: None,
+        metadata: json!({}),
+        attachments: Vec::new(),
+    }))
+}
+
+/// `TEST_SENDER`'s `approve` or `deny` of `approval_id` in `TEST_ROOM`.
+pub fn approval_event(approval_id: &str, approve: bool) -> ProtocolEvent {
+    ProtocolEvent::new(ProtocolBody::ApprovalDecision(ApprovalDecisionPayload {
+        approval_id: approval_id.to_string(),
+        decision: if approve { "approve" } else { "deny" }.to_string(),
+        room_id: TEST_ROOM.to_string(),
+        workspace_id: TEST_WORKSPACE.to_string(),
+        sender_id: TEST_SENDER.to_string(),
+        in_reply_to: String::new(),
+        reason: None,
+        params: None,
+    }))
+}
+
+/// The `approval_id` an `approval_request` reply carries.
+pub fn approval_id(reply: &OutboundMessage) -> Option<&str> {
+    reply.metadata["data"]["approval_id"].as_str()
+}
+
+/// A temp directory that is the only allowed root of its policy, and the working directory
+/// of its actions, for running one action's preflight, `validate` and `execute` and checking
+/// what it did to the files. Removed on drop.
+pub struct ActionSandbox {
+    /// The sandbox directory and the backups beside it.
+    base: PathBuf,
+    root: PathBuf,
+    /// `dry_run` off, backups on; change the policy or flags before running.
+    pub ctx: ActionContext,
+    /// The default preflight settings; change the config or register checks before running.
+    pub preflight: PreflightEngine,
+}
+
+impl ActionSandbox {
+    pub fn new() -> Result<Self> {
+        let base = env::temp_dir().join(format!("robit-sandbox-{}", uuid::Uuid::now_v7()));
+        let root = base.join("root");
+        fs::create_dir_all(&root)?;
+        // The temp directory is a symlink on macOS; actions report canonical paths.
+        let root = root.canonicalize()?;
+        let mut policy = Policy::default_with_home();
+        policy.allowed_roots = vec![root.clone()];
+        let ctx = ActionContext {
+            cwd: root.clone(),
+            dry_run: false,
+            policy,
+            backup_dir: Some(base.join("backups")),
+            deadline: None,
+            preflight: None,
+        };
+        Ok(Self {
+            base,
+            root,
+            ctx,
+            preflight: PreflightEngine::new(PreflightConfig::default()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// `relative` inside the sandbox.
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    /// `relative` inside the sandbox, as a `path` param.
+    pub fn path_param(&self, relative: &str) -> String {
+        self.path(relative).display().to_string()
+    }
+
+    /// Writes a file, and its parent directories, before the action runs.
+    pub fn write(&self, relative: &str, contents: &str) -> Result<PathBuf> {
+        let path = self.path(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    pub fn read(&self, relative: &str) -> Result<String> {
+        fs::read_to_string(self.path(relative)).map_err(|err| anyhow!("{relative}: {err}"))
+    }
+
+    pub fn exists(&self, relative: &str) -> bool {
+        self.path(relative).exists()
+    }
+
+    /// Names in the sandbox directory, recursively and sorted, with `/` between parts and a
+    /// trailing `/` on directories.
+    pub fn listing(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        list_into(&self.root, "", &mut names);
+        names.sort();
+        names
+    }
+
+    /// What the policy and `validate` say about `params`, without running the action.
+    pub fn validate(&self, action: &dyn ActionHandler, params: &Value) -> Result<()> {
+        self.ctx.policy.check_capabilities(&action.spec())?;
+        action.validate(&self.ctx, params)
+    }
+
+    /// What preflight reports for `params`. Errors when the policy refuses the action.
+    pub fn report(&self, action: &dyn ActionHandler, params: &Value) -> Result<PreflightReport> {
+        self.preflight.check(&action.spec(), params, &self.run_context())
+    }
+
+    /// Runs preflight, validates and then executes, as the engine does once a run is approved.
+    /// A report preflight would block on is an error, as in a strict engine.
+    pub fn run(&self, action: &dyn ActionHandler, params: &Value) -> Result<ActionOutcome> {
+        let report = self.report(action, params)?;
+        if !report.allowed {
+            return Err(anyhow!("preflight blocked: {}", report.summary()));
+        }
+        let ctx = self.run_context();
+        action.validate(&ctx, params)?;
+        action.execute(&ctx, params)
+    }
+
+    /// `ctx` with the preflight settings, as the engine hands it to actions.
+    fn run_context(&self) -> ActionContext {
+        let mut ctx = self.ctx.clone();
+        let config = self.preflight.config();
+        ctx.preflight = config.enabled.then(|| Arc::new(config.clone()));
+        ctx
+    }
+
+    /// Panics unless `relative` is a file holding exactly `expected`.
+    pub fn assert_file(&self, relative: &str, expected: &str) {
+        match self.read(relative) {
+            Ok(actual) => assert_eq!(actual, expected, "contents of {relative}"),
+            Err(err) => panic!("expected file {err}"),
+        }
+    }
+
+    /// Panics if `relative` exists.
+    pub fn assert_missing(&self, relative: &str) {
+        assert!(!self.exists(relative), "{relative} should not exist");
+    }
+}
+
+impl Drop for ActionSandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.base);
+    }
+}
+
+fn list_into(dir: &Path, prefix: &str, names: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            list_into(&entry.path(), &format!("{name}/"), names);
+            names.push(format!("{name}/"));
+        } else {
+            names.push(name);
+        }
+    }
+}
+
+/// Panics unless `outcome.data` has `expected` at the JSON pointer `pointer`, e.g.
+/// `/entries/0/name`.
+pub fn assert_data(outcome: &ActionOutcome, pointer: &str, expected: Value) {
+    match outcome.data.pointer(pointer) {
+        Some(actual) => assert_eq!(actual, &expected, "outcome data at {pointer}"),
+        None => panic!("outcome data has nothing at {pointer}: {}", outcome.data),
+    }
+}
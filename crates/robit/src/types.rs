@@ -36,6 +36,48 @@ pub struct PlanStep {
     pub note: Option<String>,
     #[serde(default)]
     pub requires_approval: Option<bool>,
+    /// Extra attempts after a failed execution.
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Seconds to wait between attempts.
+    #[serde(default)]
+    pub retry_delay: Option<u64>,
+    /// Fails an attempt that runs longer than this.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Reverses this step if a later step fails; params may reference this step's result.
+    #[serde(default)]
+    pub undo: Option<UndoStep>,
+    /// Runs the step once per item instead of once.
+    #[serde(default)]
+    pub foreach: Option<ForEach>,
+}
+
+/// Compensating action for a completed plan step.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UndoStep {
+    pub action: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// Items a plan step is repeated over. Set exactly one of `glob` and `items`; params refer
+/// to the current item as `{{<var>}}`, or `{{<var>.<field>}}` for object items. Path items
+/// also have `name`, `stem`, `ext`, and `dir`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ForEach {
+    /// Files matching a pattern such as `~/Desktop/*.png`; wildcards only in the file name.
+    #[serde(default)]
+    pub glob: Option<String>,
+    /// An array, or a reference to one such as `{{steps.s1.data.entries}}`.
+    #[serde(default)]
+    pub items: Option<Value>,
+    #[serde(default = "default_foreach_var")]
+    pub var: String,
+}
+
+fn default_foreach_var() -> String {
+    "item".to_string()
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -47,8 +89,14 @@ pub struct ActionOutcome {
 #[derive(Clone, Debug)]
 pub enum PlannerResponse {
     Action(ActionRequest),
+    /// Several steps, proposed for approval as one plan.
+    Plan(Vec<PlanStep>),
     NeedInput { prompt: String },
     Unknown { message: String },
+    /// Answered with text, no action.
+    Reply(String),
+    /// Not handled here; the next planner in the chain, or the AI, gets the message.
+    Pass,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
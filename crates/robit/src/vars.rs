@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// Variables set with `set var name=value`, per workspace. Explicit action params refer to
+/// them as `$name` or `${name}`; unknown names are left as typed. Aliases use the same store.
+#[derive(Default)]
+pub(crate) struct VarStore {
+    workspaces: BTreeMap<String, BTreeMap<String, String>>,
+    persist_path: Option<PathBuf>,
+}
+
+impl VarStore {
+    pub(crate) fn enable_persistence(&mut self, path: PathBuf) -> Result<()> {
+        self.persist_path = Some(path.clone());
+        if !path.exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)?;
+        self.workspaces = serde_json::from_str(&content)?;
+        Ok(())
+    }
+
+    /// An empty value removes the variable.
+    pub(crate) fn set(&mut self, workspace_id: &str, name: &str, value: &str) -> Result<()> {
+        if !is_var_name(name) {
+            return Err(anyhow!(
+                "invalid name {name:?}; use letters, digits and _"
+            ));
+        }
+        let vars = self.workspaces.entry(workspace_id.to_string()).or_default();
+        if value.is_empty() {
+            vars.remove(name);
+        } else {
+            vars.insert(name.to_string(), value.to_string());
+        }
+        self.persist()
+    }
+
+    pub(crate) fn all(&self, workspace_id: &str) -> Option<&BTreeMap<String, String>> {
+        self.workspaces
+            .get(workspace_id)
+            .filter(|vars| !vars.is_empty())
+    }
+
+    /// Replaces variables in every string inside `params`.
+    pub(crate) fn substitute(&self, workspace_id: &str, params: &Value) -> Value {
+        let Some(vars) = self.all(workspace_id) else {
+            return params.clone();
+        };
+        substitute_value(params, vars)
+    }
+
+    fn persist(&self) -> Result<()> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self.workspaces)?)?;
+        Ok(())
+    }
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+fn substitute_value(value: &Value, vars: &BTreeMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(substitute_text(text, vars)),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| substitute_value(item, vars))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| (key.clone(), substitute_value(item, vars)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn substitute_text(text: &str, vars: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        match vars.get(name) {
+            Some(value) => {
+                out.push_str(value);
+                rest = &after[consumed..];
+            }
+            None => {
+                out.push('$');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
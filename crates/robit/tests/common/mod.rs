@@ -0,0 +1,21 @@
+use std::env;
+use std::fs;
+use std::sync::Once;
+
+/// Points `ROBIT_HOME` at an empty temp directory and `ROBIT_CONFIG_PATH` at an empty file in
+/// it, so the engines under test read neither the user config nor the repo's
+/// `configs/policy.toml`, and write no state outside it.
+pub fn isolate() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let home = env::temp_dir().join(format!("robit-tests-{}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+        let config = home.join("empty.toml");
+        fs::write(&config, "").unwrap();
+        // SAFETY: set once, before any test in this binary reads the environment.
+        unsafe {
+            env::set_var("ROBIT_HOME", &home);
+            env::set_var("ROBIT_CONFIG_PATH", &config);
+        }
+    });
+}
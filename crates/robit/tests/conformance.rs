@@ -0,0 +1,33 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use robit::conformance::{builtin_fixtures, check, load_fixtures, ProtocolFixture};
+use robit::{default_registry, Engine};
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/protocol")
+}
+
+fn names(fixtures: Vec<ProtocolFixture>) -> Vec<String> {
+    let mut names: Vec<String> = fixtures.into_iter().map(|fixture| fixture.name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn builtin_fixtures_are_the_files_on_disk() {
+    let builtin = names(builtin_fixtures().unwrap());
+    let on_disk = names(load_fixtures(&fixtures_dir()).unwrap());
+    assert_eq!(builtin, on_disk);
+}
+
+#[test]
+fn engine_passes_the_protocol_fixtures() {
+    common::isolate();
+    let fixtures = load_fixtures(&fixtures_dir()).unwrap();
+    assert!(!fixtures.is_empty());
+    let report = check(&fixtures, || Engine::builder().registry(default_registry()).build());
+    assert!(report.passed(), "{report}");
+    assert_eq!(report.fixtures, fixtures.len());
+}
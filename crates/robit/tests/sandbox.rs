@@ -0,0 +1,72 @@
+use robit::actions::fs_ops::{ReplaceTextAction, RestoreFileAction, WriteFileAction};
+use robit::actions::shell::ShellRunAction;
+use robit::actions::ActionHandler;
+use robit::testing::{assert_data, ActionSandbox};
+use serde_json::json;
+
+#[test]
+fn relative_paths_resolve_in_the_sandbox() {
+    let sandbox = ActionSandbox::new().unwrap();
+    sandbox.write("notes.txt", "old").unwrap();
+    let params = json!({"path": "notes.txt", "find": "old", "replace": "new"});
+    let outcome = sandbox.run(&ReplaceTextAction, &params).unwrap();
+    assert_data(&outcome, "/replaced", json!(1));
+    sandbox.assert_file("notes.txt", "new");
+}
+
+#[test]
+fn run_stops_where_preflight_blocks() {
+    let mut sandbox = ActionSandbox::new().unwrap();
+    let mut config = sandbox.preflight.config().clone();
+    config.blocked_roots = vec![sandbox.path("private")];
+    sandbox.preflight.set_config(config);
+    let params = json!({"path": "private/key.txt", "content": "secret", "create_parents": true});
+
+    let report = sandbox.report(&WriteFileAction, &params).unwrap();
+    assert!(!report.allowed);
+    let err = sandbox.run(&WriteFileAction, &params).unwrap_err();
+    assert!(err.to_string().starts_with("preflight blocked"), "{err}");
+    sandbox.assert_missing("private/key.txt");
+
+    sandbox.run(&WriteFileAction, &json!({"path": "public.txt", "content": "hi"})).unwrap();
+    sandbox.assert_file("public.txt", "hi");
+}
+
+#[test]
+fn restore_deletes_only_the_file_the_undone_action_created() {
+    let sandbox = ActionSandbox::new().unwrap();
+    sandbox.write("keep.txt", "keep").unwrap();
+    let params = json!({"path": "new.txt", "content": "hi"});
+    let outcome = sandbox.run(&WriteFileAction, &params).unwrap();
+    let undo = WriteFileAction.undo(&params, &outcome).unwrap();
+
+    let err = sandbox.run(&RestoreFileAction, &json!({"path": "keep.txt"})).unwrap_err();
+    assert!(err.to_string().contains("backup"), "{err}");
+    let mut other = undo.params.clone();
+    other["path"] = json!(sandbox.path_param("keep.txt"));
+    assert!(sandbox.run(&RestoreFileAction, &other).is_err());
+    sandbox.assert_file("keep.txt", "keep");
+
+    sandbox.run(&RestoreFileAction, &undo.params).unwrap();
+    sandbox.assert_missing("new.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn timeout_kills_the_commands_children() {
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    let mut sandbox = ActionSandbox::new().unwrap();
+    sandbox.ctx.deadline = Some(Instant::now() + Duration::from_millis(500));
+    let command = "sleep 30 & echo $! > sleep.pid; wait";
+    let params = json!({"command": command, "cwd": sandbox.path_param(".")});
+    let err = sandbox.run(&ShellRunAction, &params).unwrap_err();
+    assert!(err.to_string().contains("time limit"), "{err}");
+
+    let pid = sandbox.read("sleep.pid").unwrap();
+    let state = Command::new("ps").args(["-o", "stat=", "-p", pid.trim()]).output().unwrap();
+    let state = String::from_utf8_lossy(&state.stdout);
+    // Gone, or a zombie waiting for init to reap it.
+    assert!(state.trim().is_empty() || state.starts_with('Z'), "sleep is still {state}");
+}